@@ -0,0 +1,154 @@
+use crate::transition::Transition;
+
+use std::fmt;
+use std::io;
+use std::process::{Child, Command};
+use windows::Win32::UI::Input::KeyboardAndMouse::{HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_SHIFT, MOD_WIN, VIRTUAL_KEY, VK_F1};
+
+/// Represent what starts a list of actions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+  /// System-wide key combination, written as the modifiers and the key joined by `+` (e.g. "ctrl+alt+shift+b")
+  Chord(String)
+}
+
+/// Represent something done in response to a trigger
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+  /// Set the brightness to the given value, using its own transition instead of the one of the knob
+  Set(i32, Transition),
+  /// Move the brightness by the given number of steps, like turning the knob would
+  Step(i32),
+  /// Run a command through the shell, without waiting for it to finish
+  RunCommand(String),
+  /// Stop applying the knob adjustments, or start applying them again if they were stopped already
+  Pause,
+  /// Switch the knob to its next mode, like pressing it would
+  CycleMode,
+  /// Switch the monitors to their next input source
+  CycleInput,
+  /// Apply the preset of the given name, easing the monitors into it with the transition of the knob
+  ApplyPreset(String)
+}
+
+/// Bind a trigger to the actions that are run, in order, every time it fires
+#[derive(Debug, Clone)]
+pub struct ActionBinding {
+  pub trigger: Trigger,
+  pub actions: Vec<Action>
+}
+
+impl ActionBinding {
+  pub fn new(trigger: Trigger, actions: Vec<Action>) -> Self {
+    Self { trigger, actions }
+  }
+}
+
+/// Represent a problem with the bindings that makes it ambiguous what a key press does, the bindings being referred to
+/// by their index
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BindingConflict {
+  /// The chord can't be parsed, so the binding would never fire
+  InvalidChord { index: usize, chord: String },
+  /// Both bindings are triggered by the same chord, even if written differently (e.g. "ctrl+b" and "Control+B")
+  SameChord { first_index: usize, second_index: usize, chord: String },
+  /// The chord uses a key sent by the knob, which would both adjust the brightness and run the actions
+  KnobKey { index: usize, chord: String }
+}
+
+impl fmt::Display for BindingConflict {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      BindingConflict::InvalidChord { index, chord } => write!(f, "binding #{} uses the key combination \"{}\", which can't be parsed", index + 1, chord),
+      BindingConflict::SameChord { first_index, second_index, chord } => {
+        write!(f, "bindings #{} and #{} are both triggered by the key combination \"{}\"", first_index + 1, second_index + 1, chord)
+      },
+      BindingConflict::KnobKey { index, chord } => write!(f, "binding #{} uses the key combination \"{}\", whose key is sent by the knob", index + 1, chord)
+    }
+  }
+}
+
+/// Look for the bindings that conflict with each other or with the keys sent by the knob. Hotkeys can only be
+/// registered once, and the keyboard hook sees the knob keys before anything else, so any of these would end up making
+/// some bindings never fire or fire along with something else depending on the order things are registered in
+pub fn find_conflicts(bindings: &[ActionBinding], knob_keys: &[VIRTUAL_KEY]) -> Vec<BindingConflict> {
+  let mut conflicts = Vec::new();
+  let mut parsed_chords: Vec<(usize, (HOT_KEY_MODIFIERS, u32))> = Vec::new();
+  for (index, binding) in bindings.iter().enumerate() {
+    let Trigger::Chord(chord) = &binding.trigger;
+    let Some(parsed_chord) = parse_chord(chord) else {
+      conflicts.push(BindingConflict::InvalidChord { index, chord: chord.clone() });
+      continue;
+    };
+
+    if knob_keys.iter().any(|key| key.0 as u32 == parsed_chord.1) {
+      conflicts.push(BindingConflict::KnobKey { index, chord: chord.clone() });
+    }
+    if let Some((first_index, _)) = parsed_chords.iter().find(|(_, other_chord)| *other_chord == parsed_chord) {
+      conflicts.push(BindingConflict::SameChord { first_index: *first_index, second_index: index, chord: chord.clone() });
+    }
+    parsed_chords.push((index, parsed_chord));
+  }
+  conflicts
+}
+
+/// Parse a chord into the modifiers and the virtual-key code expected by RegisterHotKey. Keys can be letters, digits or
+/// function keys (F1-F24)
+pub fn parse_chord(chord: &str) -> Option<(HOT_KEY_MODIFIERS, u32)> {
+  let mut modifiers = HOT_KEY_MODIFIERS(0);
+  let mut key = None;
+  for part in chord.split('+').map(|part| part.trim().to_lowercase()) {
+    match parse_modifier(&part) {
+      Some(modifier) => modifiers |= modifier,
+      None if key.is_some() => return None,
+      None => key = Some(parse_key(&part)?)
+    };
+  }
+  key.map(|key| (modifiers, key))
+}
+
+/// Parse modifiers joined by `+` (e.g. "ctrl+alt"), without any other key
+pub fn parse_modifiers(modifiers: &str) -> Option<HOT_KEY_MODIFIERS> {
+  modifiers.split('+').try_fold(HOT_KEY_MODIFIERS(0), |parsed, part| Some(parsed | parse_modifier(&part.trim().to_lowercase())?))
+}
+
+fn parse_modifier(modifier: &str) -> Option<HOT_KEY_MODIFIERS> {
+  match modifier {
+    "ctrl" | "control" => Some(MOD_CONTROL),
+    "alt" => Some(MOD_ALT),
+    "shift" => Some(MOD_SHIFT),
+    "win" => Some(MOD_WIN),
+    _ => None
+  }
+}
+
+/// Parse the name of a single key, as used for the keys sent by the knob. Besides the keys chords can use, any other key
+/// can be given by its virtual-key code in hexadecimal (e.g. "0xaf")
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/inputdev/virtual-key-codes
+pub fn parse_virtual_key(name: &str) -> Option<VIRTUAL_KEY> {
+  let name = name.trim().to_lowercase();
+  let key = match name.strip_prefix("0x") {
+    Some(code) => u32::from_str_radix(code, 16).ok().filter(|code| (1..=0xfe).contains(code))?,
+    None => parse_key(&name)?
+  };
+  Some(VIRTUAL_KEY(key as u16))
+}
+
+fn parse_key(key: &str) -> Option<u32> {
+  let mut chars = key.chars();
+  match (chars.next(), chars.next()) {
+    // Letters and digits share their virtual-key codes with their uppercase ASCII codes
+    (Some(c), None) if c.is_ascii_alphanumeric() => Some(c.to_ascii_uppercase() as u32),
+    (Some('f'), Some(_)) => match key[1..].parse::<u32>() {
+      Ok(number @ 1..=24) => Some(VK_F1.0 as u32 + number - 1),
+      _ => None
+    },
+    _ => None
+  }
+}
+
+/// Run a command through the shell, leaving it running in the background
+pub fn run_command(command: &str) -> io::Result<Child> {
+  Command::new("cmd").args(["/C", command]).spawn()
+}
@@ -0,0 +1,150 @@
+use crate::desktop::is_secure_desktop_active;
+use crate::keyboard_knob::KnobAdjustmentEvent;
+use crate::monitor_group::MonitorGroup;
+use crate::shutdown::StopSignal;
+use crate::stats;
+use crate::transition::Transition;
+
+use crossbeam_channel::{Receiver, Select, never};
+use std::cmp::max;
+use std::io;
+use std::time::{Duration, Instant};
+use tracing::trace;
+
+/// Something the brightness can be written to, which is a group of monitors in the program. It lets the transitions
+/// run against a stand-in for the monitors, such as the `MockTarget` of the builds with the "mock-target" feature
+pub trait BrightnessTarget {
+  /// Get the refresh rate of the fastest of the monitors, which the transitions are run at
+  fn refresh_rate_hz(&self) -> u16;
+
+  /// Set the brightness of the monitors, from 0 to 100
+  fn set_brightness(&mut self, value: u16) -> io::Result<()>;
+
+  /// Wake up the monitors found asleep, or leave them out, before a transition starting at the given value
+  fn check_power(&mut self, _brightness: u16) {}
+}
+
+impl BrightnessTarget for MonitorGroup {
+  fn refresh_rate_hz(&self) -> u16 {
+    MonitorGroup::refresh_rate_hz(self)
+  }
+
+  fn set_brightness(&mut self, value: u16) -> io::Result<()> {
+    MonitorGroup::set_brightness(self, value)
+  }
+
+  fn check_power(&mut self, brightness: u16) {
+    MonitorGroup::check_power(self, brightness)
+  }
+}
+
+/// Move the brightness of monitors smoothly from one value to another, one frame at a time at the refresh rate of the
+/// fastest of them
+#[derive(Debug, Clone)]
+pub struct BrightnessAnimator {
+  transition: Transition,
+  events_rx: Receiver<KnobAdjustmentEvent>,
+  stop_rx: StopSignal
+}
+
+impl BrightnessAnimator {
+  /// Create an animator running every transition to the end
+  pub fn new(transition: Transition) -> Self {
+    Self { transition, events_rx: never(), stop_rx: never() }
+  }
+
+  /// Interrupt the transitions as soon as a knob adjustment event comes through, without consuming it, or the stop
+  /// signal is received
+  pub fn interrupted_by(self, events_rx: Receiver<KnobAdjustmentEvent>, stop_rx: StopSignal) -> Self {
+    Self { events_rx, stop_rx, ..self }
+  }
+
+  /// Adjust the brightness of the monitors by smoothly transitioning from the previous value. If a new knob adjustment
+  /// event comes through while waiting for the next frame, the transition is interrupted before finishing and the new
+  /// event takes priority. The transition is also interrupted when the secure desktop becomes active, or when the stop
+  /// signal is received. Returns the brightness the monitors were left at, which is somewhere between the previous and
+  /// the target values when interrupted, so that the next transition picks up from there without jumping back
+  ///
+  /// Monitors found asleep are woken up or left out beforehand, when the group checks their power mode
+  ///
+  /// Every value written along the way is passed to `on_frame` along with the monitors it was written to, for the
+  /// user-facing state to move in sync with the backlight rather than jumping to the target value once settled
+  pub fn animate<T: BrightnessTarget>(&self, monitors: &mut T, prev_value: i32, target_value: i32, mut on_frame: impl FnMut(&T, i32)) -> io::Result<i32> {
+    let from_brightness = prev_value as f64;
+    let to_brightness = target_value as f64;
+    monitors.check_power(prev_value as u16);
+
+    // Compute the number of frames required to smoothly transition to the next brightness value in the given duration
+    let refresh_rate = monitors.refresh_rate_hz() as f32;
+    let n_frames = max(((self.transition.duration.as_millis() as f32 * refresh_rate) / 1000.0).ceil() as i32, 1);
+
+    let frame_time = Duration::from_millis(((1.0 / refresh_rate) * 1000.0).floor() as u64);
+    let mut prev_brightness = -1;
+    let mut displayed_brightness = prev_value;
+
+    // New knob adjustment events and the stop signal end the wait for the next frame, without the events being consumed
+    let mut interruptions = Select::new();
+    interruptions.recv(&self.events_rx);
+    interruptions.recv(&self.stop_rx);
+    let started_at = Instant::now();
+
+    for frame in 1..=n_frames {
+      // Ease to the target brightness
+      let t = frame as f64 / n_frames as f64;
+      let next_brightness = self.transition.easing.ease(from_brightness, to_brightness, t);
+      let next_brightness = (if from_brightness < to_brightness { next_brightness.ceil() } else { next_brightness.floor() }) as i32;
+
+      // Stop writing as soon as the secure desktop shows up, the state is re-read once it goes away
+      if is_secure_desktop_active() { return Ok(displayed_brightness); }
+
+      // Avoid unnecessary updates
+      if next_brightness != prev_brightness {
+        trace!("frame #{}\tvalue {}\tt {}", frame, next_brightness, t);
+        monitors.set_brightness(next_brightness as u16)?;
+        displayed_brightness = next_brightness;
+        on_frame(monitors, displayed_brightness);
+      }
+
+      // Frames are scheduled from the start of the transition rather than from the end of the previous one, so that
+      // the coarse resolution of the system timer and the time spent writing don't add up over the transition. The
+      // events interrupting it are left for the caller to fold into the next target, which is where they are counted
+      if interruptions.ready_deadline(started_at + frame_time * frame as u32).is_ok() {
+        return Ok(displayed_brightness);
+      }
+
+      prev_brightness = next_brightness;
+    }
+
+    Ok(target_value)
+  }
+}
+
+/// Fold a batch of knob adjustment events into the same target, which is then reached with a single transition rather
+/// than one restarted on every notch. Every event is passed to `fold` in turn, except for the first one after which
+/// `ends_batch` is true, which is returned for the caller to handle once the target was reached. The events are only
+/// taken from the iterator until then, the ones after it are left waiting in line
+pub fn fold_events(events: impl IntoIterator<Item = KnobAdjustmentEvent>, ends_batch: impl Fn(&KnobAdjustmentEvent) -> bool, mut fold: impl FnMut(KnobAdjustmentEvent)) -> Option<KnobAdjustmentEvent> {
+  for (index, event) in events.into_iter().enumerate() {
+    if index > 0 && ends_batch(&event) {
+      return Some(event);
+    }
+    if index > 0 {
+      stats::record_coalesced();
+    }
+    fold(event);
+    stats::record_processed();
+  }
+  None
+}
+
+/// Move the target brightness by the given number of steps, leaving it to the caller to keep it within the range. While
+/// a transition is still under way, turning the other way is relative to the brightness currently displayed rather than
+/// to the target, so that the change of direction shows up right away instead of after the rest of the transition is
+/// undone
+pub fn nudge(displayed_value: i32, target_value: i32, delta: i32) -> i32 {
+  let base_value = match (target_value - displayed_value).signum() * delta.signum() < 0 {
+    true => displayed_value,
+    false => target_value
+  };
+  base_value + delta
+}
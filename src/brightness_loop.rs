@@ -0,0 +1,1247 @@
+use crate::{capabilities, display_snapshot, stats, usage, vendor_software};
+use crate::BrightnessAnimator;
+use crate::animator::{fold_events, nudge};
+use crate::routing::route_selection;
+use crate::actions::{Action, run_command};
+use crate::config::{Config, MonitorConfig};
+use crate::content_dimming::{ContentDimming, average_luminance};
+use crate::desktop::is_secure_desktop_active;
+use crate::gesture::StepGesture;
+use crate::keyboard_knob::{KnobAction, KnobAdjustmentEvent};
+use crate::knob_mode::{KnobMode, ModeCycle, PressAction};
+use crate::monitor::{CONTRAST_VCP_CODE, Monitor, check_physical_handles, is_disconnection_error};
+use crate::monitor_group::{MonitorGroup, MonitorSelection};
+use crate::osd::OsdState;
+use crate::saved_brightness::SavedBrightness;
+use crate::schedule::{RampStep, Schedule};
+use crate::shared_state::{FLAG_PAUSED, SharedChange, SharedMonitorState, SharedState};
+use crate::shutdown::StopSignal;
+use crate::state::{AppliedChange, BrightnessState, ChangeSource, StateSnapshot};
+use crate::system_events::SystemEvent;
+use crate::transition::Transition;
+use crate::vendor_software::VendorSoftware;
+use crate::watch::WatchSender;
+
+use crossbeam_channel::{Receiver, Sender, after, at, never, select, tick, unbounded};
+use std::collections::BTreeMap;
+use std::io;
+use std::iter;
+use std::mem;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+/// How long the display configuration is left to settle after a change before the monitors are enumerated again
+const REENUMERATE_DELAY: Duration = Duration::from_millis(500);
+/// How long the monitors are left to wake up after the system resumed before they are opened again
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+
+/// Channels the brightness thread talks to the other stages through
+pub struct BrightnessChannels {
+  pub events_rx: Receiver<KnobAdjustmentEvent>,
+  pub system_rx: Receiver<SystemEvent>,
+  pub state_tx: WatchSender<Option<StateSnapshot>>,
+  pub osd_tx: WatchSender<Option<OsdState>>,
+  pub overlay_tx: WatchSender<u8>,
+  /// Every change applied to the brightness, which unlike the snapshots are never merged together
+  pub changes_tx: Sender<AppliedChange>,
+  /// Shared memory block the state is published to for other processes, when it could be created
+  pub shared_state: Option<SharedState>
+}
+
+/// Adjust the brightness of the given monitors following the knob, the system events and the schedule, until asked to
+/// stop or until the input stages are gone. The opacity of the dimming overlay is only sent when it's shown
+///
+/// All the monitors are set to the same brightness, which is the one of the first of them. The group is empty while
+/// they are all disconnected, in which case the brightness they had is kept around so that it can be restored as soon
+/// as they are adopted again
+pub fn run_brightness_loop(stop_rx: StopSignal, config: &Config, mut monitors: MonitorGroup, monitor_selection: MonitorSelection, overlay_shown: bool, channels: BrightnessChannels) {
+  let BrightnessChannels { events_rx, system_rx, state_tx, osd_tx, overlay_tx, changes_tx, mut shared_state } = channels;
+  let action_bindings = config.action_bindings();
+  let mut saved_brightness = SavedBrightness::load().unwrap_or_else(|e| {
+    error!("unable to read the saved brightness - {}", e);
+    SavedBrightness::default()
+  });
+  // The brightness saved the last time stands in for the one of the monitors when it can't be read, such as while none
+  // is connected yet, but it's only shown until they could be read
+  let (brightness, known) = match monitors.get_brightness() {
+    Ok(value) => (value as i32, true),
+    Err(_) => {
+      let saved_value = saved_brightness.find(&monitors.ids()).or_else(|| saved_brightness.monitors.values().next().copied());
+      (saved_value.map_or(config.max_brightness, i32::from).clamp(config.min_brightness, config.max_brightness), false)
+    }
+  };
+  let mut state = BrightnessState::new(brightness, known, monitor_selection, monitors.names());
+  if config.linked_monitors {
+    state.offsets = Some(BTreeMap::new());
+    state.link(&mut monitors);
+  }
+  if config.restore_brightness {
+    restore_saved_brightness(&mut monitors, &mut state, &saved_brightness, config);
+  }
+
+  // Some panels apply brightness changes unreliably for a while after being powered on, so every write is verified
+  // until the monitor has warmed up, and retried when it didn't stick
+  let mut warm_up = WarmUp::new(config.warm_up_period);
+  if !monitors.is_empty() {
+    warm_up.start();
+  }
+  let mut unverified = false;
+  let mut write_retries = 0;
+  let mut write_retry_timer = never();
+
+  if let Err(e) = usage::prune() {
+    error!("unable to prune the usage records - {}", e);
+  }
+  for (monitor_name, value) in monitors.brightness_by_name(state.current as u16) {
+    usage::record_start(&monitor_name, value);
+  }
+
+  let mut zero_floor = ZeroFloor::new(config.zero_floor, config.zero_floor_breakthrough_delay, config.min_brightness);
+  let mut overlay_dimming = OverlayDimming::new(if overlay_shown { config.dimming_overlay_max_opacity } else { 0 });
+  let mut acceleration = Acceleration::new(config.acceleration_window, config.max_acceleration);
+  let mut step_gesture = StepGesture::new(config.coarse_step_size, config.wiggle_window, config.coarse_step_timeout);
+  // Nothing wakes this thread up while the knob is idle, unless a feature that needs it is enabled. Handles only leak
+  // across hotplug cycles, so they are checked once more a while after each display change rather than periodically
+  let mut handle_check_timer = never();
+  // Handles of the monitors go stale once they are unplugged or re-docked, so they are all opened again after every
+  // display change
+  let mut reenumerate_timer = never();
+  // Set once the system resumed, for the brightness to be read back from the monitors rather than restored, given that
+  // they might have been adjusted through their buttons or by another computer in the meantime
+  let mut resumed = false;
+  // Changes made behind our back, such as through the buttons of the monitor, are picked up by polling it
+  let mut resync_backoff = ResyncBackoff::new(config.resync_external_changes, config.resync_min_interval, config.resync_max_interval);
+  let mut resync_timer = if monitors.is_empty() { never() } else { resync_backoff.restart() };
+
+  // Monitor control applications that are running alongside this one, when co-operating with them
+  let mut vendor_software = detect_vendor_software(None, config.vendor_cooperation);
+  let vendor_check_ticker = if config.vendor_cooperation { tick(config.vendor_check_interval) } else { never() };
+  let mut mode_cycle = ModeCycle::new(&config.knob_modes, config.mode_timeout, &config.limit_behavior, config.limit_hold_delay);
+  let mut mode_timer = never();
+  // Contrast adjusted with the contrast modifiers held, along with when that last happened
+  let mut contrast: Option<(i32, Instant)> = None;
+  let mut schedule = Schedule::new(&config.schedule, config.location);
+  let mut schedule_timer = schedule.timer();
+  let mut content_dimming = ContentDimming::new(config.content_max_dimming, config.content_dark_threshold);
+  let content_sample_ticker = if config.content_dimming { tick(config.content_sample_interval) } else { never() };
+  // Monitors being adjusted, which are the selected ones unless the source of the last knob adjustments is routed to
+  // others, along with their display when it's followed
+  let mut active_selection = state.selection.clone();
+  let mut followed_display = match &state.selection {
+    MonitorSelection::Followed(followed) => Some(followed.display()),
+    _ => None
+  };
+  // Knob adjustment event that ended the previous batch by being routed to other monitors, which starts the next one
+  let (carried_tx, carried_rx) = unbounded::<KnobAdjustmentEvent>();
+  // Set when the knob was pressed right after being turned, to switch the mode once the brightness reached its target
+  let mut deferred_cycle = false;
+  let press_cycles_inputs = config.press_action == PressAction::CycleInput;
+  // Set once the monitors were put in standby by holding the knob, until it's turned again
+  let mut in_standby = false;
+  // Transition of the next brightness change, which is the one of the knob unless an action asked for another one
+  let knob_transition = config.knob_transition();
+  let mut next_transition = knob_transition;
+
+  let mut published_snapshot = None;
+
+  let mut first_iteration = true;
+  loop {
+    // Every iteration past the first one follows the thread being woken up by one of the channels or timers below
+    if !mem::take(&mut first_iteration) {
+      stats::record_wakeup();
+    }
+    // Forget about the monitors that got disconnected during the previous iteration
+    let disconnected_names = monitors.take_disconnected();
+    if !disconnected_names.is_empty() {
+      for monitor_name in &disconnected_names {
+        info!("{} disconnected", monitor_name);
+        usage::record_stop(monitor_name);
+      }
+      state.monitor_names = monitors.names();
+      if monitors.is_empty() {
+        info!("every monitor disconnected, waiting for them to be connected again");
+        mode_cycle.reset();
+        mode_timer = never();
+      }
+    }
+
+    // Publish the outcome of the previous iteration, with every reader getting the same snapshot of it
+    let snapshot = state.snapshot(&mode_cycle, &monitors);
+    if let Some(shared_state) = shared_state.as_mut() {
+      if published_snapshot.as_ref() != Some(&snapshot) {
+        let monitor_states: Vec<SharedMonitorState> = snapshot.monitors
+          .iter()
+          .map(|monitor| SharedMonitorState { name: monitor.name.clone(), brightness: monitor.brightness })
+          .collect();
+        let change = snapshot.last_change.as_ref().map_or(SharedChange::default(), |change| SharedChange {
+          count: change.sequence,
+          source: change.source as u32,
+          from: change.from as i32,
+          to: change.to as i32,
+          duration_ms: change.duration.as_millis() as u32
+        });
+        shared_state.publish(&monitor_states, if snapshot.paused { FLAG_PAUSED } else { 0 }, snapshot.mode as u32, change);
+        published_snapshot = Some(snapshot.clone());
+      }
+    }
+    state_tx.send(Some(snapshot));
+    // Nobody listens to the changes when the IPC server is off
+    for change in state.take_changes() {
+      let _ = changes_tx.send(change);
+    }
+
+    // Monitors set aside after failing too many times in a row are tried again once their cooldown is over, which
+    // only wakes this thread up while there are some. The probes wait for the secure desktop to be left, which wakes
+    // this thread up on its own, rather than the timer firing again straight away while they are overdue
+    let probes_allowed = state.known && !is_secure_desktop_active();
+    let quarantine_probe_timer = monitors.next_probe().filter(|_| probes_allowed).map_or_else(never, at);
+    select! {
+      recv(stop_rx) -> _ => break,
+      recv(if carried_rx.is_empty() { &events_rx } else { &carried_rx }) -> received => {
+        let Ok(mut received) = received else { break };
+        if state.paused {
+          continue;
+        }
+        let leading_config = leading_monitor_config(config, &monitors);
+        let knob_inverted = leading_config.is_some_and(|monitor_config| monitor_config.inverted);
+        let knob_step_size = leading_config.and_then(|monitor_config| monitor_config.step_size).unwrap_or(config.step_size);
+        let (knob_min, knob_max) = leading_config.map_or((config.min_brightness, config.max_brightness), |monitor_config| {
+          monitor_config.brightness_range(config.min_brightness, config.max_brightness)
+        });
+        if knob_inverted {
+          received.action = received.action.reversed();
+        }
+
+        // The knob adjustment waking the monitors up from the standby is not applied, they are not visible yet
+        if mem::take(&mut in_standby) {
+          info!("waking the monitors up");
+          monitors.wake().iter().for_each(|(monitor_name, e)| error!("unable to wake {} up - {}", monitor_name, e));
+          events_rx.try_iter().for_each(drop);
+          stats::record_processed();
+          continue;
+        }
+
+        // Pressing the knob switches it to its next mode when there's any, the secondary ones being adjusted here
+        if received.action == KnobAction::Press && press_cycles_inputs {
+          cycle_inputs(&mut monitors);
+          stats::record_processed();
+          continue;
+        }
+        if received.action == KnobAction::Press && mode_cycle.can_cycle() {
+          cycle_knob_mode(&mut mode_cycle, &mut monitors);
+          mode_timer = mode_cycle.timer();
+          osd_tx.send(Some(knob_osd_state(&mode_cycle, state.current)));
+          continue;
+        }
+        let contrast_modifiers = config.contrast_modifiers.0;
+        if contrast_modifiers != 0 && received.modifiers.0 & contrast_modifiers == contrast_modifiers && received.action != KnobAction::Press {
+          let step_size = knob_step_size * acceleration.multiplier(&received);
+          if let Some(value) = adjust_contrast(&mut contrast, &mut monitors, &received, step_size, config.mode_timeout) {
+            show_osd(&osd_tx, OsdState { mode: KnobMode::Contrast, value: value as u16, coarse: false });
+          }
+          stats::record_processed();
+          continue;
+        }
+        if mode_cycle.current() != KnobMode::Brightness {
+          let step_size = knob_step_size * acceleration.multiplier(&received);
+          adjust_knob_mode(&mut mode_cycle, &mut monitors, &received, step_size);
+          mode_timer = mode_cycle.timer();
+          // Shown even when the value didn't change, as the feedback of the knob being turned past a limit
+          show_osd(&osd_tx, knob_osd_state(&mode_cycle, state.current));
+          stats::record_processed();
+          continue;
+        }
+
+        // The events adjust the monitors their source is routed to, which are switched to once the previous ones
+        // settled. The display that's followed changes as the user moves around, the knob adjusts the one they were on
+        // when they started turning it
+        let routed_selection = route_selection(&config.routes, &received, &state.selection);
+        let routed_display = match &routed_selection {
+          MonitorSelection::Followed(followed) => Some(followed.display()),
+          _ => None
+        };
+        if state.target == state.current && (routed_selection != active_selection || routed_display != followed_display) {
+          active_selection = routed_selection.clone();
+          followed_display = routed_display;
+          match open_monitors(&routed_selection, config) {
+            Ok(routed_monitors) => switch_monitors(&mut monitors, &mut state, routed_monitors),
+            Err(e) => error!("unable to open the monitors the knob is routed to, adjusting the previous ones - {}", e)
+          };
+        }
+
+        // The other application might have changed the brightness behind our back since the last adjustment, so the
+        // monitor is read again before building on top of its value, which is also done until it could be read once
+        if (vendor_software.is_some() || !state.known) && state.target == state.current {
+          if let Ok(value) = monitors.get_brightness() {
+            state.current = value as i32;
+            state.target = state.current;
+            state.known = true;
+          }
+        }
+        next_transition = knob_transition;
+
+        // Spinning the knob quickly queues up several events before the brightness gets to change, so the ones that are
+        // already waiting are folded into the same target, which is then reached with a single transition rather than
+        // one restarted on every notch
+        let mut pushed_past_limit = false;
+        let mut wiggled = false;
+        let queued_events = events_rx.try_iter().map(|mut event| {
+          if knob_inverted {
+            event.action = event.action.reversed();
+          }
+          event
+        });
+        // A press switching to another mode ends the batch, the events after it belong to that mode, and so does an
+        // event routed to other monitors
+        let press_ends_batch = press_cycles_inputs || mode_cycle.can_cycle();
+        let selected = state.selection.clone();
+        let ends_batch = |event: &KnobAdjustmentEvent| {
+          (event.action == KnobAction::Press && press_ends_batch) || route_selection(&config.routes, event, &selected) != routed_selection
+        };
+        let batch_end = fold_events(iter::once(received).chain(queued_events), ends_batch, |event| {
+          let prev_target = state.target;
+          let step_size = step_gesture.step_size(knob_step_size, event.timestamp) * acceleration.multiplier(&event);
+          let target = match event.action {
+            // Turning the knob down at the lowest brightness darkens the overlay instead, which turning it back up
+            // lightens first
+            _ if overlay_dimming.adjust(state.target, knob_min, &event, step_size) => {
+              overlay_tx.send(overlay_dimming.alpha());
+              state.target
+            },
+            KnobAction::Increment => nudge(state.current, state.target, step_size),
+            KnobAction::Decrement => nudge(state.current, state.target, -step_size),
+            KnobAction::Press => state.target,
+            KnobAction::Partial(fraction) => {
+              let target = state.target as f64 + state.remainder + fraction * step_size as f64;
+              state.remainder = target - target.round();
+              target.round() as i32
+            }
+          };
+          let limited_target = mode_cycle.limit().apply(state.target, target, knob_min, knob_max, event.timestamp);
+          pushed_past_limit |= limited_target != target;
+          state.target = zero_floor.apply(state.target, limited_target, &event);
+          state.source = ChangeSource::from(event.source);
+          if !matches!(event.action, KnobAction::Partial(_)) || limited_target != target {
+            state.remainder = 0.0;
+          }
+          // The notches of the wiggle are undone, it only switches the step size
+          if let Some(start_target) = step_gesture.notch(&event, prev_target) {
+            let step_size = step_gesture.step_size(knob_step_size, event.timestamp);
+            info!("knob wiggled, now stepping by {}", step_size);
+            state.target = start_target;
+            wiggled = true;
+          }
+        });
+        match batch_end {
+          Some(event) if event.action == KnobAction::Press && press_ends_batch => {
+            stats::record_processed();
+            deferred_cycle = true;
+          },
+          // Reversed back, the next batch reversing it again
+          Some(mut event) => {
+            if knob_inverted {
+              event.action = event.action.reversed();
+            }
+            let _ = carried_tx.send(event);
+          },
+          None => {}
+        };
+
+        // Nothing moves when the knob is turned further past a limit it's stopped at, but it's worth showing why, as well
+        // as the step size switched to by a wiggle
+        if (pushed_past_limit || wiggled) && state.target == state.current {
+          show_osd(&osd_tx, OsdState { mode: KnobMode::Brightness, value: state.current as u16, coarse: step_gesture.is_coarse(Instant::now()) });
+        }
+      },
+      recv(reenumerate_timer) -> _ => {
+        reenumerate_timer = never();
+        // Nothing might be connected yet, in which case the monitors are enumerated again on the next display change
+        let Ok(enumerated_monitors) = open_monitors(&active_selection, config) else { continue };
+        let previous_names = state.monitor_names.clone();
+        if mem::take(&mut resumed) {
+          switch_monitors(&mut monitors, &mut state, enumerated_monitors);
+          warm_up.start();
+        } else if adopt_monitors(&mut monitors, &mut state, enumerated_monitors) {
+          warm_up.start();
+          unverified = true;
+        }
+        if let MonitorSelection::Followed(followed) = &active_selection {
+          followed_display = Some(followed.display());
+        }
+        if state.monitor_names != previous_names {
+          // The value of the secondary mode was the one of the previous monitors
+          mode_cycle.reset();
+          mode_timer = never();
+        }
+      },
+      recv(handle_check_timer) -> _ => {
+        verify_physical_handles(&monitors);
+        handle_check_timer = never();
+      },
+      recv(write_retry_timer) -> _ => write_retry_timer = never(),
+      recv(resync_timer) -> _ => {
+        // Polling stops while the secure desktop is active, which covers the lock screen, and starts over once it's gone
+        if is_secure_desktop_active() || monitors.is_empty() {
+          resync_timer = never();
+          continue;
+        }
+
+        // Mid-transition values are still ours to set, so the monitors are only read while nothing is going on
+        let mut changed = false;
+        if state.target == state.current && !unverified {
+          if let Ok(value) = monitors.get_brightness() {
+            let value = value as i32;
+            changed = state.known && value != state.current;
+            if !state.known {
+              info!("read the brightness of the monitors, {}", value);
+              state.current = value;
+              state.target = value;
+              state.known = true;
+            }
+            if changed {
+              info!("brightness changed to {} from outside", value);
+              record_set(&monitors, value);
+              state.source = ChangeSource::External;
+              state.record_change(state.current, value, Duration::ZERO, &monitors);
+              state.current = value;
+              state.target = value;
+            }
+          }
+        }
+        resync_timer = resync_backoff.next(changed);
+      },
+      recv(mode_timer) -> _ => {
+        if mode_cycle.reset() {
+          info!("knob left alone, adjusting the brightness again");
+        }
+        mode_timer = never();
+      },
+      recv(schedule_timer) -> _ => {
+        if let Some(entry) = schedule.take_due() {
+          let value = entry.brightness.clamp(config.min_brightness, config.max_brightness);
+          match entry.ramp.is_zero() {
+            true => {
+              info!("setting the brightness to {} as scheduled at {}", value, entry.time);
+              state.remainder = 0.0;
+              state.target = value;
+              state.source = ChangeSource::Schedule;
+            },
+            false => {
+              info!("ramping the brightness to {} over {}s as scheduled at {}", value, entry.ramp.as_secs(), entry.time);
+              schedule.start_ramp(&entry, state.target, value);
+            }
+          };
+        }
+        match schedule.ramp_step(state.target) {
+          RampStep::Moved(value) => {
+            state.remainder = 0.0;
+            state.target = value;
+            state.source = ChangeSource::Schedule;
+          },
+          RampStep::Overridden => {
+            for (monitor_name, value) in monitors.brightness_by_name(state.target as u16) {
+              usage::record_override(&monitor_name, value);
+            }
+          },
+          RampStep::Idle => {}
+        };
+        schedule_timer = schedule.timer();
+      },
+      recv(content_sample_ticker) -> _ => {
+        // The content is only followed once the brightness settled, for the dimming not to fight the transitions
+        if state.paused || monitors.is_empty() || state.target != state.current || is_secure_desktop_active() {
+          continue;
+        }
+        let (min_brightness, max_brightness) = leading_monitor_config(config, &monitors).map_or((config.min_brightness, config.max_brightness), |monitor_config| {
+          monitor_config.brightness_range(config.min_brightness, config.max_brightness)
+        });
+        let Some(luminance) = average_luminance() else { continue };
+        if let Some(value) = content_dimming.adjust(state.target, luminance, min_brightness, max_brightness) {
+          info!("setting the brightness to {} for content with a luminance of {:.2}", value, luminance);
+          state.remainder = 0.0;
+          state.target = value;
+          state.source = ChangeSource::Content;
+        }
+      },
+      recv(quarantine_probe_timer) -> _ => {
+        if is_secure_desktop_active() {
+          continue;
+        }
+        for monitor_name in monitors.probe_quarantined(state.current as u16) {
+          info!("{} answers again, adjusting it along with the others", monitor_name);
+        }
+      },
+      recv(vendor_check_ticker) -> _ => vendor_software = detect_vendor_software(vendor_software, config.vendor_cooperation),
+      recv(system_rx) -> system_event => {
+        let Ok(system_event) = system_event else { break };
+        if system_event == SystemEvent::DisplayChanged {
+          verify_physical_handles(&monitors);
+          handle_check_timer = after(config.handle_check_delay);
+          resync_timer = resync_backoff.restart();
+        }
+        match system_event {
+          SystemEvent::EnteredSecureDesktop => {
+            info!("secure desktop active, pausing brightness updates");
+            resync_timer = never();
+          },
+          SystemEvent::LeftSecureDesktop => {
+            resync_timer = resync_backoff.restart();
+            // Writes issued right before the switch might have been dropped, so the monitor is the only source of truth
+            info!("secure desktop closed, resuming brightness updates");
+            if !monitors.is_empty() {
+              match monitors.get_brightness() {
+                Ok(value) => {
+                  // The target only built on top of the saved brightness while it wasn't known
+                  if !state.known {
+                    state.target = value as i32;
+                  }
+                  state.current = value as i32;
+                  state.known = true;
+                },
+                Err(e) if is_disconnection_error(&e) => {},
+                Err(e) => error!("unable to read the brightness of the monitors - {}", e)
+              };
+            }
+          },
+          // Notifications come in bursts while a dock is plugged in, so the monitors are enumerated once they settled
+          SystemEvent::DisplayChanged => reenumerate_timer = after(if resumed { RESUME_DELAY } else { REENUMERATE_DELAY }),
+          SystemEvent::Resumed => {
+            info!("resumed from sleep, opening the monitors again in {}s", RESUME_DELAY.as_secs());
+            resumed = true;
+            reenumerate_timer = after(RESUME_DELAY);
+            resync_timer = resync_backoff.restart();
+            // The timers didn't necessarily run while the system was asleep
+            schedule_timer = schedule.timer();
+          },
+          SystemEvent::ClockChanged => {
+            info!("system time changed, rescheduling");
+            schedule_timer = schedule.timer();
+          },
+          SystemEvent::KeyboardConnected => info!("keyboard connected"),
+          SystemEvent::KeyboardDisconnected => info!("keyboard disconnected"),
+          SystemEvent::ActionTriggered(index) => {
+            let Some(binding) = action_bindings.get(index) else { continue };
+            for action in &binding.actions {
+              match action {
+                Action::Set(value, transition) => {
+                  // Forget about the knob events still waiting in line, the value being set takes priority over them
+                  let value = (*value).clamp(config.min_brightness, config.max_brightness);
+                  info!("setting the brightness to {}", value);
+                  events_rx.try_iter().for_each(drop);
+                  state.remainder = 0.0;
+                  state.target = value;
+                  state.source = ChangeSource::Action;
+                  if *transition != Transition::INSTANT {
+                    next_transition = *transition;
+                    continue;
+                  }
+
+                  // Write straight away, without any animation
+                  if !monitors.is_empty() && state.known {
+                    match monitors.set_brightness(value as u16) {
+                      Ok(_) => {
+                        state.record_change(state.current, value, Duration::ZERO, &monitors);
+                        state.current = value;
+                        record_set(&monitors, state.current);
+                      },
+                      Err(e) => error!("unable to set the brightness of the monitors - {}", e)
+                    };
+                  }
+                },
+                Action::Step(steps) => {
+                  state.target = nudge(state.current, state.target, steps * config.step_size).clamp(config.min_brightness, config.max_brightness);
+                  state.source = ChangeSource::Action;
+                },
+                Action::RunCommand(command) => {
+                  if let Err(e) = run_command(command) {
+                    error!("unable to run \"{}\" - {}", command, e);
+                  }
+                },
+                Action::Pause => {
+                  state.paused = !state.paused;
+                  info!("knob adjustments {}", if state.paused { "paused" } else { "resumed" });
+                },
+                Action::CycleMode => {
+                  cycle_knob_mode(&mut mode_cycle, &mut monitors);
+                  mode_timer = mode_cycle.timer();
+                  osd_tx.send(Some(knob_osd_state(&mode_cycle, state.current)));
+                },
+                Action::CycleInput => cycle_inputs(&mut monitors),
+                Action::ApplyPreset(name) => if let Some(value) = apply_preset(name, config, &mut monitors, &mut state) {
+                  events_rx.try_iter().for_each(drop);
+                  state.remainder = 0.0;
+                  state.target = value;
+                  state.source = ChangeSource::Action;
+                  next_transition = knob_transition;
+                }
+              };
+            }
+          },
+          SystemEvent::StandbyRequested if !monitors.is_empty() => {
+            info!("putting the monitors in standby");
+            monitors.standby().iter().for_each(|(monitor_name, e)| error!("unable to put {} in standby - {}", monitor_name, e));
+            in_standby = true;
+          },
+          SystemEvent::StandbyRequested => {},
+          SystemEvent::PauseToggled => {
+            state.paused = !state.paused;
+            info!("knob adjustments {}", if state.paused { "paused" } else { "resumed" });
+          },
+          SystemEvent::PauseRequested(paused) => if paused != state.paused {
+            state.paused = paused;
+            info!("knob adjustments {} by another program", if state.paused { "paused" } else { "resumed" });
+          },
+          SystemEvent::BrightnessRequested(value) => {
+            // Like the actions, the value being set takes priority over the knob events waiting in line
+            let value = value.clamp(config.min_brightness, config.max_brightness);
+            info!("setting the brightness to {} as asked by another program", value);
+            events_rx.try_iter().for_each(drop);
+            state.remainder = 0.0;
+            state.target = value;
+            state.source = ChangeSource::Ipc;
+          },
+          SystemEvent::PresetSelected(name) => if let Some(value) = apply_preset(&name, config, &mut monitors, &mut state) {
+            events_rx.try_iter().for_each(drop);
+            state.remainder = 0.0;
+            state.target = value;
+            state.source = ChangeSource::Action;
+            next_transition = knob_transition;
+          },
+          SystemEvent::MonitorsSelected(selection) if selection == state.selection => {},
+          SystemEvent::MonitorsSelected(selection) => {
+            followed_display = match &selection {
+              MonitorSelection::Followed(followed) => Some(followed.display()),
+              _ => None
+            };
+            match open_monitors(&selection, config) {
+              Ok(selected_monitors) => {
+                switch_monitors(&mut monitors, &mut state, selected_monitors);
+                active_selection = selection.clone();
+                state.selection = selection;
+
+                // The value of the secondary mode was the one of the previous monitors
+                mode_cycle.reset();
+                mode_timer = never();
+              },
+              Err(e) => error!("unable to open the selected monitors - {}", e)
+            };
+          }
+        };
+      }
+    }
+
+    // Knob adjustments are dropped while the monitors are disconnected, or until their brightness could be read
+    if monitors.is_empty() || !state.known {
+      state.target = state.current;
+      deferred_cycle = false;
+      continue;
+    }
+
+    // Avoid unnecessary calls, and don't even try to talk to the monitor while the secure desktop is active
+    if state.target != state.current && !is_secure_desktop_active() {
+      let transition = mem::replace(&mut next_transition, knob_transition);
+      let started_at = Instant::now();
+      let result = match vendor_software.filter(|software| software.has_cli()) {
+        Some(software) => software.set_brightness(state.target as u16).map(|_| state.target),
+        None => {
+          // Show every frame rather than the settled value only, so that the tray and the OSD follow the backlight as
+          // it moves
+          let show_frame = |monitors: &MonitorGroup, value: i32| {
+            state_tx.send_if_modified(|snapshot| match snapshot {
+              Some(snapshot) => snapshot.show_brightness(value as u16, monitors),
+              None => false
+            });
+            osd_tx.send(Some(OsdState { mode: KnobMode::Brightness, value: value as u16, coarse: step_gesture.is_coarse(Instant::now()) }));
+          };
+          // The brightness is prevented from changing too fast regardless of how fast the knob is turned
+          let transition = transition.limited_to_rate((state.target - state.current) as f64, config.max_brightness_rate);
+          BrightnessAnimator::new(transition)
+            .interrupted_by(events_rx.clone(), stop_rx.clone())
+            .animate(&mut monitors, state.current, state.target, show_frame)
+        }
+      };
+      state.current = match result {
+        Err(e) if is_disconnection_error(&e) => {
+          state.target = state.current;
+          state.current
+        },
+        Err(_) => state.current,
+        Ok(value) => {
+          resync_timer = resync_backoff.restart();
+          if value != state.current {
+            record_set(&monitors, value);
+            state.record_change(state.current, value, started_at.elapsed(), &monitors);
+          }
+          unverified = value == state.target && (warm_up.is_active() || state.monitor_names.iter().any(|name| has_verified_writes(name, config)));
+          osd_tx.send(Some(OsdState { mode: KnobMode::Brightness, value: value as u16, coarse: step_gesture.is_coarse(Instant::now()) }));
+          value
+        }
+      };
+    }
+
+    // Read the brightness back once the transition has settled, and try again in a while if the write was ignored
+    if unverified && !is_secure_desktop_active() {
+      unverified = false;
+      if let Ok(values) = monitors.get_all_group_brightness(state.current as u16) {
+        // Monitors that didn't take the write are all set again, the ones that did only get the same value twice
+        match values.into_iter().map(|value| value as i32).find(|value| *value != state.current) {
+          None => write_retries = 0,
+          Some(value) if warm_up.is_active() => {
+            info!("monitors still warming up, retrying to set the brightness to {}", state.target);
+            state.current = value;
+            write_retry_timer = after(config.warm_up_retry_delay);
+          },
+          Some(value) if write_retries < config.verified_write_retries => {
+            info!("a monitor ignored the brightness write, retrying to set it to {}", state.target);
+            write_retries += 1;
+            state.current = value;
+            write_retry_timer = after(config.verified_write_retry_delay);
+          },
+          Some(value) => {
+            // Give up and stick to what the monitor reports, rather than fighting it forever
+            error!("a monitor keeps ignoring the brightness writes, it reports {} instead of {}", value, state.target);
+            stats::record_ignored_write();
+            write_retries = 0;
+            state.current = value;
+            state.target = value;
+          }
+        };
+      }
+    }
+
+    if mem::take(&mut deferred_cycle) {
+      match press_cycles_inputs {
+        true => cycle_inputs(&mut monitors),
+        false => {
+          cycle_knob_mode(&mut mode_cycle, &mut monitors);
+          mode_timer = mode_cycle.timer();
+          osd_tx.send(Some(knob_osd_state(&mode_cycle, state.current)));
+        }
+      };
+    }
+  }
+
+  // Skip the animation and apply the last target right away if a transition was interrupted by the stop signal
+  if !monitors.is_empty() && state.target != state.current && !is_secure_desktop_active() {
+    match monitors.set_brightness(state.target as u16) {
+      Ok(_) => record_set(&monitors, state.target),
+      Err(e) => error!("unable to apply the final brightness - {}", e)
+    };
+  }
+  // The monitors disconnected in the meantime keep the brightness saved for them the last time
+  if !monitors.is_empty() && state.known {
+    saved_brightness.update(monitors.brightness_by_id(state.target as u16));
+    if let Err(e) = saved_brightness.save() {
+      error!("unable to save the brightness of the monitors - {}", e);
+    }
+  }
+  let mut stopped_names = monitors.names();
+  stopped_names.append(&mut monitors.take_disconnected());
+  stopped_names.iter().for_each(|monitor_name| usage::record_stop(monitor_name));
+
+  // Destroy the physical monitor handles explicitly, then make sure nothing else was left open
+  drop(monitors);
+  verify_physical_handles(&MonitorGroup::default());
+
+  let stats = stats::snapshot();
+  info!(
+    "{} knob adjustment events processed, {} dropped, {} coalesced, {} writes ignored by the monitor, {} wakeups",
+    stats.events_processed, stats.events_dropped, stats.events_coalesced, stats.writes_ignored, stats.wakeups
+  );
+}
+
+/// Open the monitors matching the selection, setting aside the misbehaving and the asleep ones, and leaving out the
+/// excluded ones as configured
+pub fn open_monitors(selection: &MonitorSelection, config: &Config) -> io::Result<MonitorGroup> {
+  MonitorGroup::open(selection).map(|monitors| {
+    monitors
+      .with_quarantine(config.quarantine_failures, config.quarantine_cooldown)
+      .with_power_check(config.asleep_monitors)
+      .with_monitors_configured(|monitor| configure_monitor(monitor, config))
+  })
+}
+
+/// Apply the settings of a single monitor, returning whether the knob adjusts it
+fn configure_monitor(monitor: &mut Monitor, config: &Config) -> bool {
+  if config.hdr_sdr_white_level && monitor.display().is_some() {
+    if let Err(e) = monitor.enable_sdr_white_level() {
+      error!("unable to find the SDR white level of {} - {}", monitor.name(), e);
+    }
+  }
+  let Some(monitor_config) = config.monitors.get(&monitor.id()) else { return true };
+  let (min_brightness, max_brightness) = monitor_config.brightness_range(config.min_brightness, config.max_brightness);
+  monitor.brightness_range = Some((min_brightness.clamp(0, 100) as u16, max_brightness.clamp(0, 100) as u16));
+  #[cfg(feature = "gamma-dimming")]
+  if let Some(share) = monitor_config.software_dimming {
+    if let Err(e) = monitor.enable_software_dimming(share) {
+      error!("unable to dim the picture of {} - {}", monitor.name(), e);
+    }
+  }
+  #[cfg(not(feature = "gamma-dimming"))]
+  if monitor_config.software_dimming.is_some() {
+    info!("built without the \"gamma-dimming\" feature, the picture of {} is not dimmed", monitor.name());
+  }
+  !monitor_config.excluded
+}
+
+/// Get the settings of the monitor the others follow, when it has any
+fn leading_monitor_config<'a>(config: &'a Config, monitors: &MonitorGroup) -> Option<&'a MonitorConfig> {
+  monitors.monitors().next().and_then(|monitor| config.monitors.get(&monitor.id()))
+}
+
+/// Get the monitors adjusted by the knob according to the config, before any change made from the tray icon
+pub fn configured_selection(config: &Config) -> MonitorSelection {
+  if let Some(followed) = config.follow_monitor {
+    return MonitorSelection::Followed(followed);
+  }
+  match (config.control_all_monitors, config.controlled_monitors.as_slice()) {
+    (false, _) => MonitorSelection::Primary,
+    (true, []) => MonitorSelection::All,
+    (true, names) => MonitorSelection::Named(names.to_vec())
+  }
+}
+
+/// Check whether the writes to the given monitor are verified by reading them back
+fn has_verified_writes(monitor_name: &str, config: &Config) -> bool {
+  config.verified_write_monitors.iter().any(|name| monitor_name.contains(name.as_str()))
+}
+
+/// Look for monitor control applications running alongside this one when co-operating with them, logging whenever the
+/// outcome differs from the previous one
+fn detect_vendor_software(previous: Option<&'static VendorSoftware>, vendor_cooperation: bool) -> Option<&'static VendorSoftware> {
+  if !vendor_cooperation {
+    return None;
+  }
+
+  let detected = vendor_software::detect_running();
+  match (previous, detected) {
+    (None, Some(software)) => info!("{} is running, co-operating with it over the monitor brightness", software.name),
+    (Some(software), None) => info!("{} is not running anymore", software.name),
+    _ => {}
+  };
+  detected
+}
+
+/// Record that the monitors were set to the given brightness, which each of them is at once offset and kept within its
+/// own range
+fn record_set(monitors: &MonitorGroup, value: i32) {
+  for (monitor_name, monitor_value) in monitors.brightness_by_name(value as u16) {
+    usage::record_set(&monitor_name, monitor_value);
+  }
+}
+
+/// Replace the monitors being adjusted with the given ones. The ongoing transition is settled first, the new monitors
+/// then start from where they are
+fn switch_monitors(monitors: &mut MonitorGroup, state: &mut BrightnessState, mut new_monitors: MonitorGroup) {
+  state.monitor_names.iter().for_each(|monitor_name| usage::record_stop(monitor_name));
+  state.link(&mut new_monitors);
+  if let Ok(value) = new_monitors.get_brightness() {
+    state.current = value as i32;
+    state.known = true;
+  }
+  state.target = state.current;
+  *monitors = new_monitors;
+  state.monitor_names = monitors.names();
+  for (monitor_name, value) in monitors.brightness_by_name(state.current as u16) {
+    usage::record_start(&monitor_name, value);
+  }
+  info!("now adjusting {}", state.monitor_names.join(", "));
+}
+
+/// Replace the monitors with the ones enumerated after a display change, the handles of the previous ones being possibly
+/// stale. The monitors that were not adjusted until now are set to the current brightness, returning whether there were
+/// any. While the brightness isn't known, it's read from the enumerated monitors instead. The previous monitors are kept
+/// when that fails, so that it's tried again on the next display change
+fn adopt_monitors(monitors: &mut MonitorGroup, state: &mut BrightnessState, mut enumerated_monitors: MonitorGroup) -> bool {
+  if !state.known {
+    let Ok(value) = enumerated_monitors.get_brightness() else { return false };
+    info!("read the brightness of the monitors, {}", value);
+    state.current = value as i32;
+    state.target = state.current;
+    state.known = true;
+  }
+  state.link(&mut enumerated_monitors);
+  let names = enumerated_monitors.names();
+  let connected_names: Vec<&String> = names.iter().filter(|name| !state.monitor_names.contains(name)).collect();
+  if !connected_names.is_empty() {
+    let joined_names = connected_names.iter().map(|name| name.as_str()).collect::<Vec<_>>().join(", ");
+    info!("{} connected, setting the brightness to {}", joined_names, state.current);
+    if let Err(e) = enumerated_monitors.set_brightness(state.current as u16) {
+      error!("unable to restore the brightness of the monitors - {}", e);
+      return false;
+    }
+    for (monitor_name, value) in enumerated_monitors.brightness_by_name(state.current as u16) {
+      if connected_names.contains(&&monitor_name) {
+        usage::record_start(&monitor_name, value);
+      }
+    }
+  }
+  for monitor_name in state.monitor_names.iter().filter(|name| !names.contains(name)) {
+    info!("{} disconnected", monitor_name);
+    usage::record_stop(monitor_name);
+  }
+
+  let adopted_new_monitors = !connected_names.is_empty();
+  *monitors = enumerated_monitors;
+  state.monitor_names = names;
+  adopted_new_monitors
+}
+
+/// Set the monitors back to the brightness they were left at when the program last exited, which is the one of the
+/// first of them that has one saved. The others get the offset they were left at when linked
+fn restore_saved_brightness(monitors: &mut MonitorGroup, state: &mut BrightnessState, saved_brightness: &SavedBrightness, config: &Config) {
+  let Some(value) = saved_brightness.find(&monitors.ids()) else { return };
+  let value = (value as i32).clamp(config.min_brightness, config.max_brightness);
+  if let Some(offsets) = state.offsets.as_mut() {
+    for monitor in monitors.monitors_mut() {
+      let Some(saved_value) = saved_brightness.get(&monitor.id()) else { continue };
+      monitor.brightness_offset = saved_value as i32 - value;
+      offsets.insert(monitor.id(), monitor.brightness_offset);
+    }
+  }
+
+  info!("restoring the brightness the monitors were left at, {}", value);
+  match monitors.set_brightness(value as u16) {
+    Ok(_) => {
+      state.current = value;
+      state.target = value;
+      state.known = true;
+    },
+    Err(e) => error!("unable to restore the brightness of the monitors - {}", e)
+  };
+}
+
+/// Run a consistency check of the physical monitor handles, given the monitors currently in use
+fn verify_physical_handles(monitors: &MonitorGroup) {
+  let check = check_physical_handles(&monitors.all_monitors().collect::<Vec<_>>());
+  if check.leaked > 0 || check.duplicated > 0 {
+    info!("recovered {} leaked and {} duplicated physical monitor handles", check.leaked, check.duplicated);
+  }
+}
+
+/// Track the grace period following the moment a monitor was powered on or plugged in, during which it might ignore
+/// the brightness writes
+struct WarmUp {
+  period: Duration,
+  until: Option<Instant>
+}
+
+impl WarmUp {
+  fn new(period: Duration) -> Self {
+    Self { period, until: None }
+  }
+
+  fn start(&mut self) {
+    self.until = Some(Instant::now() + self.period);
+  }
+
+  fn is_active(&self) -> bool {
+    self.until.is_some_and(|until| Instant::now() < until)
+  }
+}
+
+/// Pace the polling for brightness changes made behind our back. Polls are frequent for a while after something
+/// happened, such as a write of ours or a display change, then move further apart as long as nothing changes
+struct ResyncBackoff {
+  enabled: bool,
+  min_interval: Duration,
+  max_interval: Duration,
+  interval: Duration
+}
+
+impl ResyncBackoff {
+  fn new(enabled: bool, min_interval: Duration, max_interval: Duration) -> Self {
+    Self { enabled, min_interval, max_interval, interval: min_interval }
+  }
+
+  /// Start polling frequently again
+  fn restart(&mut self) -> Receiver<Instant> {
+    self.interval = self.min_interval;
+    self.schedule()
+  }
+
+  /// Schedule the poll following one that found the brightness changed or not
+  fn next(&mut self, changed: bool) -> Receiver<Instant> {
+    self.interval = match changed {
+      true => self.min_interval,
+      false => (self.interval * 2).min(self.max_interval)
+    };
+    self.schedule()
+  }
+
+  fn schedule(&self) -> Receiver<Instant> {
+    match self.enabled {
+      true => after(self.interval),
+      false => never()
+    }
+  }
+}
+
+/// Make the steps grow while the knob is turned quickly, by one step size for every notch coming within the window of
+/// the previous one in the same direction. The timestamps of the events are used rather than when they are processed,
+/// so that the events queued up while a transition was under way still count as a quick turn
+struct Acceleration {
+  window: Duration,
+  max_multiplier: u32,
+  multiplier: u32,
+  /// When the previous notch came, along with its direction
+  last_notch: Option<(Instant, i32)>
+}
+
+impl Acceleration {
+  fn new(window: Duration, max_multiplier: u32) -> Self {
+    Self {
+      window,
+      max_multiplier: max_multiplier.max(1),
+      multiplier: 1,
+      last_notch: None
+    }
+  }
+
+  /// Get the multiple of the step size to apply for the given event
+  fn multiplier(&mut self, event: &KnobAdjustmentEvent) -> i32 {
+    let direction = match event.action {
+      KnobAction::Increment => 1,
+      KnobAction::Decrement => -1,
+      KnobAction::Partial(fraction) => fraction.signum() as i32,
+      KnobAction::Press => {
+        self.last_notch = None;
+        self.multiplier = 1;
+        return 1;
+      }
+    };
+    let is_quick = matches!(
+      self.last_notch,
+      Some((at, last_direction)) if last_direction == direction && event.timestamp.saturating_duration_since(at) <= self.window
+    );
+    self.multiplier = if is_quick { (self.multiplier + 1).min(self.max_multiplier) } else { 1 };
+    self.last_notch = Some((event.timestamp, direction));
+    self.multiplier as i32
+  }
+}
+
+/// Stop decreasing brightness values at a floor above zero, to prevent accidental blackouts that make the screen hard
+/// to recover. Going past the floor takes a deliberate extra turn, started after the knob has been still for a while,
+/// or a press of the knob
+struct ZeroFloor {
+  floor: Option<i32>,
+  breakthrough_delay: Duration,
+  min_value: i32,
+  held_since: Option<Instant>
+}
+
+impl ZeroFloor {
+  fn new(floor: Option<i32>, breakthrough_delay: Duration, min_value: i32) -> Self {
+    Self {
+      floor,
+      breakthrough_delay,
+      min_value,
+      held_since: None
+    }
+  }
+
+  /// Filter the target brightness computed for an event, given the brightness targeted before it
+  fn apply(&mut self, prev_value: i32, target_value: i32, event: &KnobAdjustmentEvent) -> i32 {
+    let Some(floor) = self.floor.filter(|floor| *floor > self.min_value) else { return target_value };
+
+    if prev_value < floor {
+      self.held_since = None;
+      return target_value;
+    }
+
+    // Reaching the floor from above, or trying to skip past it, stops right at it
+    if prev_value > floor {
+      if target_value <= floor {
+        self.held_since = Some(event.timestamp);
+        return floor;
+      }
+      self.held_since = None;
+      return target_value;
+    }
+
+    if event.action == KnobAction::Press {
+      self.held_since = None;
+      return self.min_value;
+    }
+    if target_value >= floor {
+      if target_value > floor { self.held_since = None; }
+      return target_value;
+    }
+
+    // Events from the same turn that reached the floor keep it held, while a new turn breaks through it
+    match self.held_since {
+      Some(since) if event.timestamp.duration_since(since) < self.breakthrough_delay => {
+        self.held_since = Some(event.timestamp);
+        floor
+      },
+      _ => {
+        self.held_since = None;
+        self.min_value
+      }
+    }
+  }
+}
+
+/// Keep darkening the screens with the overlay once the brightness is at its lowest and the knob is still turned down,
+/// which turning the knob back up undoes before the brightness goes up again
+struct OverlayDimming {
+  max_opacity: f64,
+  /// Opacity of the overlay, in percent
+  opacity: f64
+}
+
+impl OverlayDimming {
+  /// The overlay is never shown when the maximum opacity is 0
+  fn new(max_opacity: i32) -> Self {
+    Self { max_opacity: max_opacity.clamp(0, 100) as f64, opacity: 0.0 }
+  }
+
+  /// Apply a knob adjustment to the overlay when it's the one the knob adjusts, given the brightness targeted before
+  /// it, returning whether it was
+  fn adjust(&mut self, target: i32, min_value: i32, event: &KnobAdjustmentEvent, step_size: i32) -> bool {
+    let delta = match event.action {
+      KnobAction::Increment => step_size as f64,
+      KnobAction::Decrement => -step_size as f64,
+      KnobAction::Partial(fraction) => fraction * step_size as f64,
+      KnobAction::Press => return false
+    };
+    let adjusted = self.max_opacity > 0.0 && if delta < 0.0 { target <= min_value } else { self.opacity > 0.0 };
+    if adjusted {
+      self.opacity = (self.opacity - delta).clamp(0.0, self.max_opacity);
+    }
+    adjusted
+  }
+
+  /// Get the opacity of the overlay, from 0 to 255
+  fn alpha(&self) -> u8 {
+    (self.opacity * 255.0 / 100.0).round() as u8
+  }
+}
+
+/// Switch the knob to its next mode, skipping the secondary ones whose value can't be read, which are usually missing
+/// from the monitors, such as the volume of the ones without speakers
+fn cycle_knob_mode(mode_cycle: &mut ModeCycle, monitors: &mut MonitorGroup) {
+  let mode = loop {
+    let mode = mode_cycle.next_mode();
+    if mode == KnobMode::Brightness {
+      break mode;
+    }
+    match monitors.get_vcp(mode.vcp_code()) {
+      Ok(value) => {
+        mode_cycle.set_value(value as i32);
+        break mode;
+      },
+      Err(e) => error!("unable to read the {} of the monitors, skipping it - {}", mode, e)
+    };
+  };
+  info!("the knob now adjusts the {}", mode);
+}
+
+/// Switch every monitor to its next input source, which it might not come back from on its own if there's nothing
+/// plugged into it
+fn cycle_inputs(monitors: &mut MonitorGroup) {
+  for (monitor_name, result) in monitors.cycle_inputs() {
+    match result {
+      Ok(input) => {
+        let input_name = capabilities::input_name(input).map_or_else(|| format!("input 0x{:02x}", input), String::from);
+        info!("switched {} to {}", monitor_name, input_name);
+      },
+      Err(e) => error!("unable to switch the input of {} - {}", monitor_name, e)
+    };
+  }
+}
+
+/// Apply the preset of the given name, easing the monitors into its contrast and giving them the offsets of its
+/// brightness straight away, then return the brightness for the monitors to be transitioned to. Monitors that are not
+/// linked lose their offset once they are enumerated again
+fn apply_preset(name: &str, config: &Config, monitors: &mut MonitorGroup, state: &mut BrightnessState) -> Option<i32> {
+  let Some(preset) = config.presets.get(name) else {
+    error!("there's no preset named \"{}\"", name);
+    return None;
+  };
+  info!("applying the {} preset", name);
+
+  // The brightness of the preset is the one of the monitor the others follow when the preset only has single ones
+  let leading_values = monitors.monitors().next().and_then(|monitor| preset.monitors.get(&monitor.id()));
+  let brightness = preset.brightness
+    .or(leading_values.and_then(|values| values.brightness))
+    .unwrap_or(state.target)
+    .clamp(config.min_brightness, config.max_brightness);
+  let transition = config.knob_transition();
+  for monitor in monitors.monitors_mut() {
+    let values = preset.monitors.get(&monitor.id());
+    match values.and_then(|values| values.brightness) {
+      Some(value) => monitor.brightness_offset = value - brightness,
+      // Linked monitors keep their offset, the others are set to the brightness of the preset
+      None if state.offsets.is_none() => monitor.brightness_offset = 0,
+      None => {}
+    };
+    if let Some(offsets) = state.offsets.as_mut() {
+      offsets.insert(monitor.id(), monitor.brightness_offset);
+    }
+
+    let Some(contrast) = values.and_then(|values| values.contrast).or(preset.contrast) else { continue };
+    let contrast = contrast.clamp(0, 100) as u16;
+    let result = monitor.get_contrast().and_then(|current| match current == contrast {
+      true => Ok(()),
+      false => display_snapshot::ease_feature(monitor, CONTRAST_VCP_CODE, current, contrast, transition)
+    });
+    if let Err(e) = result {
+      error!("unable to set the contrast of {} - {}", monitor.name(), e);
+    }
+  }
+  Some(brightness)
+}
+
+/// Get what the OSD shows for the mode the knob is in, which is the brightness unless it's in a secondary mode
+fn knob_osd_state(mode_cycle: &ModeCycle, brightness: i32) -> OsdState {
+  OsdState { mode: mode_cycle.current(), value: mode_cycle.value().unwrap_or(brightness) as u16, coarse: false }
+}
+
+/// Apply a knob action to the secondary mode the knob is in, writing the new value straight away
+fn adjust_knob_mode(mode_cycle: &mut ModeCycle, monitors: &mut MonitorGroup, event: &KnobAdjustmentEvent, step_size: i32) {
+  let mode = mode_cycle.current();
+  let Some(value) = mode_cycle.adjust(event, step_size) else { return };
+  info!("setting the {} to {}", mode, value);
+  if let Err(e) = monitors.set_vcp(mode.vcp_code(), value as u16) {
+    error!("unable to set the {} of the monitors - {}", mode, e);
+  }
+}
+
+/// Apply a knob action to the contrast, returning its new value. It's read from the monitors again once it's been left
+/// alone for the timeout, in case it was changed from their buttons in the meantime
+fn adjust_contrast(contrast: &mut Option<(i32, Instant)>, monitors: &mut MonitorGroup, event: &KnobAdjustmentEvent, step_size: i32, timeout: Duration) -> Option<i32> {
+  let value = match *contrast {
+    Some((value, adjusted_at)) if adjusted_at.elapsed() < timeout => value,
+    _ => match monitors.get_contrast() {
+      Ok(value) => value as i32,
+      Err(e) => {
+        error!("unable to read the contrast of the monitors - {}", e);
+        return None;
+      }
+    }
+  };
+  let delta = match event.action {
+    KnobAction::Increment => step_size,
+    KnobAction::Decrement => -step_size,
+    KnobAction::Partial(fraction) => (fraction * step_size as f64).round() as i32,
+    KnobAction::Press => 0
+  };
+  let target = (value + delta).clamp(0, 100);
+  *contrast = Some((target, Instant::now()));
+  if target != value {
+    info!("setting the contrast to {}", target);
+    if let Err(e) = monitors.set_contrast(target as u16) {
+      error!("unable to set the contrast of the monitors - {}", e);
+    }
+  }
+  Some(target)
+}
+
+/// Show the OSD with the given value, even if it's the one it showed last
+fn show_osd(osd_tx: &WatchSender<Option<OsdState>>, osd_state: OsdState) {
+  osd_tx.send_if_modified(|shown_state| {
+    *shown_state = Some(osd_state);
+    true
+  });
+}
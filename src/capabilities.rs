@@ -0,0 +1,142 @@
+use crate::usage::json_escape;
+
+use ddc::FeatureCode;
+use mccs::{Capabilities, Type};
+use std::fmt::Write as _;
+
+/// Get the name of the most common VCP features, as given by the MCCS standard
+pub fn feature_name(code: FeatureCode) -> Option<&'static str> {
+  match code {
+    0x02 => Some("New control value"),
+    0x04 => Some("Restore factory defaults"),
+    0x05 => Some("Restore factory brightness/contrast defaults"),
+    0x08 => Some("Restore factory color defaults"),
+    0x10 => Some("Brightness"),
+    0x12 => Some("Contrast"),
+    0x14 => Some("Select color preset"),
+    0x16 => Some("Video gain: red"),
+    0x18 => Some("Video gain: green"),
+    0x1a => Some("Video gain: blue"),
+    0x52 => Some("Active control"),
+    0x60 => Some("Input source"),
+    0x62 => Some("Audio speaker volume"),
+    0x6c => Some("Video black level: red"),
+    0x6e => Some("Video black level: green"),
+    0x70 => Some("Video black level: blue"),
+    0x8d => Some("Audio mute"),
+    0xac => Some("Horizontal frequency"),
+    0xae => Some("Vertical frequency"),
+    0xb2 => Some("Flat panel sub-pixel layout"),
+    0xb6 => Some("Display technology type"),
+    0xc6 => Some("Application enable key"),
+    0xc8 => Some("Display controller type"),
+    0xc9 => Some("Display firmware level"),
+    0xca => Some("OSD"),
+    0xcc => Some("OSD language"),
+    0xd6 => Some("Power mode"),
+    0xdc => Some("Display mode"),
+    0xdf => Some("VCP version"),
+    _ => None
+  }
+}
+
+/// Get the name of an input source, as given by the MCCS standard
+pub fn input_name(value: u8) -> Option<&'static str> {
+  match value {
+    0x01 => Some("VGA 1"),
+    0x02 => Some("VGA 2"),
+    0x03 => Some("DVI 1"),
+    0x04 => Some("DVI 2"),
+    0x05 => Some("Composite 1"),
+    0x06 => Some("Composite 2"),
+    0x07 => Some("S-Video 1"),
+    0x08 => Some("S-Video 2"),
+    0x09 => Some("Tuner 1"),
+    0x0a => Some("Tuner 2"),
+    0x0b => Some("Tuner 3"),
+    0x0c => Some("Component 1"),
+    0x0d => Some("Component 2"),
+    0x0e => Some("Component 3"),
+    0x0f => Some("DisplayPort 1"),
+    0x10 => Some("DisplayPort 2"),
+    0x11 => Some("HDMI 1"),
+    0x12 => Some("HDMI 2"),
+    _ => None
+  }
+}
+
+/// Describe the capabilities of a monitor in a human-readable way, listing every supported VCP feature on a line of
+/// its own along with its allowed values, for the ones that only take a few
+pub fn format_text(monitor_name: &str, capabilities: &Capabilities) -> String {
+  let mut text = format!("{}\n", monitor_name);
+  if let Some(model) = &capabilities.model {
+    let _ = writeln!(text, "  Model: {}", model);
+  }
+  if let Some(ty) = &capabilities.ty {
+    let _ = writeln!(text, "  Type: {}", type_name(ty));
+  }
+  if let Some(version) = &capabilities.mccs_version {
+    let _ = writeln!(text, "  MCCS version: {}.{}", version.major, version.minor);
+  }
+
+  let _ = writeln!(text, "  Features:");
+  for (code, descriptor) in &capabilities.vcp_features {
+    let name = descriptor.name.as_deref().or_else(|| feature_name(*code)).unwrap_or("Unknown");
+    let _ = write!(text, "    0x{:02x} {}", code, name);
+    if !descriptor.values.is_empty() {
+      let values: Vec<String> = descriptor.values
+        .iter()
+        .map(|(value, value_name)| match value_name {
+          Some(value_name) => format!("0x{:02x} ({})", value, value_name),
+          None => format!("0x{:02x}", value)
+        })
+        .collect();
+      let _ = write!(text, ": {}", values.join(", "));
+    }
+    text.push('\n');
+  }
+  text
+}
+
+/// Describe the capabilities of several monitors as a JSON array, with one object per monitor
+pub fn format_json(monitors: &[(String, Capabilities)]) -> String {
+  let monitors: Vec<String> = monitors
+    .iter()
+    .map(|(monitor_name, capabilities)| {
+      let features: Vec<String> = capabilities.vcp_features
+        .iter()
+        .map(|(code, descriptor)| {
+          let name = descriptor.name.as_deref().or_else(|| feature_name(*code));
+          let values: Vec<String> = descriptor.values().map(|value| value.to_string()).collect();
+          format!("{{\"code\":{},\"name\":{},\"values\":[{}]}}", code, json_string(name), values.join(","))
+        })
+        .collect();
+      let version = capabilities.mccs_version.as_ref().map(|version| format!("{}.{}", version.major, version.minor));
+      format!(
+        "{{\"monitor\":\"{}\",\"model\":{},\"type\":{},\"mccs_version\":{},\"features\":[{}]}}",
+        json_escape(monitor_name),
+        json_string(capabilities.model.as_deref()),
+        json_string(capabilities.ty.as_ref().map(type_name).as_deref()),
+        json_string(version.as_deref()),
+        features.join(",")
+      )
+    })
+    .collect();
+  format!("[{}]", monitors.join(","))
+}
+
+fn type_name(ty: &Type) -> String {
+  match ty {
+    Type::Crt => "CRT".to_string(),
+    Type::Lcd => "LCD".to_string(),
+    Type::Led => "LED".to_string(),
+    Type::Unknown(name) => name.clone()
+  }
+}
+
+fn json_string(value: Option<&str>) -> String {
+  match value {
+    Some(value) => format!("\"{}\"", json_escape(value)),
+    None => "null".to_string()
+  }
+}
@@ -0,0 +1,175 @@
+use gmmk_pro_brightness_knob::monitor_group::MonitorSelection;
+use gmmk_pro_brightness_knob::usage::ReportPeriod;
+
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use tracing::level_filters::LevelFilter;
+use ddc::FeatureCode;
+use std::str::FromStr;
+
+/// Make your Glorious GMMK PRO keyboard's knob adjust the brightness of your display
+#[derive(Debug, Parser)]
+#[command(version, about)]
+pub struct Cli {
+  /// Log every DDC/CI transaction to a trace file
+  #[arg(long, global = true)]
+  pub trace_ddc: bool,
+  /// Log more details, such as every frame of the transitions when given twice
+  #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "quiet")]
+  pub verbose: u8,
+  /// Only log the errors
+  #[arg(short, long, global = true)]
+  pub quiet: bool,
+  /// Adjust the brightness once by the given amount, such as +10 or -5, with the same transition as the knob, then exit
+  #[arg(long, value_name = "DELTA", allow_hyphen_values = true)]
+  pub once: Option<i32>,
+  #[command(subcommand)]
+  pub command: Option<Command>
+}
+
+impl Cli {
+  pub fn log_level(&self) -> LevelFilter {
+    match (self.quiet, self.verbose) {
+      (true, _) => LevelFilter::ERROR,
+      (false, 0) => LevelFilter::INFO,
+      (false, 1) => LevelFilter::DEBUG,
+      (false, _) => LevelFilter::TRACE
+    }
+  }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Adjust the brightness as the knob is turned, which is what happens when no command is given
+  Run,
+  /// List the monitors whose brightness can be controlled, along with their current brightness and their identifier
+  List,
+  /// Print the brightness of the monitors adjusted by the knob
+  Get,
+  /// Set the brightness of the monitors adjusted by the knob
+  Set {
+    value: i32
+  },
+  /// Stop the running instance from applying the knob adjustments
+  Pause,
+  /// Make the running instance apply the knob adjustments again
+  Resume,
+  /// Print the most recent DDC/CI transactions of the running instance, which has to be started with --trace-ddc, one
+  /// per line with their timestamp, monitor, operation, VCP code, value, duration and result, separated by tabs
+  Trace,
+  /// Read or write any VCP feature of the monitors, such as 0x12 for the contrast
+  Vcp {
+    /// Monitor to talk to, either its number as printed by `list` or part of its name, instead of the ones adjusted by
+    /// the knob
+    #[arg(long, global = true, value_parser = MonitorSelection::from_str)]
+    monitor: Option<MonitorSelection>,
+    #[command(subcommand)]
+    command: VcpCommand
+  },
+  /// Print the MCCS capabilities of the monitors, which are the VCP features they support along with their allowed
+  /// values
+  #[command(visible_alias = "caps")]
+  Capabilities {
+    /// Monitor to ask, either its number as printed by `list` or part of its name, instead of the ones adjusted by the
+    /// knob
+    #[arg(long, value_parser = MonitorSelection::from_str)]
+    monitor: Option<MonitorSelection>,
+    /// Print the capabilities as JSON
+    #[arg(long)]
+    json: bool
+  },
+  /// Save the brightness, contrast, color preset and input of every monitor under the given name
+  Snapshot {
+    name: String
+  },
+  /// Bring every monitor back to the state saved under the given name, easing the brightness and the contrast into it
+  Restore {
+    name: String,
+    /// Duration of the transition of the brightness and the contrast, in milliseconds
+    #[arg(long, value_name = "MS", default_value_t = 1000)]
+    duration_ms: u64
+  },
+  /// Copy the executable to the install directory and start it at logon
+  Install,
+  /// Undo the installation, removing the config file and the usage records as well
+  Uninstall,
+  /// Start this executable at logon from where it is, or stop doing so, without installing it. Registered as the
+  /// elevated helper task from an elevated prompt, through the Run key of the current user otherwise
+  Startup {
+    #[arg(value_enum, default_value_t = StartupAction::Status)]
+    action: StartupAction
+  },
+  /// Register the program as a service started at boot, without a logged-in console window, from an elevated prompt.
+  /// The service runs in session 0, apart from the logged-in user, so it can't drive the monitors the user sees nor
+  /// capture the knob or the action bindings. Only its IPC commands are reachable from the session of the user
+  InstallService,
+  /// Stop and remove the service, from an elevated prompt
+  UninstallService,
+  /// Run as the service registered by `install-service`, which is only meant to be done by the service control manager
+  #[command(hide = true)]
+  Service,
+  /// Register the elevated helper task, from an elevated prompt
+  InstallHelper,
+  /// Remove the elevated helper task, from an elevated prompt
+  UninstallHelper,
+  /// Summarize how the brightness was used over the last day or week
+  Report {
+    #[arg(value_enum, default_value_t = ReportPeriod::Day)]
+    period: ReportPeriod,
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool
+  },
+  /// Print every brightness change as it's applied by the running instance, one line per monitor with its name, the
+  /// previous and the new brightness, what made the change and how long it took in milliseconds, separated by tabs
+  Watch
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StartupAction {
+  /// Start the program at logon
+  Enable,
+  /// Stop starting the program at logon
+  Disable,
+  /// Print whether the program is started at logon, and how
+  Status
+}
+
+#[derive(Debug, Subcommand)]
+pub enum VcpCommand {
+  /// Print the current and maximum values of a feature, for every monitor
+  Get {
+    /// Code of the feature, in hex
+    #[arg(value_parser = parse_vcp_code)]
+    code: FeatureCode,
+    /// Print the values in hex
+    #[arg(long)]
+    hex: bool,
+    /// Print the bytes of the reply as they came, which are the type, the maximum then the current value
+    #[arg(long, conflicts_with = "hex")]
+    raw: bool
+  },
+  /// Write a feature to every monitor
+  Set {
+    /// Code of the feature, in hex
+    #[arg(value_parser = parse_vcp_code)]
+    code: FeatureCode,
+    /// Value to write, in hex when prefixed with 0x
+    #[arg(value_parser = parse_vcp_value)]
+    value: u16
+  }
+}
+
+/// VCP codes are always written in hex, as in the MCCS standard and in the manuals of the monitors
+fn parse_vcp_code(arg: &str) -> Result<FeatureCode, String> {
+  let digits = arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")).unwrap_or(arg);
+  FeatureCode::from_str_radix(digits, 16).map_err(|e| format!("invalid VCP code \"{}\" - {}", arg, e))
+}
+
+fn parse_vcp_value(arg: &str) -> Result<u16, String> {
+  let result = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+    Some(digits) => u16::from_str_radix(digits, 16),
+    None => arg.parse()
+  };
+  result.map_err(|e| format!("invalid VCP value \"{}\" - {}", arg, e))
+}
+
@@ -0,0 +1,118 @@
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use windows::Win32::Foundation::{FILETIME, SYSTEMTIME};
+use windows::Win32::System::Time::{
+  FileTimeToSystemTime, GetDynamicTimeZoneInformation, SystemTimeToFileTime, SystemTimeToTzSpecificLocalTimeEx, TzSpecificLocalTimeToSystemTimeEx,
+  DYNAMIC_TIME_ZONE_INFORMATION
+};
+
+/// Number of 100 ns intervals between 1601-01-01, where FILETIME starts, and the UNIX epoch
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+const ONE_HOUR: Duration = Duration::from_secs(60 * 60);
+const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Time of the day on the local wall clock, written as "HH:MM" in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+  pub hour: u16,
+  pub minute: u16
+}
+
+impl FromStr for TimeOfDay {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let invalid = || format!("invalid time of the day \"{}\", expected HH:MM", value);
+    let (hour, minute) = value.split_once(':').ok_or_else(invalid)?;
+    let hour: u16 = hour.parse().map_err(|_| invalid())?;
+    let minute: u16 = minute.parse().map_err(|_| invalid())?;
+    match hour < 24 && minute < 60 {
+      true => Ok(Self { hour, minute }),
+      false => Err(invalid())
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for TimeOfDay {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+impl fmt::Display for TimeOfDay {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{:02}:{:02}", self.hour, self.minute)
+  }
+}
+
+/// Get the first moment after the given one at which the local wall clock shows the given time of the day. The time
+/// zone and its daylight saving time rules are the ones in effect right now, for the year of each day looked at, so
+/// the result goes stale when they change and must be computed again then. A time skipped when moving to daylight
+/// saving time happens an hour later on that day, and a time repeated when moving back only happens the first time
+pub fn next_occurrence(time_of_day: TimeOfDay, after: SystemTime) -> Option<SystemTime> {
+  let time_zone = current_time_zone()?;
+  let mut local_date = to_local(&time_zone, after)?;
+
+  // Whatever the offsets, the time happens at least once over the next three days
+  for _ in 0..3 {
+    let local_time = SYSTEMTIME { wHour: time_of_day.hour, wMinute: time_of_day.minute, wSecond: 0, wMilliseconds: 0, ..local_date };
+    let mut occurrence = to_utc(&time_zone, &local_time)?;
+    let earlier = occurrence - ONE_HOUR;
+    if to_local(&time_zone, earlier).is_some_and(|shown_time| same_minute(&shown_time, &local_time)) {
+      occurrence = earlier;
+    }
+    if occurrence > after {
+      return Some(occurrence);
+    }
+
+    // Moving on from noon keeps the next day from being skipped or repeated by a change of offset
+    let noon = to_utc(&time_zone, &SYSTEMTIME { wHour: 12, wMinute: 0, wSecond: 0, wMilliseconds: 0, ..local_date })?;
+    local_date = to_local(&time_zone, noon + ONE_DAY)?;
+  }
+  None
+}
+
+fn current_time_zone() -> Option<DYNAMIC_TIME_ZONE_INFORMATION> {
+  let mut time_zone = DYNAMIC_TIME_ZONE_INFORMATION::default();
+  // Reference: https://learn.microsoft.com/en-us/windows/win32/api/timezoneapi/nf-timezoneapi-getdynamictimezoneinformation#return-value
+  match unsafe { GetDynamicTimeZoneInformation(&mut time_zone) } {
+    u32::MAX => None,
+    _ => Some(time_zone)
+  }
+}
+
+fn to_local(time_zone: &DYNAMIC_TIME_ZONE_INFORMATION, time: SystemTime) -> Option<SYSTEMTIME> {
+  let utc_time = to_system_time(time)?;
+  let mut local_time = SYSTEMTIME::default();
+  unsafe { SystemTimeToTzSpecificLocalTimeEx(Some(time_zone), &utc_time, &mut local_time) }.as_bool().then_some(local_time)
+}
+
+fn to_utc(time_zone: &DYNAMIC_TIME_ZONE_INFORMATION, local_time: &SYSTEMTIME) -> Option<SystemTime> {
+  let mut utc_time = SYSTEMTIME::default();
+  if !unsafe { TzSpecificLocalTimeToSystemTimeEx(Some(time_zone), local_time, &mut utc_time) }.as_bool() {
+    return None;
+  }
+  from_system_time(&utc_time)
+}
+
+fn to_system_time(time: SystemTime) -> Option<SYSTEMTIME> {
+  let intervals = time.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64 / 100 + FILETIME_UNIX_EPOCH;
+  let file_time = FILETIME { dwLowDateTime: intervals as u32, dwHighDateTime: (intervals >> 32) as u32 };
+  let mut system_time = SYSTEMTIME::default();
+  unsafe { FileTimeToSystemTime(&file_time, &mut system_time) }.as_bool().then_some(system_time)
+}
+
+fn from_system_time(system_time: &SYSTEMTIME) -> Option<SystemTime> {
+  let mut file_time = FILETIME::default();
+  if !unsafe { SystemTimeToFileTime(system_time, &mut file_time) }.as_bool() {
+    return None;
+  }
+  let intervals = ((file_time.dwHighDateTime as u64) << 32 | file_time.dwLowDateTime as u64).checked_sub(FILETIME_UNIX_EPOCH)?;
+  Some(UNIX_EPOCH + Duration::from_nanos(intervals * 100))
+}
+
+fn same_minute(a: &SYSTEMTIME, b: &SYSTEMTIME) -> bool {
+  (a.wYear, a.wMonth, a.wDay, a.wHour, a.wMinute) == (b.wYear, b.wMonth, b.wDay, b.wHour, b.wMinute)
+}
@@ -0,0 +1,482 @@
+use crate::actions::{Action, ActionBinding, Trigger, parse_modifiers, parse_virtual_key};
+use crate::foreground::{FullscreenKind, ScreenRegion};
+use crate::keyboard_knob::{DEFAULT_DECREMENT_KEY, DEFAULT_INCREMENT_KEY, GMMK_PRO_KEYBOARD_IDS, EventSource, KnobInput};
+use crate::knob_mode::{KnobMode, PressAction};
+use crate::monitor_group::{AsleepMonitors, FollowedMonitor};
+use crate::paths::config_dir;
+use crate::range_limit::LimitBehavior;
+use crate::routing::Route;
+use crate::schedule::ScheduleEntry;
+use crate::sun::Location;
+use crate::transition::{Easing, Transition};
+
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use windows::Win32::UI::Input::KeyboardAndMouse::{HOT_KEY_MODIFIERS, VIRTUAL_KEY};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Config file written by the installer, listing every setting along with its default value
+const DEFAULT_CONFIG: &str = include_str!("../config.example.toml");
+
+/// Settings of the program, read from the config file. Every setting is optional and falls back to its default value
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+  /// Adjust every monitor answering to brightness requests rather than the primary one only
+  pub control_all_monitors: bool,
+  /// Restrict the knob to the monitors whose description contains any of these, unless empty
+  pub controlled_monitors: Vec<String>,
+  /// Adjust the monitor of the display the user is on instead, which takes precedence over the other two
+  pub follow_monitor: Option<FollowedMonitor>,
+  /// Monitors adjusted by the knob adjustment events of the given sources, rather than the selected ones
+  pub routes: BTreeMap<EventSource, Route>,
+  pub min_brightness: i32,
+  pub max_brightness: i32,
+  /// Set the monitors back to the brightness they were left at when the program last exited, rather than keeping the
+  /// one they are at. It's saved on exit either way
+  pub restore_brightness: bool,
+  /// Move every monitor by the same amount when the knob is turned, keeping the differences between their brightness
+  /// rather than setting them all to the same one
+  pub linked_monitors: bool,
+  /// Adjust the brightness of the SDR content of the displays in HDR mode, rather than their brightness over DDC/CI
+  pub hdr_sdr_white_level: bool,
+  /// Brightness change of a single knob notch
+  pub step_size: i32,
+  /// Notches coming within this long of the previous one in the same direction make the steps grow, up to the given
+  /// multiple of the step size, which disables the acceleration when 1
+  #[serde(rename = "acceleration_window_ms", deserialize_with = "milliseconds")]
+  pub acceleration_window: Duration,
+  pub max_acceleration: u32,
+  /// Step size switched to by wiggling the knob back and forth, which disables the gesture when unset
+  pub coarse_step_size: Option<i32>,
+  #[serde(rename = "wiggle_window_ms", deserialize_with = "milliseconds")]
+  pub wiggle_window: Duration,
+  #[serde(rename = "coarse_step_timeout_ms", deserialize_with = "milliseconds")]
+  pub coarse_step_timeout: Duration,
+  /// What turning the knob past either end of the range does, for each of its modes, which are clamped by default
+  pub limit_behavior: BTreeMap<KnobMode, LimitBehavior>,
+  #[serde(rename = "limit_hold_delay_ms", deserialize_with = "milliseconds")]
+  pub limit_hold_delay: Duration,
+  #[serde(rename = "animation_duration_ms", deserialize_with = "milliseconds")]
+  pub animation_duration: Duration,
+  pub animation_easing: Easing,
+  /// Fastest the brightness can change, in percent per second, for the sake of photosensitive users
+  pub max_brightness_rate: Option<f64>,
+  /// Brightness at which decreasing stops, until the knob is turned once more, to prevent accidental blackouts
+  pub zero_floor: Option<i32>,
+  #[serde(rename = "zero_floor_breakthrough_delay_ms", deserialize_with = "milliseconds")]
+  pub zero_floor_breakthrough_delay: Duration,
+  /// Darken the screens with an overlay when the knob is turned down past the lowest brightness, up to the given
+  /// opacity in percent
+  pub dimming_overlay: bool,
+  pub dimming_overlay_max_opacity: i32,
+  /// Brightness to set at given times of the day, on the local wall clock. Daylight saving time and time zone changes
+  /// are followed, so that the entries keep happening at the same wall clock time
+  pub schedule: Vec<ScheduleEntry>,
+  /// Latitude and longitude the entries of the schedule at sunrise or sunset follow the sun at
+  pub location: Option<Location>,
+  /// Lower the brightness while the screens show dark content, by up to the maximum dimming for a black screen
+  pub content_dimming: bool,
+  #[serde(rename = "content_sample_interval_ms", deserialize_with = "milliseconds")]
+  pub content_sample_interval: Duration,
+  pub content_max_dimming: i32,
+  /// Average luminance of the screens, from 0 to 1, under which the content counts as dark
+  pub content_dark_threshold: f64,
+  /// Settings the knob cycles through when pressed, after the brightness which it goes back to after the timeout
+  pub knob_modes: Vec<KnobMode>,
+  #[serde(rename = "mode_timeout_ms", deserialize_with = "milliseconds")]
+  pub mode_timeout: Duration,
+  /// What pressing the knob does
+  pub press_action: PressAction,
+  /// Modifiers joined by `+` (e.g. "shift") to hold for the knob to adjust the contrast instead, none when empty
+  #[serde(deserialize_with = "modifiers")]
+  pub contrast_modifiers: HOT_KEY_MODIFIERS,
+  pub event_queue_capacity: usize,
+  #[serde(rename = "warm_up_period_ms", deserialize_with = "milliseconds")]
+  pub warm_up_period: Duration,
+  #[serde(rename = "warm_up_retry_delay_ms", deserialize_with = "milliseconds")]
+  pub warm_up_retry_delay: Duration,
+  /// Monitors whose description contains any of these get every write verified, for the ones that acknowledge writes
+  /// they silently ignore
+  pub verified_write_monitors: Vec<String>,
+  pub verified_write_retries: u32,
+  #[serde(rename = "verified_write_retry_delay_ms", deserialize_with = "milliseconds")]
+  pub verified_write_retry_delay: Duration,
+  #[serde(rename = "handle_check_delay_ms", deserialize_with = "milliseconds")]
+  pub handle_check_delay: Duration,
+  /// Set aside the monitors failing this many operations in a row, so that they don't stall every adjustment, unless 0
+  pub quarantine_failures: u32,
+  #[serde(rename = "quarantine_cooldown_ms", deserialize_with = "milliseconds")]
+  pub quarantine_cooldown: Duration,
+  /// What's done with the monitors found in standby or turned off before a transition, which drop the writes otherwise
+  pub asleep_monitors: AsleepMonitors,
+  /// Poll the monitors for brightness changes made without the knob, such as through their own buttons
+  pub resync_external_changes: bool,
+  #[serde(rename = "resync_min_interval_ms", deserialize_with = "milliseconds")]
+  pub resync_min_interval: Duration,
+  #[serde(rename = "resync_max_interval_ms", deserialize_with = "milliseconds")]
+  pub resync_max_interval: Duration,
+  /// Turn the mouse wheel into knob adjustments, for testing without the keyboard
+  pub emulate_knob: bool,
+  pub wheel_delta_divisor: u16,
+  pub smooth_scrolling: bool,
+  pub emulation_blacklist: Vec<String>,
+  pub pause_emulation_in_fullscreen: bool,
+  /// Kind of fullscreen of the windows of the given processes, for the ones that are not detected properly
+  pub fullscreen_overrides: BTreeMap<String, FullscreenKind>,
+  /// Modifiers joined by `+` (e.g. "ctrl+alt") to hold for the wheel to adjust the brightness, none when empty
+  #[serde(deserialize_with = "modifiers")]
+  pub emulation_modifiers: HOT_KEY_MODIFIERS,
+  /// Regions of the screen over which the wheel adjusts the brightness without holding the modifiers
+  pub emulation_regions: Vec<ScreenRegion>,
+  /// Vendor and product IDs of the keyboards with a knob
+  pub keyboard_ids: Vec<(u16, u16)>,
+  /// Capture the knob keys through the keyboard hook, or through the Raw Input of the keyboards above only
+  pub knob_input: KnobInput,
+  /// Part of the device path of the only keyboard whose keystrokes are taken as the knob's, any of the keyboards above
+  /// when unset
+  pub knob_device: Option<String>,
+  /// Register the low-level hook again when Windows removed it, checked only when input comes in that it missed
+  pub hook_watchdog: bool,
+  /// Keys sent by the knob when it's turned, either as a key name (e.g. "f20") or a virtual-key code (e.g. "0xaf")
+  #[serde(deserialize_with = "virtual_key")]
+  pub increment_key: VIRTUAL_KEY,
+  #[serde(deserialize_with = "virtual_key")]
+  pub decrement_key: VIRTUAL_KEY,
+  /// Key sent by the knob when it's pressed, none when empty
+  #[serde(deserialize_with = "optional_virtual_key")]
+  pub press_key: Option<VIRTUAL_KEY>,
+  /// How long the press key has to be held for the monitors to be put in standby, never when unset
+  #[serde(rename = "standby_hold_ms", deserialize_with = "optional_milliseconds")]
+  pub standby_hold: Option<Duration>,
+  pub suppress_knob_keys: bool,
+  pub learn_knob_keys: bool,
+  pub capture_volume_controls: bool,
+  pub hybrid_layers: bool,
+  /// Key held for the volume controls of the knob to adjust the brightness with the hybrid layers, none when empty
+  #[serde(deserialize_with = "optional_virtual_key")]
+  pub hybrid_layer_key: Option<VIRTUAL_KEY>,
+  /// Talk to the QMK firmware of the keyboard over raw HID, which reports the knob and takes the brightness
+  pub raw_hid: bool,
+  /// Make the brightness of the backlight of the keyboard follow the one of the monitors, over raw HID, from the lowest
+  /// one in percent
+  pub keyboard_backlight_sync: bool,
+  pub keyboard_backlight_min: u16,
+  pub vendor_cooperation: bool,
+  #[serde(rename = "vendor_check_interval_ms", deserialize_with = "milliseconds")]
+  pub vendor_check_interval: Duration,
+  /// Show an overlay with the value being adjusted every time the knob is turned
+  pub show_osd: bool,
+  /// Show an icon in the notification area, with a menu to pause the knob, select the monitors and exit
+  pub show_tray_icon: bool,
+  /// Keep controlling the monitors when started without an interactive desktop, such as from a service, instead of
+  /// refusing to start. The knob, the tray icon and the OSD are all left out in that case
+  pub run_without_desktop: bool,
+  /// Accept commands from external programs over a named pipe, such as setting the brightness or pausing the knob
+  pub ipc_server: bool,
+  /// Write the logs to a file in the data directory as well, rotated daily, mostly for when there's no console to read
+  /// them from as when running as a service
+  pub log_file: bool,
+  /// Language of the user-facing strings, the one of the user when unset
+  pub locale: Option<String>,
+  /// Settings of single monitors, keyed by the identifier printed by `list` which comes from their EDID
+  pub monitors: BTreeMap<String, MonitorConfig>,
+  /// Brightness and contrast applied all at once by the `apply-preset` action or from the tray icon, by name
+  pub presets: BTreeMap<String, PresetConfig>,
+  pub bindings: Vec<BindingConfig>
+}
+
+impl Default for Config {
+  fn default() -> Self {
+    Self {
+      control_all_monitors: true,
+      controlled_monitors: Vec::new(),
+      follow_monitor: None,
+      routes: BTreeMap::new(),
+      min_brightness: 0,
+      max_brightness: 100,
+      restore_brightness: false,
+      linked_monitors: false,
+      hdr_sdr_white_level: true,
+      step_size: 1,
+      acceleration_window: Duration::from_millis(60),
+      max_acceleration: 1,
+      coarse_step_size: None,
+      wiggle_window: Duration::from_millis(400),
+      coarse_step_timeout: Duration::from_secs(3),
+      limit_behavior: BTreeMap::new(),
+      limit_hold_delay: Duration::from_millis(600),
+      animation_duration: Duration::from_millis(0),
+      animation_easing: Easing::EaseInOut,
+      max_brightness_rate: None,
+      zero_floor: None,
+      zero_floor_breakthrough_delay: Duration::from_millis(600),
+      dimming_overlay: false,
+      dimming_overlay_max_opacity: 80,
+      schedule: Vec::new(),
+      location: None,
+      content_dimming: false,
+      content_sample_interval: Duration::from_secs(2),
+      content_max_dimming: 20,
+      content_dark_threshold: 0.3,
+      knob_modes: Vec::new(),
+      mode_timeout: Duration::from_secs(10),
+      press_action: PressAction::CycleMode,
+      contrast_modifiers: HOT_KEY_MODIFIERS(0),
+      event_queue_capacity: 64,
+      warm_up_period: Duration::from_secs(30),
+      warm_up_retry_delay: Duration::from_secs(2),
+      verified_write_monitors: Vec::new(),
+      verified_write_retries: 2,
+      verified_write_retry_delay: Duration::from_millis(250),
+      handle_check_delay: Duration::from_secs(60),
+      quarantine_failures: 3,
+      quarantine_cooldown: Duration::from_secs(30),
+      asleep_monitors: AsleepMonitors::Skip,
+      resync_external_changes: false,
+      resync_min_interval: Duration::from_secs(2),
+      resync_max_interval: Duration::from_secs(120),
+      emulate_knob: false,
+      wheel_delta_divisor: 1,
+      smooth_scrolling: false,
+      emulation_blacklist: Vec::new(),
+      pause_emulation_in_fullscreen: false,
+      fullscreen_overrides: BTreeMap::new(),
+      emulation_modifiers: HOT_KEY_MODIFIERS(0),
+      emulation_regions: Vec::new(),
+      keyboard_ids: GMMK_PRO_KEYBOARD_IDS.to_vec(),
+      knob_input: KnobInput::Hook,
+      knob_device: None,
+      hook_watchdog: false,
+      increment_key: DEFAULT_INCREMENT_KEY,
+      decrement_key: DEFAULT_DECREMENT_KEY,
+      press_key: None,
+      standby_hold: None,
+      suppress_knob_keys: false,
+      learn_knob_keys: false,
+      capture_volume_controls: false,
+      hybrid_layers: false,
+      hybrid_layer_key: None,
+      raw_hid: false,
+      keyboard_backlight_sync: false,
+      keyboard_backlight_min: 10,
+      vendor_cooperation: false,
+      vendor_check_interval: Duration::from_secs(30),
+      show_osd: true,
+      show_tray_icon: true,
+      run_without_desktop: false,
+      ipc_server: true,
+      log_file: false,
+      locale: None,
+      monitors: BTreeMap::new(),
+      presets: BTreeMap::new(),
+      // Failsafe hotkey for when the screen gets so dark that it can't be recovered otherwise
+      bindings: vec![BindingConfig {
+        chord: "ctrl+alt+shift+b".to_string(),
+        actions: vec![ActionConfig::Set { value: 60, duration: Duration::ZERO, easing: Easing::EaseInOut }]
+      }]
+    }
+  }
+}
+
+impl Config {
+  /// Transition of the brightness changes made by the knob
+  pub fn knob_transition(&self) -> Transition {
+    Transition::new(self.animation_duration, self.animation_easing)
+  }
+
+  pub fn action_bindings(&self) -> Vec<ActionBinding> {
+    self.bindings.iter().map(|binding| binding.to_action_binding()).collect()
+  }
+
+  /// Make sure that neither the range the brightness is kept within nor the one of any monitor is inverted, which
+  /// nothing could be kept within. The range of a monitor is checked once merged with the general one, given that
+  /// either of its bounds can be left to it
+  fn check_brightness_range(&self) -> Result<(), ConfigError> {
+    if self.min_brightness > self.max_brightness {
+      return Err(ConfigError::InvalidRange(format!(
+        "min_brightness ({}) is above max_brightness ({})", self.min_brightness, self.max_brightness
+      )));
+    }
+    for (monitor_id, monitor_config) in &self.monitors {
+      let (min_brightness, max_brightness) = monitor_config.brightness_range(self.min_brightness, self.max_brightness);
+      if min_brightness > max_brightness {
+        return Err(ConfigError::InvalidRange(format!(
+          "the minimum brightness of monitor \"{}\" ({}) is above its maximum one ({})", monitor_id, min_brightness, max_brightness
+        )));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Settings of a single monitor, which take precedence over the general ones for it
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MonitorConfig {
+  /// Range the brightness of the monitor is kept within, the general one when unset
+  pub min_brightness: Option<i32>,
+  pub max_brightness: Option<i32>,
+  /// Brightness change of a single knob notch, while the monitor is the one the others follow
+  pub step_size: Option<i32>,
+  /// Turn the brightness down when the knob is turned clockwise, while the monitor is the one the others follow
+  pub inverted: bool,
+  /// Leave the monitor out of the ones adjusted by the knob
+  pub excluded: bool,
+  /// Share of the range of the brightness, from 0, over which the picture is dimmed with the backlight at its lowest
+  pub software_dimming: Option<u16>
+}
+
+impl MonitorConfig {
+  /// Get the range the brightness of the monitor is kept within, given the general one
+  pub fn brightness_range(&self, min_brightness: i32, max_brightness: i32) -> (i32, i32) {
+    (self.min_brightness.unwrap_or(min_brightness), self.max_brightness.unwrap_or(max_brightness))
+  }
+}
+
+/// Values applied to the monitors by a preset, leaving the ones that are unset alone
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresetConfig {
+  pub brightness: Option<i32>,
+  pub contrast: Option<i32>,
+  /// Values of single monitors, keyed by their identifier, which take precedence over the ones above
+  pub monitors: BTreeMap<String, PresetValues>
+}
+
+/// Values applied to a single monitor by a preset
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresetValues {
+  pub brightness: Option<i32>,
+  pub contrast: Option<i32>
+}
+
+/// Bind a chord to a list of actions, as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BindingConfig {
+  pub chord: String,
+  pub actions: Vec<ActionConfig>
+}
+
+impl BindingConfig {
+  fn to_action_binding(&self) -> ActionBinding {
+    let actions = self.actions.iter().map(|action| match action {
+      ActionConfig::Set { value, duration, easing } => Action::Set(*value, Transition::new(*duration, *easing)),
+      ActionConfig::Step(steps) => Action::Step(*steps),
+      ActionConfig::RunCommand(command) => Action::RunCommand(command.clone()),
+      ActionConfig::Pause => Action::Pause,
+      ActionConfig::CycleMode => Action::CycleMode,
+      ActionConfig::CycleInput => Action::CycleInput,
+      ActionConfig::ApplyPreset(name) => Action::ApplyPreset(name.clone())
+    });
+    ActionBinding::new(Trigger::Chord(self.chord.clone()), actions.collect())
+  }
+}
+
+/// Represent an action as written in the config file, such as `{ set = { value = 60 } }` or `"pause"`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ActionConfig {
+  Set {
+    value: i32,
+    #[serde(default, rename = "duration_ms", deserialize_with = "milliseconds")]
+    duration: Duration,
+    #[serde(default = "default_easing")]
+    easing: Easing
+  },
+  Step(i32),
+  RunCommand(String),
+  Pause,
+  CycleMode,
+  CycleInput,
+  ApplyPreset(String)
+}
+
+fn default_easing() -> Easing {
+  Easing::EaseInOut
+}
+
+/// Durations are written as a number of milliseconds
+pub(crate) fn milliseconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+  u64::deserialize(deserializer).map(Duration::from_millis)
+}
+
+fn optional_milliseconds<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+  milliseconds(deserializer).map(Some)
+}
+
+fn virtual_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<VIRTUAL_KEY, D::Error> {
+  let name = String::deserialize(deserializer)?;
+  parse_virtual_key(&name).ok_or_else(|| serde::de::Error::custom(format!("unknown key \"{}\"", name)))
+}
+
+fn optional_virtual_key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<VIRTUAL_KEY>, D::Error> {
+  let name = String::deserialize(deserializer)?;
+  match name.trim().is_empty() {
+    true => Ok(None),
+    false => parse_virtual_key(&name).map(Some).ok_or_else(|| serde::de::Error::custom(format!("unknown key \"{}\"", name)))
+  }
+}
+
+fn modifiers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HOT_KEY_MODIFIERS, D::Error> {
+  let modifiers = String::deserialize(deserializer)?;
+  match modifiers.trim().is_empty() {
+    true => Ok(HOT_KEY_MODIFIERS(0)),
+    false => parse_modifiers(&modifiers).ok_or_else(|| serde::de::Error::custom(format!("unknown modifiers \"{}\"", modifiers)))
+  }
+}
+
+/// Load the config file sitting next to the executable, or the one in the config directory otherwise. The default
+/// settings are used when there's none. Returns the path of the file that was loaded, if any
+pub fn load() -> Result<(Config, Option<PathBuf>), ConfigError> {
+  let Some(path) = config_paths()?.into_iter().find(|path| path.is_file()) else {
+    return Ok((Config::default(), None));
+  };
+  let config: Config = toml::from_str(&fs::read_to_string(&path)?)?;
+  config.check_brightness_range()?;
+  Ok((config, Some(path)))
+}
+
+/// Write the default config file to the config directory, unless there's one already
+pub fn write_default() -> io::Result<PathBuf> {
+  let path = config_dir()?.join(CONFIG_FILE_NAME);
+  if !path.exists() {
+    fs::write(&path, DEFAULT_CONFIG)?;
+  }
+  Ok(path)
+}
+
+/// Get the paths the config file is looked for at, in order of precedence
+fn config_paths() -> io::Result<Vec<PathBuf>> {
+  let exe_path = env::current_exe()?;
+  let exe_dir = exe_path.parent().unwrap_or(Path::new("."));
+  Ok(vec![exe_dir.join(CONFIG_FILE_NAME), config_dir()?.join(CONFIG_FILE_NAME)])
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+  IOError(io::Error),
+  ParseError(toml::de::Error),
+  /// A minimum brightness is above the maximum one it goes with
+  InvalidRange(String)
+}
+
+impl From<io::Error> for ConfigError {
+  fn from(value: io::Error) -> Self {
+    ConfigError::IOError(value)
+  }
+}
+
+impl From<toml::de::Error> for ConfigError {
+  fn from(value: toml::de::Error) -> Self {
+    ConfigError::ParseError(value)
+  }
+}
@@ -0,0 +1,105 @@
+use std::mem::size_of;
+use std::ptr;
+use windows::Win32::Devices::HumanInterfaceDevice::{HidP_GetUsages, HidP_Input, HidP_MaxUsageListLength};
+use windows::Win32::Foundation::{HANDLE, HWND, LPARAM};
+use windows::Win32::UI::Input::{
+  GetRawInputData, GetRawInputDeviceInfoW, RegisterRawInputDevices, HRAWINPUT, RAWINPUTDEVICE, RAWINPUTHEADER, RIDEV_INPUTSINK, RIDEV_REMOVE,
+  RIDI_PREPARSEDDATA, RID_INPUT, RIM_TYPEHID
+};
+
+// Usages of the Consumer Page, as defined by the HID Usage Tables specification
+const CONSUMER_USAGE_PAGE: u16 = 0x0c;
+const CONSUMER_CONTROL_USAGE: u16 = 0x01;
+const MUTE_USAGE: u16 = 0xe2;
+const VOLUME_INCREMENT_USAGE: u16 = 0xe9;
+const VOLUME_DECREMENT_USAGE: u16 = 0xea;
+
+/// Represent a volume control pressed on a consumer-control device, such as a knob left at its default mapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VolumeControl {
+  Up,
+  Down,
+  Mute
+}
+
+/// Receive the input of every consumer-control device as WM_INPUT messages posted to the given window, even while it's
+/// not in the foreground
+pub fn register_consumer_control(hwnd: HWND) -> windows::core::Result<()> {
+  let device = RAWINPUTDEVICE { usUsagePage: CONSUMER_USAGE_PAGE, usUsage: CONSUMER_CONTROL_USAGE, dwFlags: RIDEV_INPUTSINK, hwndTarget: hwnd };
+  match unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32) }.as_bool() {
+    true => Ok(()),
+    false => Err(windows::core::Error::from_win32())
+  }
+}
+
+pub fn unregister_consumer_control() {
+  let device = RAWINPUTDEVICE { usUsagePage: CONSUMER_USAGE_PAGE, usUsage: CONSUMER_CONTROL_USAGE, dwFlags: RIDEV_REMOVE, hwndTarget: HWND(0) };
+  unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32); }
+}
+
+/// Get the volume controls pressed in the report carried by a WM_INPUT message
+pub fn read_volume_controls(l_param: LPARAM) -> Vec<VolumeControl> {
+  unsafe {
+    let raw_input = HRAWINPUT(l_param.0);
+    let header_size = size_of::<RAWINPUTHEADER>() as u32;
+    let mut size = 0u32;
+    if GetRawInputData(raw_input, RID_INPUT, None, &mut size, header_size) != 0 || size == 0 {
+      return Vec::new();
+    }
+
+    let mut buffer = vec![0u8; size as usize];
+    if GetRawInputData(raw_input, RID_INPUT, Some(buffer.as_mut_ptr() as *mut _), &mut size, header_size) != size {
+      return Vec::new();
+    }
+    let header = ptr::read_unaligned(buffer.as_ptr() as *const RAWINPUTHEADER);
+    if header.dwType != RIM_TYPEHID.0 {
+      return Vec::new();
+    }
+
+    // The HID reports follow the header and their size and count, all of them being 32 bit integers
+    let hid_offset = size_of::<RAWINPUTHEADER>();
+    let report_size = ptr::read_unaligned(buffer.as_ptr().add(hid_offset) as *const u32) as usize;
+    let report_count = ptr::read_unaligned(buffer.as_ptr().add(hid_offset + 4) as *const u32) as usize;
+    let reports_offset = hid_offset + 8;
+    if report_size == 0 || reports_offset + report_size * report_count > buffer.len() {
+      return Vec::new();
+    }
+
+    let Some(mut preparsed_data) = preparsed_data(header.hDevice) else { return Vec::new() };
+    let preparsed_data_ptr = preparsed_data.as_mut_ptr() as isize;
+    let max_usages = HidP_MaxUsageListLength(HidP_Input, CONSUMER_USAGE_PAGE, preparsed_data_ptr);
+
+    let mut controls = Vec::new();
+    for report in buffer[reports_offset..reports_offset + report_size * report_count].chunks_exact_mut(report_size) {
+      let mut usages = vec![0u16; max_usages.max(1) as usize];
+      let mut usage_count = usages.len() as u32;
+      if HidP_GetUsages(HidP_Input, CONSUMER_USAGE_PAGE, 0, usages.as_mut_ptr(), &mut usage_count, preparsed_data_ptr, report).is_err() {
+        continue;
+      }
+
+      controls.extend(usages[..usage_count as usize].iter().filter_map(|usage| match *usage {
+        VOLUME_INCREMENT_USAGE => Some(VolumeControl::Up),
+        VOLUME_DECREMENT_USAGE => Some(VolumeControl::Down),
+        MUTE_USAGE => Some(VolumeControl::Mute),
+        _ => None
+      }));
+    }
+    controls
+  }
+}
+
+/// Get the data describing the reports of a HID device, which is needed to make sense of them
+unsafe fn preparsed_data(device: HANDLE) -> Option<Vec<u64>> {
+  let mut size = 0u32;
+  GetRawInputDeviceInfoW(device, RIDI_PREPARSEDDATA, None, &mut size);
+  if size == 0 {
+    return None;
+  }
+
+  // Backed by 64 bit integers to keep the buffer aligned
+  let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+  match GetRawInputDeviceInfoW(device, RIDI_PREPARSEDDATA, Some(buffer.as_mut_ptr() as *mut _), &mut size) {
+    u32::MAX => None,
+    _ => Some(buffer)
+  }
+}
@@ -0,0 +1,104 @@
+use std::ffi::c_void;
+use std::mem::size_of;
+use windows::Win32::Foundation::HWND;
+use windows::Win32::Graphics::Gdi::{
+  CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, GetDC, GetDIBits, ReleaseDC, SelectObject, SetStretchBltMode, StretchBlt,
+  BITMAPINFO, BITMAPINFOHEADER, BI_RGB, DIB_RGB_COLORS, HALFTONE, HDC, SRCCOPY
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetSystemMetrics, SM_CXVIRTUALSCREEN, SM_CYVIRTUALSCREEN, SM_XVIRTUALSCREEN, SM_YVIRTUALSCREEN};
+
+/// Size of the copy of the screens the luminance is averaged over, which is plenty given that every pixel of it blends
+/// the ones of the area of the screens it covers
+const SAMPLE_WIDTH: i32 = 64;
+const SAMPLE_HEIGHT: i32 = 36;
+
+/// Lower the brightness while the screens show dark content, on top of the one set with the knob, which saves power
+/// and spares OLED panels some wear
+#[derive(Debug)]
+pub struct ContentDimming {
+  max_dimming: i32,
+  dark_threshold: f64,
+  /// Dimming applied last, along with the brightness it resulted in
+  applied: Option<(i32, i32)>
+}
+
+impl ContentDimming {
+  pub fn new(max_dimming: i32, dark_threshold: f64) -> Self {
+    Self { max_dimming, dark_threshold, applied: None }
+  }
+
+  /// Get the brightness to move to for the given luminance of the screens, given the one being moved to, when it's not
+  /// that one already. The dimming is taken off the brightness it set last, or off the new one when something else,
+  /// such as the knob, changed it since, which is how the knob keeps setting the brightness the dimming starts from
+  pub fn adjust(&mut self, target: i32, luminance: f64, min_brightness: i32, max_brightness: i32) -> Option<i32> {
+    let undimmed = match self.applied {
+      Some((dimming, value)) if value == target => target + dimming,
+      _ => target
+    };
+    let value = (undimmed - self.dimming(luminance)).clamp(min_brightness, max_brightness);
+    self.applied = Some((undimmed - value, value));
+    (value != target).then_some(value)
+  }
+
+  /// Get how much to lower the brightness by for the given luminance, which goes up linearly from nothing at the dark
+  /// threshold to the maximum for a black screen
+  fn dimming(&self, luminance: f64) -> i32 {
+    match luminance < self.dark_threshold {
+      true => (self.max_dimming as f64 * (1.0 - luminance / self.dark_threshold)).round() as i32,
+      false => 0
+    }
+  }
+}
+
+/// Get the average luminance of what the screens show, from 0 for black to 1 for white, out of a downscaled copy of the
+/// whole virtual desktop. Fails while the secure desktop is the input desktop, which can't be copied from
+pub fn average_luminance() -> Option<f64> {
+  unsafe {
+    let (x, y) = (GetSystemMetrics(SM_XVIRTUALSCREEN), GetSystemMetrics(SM_YVIRTUALSCREEN));
+    let (width, height) = (GetSystemMetrics(SM_CXVIRTUALSCREEN), GetSystemMetrics(SM_CYVIRTUALSCREEN));
+    let screen_dc = GetDC(HWND(0));
+    if screen_dc.is_invalid() {
+      return None;
+    }
+    let memory_dc = CreateCompatibleDC(screen_dc);
+    let bitmap = CreateCompatibleBitmap(screen_dc, SAMPLE_WIDTH, SAMPLE_HEIGHT);
+    let previous_bitmap = SelectObject(memory_dc, bitmap);
+
+    // Halftoning averages the pixels each one of the copy covers, where the default mode drops all but one of them
+    SetStretchBltMode(memory_dc, HALFTONE);
+    let copied = StretchBlt(memory_dc, 0, 0, SAMPLE_WIDTH, SAMPLE_HEIGHT, screen_dc, x, y, width, height, SRCCOPY).as_bool();
+
+    let mut bitmap_info = BITMAPINFO {
+      bmiHeader: BITMAPINFOHEADER {
+        biSize: size_of::<BITMAPINFOHEADER>() as u32,
+        biWidth: SAMPLE_WIDTH,
+        biHeight: -SAMPLE_HEIGHT,
+        biPlanes: 1,
+        biBitCount: 32,
+        biCompression: BI_RGB.0 as u32,
+        ..Default::default()
+      },
+      ..Default::default()
+    };
+    let mut pixels = vec![0u32; (SAMPLE_WIDTH * SAMPLE_HEIGHT) as usize];
+    // The bitmap can't be selected into a device context while its bits are read
+    SelectObject(memory_dc, previous_bitmap);
+    let lines = match copied {
+      true => GetDIBits(HDC(memory_dc.0), bitmap, 0, SAMPLE_HEIGHT as u32, Some(pixels.as_mut_ptr() as *mut c_void), &mut bitmap_info, DIB_RGB_COLORS),
+      false => 0
+    };
+
+    DeleteObject(bitmap);
+    DeleteDC(memory_dc);
+    ReleaseDC(HWND(0), screen_dc);
+    (lines == SAMPLE_HEIGHT).then(|| pixels.iter().map(|pixel| luma(*pixel)).sum::<f64>() / pixels.len() as f64)
+  }
+}
+
+/// Get the luma of a pixel laid out as 0x00RRGGBB
+///
+/// Reference: https://en.wikipedia.org/wiki/Rec._709#Luma_coefficients
+fn luma(pixel: u32) -> f64 {
+  let channel = |shift: u32| ((pixel >> shift) & 0xff) as f64 / 255.0;
+  0.2126 * channel(16) + 0.7152 * channel(8) + 0.0722 * channel(0)
+}
@@ -0,0 +1,76 @@
+use crate::paths::data_dir;
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+const TRACE_FILE_NAME: &str = "ddc-trace.log";
+const RING_BUFFER_CAPACITY: usize = 1024;
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// Represent the kind of transaction exchanged with a monitor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+  Read,
+  Write
+}
+
+/// Log of the transactions exchanged with the monitors, kept both in a dedicated file and in a bounded in-memory
+/// buffer holding the most recent ones
+struct Trace {
+  file: File,
+  recent: VecDeque<String>
+}
+
+/// Start tracing the transactions, appending them to the trace file in the data directory
+pub fn enable() -> io::Result<PathBuf> {
+  let path = data_dir()?.join(TRACE_FILE_NAME);
+  let file = OpenOptions::new().create(true).append(true).open(&path)?;
+  *TRACE.lock().unwrap() = Some(Trace { file, recent: VecDeque::with_capacity(RING_BUFFER_CAPACITY) });
+  Ok(path)
+}
+
+pub fn is_enabled() -> bool {
+  TRACE.lock().unwrap().is_some()
+}
+
+/// Record a transaction with a monitor, if tracing is enabled. `value` is the raw value written, or the one read back
+/// when the read succeeded
+pub fn record(monitor: &str, operation: Operation, vcp_code: u8, value: Option<u16>, duration: Duration, result: Result<(), &io::Error>) {
+  let mut trace = TRACE.lock().unwrap();
+  let Some(trace) = trace.as_mut() else { return };
+
+  let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_millis()).unwrap_or(0);
+  let operation = match operation {
+    Operation::Read => "read",
+    Operation::Write => "write"
+  };
+  let value = value.map(|value| format!("0x{:04x}", value)).unwrap_or_else(|| "-".to_string());
+  // The messages of the system errors end with a line break, while every transaction is kept to a single line
+  let result = match result {
+    Ok(_) => "ok".to_string(),
+    Err(e) => format!("error: {}", e.to_string().trim_end().replace(['\r', '\n'], " "))
+  };
+  let line = format!(
+    "{}\t{}\t{}\t0x{:02x}\t{}\t{}us\t{}",
+    timestamp, monitor.replace(['\t', '\n'], " "), operation, vcp_code, value, duration.as_micros(), result
+  );
+
+  if let Err(e) = writeln!(trace.file, "{}", line) {
+    error!("unable to write to the DDC trace - {}", e);
+  }
+  if trace.recent.len() == RING_BUFFER_CAPACITY {
+    trace.recent.pop_front();
+  }
+  trace.recent.push_back(line);
+}
+
+/// Get the most recent transactions, oldest first
+pub fn dump() -> Vec<String> {
+  TRACE.lock().unwrap().as_ref().map(|trace| trace.recent.iter().cloned().collect()).unwrap_or_default()
+}
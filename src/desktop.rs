@@ -0,0 +1,137 @@
+use crate::system_events::SystemEvent;
+
+use std::fmt;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, Ordering};
+use windows::Win32::Foundation::{HANDLE, HMODULE, HWND, LPARAM, WPARAM};
+use windows::Win32::System::RemoteDesktop::ProcessIdToSessionId;
+use windows::Win32::System::StationsAndDesktops::{
+  CloseDesktop, GetProcessWindowStation, GetUserObjectInformationW, OpenInputDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_READOBJECTS, UOI_FLAGS,
+  UOI_NAME, USEROBJECTFLAGS
+};
+use windows::Win32::System::Threading::GetCurrentProcessId;
+use windows::Win32::UI::Accessibility::{HWINEVENTHOOK, SetWinEventHook, UnhookWinEvent};
+use windows::Win32::UI::WindowsAndMessaging::{EVENT_SYSTEM_DESKTOPSWITCH, PostMessageW, WINEVENT_OUTOFCONTEXT, WSF_VISIBLE};
+
+/// Application-defined message posted to the message loop every time the input desktop changes
+pub const DESKTOP_SWITCH_MSG: u32 = 0x0504;
+
+static SECURE_DESKTOP_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Represent why the process has no interactive desktop to capture the input of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonInteractiveSession {
+  /// Session 0 is where the services run, isolated from the desktops of the logged-on users
+  Session0,
+  /// The window station of the process never receives user input, as with the services that can't interact with the
+  /// desktop
+  HiddenWindowStation
+}
+
+impl fmt::Display for NonInteractiveSession {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      NonInteractiveSession::Session0 => write!(f, "running in session 0, which has no interactive desktop"),
+      NonInteractiveSession::HiddenWindowStation => write!(f, "running in a window station that doesn't receive user input")
+    }
+  }
+}
+
+/// Check whether the process can see the input of the logged-on user, which the low-level hooks, the tray icon and the
+/// OSD all need. Outside of an interactive desktop the hooks either fail with an obscure error or are never called
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/services/interactive-services
+pub fn check_interactive_session() -> Result<(), NonInteractiveSession> {
+  unsafe {
+    let mut session_id = 0u32;
+    if ProcessIdToSessionId(GetCurrentProcessId(), &mut session_id).as_bool() && session_id == 0 {
+      return Err(NonInteractiveSession::Session0);
+    }
+
+    // Failing to inspect the window station is not a reason to refuse to start, the hooks will tell soon enough
+    let Ok(window_station) = GetProcessWindowStation() else { return Ok(()) };
+    let mut flags = USEROBJECTFLAGS::default();
+    let succeeded = GetUserObjectInformationW(
+      HANDLE(window_station.0),
+      UOI_FLAGS,
+      Some(&mut flags as *mut _ as *mut _),
+      size_of::<USEROBJECTFLAGS>() as u32,
+      None
+    ).as_bool();
+    match succeeded && flags.dwFlags & WSF_VISIBLE as u32 == 0 {
+      true => Err(NonInteractiveSession::HiddenWindowStation),
+      false => Ok(())
+    }
+  }
+}
+
+/// Check whether the secure desktop was active the last time the input desktop changed
+pub fn is_secure_desktop_active() -> bool {
+  SECURE_DESKTOP_ACTIVE.load(Ordering::Acquire)
+}
+
+/// Register a hook that gets notified whenever the input desktop changes. It must be called from a thread that runs
+/// a message loop, where the `DESKTOP_SWITCH_MSG` message will be posted to
+pub fn register_desktop_switch_hook() -> HWINEVENTHOOK {
+  unsafe {
+    SetWinEventHook(
+      EVENT_SYSTEM_DESKTOPSWITCH,
+      EVENT_SYSTEM_DESKTOPSWITCH,
+      HMODULE(0),
+      Some(desktop_switch_hook),
+      0,
+      0,
+      WINEVENT_OUTOFCONTEXT
+    )
+  }
+}
+
+pub fn unregister_desktop_switch_hook(hook_id: HWINEVENTHOOK) {
+  unsafe { UnhookWinEvent(hook_id); }
+}
+
+/// Inspect the current input desktop and record the transition, if any
+pub fn update_desktop_state() -> Option<SystemEvent> {
+  let is_secure = is_input_desktop_secure();
+  let was_secure = SECURE_DESKTOP_ACTIVE.swap(is_secure, Ordering::AcqRel);
+
+  match (was_secure, is_secure) {
+    (false, true) => Some(SystemEvent::EnteredSecureDesktop),
+    (true, false) => Some(SystemEvent::LeftSecureDesktop),
+    _ => None
+  }
+}
+
+/// Check whether the desktop currently receiving user input is anything other than the interactive "Default" one. A
+/// normal user process is not allowed to open the secure desktop at all, so failing to open it counts as secure too
+fn is_input_desktop_secure() -> bool {
+  unsafe {
+    let desktop = match OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), false, DESKTOP_READOBJECTS) {
+      Ok(desktop) => desktop,
+      Err(_) => return true
+    };
+
+    let mut name = [0u16; 64];
+    let mut name_length = 0u32;
+    let succeeded = GetUserObjectInformationW(
+      HANDLE(desktop.0),
+      UOI_NAME,
+      Some(name.as_mut_ptr() as *mut _),
+      (name.len() * 2) as u32,
+      Some(&mut name_length)
+    ).as_bool();
+    CloseDesktop(desktop);
+
+    let name = String::from_utf16_lossy(&name);
+    !succeeded || !name.trim_end_matches('\0').eq_ignore_ascii_case("Default")
+  }
+}
+
+/// Handle desktop switch events
+///
+/// Note: out-of-context WinEvent hooks are called on the thread that registered them, while it is pumping messages,
+/// so it's enough to post a message to its queue
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-setwineventhook#remarks
+unsafe extern "system" fn desktop_switch_hook(_hook: HWINEVENTHOOK, _event: u32, _hwnd: HWND, _id_object: i32, _id_child: i32, _id_event_thread: u32, _event_time: u32) {
+  PostMessageW(HWND(0), DESKTOP_SWITCH_MSG, WPARAM(0), LPARAM(0));
+}
@@ -0,0 +1,124 @@
+use crate::shutdown::{StopSignal, forward_stop_to_message_loop};
+use crate::watch::WatchReceiver;
+
+use tracing::error;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{BOOL, COLORREF, HMODULE, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{EnumDisplayMonitors, GetStockObject, BLACK_BRUSH, HBRUSH, HDC, HMONITOR};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{
+  CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW, PostMessageW, PostThreadMessageW, RegisterClassW,
+  SetLayeredWindowAttributes, SetWindowPos, TranslateMessage, HMENU, HWND_TOPMOST, LWA_ALPHA, MSG, SWP_NOACTIVATE, SWP_SHOWWINDOW,
+  WM_DISPLAYCHANGE, WNDCLASSW, WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST, WS_EX_TRANSPARENT, WS_POPUP
+};
+
+/// Application-defined message posted to the overlay thread when the opacity changed, or when the screens did
+const OVERLAY_UPDATE_MSG: u32 = 0x0514;
+
+const OVERLAY_WINDOW_CLASS: PCWSTR = w!("GmmkProBrightnessKnobDimmingOverlay");
+
+/// Darken every screen beyond the lowest brightness of its backlight, with a black window covering it at the opacity
+/// sent by the brightness thread. The windows only exist while the opacity is above 0, and never take the focus nor
+/// the mouse clicks. Runs until the stop signal is received
+pub fn run_dimming_overlay(stop_rx: StopSignal, mut opacity_rx: WatchReceiver<u8>) -> windows::core::Result<()> {
+  unsafe {
+    let instance = GetModuleHandleW(None)?;
+    let window_class = WNDCLASSW {
+      lpfnWndProc: Some(overlay_window_proc),
+      hInstance: instance,
+      lpszClassName: OVERLAY_WINDOW_CLASS,
+      hbrBackground: HBRUSH(GetStockObject(BLACK_BRUSH).0),
+      ..Default::default()
+    };
+    RegisterClassW(&window_class);
+
+    // There's no window to post the updates to while the screens aren't dimmed, so they go to the thread instead
+    let thread_id = GetCurrentThreadId();
+    opacity_rx.on_change(move || {
+      PostThreadMessageW(thread_id, OVERLAY_UPDATE_MSG, WPARAM(0), LPARAM(0));
+    });
+
+    // Make GetMessageW return as soon as the stop signal is received
+    forward_stop_to_message_loop(stop_rx);
+
+    let mut overlay_windows = Vec::new();
+    let mut msg: MSG = Default::default();
+    while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+      match msg.message {
+        OVERLAY_UPDATE_MSG => {
+          if let Err(e) = update_overlay_windows(&mut overlay_windows, instance, opacity_rx.latest()) {
+            error!("unable to dim the screens - {}", e);
+          }
+        },
+        _ => {
+          TranslateMessage(&msg);
+          DispatchMessageW(&msg);
+        }
+      };
+    }
+
+    overlay_windows.into_iter().for_each(|hwnd| { DestroyWindow(hwnd); });
+  }
+  Ok(())
+}
+
+/// Cover every screen with a window at the given opacity, creating the missing windows and destroying the ones left
+/// over, which is all of them once the opacity is back to 0
+unsafe fn update_overlay_windows(overlay_windows: &mut Vec<HWND>, instance: HMODULE, opacity: u8) -> windows::core::Result<()> {
+  let screens = if opacity == 0 { Vec::new() } else { screen_rects() };
+  while overlay_windows.len() > screens.len() {
+    if let Some(hwnd) = overlay_windows.pop() {
+      DestroyWindow(hwnd);
+    }
+  }
+  for (index, screen) in screens.iter().enumerate() {
+    if index == overlay_windows.len() {
+      overlay_windows.push(create_overlay_window(instance)?);
+    }
+    let hwnd = overlay_windows[index];
+    let (width, height) = (screen.right - screen.left, screen.bottom - screen.top);
+    SetLayeredWindowAttributes(hwnd, COLORREF(0), opacity, LWA_ALPHA);
+    SetWindowPos(hwnd, HWND_TOPMOST, screen.left, screen.top, width, height, SWP_NOACTIVATE | SWP_SHOWWINDOW);
+  }
+  Ok(())
+}
+
+unsafe fn create_overlay_window(instance: HMODULE) -> windows::core::Result<HWND> {
+  // Layered and transparent windows let the mouse clicks through, which keeps the overlay from getting in the way
+  let hwnd = CreateWindowExW(
+    WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+    OVERLAY_WINDOW_CLASS,
+    OVERLAY_WINDOW_CLASS,
+    WS_POPUP,
+    0, 0, 0, 0,
+    HWND(0),
+    HMENU(0),
+    instance,
+    None
+  );
+  match hwnd.0 {
+    0 => Err(windows::core::Error::from_win32()),
+    _ => Ok(hwnd)
+  }
+}
+
+unsafe extern "system" fn overlay_window_proc(hwnd: HWND, msg: u32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+  // The windows have to follow the screens around as they are moved, resized, connected or disconnected
+  if msg == WM_DISPLAYCHANGE {
+    PostMessageW(HWND(0), OVERLAY_UPDATE_MSG, WPARAM(0), LPARAM(0));
+  }
+  DefWindowProcW(hwnd, msg, w_param, l_param)
+}
+
+/// Get the area of every screen on the virtual desktop
+unsafe fn screen_rects() -> Vec<RECT> {
+  let mut screens: Vec<RECT> = Vec::new();
+  EnumDisplayMonitors(HDC(0), None, Some(push_screen_rect), LPARAM(&mut screens as *mut Vec<RECT> as isize));
+  screens
+}
+
+unsafe extern "system" fn push_screen_rect(_hmonitor_handle: HMONITOR, _hdc: HDC, rect: *mut RECT, data: LPARAM) -> BOOL {
+  (*(data.0 as *mut Vec<RECT>)).push(*rect);
+  BOOL(1)
+}
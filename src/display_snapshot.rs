@@ -0,0 +1,140 @@
+use crate::monitor::{BRIGHTNESS_VCP_CODE, Monitor, MonitorError};
+use crate::paths::data_dir;
+use crate::transition::Transition;
+
+use ddc::FeatureCode;
+use serde::{Deserialize, Serialize};
+use std::cmp::max;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+const SNAPSHOTS_DIR_NAME: &str = "snapshots";
+
+/// VCP feature captured by the snapshots, under the name it's saved with
+struct Feature {
+  name: &'static str,
+  code: FeatureCode,
+  /// Whether the feature takes any value of a range, which is eased into on restore, rather than one of a set
+  continuous: bool
+}
+
+/// Features captured by the snapshots, in the order they are restored. The color preset goes first since switching it
+/// resets the brightness and the contrast of some monitors, and the input goes last since the monitor might stop
+/// answering once it shows another one
+const FEATURES: &[Feature] = &[
+  Feature { name: "color_preset", code: 0x14, continuous: false },
+  Feature { name: "brightness", code: BRIGHTNESS_VCP_CODE, continuous: true },
+  Feature { name: "contrast", code: 0x12, continuous: true },
+  Feature { name: "input", code: 0x60, continuous: false }
+];
+
+/// State of every monitor at some point, as saved to a snapshot file
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DisplaySnapshot {
+  pub monitors: Vec<MonitorSnapshot>
+}
+
+/// Values of the features of a monitor, leaving out the ones it doesn't support
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MonitorSnapshot {
+  pub name: String,
+  pub features: BTreeMap<String, u16>
+}
+
+/// Read the features of every monitor
+pub fn capture() -> io::Result<DisplaySnapshot> {
+  let mut snapshot = DisplaySnapshot::default();
+  for mut monitor in Monitor::enumerate_all()? {
+    let mut features = BTreeMap::new();
+    for feature in FEATURES {
+      match monitor.get_vcp(feature.code) {
+        Ok(value) => {
+          features.insert(feature.name.to_string(), value);
+        },
+        Err(MonitorError::Unsupported(_)) => {},
+        Err(e) => error!("unable to read the {} of {}, leaving it out - {}", feature.name.replace('_', " "), monitor.name(), e)
+      };
+    }
+    snapshot.monitors.push(MonitorSnapshot { name: monitor.name(), features });
+  }
+  Ok(snapshot)
+}
+
+/// Bring every monitor of the snapshot still connected back to the state it was in, using the transition for the
+/// continuous features. Monitors are matched by name, in order when several of them have the same one
+pub fn restore(snapshot: &DisplaySnapshot, transition: Transition) -> io::Result<()> {
+  let mut monitors: Vec<Option<Monitor>> = Monitor::enumerate_all()?.into_iter().map(Some).collect();
+  for monitor_snapshot in &snapshot.monitors {
+    let matching_monitor = monitors.iter_mut().find(|monitor| monitor.as_ref().is_some_and(|monitor| monitor.name() == monitor_snapshot.name));
+    let Some(mut monitor) = matching_monitor.and_then(Option::take) else {
+      error!("{} is not connected, skipping it", monitor_snapshot.name);
+      continue;
+    };
+
+    for feature in FEATURES {
+      let Some(&value) = monitor_snapshot.features.get(feature.name) else { continue };
+      let result = match monitor.get_vcp(feature.code) {
+        // Writing the input it's already on makes some monitors blank while they look for a signal again
+        Ok(current_value) if current_value == value => Ok(()),
+        Ok(current_value) if feature.continuous => ease_feature(&mut monitor, feature.code, current_value, value, transition),
+        _ => monitor.set_vcp(feature.code, value)
+      };
+      match result {
+        Ok(_) => info!("{} set to {} on {}", feature.name.replace('_', " "), value, monitor_snapshot.name),
+        Err(e) => error!("unable to set the {} of {} - {}", feature.name.replace('_', " "), monitor_snapshot.name, e)
+      };
+    }
+  }
+  Ok(())
+}
+
+/// Write the snapshot under the given name, replacing any previous one, returning the path of the file
+pub fn save(name: &str, snapshot: &DisplaySnapshot) -> io::Result<PathBuf> {
+  let path = snapshot_path(name)?;
+  let contents = toml::to_string_pretty(snapshot).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+  fs::write(&path, contents)?;
+  Ok(path)
+}
+
+pub fn load(name: &str) -> io::Result<DisplaySnapshot> {
+  let contents = fs::read_to_string(snapshot_path(name)?)?;
+  toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Get the path of the file the snapshot of the given name is saved to, refusing names that would point elsewhere
+fn snapshot_path(name: &str) -> io::Result<PathBuf> {
+  let is_valid = !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == ' ');
+  if !is_valid {
+    return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid snapshot name \"{}\", use letters, digits, spaces, - and _", name)));
+  }
+  let dir = data_dir()?.join(SNAPSHOTS_DIR_NAME);
+  fs::create_dir_all(&dir)?;
+  Ok(dir.join(format!("{}.toml", name)))
+}
+
+/// Move a feature of the monitor from one value to another over the transition, one frame at a time
+pub fn ease_feature(monitor: &mut Monitor, code: FeatureCode, from_value: u16, to_value: u16, transition: Transition) -> Result<(), MonitorError> {
+  let refresh_rate = monitor.refresh_rate_hz as f32;
+  let n_frames = max(((transition.duration.as_millis() as f32 * refresh_rate) / 1000.0).ceil() as u32, 1);
+  let frame_time = Duration::from_millis(((1.0 / refresh_rate) * 1000.0).floor() as u64);
+  let started_at = Instant::now();
+
+  let mut prev_value = from_value;
+  for frame in 1..=n_frames {
+    let value = transition.easing.ease(from_value as f64, to_value as f64, frame as f64 / n_frames as f64).round() as u16;
+    if value != prev_value {
+      monitor.set_vcp(code, value)?;
+      prev_value = value;
+    }
+    thread::sleep((started_at + frame_time * frame).saturating_duration_since(Instant::now()));
+  }
+  match prev_value == to_value {
+    true => Ok(()),
+    false => monitor.set_vcp(code, to_value)
+  }
+}
@@ -0,0 +1,128 @@
+use crate::monitor::from_wide;
+
+use std::ffi::c_void;
+use std::fmt;
+use std::mem::size_of;
+use windows::core::{PCWSTR, w};
+use windows::Win32::Foundation::ERROR_SUCCESS;
+use windows::Win32::Graphics::Gdi::{EnumDisplayDevicesW, DISPLAY_DEVICEW, DISPLAY_DEVICE_ACTIVE};
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY};
+use windows::Win32::UI::WindowsAndMessaging::EDD_GET_DEVICE_INTERFACE_NAME;
+
+const EDID_HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+/// Length of the base block, which is all that's needed to tell the monitors apart
+const EDID_BLOCK_LENGTH: usize = 128;
+/// Offsets of the four display descriptors of the base block, each of them 18 bytes long
+const DESCRIPTOR_OFFSETS: [usize; 4] = [54, 72, 90, 108];
+const SERIAL_DESCRIPTOR_TAG: u8 = 0xff;
+const NAME_DESCRIPTOR_TAG: u8 = 0xfc;
+
+/// Identity of a monitor, as described by its EDID
+///
+/// Reference: https://en.wikipedia.org/wiki/Extended_Display_Identification_Data#EDID_1.4_data_format
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edid {
+  /// Three-letter PnP ID of the manufacturer, such as DEL for Dell
+  pub manufacturer: String,
+  pub product_code: u16,
+  /// Serial number of the monitor, which is left to 0 by many manufacturers in favor of the serial descriptor
+  pub serial_number: u32,
+  /// Serial number as text, when the monitor has a serial descriptor
+  pub serial: Option<String>,
+  /// Model name, when the monitor has a name descriptor
+  pub model: Option<String>
+}
+
+impl Edid {
+  /// Parse the base block of an EDID, returning nothing when it isn't one
+  pub fn parse(bytes: &[u8]) -> Option<Self> {
+    if bytes.len() < EDID_BLOCK_LENGTH || bytes[..8] != EDID_HEADER {
+      return None;
+    }
+
+    // Three letters of five bits each, with 1 standing for A
+    let packed_manufacturer = u16::from_be_bytes([bytes[8], bytes[9]]);
+    let manufacturer = [10, 5, 0].iter().map(|shift| (b'@' + ((packed_manufacturer >> shift) & 0x1f) as u8) as char).collect();
+    Some(Self {
+      manufacturer,
+      product_code: u16::from_le_bytes([bytes[10], bytes[11]]),
+      serial_number: u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]]),
+      serial: descriptor_text(bytes, SERIAL_DESCRIPTOR_TAG),
+      model: descriptor_text(bytes, NAME_DESCRIPTOR_TAG)
+    })
+  }
+
+  /// Get a stable identifier of the monitor, such as "DEL40F5-ABC1234", which doesn't change across reboots, ports or
+  /// graphics cards. Identical monitors without a serial number share it
+  pub fn id(&self) -> String {
+    let model_id = self.model_id();
+    match (&self.serial, self.serial_number) {
+      (Some(serial), _) => format!("{}-{}", model_id, serial),
+      (None, 0) => model_id,
+      (None, serial_number) => format!("{}-{}", model_id, serial_number)
+    }
+  }
+
+  /// Get the identifier of the model of the monitor, such as "DEL40F5", which is what Windows names its devices after
+  pub fn model_id(&self) -> String {
+    format!("{}{:04X}", self.manufacturer, self.product_code)
+  }
+}
+
+impl fmt::Display for Edid {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.id())
+  }
+}
+
+/// Get the text of the display descriptor with the given tag, which ends at the first line feed
+fn descriptor_text(bytes: &[u8], tag: u8) -> Option<String> {
+  DESCRIPTOR_OFFSETS.iter().map(|offset| &bytes[*offset..*offset + 18]).find_map(|descriptor| {
+    // Descriptors holding something other than timings start with three zeros, followed by their tag
+    if descriptor[..3] != [0, 0, 0] || descriptor[3] != tag {
+      return None;
+    }
+    let text = &descriptor[5..];
+    let length = text.iter().position(|c| *c == b'\n').unwrap_or(text.len());
+    let text = String::from_utf8_lossy(&text[..length]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+  })
+}
+
+/// Read the EDID of every active monitor of a display, given its device name such as \\.\DISPLAY1, in the same order
+/// as its physical monitors. The EDID is the one cached by Windows in the registry when the monitor was plugged in
+pub(crate) fn read_display_edids(device_name: &[u16]) -> Vec<Option<Edid>> {
+  let mut edids = Vec::new();
+  let mut display_device = DISPLAY_DEVICEW { cb: size_of::<DISPLAY_DEVICEW>() as u32, ..Default::default() };
+  let mut device_index = 0;
+  while unsafe { EnumDisplayDevicesW(PCWSTR(device_name.as_ptr()), device_index, &mut display_device, EDD_GET_DEVICE_INTERFACE_NAME) }.as_bool() {
+    if display_device.StateFlags & DISPLAY_DEVICE_ACTIVE != 0 {
+      edids.push(read_edid(&from_wide(&display_device.DeviceID)));
+    }
+    device_index += 1;
+  }
+  edids
+}
+
+/// Read the EDID of a monitor given its device interface path, such as "\\?\DISPLAY#DEL40F5#5&2a0e5a2a&0&UID4353#{...}",
+/// whose middle parts are the hardware ID and the instance ID the EDID is stored under
+fn read_edid(interface_path: &str) -> Option<Edid> {
+  let mut parts = interface_path.split('#').skip(1);
+  let (hardware_id, instance_id) = (parts.next()?, parts.next()?);
+  let key_path = format!("SYSTEM\\CurrentControlSet\\Enum\\DISPLAY\\{}\\{}\\Device Parameters", hardware_id, instance_id);
+  let key_path: Vec<u16> = key_path.encode_utf16().chain([0]).collect();
+
+  // Asked for the size first, given that the extension blocks make some of them a lot longer than the base block
+  let mut length = 0u32;
+  let read = |bytes: Option<*mut c_void>, length: &mut u32| unsafe {
+    RegGetValueW(HKEY_LOCAL_MACHINE, PCWSTR(key_path.as_ptr()), w!("EDID"), RRF_RT_REG_BINARY, None, bytes, Some(length))
+  };
+  if read(None, &mut length) != ERROR_SUCCESS {
+    return None;
+  }
+  let mut bytes = vec![0u8; length as usize];
+  match read(Some(bytes.as_mut_ptr() as *mut c_void), &mut length) {
+    ERROR_SUCCESS => Edid::parse(&bytes[..length as usize]),
+    _ => None
+  }
+}
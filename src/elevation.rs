@@ -0,0 +1,147 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::Path;
+use std::process::Command;
+use windows::Win32::Foundation::{CloseHandle, HANDLE};
+use windows::Win32::Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY};
+use windows::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+const HELPER_TASK_NAME: &str = "GMMK Pro Brightness Knob (elevated)";
+
+/// Check whether the current process is running with an elevated token. Low-level hooks installed by a non-elevated
+/// process don't receive input while an elevated window has focus, because of User Interface Privilege Isolation
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/winmsg/lowlevelkeyboardproc#remarks
+pub fn is_elevated() -> bool {
+  unsafe {
+    let mut token = HANDLE::default();
+    if !OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).as_bool() {
+      return false;
+    }
+
+    let mut elevation = TOKEN_ELEVATION::default();
+    let mut return_length = 0u32;
+    let succeeded = GetTokenInformation(
+      token,
+      TokenElevation,
+      Some(&mut elevation as *mut _ as *mut _),
+      mem::size_of::<TOKEN_ELEVATION>() as u32,
+      &mut return_length
+    ).as_bool();
+
+    CloseHandle(token);
+    succeeded && elevation.TokenIsElevated != 0
+  }
+}
+
+/// Register a Task Scheduler task that starts this executable at logon with the highest privileges available, so that
+/// the knob keeps working regardless of the integrity level of the foreground process. Creating a task with the highest
+/// run level requires the current process to be elevated too
+pub fn install_helper_task() -> Result<(), HelperTaskError> {
+  install_helper_task_for(&env::current_exe()?)
+}
+
+/// Register the Task Scheduler task for the given executable rather than the current one
+pub fn install_helper_task_for(exe_path: &Path) -> Result<(), HelperTaskError> {
+  if !is_elevated() {
+    return Err(HelperTaskError::NotElevated);
+  }
+
+  let user_id = match (env::var("USERDOMAIN"), env::var("USERNAME")) {
+    (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+    (_, Ok(user)) => user,
+    _ => return Err(HelperTaskError::IOError(io::Error::new(io::ErrorKind::NotFound, "unable to determine the current user")))
+  };
+
+  // schtasks.exe can't disable the default 72 hours execution time limit nor the battery conditions from the command
+  // line, so the task is defined through its XML schema instead. The file must be UTF-16 encoded
+  //
+  // Reference: https://learn.microsoft.com/en-us/windows/win32/taskschd/task-scheduler-schema
+  let task_xml = helper_task_xml(&user_id, &exe_path.to_string_lossy());
+  let xml_path = env::temp_dir().join("gmmk-pro-brightness-knob-task.xml");
+  let xml_bytes: Vec<u8> = std::iter::once(0xfeffu16)
+    .chain(task_xml.encode_utf16())
+    .flat_map(|c| c.to_le_bytes())
+    .collect();
+  fs::write(&xml_path, xml_bytes)?;
+
+  let result = run_schtasks(&["/Create", "/TN", HELPER_TASK_NAME, "/XML", &xml_path.to_string_lossy(), "/F"]);
+  let _ = fs::remove_file(&xml_path);
+  result
+}
+
+/// Remove the Task Scheduler task previously registered by `install_helper_task`
+pub fn uninstall_helper_task() -> Result<(), HelperTaskError> {
+  if !is_elevated() {
+    return Err(HelperTaskError::NotElevated);
+  }
+
+  run_schtasks(&["/Delete", "/TN", HELPER_TASK_NAME, "/F"])
+}
+
+/// Check whether the Task Scheduler task is registered, which doesn't require elevation
+pub fn has_helper_task() -> bool {
+  run_schtasks(&["/Query", "/TN", HELPER_TASK_NAME]).is_ok()
+}
+
+fn run_schtasks(args: &[&str]) -> Result<(), HelperTaskError> {
+  let output = Command::new("schtasks.exe").args(args).output()?;
+  match output.status.success() {
+    true => Ok(()),
+    false => Err(HelperTaskError::SchtasksError(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+  }
+}
+
+fn helper_task_xml(user_id: &str, exe_path: &str) -> String {
+  format!(r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>Captures the GMMK PRO knob events even while elevated applications have focus</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <LogonTrigger>
+      <Enabled>true</Enabled>
+      <UserId>{user_id}</UserId>
+    </LogonTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{user_id}</UserId>
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>HighestAvailable</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <Priority>4</Priority>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{exe_path}</Command>
+    </Exec>
+  </Actions>
+</Task>
+"#, user_id = xml_escape(user_id), exe_path = xml_escape(exe_path))
+}
+
+fn xml_escape(value: &str) -> String {
+  value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[derive(Debug)]
+pub enum HelperTaskError {
+  NotElevated,
+  IOError(io::Error),
+  SchtasksError(String)
+}
+
+impl From<io::Error> for HelperTaskError {
+  fn from(value: io::Error) -> Self {
+    HelperTaskError::IOError(value)
+  }
+}
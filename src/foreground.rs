@@ -0,0 +1,154 @@
+use serde::Deserialize;
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::path::Path;
+use windows::core::PWSTR;
+use windows::Win32::Foundation::{CloseHandle, HWND, POINT, RECT};
+use windows::Win32::Graphics::Dwm::{DwmGetWindowAttribute, DWMWA_CLOAKED};
+use windows::Win32::Graphics::Gdi::{GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITOR_DEFAULTTONULL};
+use windows::Win32::System::Threading::{OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION};
+use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_RUNNING_D3D_FULL_SCREEN};
+use windows::Win32::UI::WindowsAndMessaging::{
+  GetAncestor, GetClassNameW, GetCursorPos, GetDesktopWindow, GetShellWindow, GetWindowRect, GetWindowThreadProcessId, WindowFromPoint, GA_ROOT
+};
+
+// Window classes of the desktop background, which covers the whole monitor without being fullscreen in any way
+const DESKTOP_WINDOW_CLASSES: [&str; 2] = ["Progman", "WorkerW"];
+// Window classes of the taskbar of the primary monitor and of the other ones
+const TASKBAR_WINDOW_CLASSES: [&str; 2] = ["Shell_TrayWnd", "Shell_SecondaryTrayWnd"];
+
+/// Represent how a window occupies the monitor it's on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FullscreenKind {
+  /// The window leaves at least part of the monitor visible, or isn't visible at all
+  Windowed,
+  /// The window covers the whole monitor while still being composed by DWM, so that overlays such as the Game Bar keep
+  /// working on top of it
+  Borderless,
+  /// The window owns the display through Direct3D exclusive fullscreen
+  Exclusive
+}
+
+/// Represent a part of the screen where the mouse wheel can be turned into knob adjustments, where scrolling is of no
+/// use anyway
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScreenRegion {
+  Taskbar,
+  /// The desktop background, between the icons as well as on them
+  Desktop
+}
+
+/// Get the region of the screen the mouse cursor is over, if it's any of them
+pub fn region_under_cursor() -> Option<ScreenRegion> {
+  unsafe {
+    let mut cursor = POINT::default();
+    if !GetCursorPos(&mut cursor).as_bool() {
+      return None;
+    }
+    let hwnd = GetAncestor(WindowFromPoint(cursor), GA_ROOT);
+    if hwnd.0 == 0 {
+      return None;
+    }
+    match window_class_name(hwnd) {
+      class_name if TASKBAR_WINDOW_CLASSES.contains(&class_name.as_str()) => Some(ScreenRegion::Taskbar),
+      _ if is_desktop_window(hwnd) => Some(ScreenRegion::Desktop),
+      _ => None
+    }
+  }
+}
+
+/// Get the executable file name (e.g. "blender.exe") of the process that owns the given window
+pub fn window_process_name(hwnd: HWND) -> Option<String> {
+  unsafe {
+    let mut process_id = 0u32;
+    GetWindowThreadProcessId(hwnd, Some(&mut process_id));
+    if process_id == 0 {
+      return None;
+    }
+
+    // The limited query right is enough for the image name, and it's granted even for most elevated processes
+    let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id).ok()?;
+    let mut path = [0u16; 1024];
+    let mut path_length = path.len() as u32;
+    let succeeded = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(path.as_mut_ptr()), &mut path_length).as_bool();
+    CloseHandle(process);
+
+    if !succeeded {
+      return None;
+    }
+
+    let path = String::from_utf16_lossy(&path[..path_length as usize]);
+    Path::new(&path).file_name().map(|name| name.to_string_lossy().to_string())
+  }
+}
+
+/// Tell whether the given window is a borderless-windowed or exclusive fullscreen one. `overrides` maps lowercase
+/// executable names to the kind to report for their windows, for the applications the heuristics get wrong
+pub fn fullscreen_kind(hwnd: HWND, overrides: &[(String, FullscreenKind)]) -> FullscreenKind {
+  if hwnd.0 == 0 {
+    return FullscreenKind::Windowed;
+  }
+
+  if !overrides.is_empty() {
+    let process_name = window_process_name(hwnd).map(|name| name.to_lowercase());
+    let kind = overrides.iter().find(|(name, _)| process_name.as_ref() == Some(name)).map(|(_, kind)| *kind);
+    if let Some(kind) = kind {
+      return kind;
+    }
+  }
+
+  // Cloaked windows, such as suspended UWP apps or the ones on another virtual desktop, can still cover the monitor
+  // while being invisible
+  if is_cloaked(hwnd) || is_desktop_window(hwnd) || !covers_monitor(hwnd) {
+    return FullscreenKind::Windowed;
+  }
+
+  // The shell only knows about the foreground application, which is the window that covers the monitor at this point
+  //
+  // Reference: https://learn.microsoft.com/en-us/windows/win32/api/shellapi/nf-shellapi-shqueryusernotificationstate
+  match unsafe { SHQueryUserNotificationState() } {
+    Ok(state) if state == QUNS_RUNNING_D3D_FULL_SCREEN => FullscreenKind::Exclusive,
+    _ => FullscreenKind::Borderless
+  }
+}
+
+fn is_cloaked(hwnd: HWND) -> bool {
+  let mut cloaked = 0u32;
+  let result = unsafe { DwmGetWindowAttribute(hwnd, DWMWA_CLOAKED, &mut cloaked as *mut u32 as *mut c_void, size_of::<u32>() as u32) };
+  result.is_ok() && cloaked != 0
+}
+
+fn is_desktop_window(hwnd: HWND) -> bool {
+  if unsafe { hwnd == GetDesktopWindow() || hwnd == GetShellWindow() } {
+    return true;
+  }
+
+  DESKTOP_WINDOW_CLASSES.contains(&window_class_name(hwnd).as_str())
+}
+
+fn window_class_name(hwnd: HWND) -> String {
+  let mut class_name = [0u16; 256];
+  let class_name_length = unsafe { GetClassNameW(hwnd, &mut class_name) };
+  String::from_utf16_lossy(&class_name[..class_name_length.max(0) as usize])
+}
+
+fn covers_monitor(hwnd: HWND) -> bool {
+  unsafe {
+    let hmonitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONULL);
+    if hmonitor.is_invalid() {
+      return false;
+    }
+
+    let mut monitor_info = MONITORINFO { cbSize: size_of::<MONITORINFO>() as u32, ..Default::default() };
+    let mut window_rect = RECT::default();
+    if !GetMonitorInfoW(hmonitor, &mut monitor_info).as_bool() || !GetWindowRect(hwnd, &mut window_rect).as_bool() {
+      return false;
+    }
+
+    let monitor_rect = monitor_info.rcMonitor;
+    window_rect.left <= monitor_rect.left && window_rect.top <= monitor_rect.top
+      && window_rect.right >= monitor_rect.right && window_rect.bottom >= monitor_rect.bottom
+  }
+}
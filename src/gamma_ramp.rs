@@ -0,0 +1,137 @@
+use crate::monitor::{BRIGHTNESS_VCP_CODE, BrightnessBackend, display_device_name, from_wide};
+
+use ddc::{FeatureCode, VcpValue};
+use std::ffi::c_void;
+use std::io;
+use windows::core::PCWSTR;
+use windows::Win32::Graphics::Gdi::{CreateDCW, DeleteDC, CreatedHDC, HMONITOR};
+use windows::Win32::UI::ColorSystem::SetDeviceGammaRamp;
+
+/// Entries of the ramp of each of the red, green and blue channels
+const RAMP_LENGTH: usize = 256;
+/// Step by which the ramp gets brighter when Windows refused it, until it's accepted
+const REFUSED_LEVEL_STEP: u16 = 5;
+
+/// Dim a display by scaling down the gamma ramp of its graphics adapter, which works with every display but only dims
+/// the picture rather than the backlight. Windows refuses the ramps that are too far from the identity one, so the
+/// darkest levels might not be reached unless "GdiIcmGammaRange" is set to 256 in the ICM registry key. The identity
+/// ramp is set back once dropped
+pub struct GammaRamp {
+  device_context: CreatedHDC,
+  device_name: String,
+  /// Level asked for last, from 0 to 100, which is what's reported back even when Windows refused to go that low
+  level: u16
+}
+
+// The device context is not tied to the thread that created it, and is only used by one of them at a time
+unsafe impl Send for GammaRamp {}
+
+impl GammaRamp {
+  /// Open the gamma ramp of the given display, which is left as it is until a level is set
+  pub fn open(hmonitor_handle: HMONITOR) -> io::Result<Self> {
+    let device_name = display_device_name(hmonitor_handle).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the display has no device name"))?;
+    let device_context = unsafe { CreateDCW(PCWSTR(device_name.as_ptr()), PCWSTR(device_name.as_ptr()), PCWSTR::null(), None) };
+    if device_context.is_invalid() {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(Self { device_context, device_name: from_wide(&device_name), level: 100 })
+  }
+
+  pub fn level(&self) -> u16 {
+    self.level
+  }
+
+  /// Scale the gamma ramp down to the given level, from 0 to 100, or to the darkest one Windows accepts above it
+  pub fn set_level(&mut self, level: u16) -> io::Result<()> {
+    let level = level.min(100);
+    let mut applied_level = level;
+    while !self.apply(applied_level) {
+      if applied_level == 100 {
+        return Err(io::Error::last_os_error());
+      }
+      applied_level = (applied_level + REFUSED_LEVEL_STEP).min(100);
+    }
+    self.level = level;
+    Ok(())
+  }
+
+  fn apply(&self, level: u16) -> bool {
+    let mut ramp = [[0u16; RAMP_LENGTH]; 3];
+    for (index, entry) in ramp[0].iter_mut().enumerate() {
+      *entry = (index as u32 * 0x101 * level as u32 / 100) as u16;
+    }
+    ramp[1] = ramp[0];
+    ramp[2] = ramp[0];
+    unsafe { SetDeviceGammaRamp(self.device_context, ramp.as_ptr() as *const c_void) }.as_bool()
+  }
+}
+
+impl BrightnessBackend for GammaRamp {
+  fn name(&self) -> String {
+    format!("{} (software dimming)", self.device_name)
+  }
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue> {
+    match code {
+      BRIGHTNESS_VCP_CODE => Ok(VcpValue { ml: 100, ..VcpValue::from_value(self.level) }),
+      _ => Err(unsupported_gamma_feature(code))
+    }
+  }
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    match code {
+      BRIGHTNESS_VCP_CODE => self.set_level(value),
+      _ => Err(unsupported_gamma_feature(code))
+    }
+  }
+}
+
+impl Drop for GammaRamp {
+  fn drop(&mut self) {
+    if self.level != 100 {
+      self.apply(100);
+    }
+    unsafe { DeleteDC(self.device_context); }
+  }
+}
+
+/// Extend the range of the brightness of a monitor below the lowest one of its backlight, by dimming its picture once
+/// the backlight is at its lowest. The given share of the range, from 0, is taken by the dimming, and the backlight is
+/// stretched over the rest of it
+pub struct SoftwareDimming {
+  gamma_ramp: GammaRamp,
+  share: u16
+}
+
+impl SoftwareDimming {
+  pub fn new(gamma_ramp: GammaRamp, share: u16) -> Self {
+    Self { gamma_ramp, share: share.clamp(1, 99) }
+  }
+
+  /// Set the picture to the level the given brightness, from 0 to 100, calls for, returning the brightness to set the
+  /// backlight to
+  pub fn set_brightness(&mut self, value: u16) -> io::Result<u16> {
+    match value < self.share {
+      true => {
+        self.gamma_ramp.set_level(((value as u32 * 100 + self.share as u32 / 2) / self.share as u32) as u16)?;
+        Ok(0)
+      },
+      false => {
+        self.gamma_ramp.set_level(100)?;
+        Ok((((value.min(100) - self.share) as u32 * 100 + (100 - self.share) as u32 / 2) / (100 - self.share) as u32) as u16)
+      }
+    }
+  }
+
+  /// Get the brightness, from 0 to 100, given the one of the backlight
+  pub fn brightness(&self, backlight_value: u16) -> u16 {
+    match backlight_value == 0 && self.gamma_ramp.level() < 100 {
+      true => ((self.gamma_ramp.level() as u32 * self.share as u32 + 50) / 100) as u16,
+      false => self.share + ((backlight_value.min(100) as u32 * (100 - self.share) as u32 + 50) / 100) as u16
+    }
+  }
+}
+
+fn unsupported_gamma_feature(code: FeatureCode) -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, format!("software dimming has no VCP feature 0x{:02x}", code))
+}
@@ -0,0 +1,71 @@
+use crate::keyboard_knob::{KnobAction, KnobAdjustmentEvent};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Number of notches making up a wiggle, which are one way, back, then the first way again
+const WIGGLE_NOTCHES: usize = 3;
+
+/// Recognize a quick back-and-forth wiggle of the knob, which switches between fine and coarse steps. Coarse steps are
+/// kept until the knob has been left alone for the timeout, or until it's wiggled once more
+#[derive(Debug)]
+pub struct StepGesture {
+  coarse_step_size: Option<i32>,
+  window: Duration,
+  timeout: Duration,
+  /// Last notches, along with their direction and the target brightness before them
+  notches: VecDeque<(Instant, i32, i32)>,
+  /// When the last notch of a coarse turn came
+  coarse_since: Option<Instant>
+}
+
+impl StepGesture {
+  /// The gesture is disabled when there's no coarse step size
+  pub fn new(coarse_step_size: Option<i32>, window: Duration, timeout: Duration) -> Self {
+    Self { coarse_step_size, window, timeout, notches: VecDeque::new(), coarse_since: None }
+  }
+
+  /// Feed a knob event along with the brightness targeted before it. Returns the brightness targeted before the wiggle
+  /// it completed, if any, for the notches of the wiggle to not move the brightness
+  pub fn notch(&mut self, event: &KnobAdjustmentEvent, prev_target: i32) -> Option<i32> {
+    self.coarse_step_size?;
+    let direction = match event.action {
+      KnobAction::Increment => 1,
+      KnobAction::Decrement => -1,
+      _ => return None
+    };
+    if self.is_coarse(event.timestamp) {
+      self.coarse_since = Some(event.timestamp);
+    }
+
+    self.notches.retain(|(timestamp, _, _)| event.timestamp.saturating_duration_since(*timestamp) < self.window);
+    self.notches.push_back((event.timestamp, direction, prev_target));
+    if self.notches.len() > WIGGLE_NOTCHES {
+      self.notches.pop_front();
+    }
+    let is_wiggle = self.notches.len() == WIGGLE_NOTCHES && self.notches.iter().zip(self.notches.iter().skip(1)).all(|(a, b)| a.1 != b.1);
+    if !is_wiggle {
+      return None;
+    }
+
+    let (_, _, start_target) = self.notches[0];
+    self.notches.clear();
+    self.coarse_since = match self.is_coarse(event.timestamp) {
+      true => None,
+      false => Some(event.timestamp)
+    };
+    Some(start_target)
+  }
+
+  /// Get the step size of the knob at the given time, which is the coarse one while it's toggled on
+  pub fn step_size(&self, fine_step_size: i32, timestamp: Instant) -> i32 {
+    match self.coarse_step_size {
+      Some(coarse_step_size) if self.is_coarse(timestamp) => coarse_step_size,
+      _ => fine_step_size
+    }
+  }
+
+  pub fn is_coarse(&self, timestamp: Instant) -> bool {
+    self.coarse_since.is_some_and(|since| timestamp.saturating_duration_since(since) < self.timeout)
+  }
+}
@@ -0,0 +1,247 @@
+use crate::keyboard_knob::{EventSource, KnobAction, KnobAdjustmentEvent, forward_event};
+use crate::shutdown::{StopSignal, forward_stop_to_message_loop};
+use crate::state::StateSnapshot;
+use crate::system_events::{
+  HID_DEVICE_CHANGE_MSG, create_notification_window, destroy_notification_window, register_hid_notifications,
+  unregister_device_notifications
+};
+use crate::watch::WatchReceiver;
+
+use crossbeam_channel::Sender;
+use hidapi::{HidApi, HidDevice, HidError};
+use std::ffi::CString;
+use std::io;
+use std::ptr;
+use std::thread;
+use tracing::{error, info};
+use windows::Win32::Foundation::{HWND, LPARAM, WPARAM};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, PostThreadMessageW, MSG};
+
+/// Usage page and usage of the raw HID interface of QMK, which is the one VIA talks to as well
+///
+/// Reference: https://docs.qmk.fm/features/rawhid
+const RAW_HID_USAGE_PAGE: u16 = 0xff60;
+const RAW_HID_USAGE: u16 = 0x61;
+/// Length of the reports of the raw HID interface, not counting the report ID
+const REPORT_LENGTH: usize = 32;
+/// Commands of the messages exchanged with the keymap, as their first byte. They are past the ones of VIA, for VIA to
+/// hand them over to `raw_hid_receive_kb` rather than answering them itself
+const KNOB_TURNED_COMMAND: u8 = 0x80;
+const KNOB_PRESSED_COMMAND: u8 = 0x81;
+const BRIGHTNESS_COMMAND: u8 = 0x82;
+/// Command of VIA setting a value of one of the lighting channels, which are the RGB matrix and its brightness here. The
+/// value is left unsaved, for the EEPROM not to be written on every turn of the knob
+///
+/// Reference: https://github.com/qmk/qmk_firmware/blob/master/quantum/via.h
+const VIA_CUSTOM_SET_VALUE_COMMAND: u8 = 0x07;
+const VIA_RGB_MATRIX_CHANNEL: u8 = 0x03;
+const VIA_RGB_MATRIX_BRIGHTNESS: u8 = 0x01;
+/// Application-defined message posted to the raw HID thread when the brightness changed
+const RAW_HID_STATE_MSG: u32 = 0x0516;
+/// Application-defined message posted to the raw HID thread when the reads from the keyboard failed, WPARAM holding the
+/// number of the connection they were made through for the ones of a connection already replaced to be told apart
+const RAW_HID_LOST_MSG: u32 = 0x0518;
+
+/// Channel to the firmware of a keyboard running QMK, through its raw HID interface. The keymap reports the knob with
+/// the messages below, without the knob having to be mapped to any key, and is sent the brightness whenever it changes:
+///
+/// - `[0x80, notches]` when the knob is turned, the notches being a signed byte that's positive when turned up
+/// - `[0x81]` when the knob is pressed
+/// - `[0x82, brightness]` sent to the keyboard, with the brightness from 0 to 100
+///
+/// The brightness of the RGB matrix of the keyboard can be made to follow the one of the monitors too, through VIA,
+/// which needs nothing from the keymap
+pub struct RawHidChannel {
+  hid_api: HidApi,
+  device: HidDevice,
+  path: CString
+}
+
+impl RawHidChannel {
+  /// Open the raw HID interface of the first connected keyboard with one of the given vendor and product IDs
+  pub fn open(keyboard_ids: &[(u16, u16)]) -> io::Result<Self> {
+    let hid_api = HidApi::new().map_err(hid_to_io_error)?;
+    let device_info = hid_api
+      .device_list()
+      .find(|device_info| {
+        keyboard_ids.contains(&(device_info.vendor_id(), device_info.product_id()))
+          && device_info.usage_page() == RAW_HID_USAGE_PAGE
+          && device_info.usage() == RAW_HID_USAGE
+      })
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no keyboard with a raw HID interface is connected"))?;
+    let device = device_info.open_device(&hid_api).map_err(hid_to_io_error)?;
+    let path = device_info.path().to_owned();
+    Ok(Self { hid_api, device, path })
+  }
+
+  /// Open the same interface a second time, for the reads to block on a thread of their own while the brightness is
+  /// written from this one
+  pub fn open_reader(&self) -> io::Result<RawHidReader> {
+    let device = self.hid_api.open_path(&self.path).map_err(hid_to_io_error)?;
+    Ok(RawHidReader { device })
+  }
+
+  /// Send the brightness to the keyboard, from 0 to 100
+  pub fn send_brightness(&self, value: u16) -> io::Result<()> {
+    self.send(&[BRIGHTNESS_COMMAND, value.min(100) as u8])
+  }
+
+  /// Set the brightness of the RGB matrix of the keyboard through VIA, from 0 to 100
+  pub fn set_backlight_brightness(&self, value: u16) -> io::Result<()> {
+    let level = (value.min(100) as u32 * 255 + 50) / 100;
+    self.send(&[VIA_CUSTOM_SET_VALUE_COMMAND, VIA_RGB_MATRIX_CHANNEL, VIA_RGB_MATRIX_BRIGHTNESS, level as u8])
+  }
+
+  /// Send the brightness of the monitors to the keyboard, mirroring it onto the backlight when asked to
+  fn send_state(&self, brightness: u16, settings: &RawHidSettings) -> io::Result<()> {
+    self.send_brightness(brightness)?;
+    match settings.backlight_min {
+      Some(backlight_min) => self.set_backlight_brightness(backlight_min + brightness * (100 - backlight_min.min(100)) / 100),
+      None => Ok(())
+    }
+  }
+
+  fn send(&self, message: &[u8]) -> io::Result<()> {
+    // The report ID comes first, which is always 0 given that the interface has a single report
+    let mut report = [0u8; REPORT_LENGTH + 1];
+    report[1..1 + message.len()].copy_from_slice(message);
+    self.device.write(&report).map(|_| ()).map_err(hid_to_io_error)
+  }
+}
+
+/// Reading end of the channel to the keyboard
+pub struct RawHidReader {
+  device: HidDevice
+}
+
+impl RawHidReader {
+  /// Wait for a message from the keyboard, for as long as it takes, returning the knob actions it carries. The wait only
+  /// ends early when the keyboard is disconnected
+  pub fn read_actions(&self) -> io::Result<Vec<KnobAction>> {
+    let mut report = [0u8; REPORT_LENGTH];
+    let length = self.device.read(&mut report).map_err(hid_to_io_error)?;
+    let actions = match report[..length] {
+      [KNOB_TURNED_COMMAND, notches, ..] => {
+        let notches = notches as i8;
+        let action = if notches > 0 { KnobAction::Increment } else { KnobAction::Decrement };
+        vec![action; notches.unsigned_abs() as usize]
+      },
+      [KNOB_PRESSED_COMMAND, ..] => vec![KnobAction::Press],
+      _ => Vec::new()
+    };
+    Ok(actions)
+  }
+}
+
+/// Settings of the channel to the keyboard
+#[derive(Debug, Clone)]
+pub struct RawHidSettings {
+  /// Vendor and product IDs of the keyboards with a knob
+  pub keyboard_ids: Vec<(u16, u16)>,
+  /// Brightness of the backlight, from 0 to 100, while the monitors are at their lowest. The backlight
+  /// is left alone when unset
+  pub backlight_min: Option<u16>
+}
+
+/// Forward the knob adjustments reported by the keyboard over raw HID, and send it the brightness as it changes, until
+/// the stop signal is received. The thread sleeps in a message loop, woken up by the changes of the brightness and by
+/// the arrival of HID devices, which is when the keyboard is looked for again after being disconnected. The reports are
+/// read on a thread of their own, which is left blocked on the read on exit given that nothing can cancel it
+pub fn run_raw_hid(stop_rx: StopSignal, events_tx: Sender<KnobAdjustmentEvent>, mut state_rx: WatchReceiver<Option<StateSnapshot>>, settings: RawHidSettings) {
+  unsafe {
+    let thread_id = GetCurrentThreadId();
+    let notification_hwnd = create_notification_window()
+      .map_err(|e| error!("unable to watch for the keyboard to connect, it's only looked for on startup - {}", e))
+      .ok();
+    let hid_notifications = notification_hwnd.map_or(ptr::null_mut(), register_hid_notifications);
+    state_rx.on_change(move || {
+      PostThreadMessageW(thread_id, RAW_HID_STATE_MSG, WPARAM(0), LPARAM(0));
+    });
+
+    // Make GetMessageW return as soon as the stop signal is received
+    forward_stop_to_message_loop(stop_rx);
+
+    let mut connection = 0;
+    let mut channel = connect(&events_tx, &mut state_rx, &settings, thread_id, connection)
+      .map_err(|e| info!("waiting for the keyboard to connect over raw HID - {}", e))
+      .ok();
+    let mut msg: MSG = Default::default();
+    while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+      match msg.message {
+        RAW_HID_STATE_MSG => {
+          let Some(open_channel) = &channel else { continue };
+          let Some(brightness) = state_rx.changed().flatten().and_then(|snapshot| snapshot.brightness) else { continue };
+          if let Err(e) = open_channel.send_state(brightness, &settings) {
+            error!("lost the raw HID connection to the keyboard - {}", e);
+            channel = None;
+            connection += 1;
+          }
+        },
+        // Keyboards expose several HID interfaces, each of them being notified on its own, so the raw HID one may not
+        // be there yet when the first of them arrives
+        HID_DEVICE_CHANGE_MSG if channel.is_none() && msg.wParam.0 != 0 => {
+          let ids = ((msg.lParam.0 >> 16) as u16, msg.lParam.0 as u16);
+          if settings.keyboard_ids.contains(&ids) {
+            connection += 1;
+            channel = connect(&events_tx, &mut state_rx, &settings, thread_id, connection).ok();
+          }
+        },
+        // The keyboard may have been connected again before its removal showed up, in which case no arrival is coming
+        RAW_HID_LOST_MSG if msg.wParam.0 == connection => {
+          connection += 1;
+          channel = connect(&events_tx, &mut state_rx, &settings, thread_id, connection)
+            .map_err(|e| info!("waiting for the keyboard to reconnect over raw HID - {}", e))
+            .ok();
+        },
+        _ => {}
+      }
+    }
+
+    unregister_device_notifications(hid_notifications);
+    if let Some(notification_hwnd) = notification_hwnd {
+      destroy_notification_window(notification_hwnd);
+    }
+  }
+}
+
+/// Open the channel to the keyboard and start reading the reports it sends, then send it the brightness, which it
+/// starts off without whatever it was sent before being disconnected
+fn connect(events_tx: &Sender<KnobAdjustmentEvent>, state_rx: &mut WatchReceiver<Option<StateSnapshot>>, settings: &RawHidSettings, thread_id: u32, connection: usize) -> io::Result<RawHidChannel> {
+  let channel = RawHidChannel::open(&settings.keyboard_ids)?;
+  spawn_reader(channel.open_reader()?, events_tx.clone(), thread_id, connection);
+  info!("talking to the keyboard over raw HID");
+  if let Some(brightness) = state_rx.latest().and_then(|snapshot| snapshot.brightness) {
+    let _ = channel.send_state(brightness, settings);
+  }
+  Ok(channel)
+}
+
+/// Forward the knob adjustments read from the keyboard until the reads fail, telling the raw HID thread when they do
+fn spawn_reader(reader: RawHidReader, events_tx: Sender<KnobAdjustmentEvent>, thread_id: u32, connection: usize) {
+  thread::spawn(move || loop {
+    match reader.read_actions() {
+      Ok(actions) => {
+        for action in actions {
+          // Nothing reads the events anymore, the program is exiting
+          if forward_event(&events_tx, KnobAdjustmentEvent::new(action, EventSource::Hid)).is_err() {
+            return;
+          }
+        }
+      },
+      Err(e) => {
+        error!("lost the raw HID connection to the keyboard - {}", e);
+        unsafe { PostThreadMessageW(thread_id, RAW_HID_LOST_MSG, WPARAM(connection), LPARAM(0)); }
+        return;
+      }
+    }
+  });
+}
+
+/// Convert HID errors to IO errors, keeping the OS error codes
+fn hid_to_io_error(err: HidError) -> io::Error {
+  match err {
+    HidError::IoError { error } => error,
+    err => io::Error::other(err.to_string())
+  }
+}
@@ -0,0 +1,131 @@
+use crate::raw_keyboard::raw_input_type;
+
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use windows::Win32::Foundation::{HWND, LPARAM};
+use windows::Win32::UI::Input::{RegisterRawInputDevices, RAWINPUTDEVICE, RIDEV_INPUTSINK, RIDEV_REMOVE, RIM_TYPEKEYBOARD, RIM_TYPEMOUSE};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+  SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYEVENTF_KEYUP, MOUSEEVENTF_MOVE, MOUSEINPUT, VIRTUAL_KEY
+};
+
+// Usages of the Generic Desktop Page, as defined by the HID Usage Tables specification
+const GENERIC_DESKTOP_USAGE_PAGE: u16 = 0x01;
+const MOUSE_USAGE: u16 = 0x02;
+const KEYBOARD_USAGE: u16 = 0x06;
+/// Extra information the probes are injected with, for the hooks to recognize them among the other events
+const PROBE_MARKER: usize = 0x4b4e_4f42;
+/// Unassigned virtual-key code the keyboard probe is released with, which does nothing even when it gets through
+const PROBE_KEY: VIRTUAL_KEY = VIRTUAL_KEY(0xe8);
+/// How much later than the last call to the hook some input has to come for the hook to be suspected of being gone,
+/// given that both times don't come from the exact same clock reading
+const INPUT_SLACK_MS: u32 = 1000;
+
+// Written by the hooks, which have no other way to reach the watchdog
+static HOOK_CALLED_AT: AtomicU32 = AtomicU32::new(0);
+static PROBE_SEEN: AtomicBool = AtomicBool::new(false);
+
+/// Represent which of the low-level hooks is watched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookKind {
+  Keyboard,
+  Mouse
+}
+
+impl HookKind {
+  pub fn name(self) -> &'static str {
+    match self {
+      HookKind::Keyboard => "keyboard",
+      HookKind::Mouse => "mouse"
+    }
+  }
+
+  fn usage(self) -> u16 {
+    match self {
+      HookKind::Keyboard => KEYBOARD_USAGE,
+      HookKind::Mouse => MOUSE_USAGE
+    }
+  }
+
+  fn raw_input_type(self) -> u32 {
+    match self {
+      HookKind::Keyboard => RIM_TYPEKEYBOARD.0,
+      HookKind::Mouse => RIM_TYPEMOUSE.0
+    }
+  }
+}
+
+/// Record a call to the hook, at the time of the event it was called for, telling whether that event is a probe. The
+/// probes are meant to be swallowed by the hook, for no other application to see them
+pub fn record_hook_call(event_time: u32, extra_info: usize) -> bool {
+  HOOK_CALLED_AT.store(event_time, Ordering::Relaxed);
+  let is_probe = extra_info == PROBE_MARKER;
+  if is_probe {
+    PROBE_SEEN.store(true, Ordering::Relaxed);
+  }
+  is_probe
+}
+
+/// Tell whether the low-level hook is still installed. Windows silently removes the hooks that take longer than the
+/// LowLevelHooksTimeout to return, which nothing reports. The Raw Input of the devices of the same kind as the hook is
+/// received alongside it, which Windows only generates once the hook was called for the same input, and the hook is
+/// suspected of being gone when some comes in that it wasn't called for. That's confirmed by injecting an event that
+/// does nothing and checking whether the hook saw it. Nothing is checked, and no timer runs, while no input comes in
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/winmsg/lowlevelkeyboardproc#remarks
+pub struct HookWatchdog {
+  kind: HookKind,
+  probing: bool
+}
+
+impl HookWatchdog {
+  /// Receive the Raw Input of the devices of the given kind as WM_INPUT messages posted to the given window
+  pub fn register(kind: HookKind, hwnd: HWND) -> windows::core::Result<Self> {
+    let device = RAWINPUTDEVICE { usUsagePage: GENERIC_DESKTOP_USAGE_PAGE, usUsage: kind.usage(), dwFlags: RIDEV_INPUTSINK, hwndTarget: hwnd };
+    match unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32) }.as_bool() {
+      true => Ok(Self { kind, probing: false }),
+      false => Err(windows::core::Error::from_win32())
+    }
+  }
+
+  pub fn unregister(self) {
+    let device = RAWINPUTDEVICE { usUsagePage: GENERIC_DESKTOP_USAGE_PAGE, usUsage: self.kind.usage(), dwFlags: RIDEV_REMOVE, hwndTarget: HWND(0) };
+    unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32); }
+  }
+
+  /// Look at the input carried by a WM_INPUT message received at the given time, injecting a probe when the hook
+  /// wasn't called for it. Returns whether a probe was injected, in which case `probe_seen` is called once the hook had
+  /// the time to see it
+  pub fn observe_input(&mut self, l_param: LPARAM, received_at: u32) -> bool {
+    if self.probing || raw_input_type(l_param) != Some(self.kind.raw_input_type()) {
+      return false;
+    }
+    // The tick count wraps around every 49.7 days, which the difference being taken as signed copes with
+    let missed_input = received_at.wrapping_sub(HOOK_CALLED_AT.load(Ordering::Relaxed)) as i32 > INPUT_SLACK_MS as i32;
+    if missed_input {
+      PROBE_SEEN.store(false, Ordering::Relaxed);
+      self.probing = send_probe(self.kind);
+    }
+    self.probing
+  }
+
+  /// Tell whether the hook saw the probe injected last, which it doesn't once it's gone
+  pub fn probe_seen(&mut self) -> bool {
+    self.probing = false;
+    PROBE_SEEN.load(Ordering::Relaxed)
+  }
+}
+
+/// Inject the release of an unassigned key or a mouse movement by nothing, depending on the hook
+fn send_probe(kind: HookKind) -> bool {
+  let input = match kind {
+    HookKind::Keyboard => INPUT {
+      r#type: INPUT_KEYBOARD,
+      Anonymous: INPUT_0 { ki: KEYBDINPUT { wVk: PROBE_KEY, dwFlags: KEYEVENTF_KEYUP, dwExtraInfo: PROBE_MARKER, ..Default::default() } }
+    },
+    HookKind::Mouse => INPUT {
+      r#type: INPUT_MOUSE,
+      Anonymous: INPUT_0 { mi: MOUSEINPUT { dwFlags: MOUSEEVENTF_MOVE, dwExtraInfo: PROBE_MARKER, ..Default::default() } }
+    }
+  };
+  unsafe { SendInput(&[input], size_of::<INPUT>() as i32) == 1 }
+}
@@ -0,0 +1,154 @@
+use crate::config;
+use crate::elevation::{HelperTaskError, has_helper_task, install_helper_task_for, is_elevated, uninstall_helper_task};
+use crate::paths::{config_dir, data_dir, install_dir};
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const RUN_KEY: &str = r"HKCU\Software\Microsoft\Windows\CurrentVersion\Run";
+const RUN_VALUE_NAME: &str = "GMMK Pro Brightness Knob";
+
+/// Represent how the installed executable is started at logon
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Autostart {
+  /// Through the Run key of the current user, which starts it with a regular token
+  RunKey,
+  /// Through the elevated helper task, when installing from an elevated prompt
+  HelperTask
+}
+
+/// Outcome of a successful installation
+#[derive(Debug, Clone)]
+pub struct Installation {
+  pub exe_path: PathBuf,
+  pub config_path: PathBuf,
+  pub autostart: Autostart
+}
+
+/// Copy the current executable to the install directory and start it at logon from there, so that it keeps working
+/// regardless of where it was downloaded to, then write the default config file unless there's one already
+pub fn install() -> Result<Installation, InstallError> {
+  let current_exe = env::current_exe()?;
+  let dir = install_dir()?;
+  let installed_exe = dir.join(current_exe.file_name().unwrap_or_default());
+  fs::create_dir_all(&dir)?;
+  if !is_running_from(&dir)? {
+    fs::copy(&current_exe, &installed_exe)?;
+  }
+
+  let autostart = register_autostart(&installed_exe)?;
+  let config_path = config::write_default()?;
+  Ok(Installation { exe_path: installed_exe, config_path, autostart })
+}
+
+/// Start the current executable at logon from where it is, without installing it anywhere
+pub fn enable_autostart() -> Result<Autostart, InstallError> {
+  register_autostart(&env::current_exe()?)
+}
+
+/// Stop starting the program at logon, however it was registered
+pub fn disable_autostart() -> Result<(), InstallError> {
+  if has_helper_task() {
+    uninstall_helper_task()?;
+  }
+  remove_run_key()
+}
+
+/// Tell how the program is started at logon, if it is
+pub fn autostart_status() -> Option<Autostart> {
+  match (has_helper_task(), has_run_key()) {
+    (true, _) => Some(Autostart::HelperTask),
+    (false, true) => Some(Autostart::RunKey),
+    (false, false) => None
+  }
+}
+
+fn register_autostart(exe_path: &Path) -> Result<Autostart, InstallError> {
+  // Starting at logon twice would run two instances fighting over the knob, so only one of the two is ever registered
+  match is_elevated() {
+    true => {
+      install_helper_task_for(exe_path)?;
+      remove_run_key()?;
+      Ok(Autostart::HelperTask)
+    },
+    false => {
+      let command = format!("\"{}\"", exe_path.display());
+      run_reg(&["add", RUN_KEY, "/v", RUN_VALUE_NAME, "/t", "REG_SZ", "/d", &command, "/f"])?;
+      Ok(Autostart::RunKey)
+    }
+  }
+}
+
+/// Undo everything done by `install`, including the config file, and remove the data stored at runtime as well. The install directory is removed
+/// shortly after this process exits when it's the installed executable that is running, since Windows doesn't allow
+/// deleting an executable while it runs
+pub fn uninstall() -> Result<(), InstallError> {
+  if has_helper_task() {
+    uninstall_helper_task()?;
+  }
+  remove_run_key()?;
+  fs::remove_dir_all(data_dir()?)?;
+  fs::remove_dir_all(config_dir()?)?;
+
+  let dir = install_dir()?;
+  if !dir.exists() {
+    return Ok(());
+  }
+  match is_running_from(&dir)? {
+    true => {
+      // The ping gives this process the time to exit before the directory is removed
+      let command = format!("ping -n 3 127.0.0.1 > nul & rmdir /S /Q \"{}\"", dir.display());
+      Command::new("cmd.exe").args(["/C", &command]).stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null()).spawn()?;
+    },
+    false => fs::remove_dir_all(&dir)?
+  };
+  Ok(())
+}
+
+/// Check whether the current executable lives in the given directory
+fn is_running_from(dir: &Path) -> io::Result<bool> {
+  let exe_dir = env::current_exe()?.parent().map(fs::canonicalize).transpose()?;
+  Ok(exe_dir == Some(fs::canonicalize(dir)?))
+}
+
+fn remove_run_key() -> Result<(), InstallError> {
+  // Deleting a value that doesn't exist fails, which is fine as far as uninstalling goes
+  if has_run_key() {
+    run_reg(&["delete", RUN_KEY, "/v", RUN_VALUE_NAME, "/f"])?;
+  }
+  Ok(())
+}
+
+fn has_run_key() -> bool {
+  run_reg(&["query", RUN_KEY, "/v", RUN_VALUE_NAME]).is_ok()
+}
+
+fn run_reg(args: &[&str]) -> Result<(), InstallError> {
+  let output = Command::new("reg.exe").args(args).output()?;
+  match output.status.success() {
+    true => Ok(()),
+    false => Err(InstallError::RegError(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+  }
+}
+
+#[derive(Debug)]
+pub enum InstallError {
+  IOError(io::Error),
+  RegError(String),
+  HelperTask(HelperTaskError)
+}
+
+impl From<io::Error> for InstallError {
+  fn from(value: io::Error) -> Self {
+    InstallError::IOError(value)
+  }
+}
+
+impl From<HelperTaskError> for InstallError {
+  fn from(value: HelperTaskError) -> Self {
+    InstallError::HelperTask(value)
+  }
+}
@@ -0,0 +1,394 @@
+use crate::ddc_trace;
+use crate::keyboard_knob::{EventSource, KnobAction, KnobAdjustmentEvent, forward_event};
+use crate::monitor_group::MonitorSelection;
+use crate::shutdown::StopSignal;
+use crate::state::{AppliedChange, StateSnapshot};
+use crate::system_events::SystemEvent;
+use crate::watch::WatchReceiver;
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender, bounded, select};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::iter;
+use std::mem::size_of;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, RawHandle};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::error;
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{ERROR_PIPE_CONNECTED, HANDLE, HLOCAL, INVALID_HANDLE_VALUE};
+use windows::Win32::Security::{PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES};
+use windows::Win32::Security::Authorization::{ConvertStringSecurityDescriptorToSecurityDescriptorW, SDDL_REVISION_1};
+use windows::Win32::Storage::FileSystem::PIPE_ACCESS_DUPLEX;
+use windows::Win32::System::Memory::LocalFree;
+use windows::Win32::System::Pipes::{
+  ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT
+};
+
+/// Name of the pipe the running instance listens on. Named pipes are only writable by the user who created them and
+/// the administrators by default, everyone else being limited to reading, which is what they are left at unless the
+/// instance runs as the service
+pub const PIPE_NAME: &str = "\\\\.\\pipe\\gmmk-brightness";
+const PIPE_NAME_WIDE: PCWSTR = w!("\\\\.\\pipe\\gmmk-brightness");
+const PIPE_BUFFER_SIZE: u32 = 4096;
+/// Number of notifications a subscriber can fall behind by before being dropped, for a client that stopped reading not
+/// to hold the others up
+const SUBSCRIBER_BACKLOG: usize = 64;
+/// Access to the pipe of the instance running as the service, which is created by LocalSystem. The interactive users
+/// can read and write it, for the commands to be sent from the session of the logged-in user, while SYSTEM, the
+/// administrators and the owner of the pipe keep full access to it
+const SERVICE_PIPE_SDDL: PCWSTR = w!("D:(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;OW)(A;;GRGW;;;IU)");
+/// Delay between two attempts at waking the server up once it's asked to stop, in case it's not waiting for a client
+const STOP_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Represent a command sent over the pipe, one per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpcCommand {
+  /// Reply with the brightness of the monitors
+  Get,
+  /// Set the brightness, with the same transition as the knob
+  Set(i32),
+  /// Turn the knob by the given number of notches, positive ones turning it up, as if it was turned by hand. The events
+  /// name the given monitor, either its number as printed by `list` or part of its name, for the route of the commands
+  Turn(i32, Option<String>),
+  /// Stop applying the knob adjustments
+  Pause,
+  /// Start applying the knob adjustments again
+  Resume,
+  /// Receive a line every time the brightness changes or the knob is paused or resumed, until disconnected
+  Subscribe,
+  /// Reply with the most recent DDC/CI transactions, when they are traced
+  Trace
+}
+
+impl FromStr for IpcCommand {
+  type Err = String;
+
+  fn from_str(line: &str) -> Result<Self, Self::Err> {
+    let words: Vec<String> = line.split_whitespace().map(|word| word.to_lowercase()).collect();
+    let words: Vec<&str> = words.iter().map(|word| word.as_str()).collect();
+    match words.as_slice() {
+      ["get"] => Ok(IpcCommand::Get),
+      ["set", value] => value.parse().map(IpcCommand::Set).map_err(|_| format!("invalid brightness \"{}\"", value)),
+      [direction @ ("up" | "down"), ..] => {
+        // The name of the monitor keeps its case, which it's matched with
+        let arguments: Vec<&str> = line.split_whitespace().skip(1).collect();
+        let (notches, monitor) = match arguments.split_first() {
+          Some((notches, monitor)) if notches.chars().all(|c| c.is_ascii_digit()) => {
+            (notches.parse::<u8>().map_err(|_| format!("invalid number of notches \"{}\"", notches))? as i32, monitor)
+          },
+          _ => (1, arguments.as_slice())
+        };
+        let monitor = match monitor.join(" ") {
+          monitor if monitor.is_empty() => None,
+          monitor => monitor.parse::<MonitorSelection>().map(|_| Some(monitor))?
+        };
+        Ok(IpcCommand::Turn(if *direction == "up" { notches } else { -notches }, monitor))
+      },
+      ["pause"] => Ok(IpcCommand::Pause),
+      ["resume"] => Ok(IpcCommand::Resume),
+      ["subscribe"] => Ok(IpcCommand::Subscribe),
+      ["trace"] => Ok(IpcCommand::Trace),
+      _ => Err(format!("unknown command \"{}\"", line.trim()))
+    }
+  }
+}
+
+impl fmt::Display for IpcCommand {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      IpcCommand::Get => write!(f, "get"),
+      IpcCommand::Set(value) => write!(f, "set {}", value),
+      IpcCommand::Turn(notches, monitor) => {
+        write!(f, "{} {}", if *notches < 0 { "down" } else { "up" }, notches.abs())?;
+        match monitor {
+          Some(monitor) => write!(f, " {}", monitor),
+          None => Ok(())
+        }
+      },
+      IpcCommand::Pause => write!(f, "pause"),
+      IpcCommand::Resume => write!(f, "resume"),
+      IpcCommand::Subscribe => write!(f, "subscribe"),
+      IpcCommand::Trace => write!(f, "trace")
+    }
+  }
+}
+
+/// Send a command to the running instance and wait for its reply, which is returned without its "error" prefix as an
+/// error when the command failed
+pub fn send_command(command: &IpcCommand) -> io::Result<String> {
+  read_reply(&mut connect(command)?)
+}
+
+/// Ask the running instance for the most recent DDC/CI transactions it traced, oldest first
+pub fn fetch_trace() -> io::Result<Vec<String>> {
+  let mut reader = connect(&IpcCommand::Trace)?;
+  let reply = read_reply(&mut reader)?;
+  let count = reply
+    .strip_prefix("trace ")
+    .and_then(|count| count.parse::<usize>().ok())
+    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("unexpected reply \"{}\"", reply)))?;
+  reader.lines().take(count).collect()
+}
+
+/// Subscribe to the notifications of the running instance, returning them one line at a time until it exits
+pub fn subscribe() -> io::Result<impl Iterator<Item = io::Result<String>>> {
+  let mut reader = connect(&IpcCommand::Subscribe)?;
+  read_reply(&mut reader)?;
+  Ok(reader.lines())
+}
+
+/// Connect to the running instance and send it a command, returning the pipe to read its reply from
+fn connect(command: &IpcCommand) -> io::Result<BufReader<File>> {
+  let mut pipe = OpenOptions::new().read(true).write(true).open(PIPE_NAME)?;
+  send_line(&mut pipe, &command.to_string())?;
+  Ok(BufReader::new(pipe))
+}
+
+fn read_reply(reader: &mut BufReader<File>) -> io::Result<String> {
+  let mut reply = String::new();
+  reader.read_line(&mut reply)?;
+  match reply.trim_end().strip_prefix("error ") {
+    Some(reason) => Err(io::Error::other(reason.to_string())),
+    None => Ok(reply.trim_end().to_string())
+  }
+}
+
+/// Accept the commands of external programs, such as AutoHotkey scripts or Stream Deck plugins, over a named pipe.
+/// Every command is answered with a single line: "ok", "brightness <value>" for `get`, or "error <reason>", but for
+/// `trace` which is answered with "trace <count>" followed by that many transactions. Subscribed clients also receive
+/// "brightness <value>", "paused" and "resumed" lines as things change, along with a
+/// "change <from> <to> <source> <duration in ms> <monitor>" line for every monitor once a change is applied, and have
+/// to keep reading them. The `up` and `down` commands go through the knob adjustment events, like the knob itself. The
+/// pipe is opened to the interactive users when running as the service. Runs until the stop signal is received
+pub fn run_ipc_server(stop_rx: StopSignal, events_tx: Sender<KnobAdjustmentEvent>, system_tx: Sender<SystemEvent>, state_rx: WatchReceiver<Option<StateSnapshot>>, changes_rx: Receiver<AppliedChange>, is_service: bool) -> io::Result<()> {
+  let security = is_service.then(PipeSecurity::for_service).transpose()?;
+  let latest_state = Arc::new(Mutex::new(None));
+  let subscribers: Arc<Mutex<Vec<Sender<String>>>> = Arc::new(Mutex::new(Vec::new()));
+  spawn_notifier(state_rx, changes_rx, latest_state.clone(), subscribers.clone());
+
+  // ConnectNamedPipe can't be interrupted, so the pipe is connected to for it to return once the stop signal is received
+  let stopping = Arc::new(AtomicBool::new(false));
+  let (done_tx, done_rx) = bounded::<()>(0);
+  {
+    let stopping = stopping.clone();
+    thread::spawn(move || {
+      let _ = stop_rx.recv();
+      stopping.store(true, Ordering::Relaxed);
+      loop {
+        let _ = OpenOptions::new().read(true).open(PIPE_NAME);
+        if done_rx.recv_timeout(STOP_RETRY_INTERVAL) != Err(RecvTimeoutError::Timeout) {
+          break;
+        }
+      }
+    });
+  }
+  let _done_tx = done_tx;
+
+  loop {
+    let pipe = create_pipe_instance(security.as_ref())?;
+    wait_for_client(&pipe)?;
+    if stopping.load(Ordering::Relaxed) {
+      return Ok(());
+    }
+
+    let events_tx = events_tx.clone();
+    let system_tx = system_tx.clone();
+    let latest_state = latest_state.clone();
+    let subscribers = subscribers.clone();
+    thread::spawn(move || {
+      if let Err(e) = serve_client(pipe, &events_tx, &system_tx, &latest_state, &subscribers) {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+          error!("unable to talk to an IPC client - {}", e);
+        }
+      }
+    });
+  }
+}
+
+/// Security descriptor every instance of the pipe is created with, when they aren't left with the default one
+struct PipeSecurity {
+  descriptor: PSECURITY_DESCRIPTOR
+}
+
+impl PipeSecurity {
+  fn for_service() -> io::Result<Self> {
+    let mut descriptor = PSECURITY_DESCRIPTOR::default();
+    match unsafe { ConvertStringSecurityDescriptorToSecurityDescriptorW(SERVICE_PIPE_SDDL, SDDL_REVISION_1, &mut descriptor, None) }.as_bool() {
+      true => Ok(Self { descriptor }),
+      false => Err(io::Error::last_os_error())
+    }
+  }
+
+  fn attributes(&self) -> SECURITY_ATTRIBUTES {
+    SECURITY_ATTRIBUTES { nLength: size_of::<SECURITY_ATTRIBUTES>() as u32, lpSecurityDescriptor: self.descriptor.0, bInheritHandle: false.into() }
+  }
+}
+
+impl Drop for PipeSecurity {
+  fn drop(&mut self) {
+    unsafe { let _ = LocalFree(HLOCAL(self.descriptor.0 as isize)); }
+  }
+}
+
+fn create_pipe_instance(security: Option<&PipeSecurity>) -> io::Result<File> {
+  let attributes = security.map(PipeSecurity::attributes);
+  unsafe {
+    let pipe_mode = PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS;
+    let attributes = attributes.as_ref().map(|attributes| attributes as *const SECURITY_ATTRIBUTES);
+    let handle = CreateNamedPipeW(PIPE_NAME_WIDE, PIPE_ACCESS_DUPLEX, pipe_mode, PIPE_UNLIMITED_INSTANCES, PIPE_BUFFER_SIZE, PIPE_BUFFER_SIZE, 0, attributes);
+    if handle == INVALID_HANDLE_VALUE {
+      return Err(io::Error::last_os_error());
+    }
+    Ok(File::from_raw_handle(handle.0 as RawHandle))
+  }
+}
+
+/// Block until a client connects to the pipe instance
+fn wait_for_client(pipe: &File) -> io::Result<()> {
+  if unsafe { ConnectNamedPipe(HANDLE(pipe.as_raw_handle() as isize), None) }.as_bool() {
+    return Ok(());
+  }
+  // Clients that connected in between the creation of the instance and the call are connected already
+  match io::Error::last_os_error() {
+    e if e.raw_os_error() == Some(ERROR_PIPE_CONNECTED.0 as i32) => Ok(()),
+    e => Err(e)
+  }
+}
+
+/// Answer the commands of a client, one line at a time, until it disconnects
+fn serve_client(pipe: File, events_tx: &Sender<KnobAdjustmentEvent>, system_tx: &Sender<SystemEvent>, latest_state: &Mutex<Option<StateSnapshot>>, subscribers: &Mutex<Vec<Sender<String>>>) -> io::Result<()> {
+  let mut writer = pipe.try_clone()?;
+  for line in BufReader::new(pipe).lines() {
+    let line = line?;
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let reply = match line.parse::<IpcCommand>() {
+      Ok(IpcCommand::Get) => match latest_state.lock().unwrap().as_ref().and_then(|state| state.brightness) {
+        Some(brightness) => format!("brightness {}", brightness),
+        None => "error no monitor is connected".to_string()
+      },
+      Ok(IpcCommand::Set(value)) => request(system_tx, SystemEvent::BrightnessRequested(value)),
+      Ok(IpcCommand::Turn(notches, monitor)) => turn(events_tx, notches, monitor),
+      Ok(IpcCommand::Pause) => request(system_tx, SystemEvent::PauseRequested(true)),
+      Ok(IpcCommand::Resume) => request(system_tx, SystemEvent::PauseRequested(false)),
+      Ok(IpcCommand::Subscribe) => {
+        subscribers.lock().unwrap().push(spawn_subscriber(writer.try_clone()?));
+        "ok".to_string()
+      },
+      Ok(IpcCommand::Trace) => match ddc_trace::is_enabled() {
+        true => {
+          let transactions = ddc_trace::dump();
+          iter::once(format!("trace {}", transactions.len())).chain(transactions).collect::<Vec<_>>().join("\n")
+        },
+        false => "error the DDC/CI traffic isn't traced, start the program with --trace-ddc".to_string()
+      },
+      Err(e) => format!("error {}", e)
+    };
+    send_line(&mut writer, &reply)?;
+  }
+  Ok(())
+}
+
+fn request(system_tx: &Sender<SystemEvent>, event: SystemEvent) -> String {
+  match system_tx.send(event) {
+    Ok(_) => "ok".to_string(),
+    Err(_) => "error the program is shutting down".to_string()
+  }
+}
+
+/// Turn the knob by the given number of notches, the notches that don't fit in the events queue being dropped as they
+/// would be for the knob
+fn turn(events_tx: &Sender<KnobAdjustmentEvent>, notches: i32, monitor: Option<String>) -> String {
+  let action = if notches > 0 { KnobAction::Increment } else { KnobAction::Decrement };
+  let monitor = monitor.and_then(|monitor| monitor.parse::<MonitorSelection>().ok());
+  for _ in 0..notches.unsigned_abs() {
+    let event = KnobAdjustmentEvent { monitor: monitor.clone(), ..KnobAdjustmentEvent::new(action, EventSource::Ipc) };
+    if forward_event(events_tx, event).is_err() {
+      return "error the program is shutting down".to_string();
+    }
+  }
+  "ok".to_string()
+}
+
+/// Write a whole line at once, so that the replies and the notifications written from another thread never interleave
+fn send_line(pipe: &mut File, line: &str) -> io::Result<()> {
+  pipe.write_all(format!("{}\n", line).as_bytes())
+}
+
+/// Write the notifications queued for a subscriber to its pipe, on a thread of its own so that a client that stopped
+/// reading only ever blocks its own writes. The thread ends once the client disconnects or is dropped
+fn spawn_subscriber(mut pipe: File) -> Sender<String> {
+  let (lines_tx, lines_rx) = bounded::<String>(SUBSCRIBER_BACKLOG);
+  thread::spawn(move || {
+    for line in lines_rx {
+      if send_line(&mut pipe, &line).is_err() {
+        break;
+      }
+    }
+  });
+  lines_tx
+}
+
+/// Keep the latest state around for the `get` commands, and notify the subscribers of its changes and of every change
+/// applied to the brightness, on a thread of its own. The snapshots of the state only tell what it's at now, while the
+/// applied changes are queued for none of them to be missed. Subscribers that disconnected or fell too far behind are
+/// forgotten about on their next notification, without the lock ever being held over a write to a pipe. The thread
+/// ends along with the brightness thread
+fn spawn_notifier(mut state_rx: WatchReceiver<Option<StateSnapshot>>, changes_rx: Receiver<AppliedChange>, latest_state: Arc<Mutex<Option<StateSnapshot>>>, subscribers: Arc<Mutex<Vec<Sender<String>>>>) {
+  let (wake_tx, wake_rx) = bounded::<()>(1);
+  state_rx.on_change(move || {
+    let _ = wake_tx.try_send(());
+  });
+
+  thread::spawn(move || {
+    let mut previous_state = state_rx.latest();
+    *latest_state.lock().unwrap() = previous_state.clone();
+    loop {
+      select! {
+        recv(wake_rx) -> woken => {
+          if woken.is_err() {
+            break;
+          }
+          let Some(state) = state_rx.changed() else { continue };
+          notify(&subscribers, &notifications(previous_state.as_ref(), state.as_ref()));
+          *latest_state.lock().unwrap() = state.clone();
+          previous_state = state;
+        },
+        recv(changes_rx) -> change => {
+          let Ok(change) = change else { break };
+          let lines: Vec<String> = change.monitors
+            .iter()
+            .map(|monitor| format!("change {} {} {} {} {}", monitor.from, monitor.to, change.source, change.duration.as_millis(), monitor.name))
+            .collect();
+          notify(&subscribers, &lines);
+        }
+      }
+    }
+  });
+}
+
+fn notify(subscribers: &Mutex<Vec<Sender<String>>>, lines: &[String]) {
+  if !lines.is_empty() {
+    subscribers.lock().unwrap().retain(|subscriber| lines.iter().all(|line| subscriber.try_send(line.clone()).is_ok()));
+  }
+}
+
+/// Get the lines telling the subscribers what changed from one state to the next
+fn notifications(previous_state: Option<&StateSnapshot>, state: Option<&StateSnapshot>) -> Vec<String> {
+  let Some(state) = state else { return Vec::new() };
+  let mut lines = Vec::new();
+  if let Some(brightness) = state.brightness.filter(|brightness| previous_state.and_then(|previous| previous.brightness) != Some(*brightness)) {
+    lines.push(format!("brightness {}", brightness));
+  }
+  if previous_state.map(|previous| previous.paused) != Some(state.paused) {
+    lines.push(if state.paused { "paused" } else { "resumed" }.to_string());
+  }
+  lines
+}
@@ -0,0 +1,73 @@
+use std::time::{Duration, Instant};
+use tracing::info;
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+  VIRTUAL_KEY, VK_F13, VK_F24, VK_MEDIA_NEXT_TRACK, VK_MEDIA_PLAY_PAUSE, VK_MEDIA_PREV_TRACK, VK_MEDIA_STOP, VK_VOLUME_DOWN,
+  VK_VOLUME_MUTE, VK_VOLUME_UP
+};
+
+/// Unrecognized keys coming this soon after a knob rotation were most likely sent by the knob too
+const KNOB_ROTATION_WINDOW: Duration = Duration::from_secs(2);
+/// Longest pause between two presses of the same key for them to count as a single turn of the knob
+const BURST_GAP: Duration = Duration::from_millis(400);
+/// Number of presses in a single burst past which a key is assumed to come from a knob
+const BURST_LENGTH: u32 = 3;
+
+/// Diagnostic mode helping to find out which keys the knob sends when it doesn't send the configured keys. It logs
+/// the unrecognized function and consumer-control keys, and suggests using the ones that come in bursts like a knob
+/// rotation does as the knob keys
+#[derive(Default)]
+pub struct KeyLearning {
+  last_knob_event: Option<Instant>,
+  burst: Option<(VIRTUAL_KEY, Instant, u32)>,
+  suggested_keys: Vec<VIRTUAL_KEY>
+}
+
+impl KeyLearning {
+  /// Remember that a recognized knob event just came through
+  pub fn record_knob_event(&mut self) {
+    self.last_knob_event = Some(Instant::now());
+  }
+
+  /// Look at a key that was released without being recognized as a knob adjustment
+  pub fn observe(&mut self, key_code: VIRTUAL_KEY) {
+    let Some(name) = key_name(key_code) else { return };
+    let now = Instant::now();
+
+    let after_rotation = self.last_knob_event.is_some_and(|since| now.duration_since(since) < KNOB_ROTATION_WINDOW);
+    info!("unrecognized key {}{}", name, if after_rotation { ", shortly after a knob rotation" } else { "" });
+
+    let presses = match self.burst {
+      Some((burst_key, last_press, presses)) if burst_key == key_code && now.duration_since(last_press) < BURST_GAP => presses + 1,
+      _ => 1
+    };
+    self.burst = Some((key_code, now, presses));
+
+    if presses >= BURST_LENGTH && !self.suggested_keys.contains(&key_code) {
+      self.suggested_keys.push(key_code);
+      info!("{} looks like a knob rotation, set increment_key or decrement_key to \"{}\"", name, config_name(key_code));
+    }
+  }
+}
+
+fn key_name(key_code: VIRTUAL_KEY) -> Option<String> {
+  let name = match key_code {
+    key_code if (VK_F13.0..=VK_F24.0).contains(&key_code.0) => return Some(format!("F{}", key_code.0 - VK_F13.0 + 13)),
+    VK_VOLUME_MUTE => "Volume Mute",
+    VK_VOLUME_DOWN => "Volume Down",
+    VK_VOLUME_UP => "Volume Up",
+    VK_MEDIA_NEXT_TRACK => "Next Track",
+    VK_MEDIA_PREV_TRACK => "Previous Track",
+    VK_MEDIA_STOP => "Stop",
+    VK_MEDIA_PLAY_PAUSE => "Play/Pause",
+    _ => return None
+  };
+  Some(name.to_string())
+}
+
+/// Get the name of the key as written in the config file, falling back to its virtual-key code for the unnamed ones
+fn config_name(key_code: VIRTUAL_KEY) -> String {
+  match (VK_F13.0..=VK_F24.0).contains(&key_code.0) {
+    true => format!("f{}", key_code.0 - VK_F13.0 + 13),
+    false => format!("{:#04x}", key_code.0)
+  }
+}
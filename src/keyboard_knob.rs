@@ -1,51 +1,362 @@
-use crossbeam_channel::{Receiver, Sender};
-use std::thread;
-use windows::Win32::Foundation::{HMODULE, HWND, LPARAM, LRESULT, WPARAM};
-use windows::Win32::System::Threading::GetCurrentThreadId;
-use windows::Win32::UI::Input::KeyboardAndMouse::{VIRTUAL_KEY, VK_F19, VK_F20};
+use crate::actions::{Trigger, parse_chord};
+use crate::consumer_control::{VolumeControl, read_volume_controls, register_consumer_control, unregister_consumer_control};
+use crate::desktop::{DESKTOP_SWITCH_MSG, register_desktop_switch_hook, unregister_desktop_switch_hook, update_desktop_state};
+use crate::foreground::{FullscreenKind, ScreenRegion, fullscreen_kind, region_under_cursor, window_process_name};
+use crate::hook_watchdog::{HookKind, HookWatchdog, record_hook_call};
+use crate::key_learning::KeyLearning;
+use crate::knob_layers::KnobLayers;
+use crate::monitor_group::MonitorSelection;
+use crate::raw_keyboard::{KeyboardFilter, read_keystroke, register_raw_keyboards, unregister_raw_keyboards};
+use crate::shutdown::{StopSignal, forward_stop_to_message_loop};
+use crate::stats;
+use crate::system_events::{
+  CLOCK_CHANGE_MSG, DISPLAY_CHANGE_MSG, HID_DEVICE_CHANGE_MSG, RESUME_MSG, SystemEvent, create_notification_window,
+  destroy_notification_window, register_hid_notifications, register_monitor_notifications, unregister_device_notifications
+};
+
+use crossbeam_channel::{Receiver, SendError, Sender, TrySendError, bounded, unbounded};
+use serde::Deserialize;
+use std::cmp::max;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use windows::Win32::Foundation::{E_FAIL, HMODULE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+  GetAsyncKeyState, RegisterHotKey, UnregisterHotKey, HOT_KEY_MODIFIERS, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, VIRTUAL_KEY,
+  VK_CONTROL, VK_F19, VK_F20, VK_LWIN, VK_MENU, VK_RWIN, VK_SHIFT, VK_VOLUME_DOWN, VK_VOLUME_MUTE, VK_VOLUME_UP
+};
 use windows::Win32::UI::WindowsAndMessaging::{
-  CallNextHookEx, DispatchMessageW, GetMessageW, PostMessageW, PostThreadMessageW, SetWindowsHookExW, TranslateMessage, UnhookWindowsHookEx,
-  HHOOK, KBDLLHOOKSTRUCT, MSG, MSLLHOOKSTRUCT, WINDOWS_HOOK_ID, WM_KEYUP, WM_QUIT, WM_SYSKEYUP
+  CallNextHookEx, DispatchMessageW, GetForegroundWindow, GetMessageW, KillTimer, PostMessageW, SetTimer, SetWindowsHookExW, TranslateMessage,
+  UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT, MSG, MSLLHOOKSTRUCT, WINDOWS_HOOK_ID, WM_HOTKEY, WM_INPUT, WM_KEYUP, WM_SYSKEYUP, WM_TIMER
 };
 
 const HC_ACTION: i32 = 0;
 const WH_KEYBOARD_LL: WINDOWS_HOOK_ID = WINDOWS_HOOK_ID(13);
 const WH_MOUSE_LL: WINDOWS_HOOK_ID = WINDOWS_HOOK_ID(14);
 const WM_MOUSEWHEEL: WPARAM = WPARAM(522usize);
+const WHEEL_DELTA: i32 = 120;
+const ACTION_HOTKEY_BASE_ID: i32 = 1;
+/// Timer of the notification window the hook is checked on, and how often
+const WATCHDOG_TIMER_ID: usize = 1;
+/// How long the hook is given to see the probe injected by the watchdog
+const WATCHDOG_PROBE_TIMEOUT_MS: u32 = 500;
+/// Keys the GMMK PRO sends when its knob is turned, once the firmware maps the knob to the brightness
+pub const DEFAULT_INCREMENT_KEY: VIRTUAL_KEY = VK_F20;
+pub const DEFAULT_DECREMENT_KEY: VIRTUAL_KEY = VK_F19;
+/// Vendor and product IDs of the GMMK PRO, for its ANSI and ISO layouts
+pub const GMMK_PRO_KEYBOARD_IDS: &[(u16, u16)] = &[(0x320f, 0x5044), (0x320f, 0x5092)];
 
-/// Represent a knob adjustment event. The values chosen for the enum items are not random, and were chosen according
-/// to Microsoft's documentation on application-defined messages
-/// 
-/// Reference: https://learn.microsoft.com/en-us/windows/win32/winmsg/about-messages-and-message-queues#application-defined-messages
-#[repr(u32)]
-pub enum KnobAdjustmentEvent {
-  Increment = 0x0500,
-  Decrement = 0x0502
+// Read from the keyboard hook, which has no other way to reach the settings
+static SUPPRESS_VOLUME_KEYS: AtomicBool = AtomicBool::new(false);
+static SUPPRESS_KNOB_KEYS: AtomicBool = AtomicBool::new(false);
+static INCREMENT_KEY: AtomicU16 = AtomicU16::new(DEFAULT_INCREMENT_KEY.0);
+static DECREMENT_KEY: AtomicU16 = AtomicU16::new(DEFAULT_DECREMENT_KEY.0);
+/// Zero when no key is sent by the knob when it's pressed
+static PRESS_KEY: AtomicU16 = AtomicU16::new(0);
+/// Only set with the hybrid layers, and only ever locked by the thread of the hook
+static KNOB_LAYERS: Mutex<Option<KnobLayers>> = Mutex::new(None);
+
+/// Represent how the keystrokes of the knob are captured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KnobInput {
+  /// Low-level keyboard hook, which sees the keystrokes of every keyboard and is able to swallow them. It delays every
+  /// keystroke of the system, and gets silently removed by Windows whenever the message loop is too slow to answer it
+  #[default]
+  Hook,
+  /// Raw Input of the keyboards with a knob only, told apart by their vendor and product IDs. It doesn't get in the
+  /// way of the keystrokes of the other applications, but doesn't keep them from receiving the knob keys either
+  RawInput
+}
+
+/// Settings of the knob adjustment handler
+#[derive(Debug, Clone)]
+pub struct HandlerSettings {
+  /// Emulate the knob using the vertical mouse scroll wheel instead of listening for the keyboard
+  pub emulate_knob: bool,
+  /// How the keystrokes of the knob are captured, when not emulating it
+  pub knob_input: KnobInput,
+  /// Part of the device path of the only keyboard whose keystrokes are taken as the knob's, such as "VID_320F&PID_5044",
+  /// rather than any of the keyboards with a knob. The keystrokes are then told apart through Raw Input
+  pub knob_device: Option<String>,
+  /// Register the low-level hook again when Windows removed it, checked only when input comes in that it missed
+  pub hook_watchdog: bool,
+  /// Keys sent by the knob when it's turned, which the keyboard hook turns into brightness adjustments
+  pub increment_key: VIRTUAL_KEY,
+  pub decrement_key: VIRTUAL_KEY,
+  /// Key sent by the knob when it's pressed, which the keyboard hook turns into presses. They only come from the volume
+  /// controls otherwise, without being able to tell how long the knob was held
+  pub press_key: Option<VIRTUAL_KEY>,
+  /// How long the press key has to be held for the monitors to be put in standby instead of the knob being pressed
+  pub standby_hold: Option<Duration>,
+  /// Swallow the knob keys once they have been turned into adjustments, so that no other application receives them
+  pub suppress_knob_keys: bool,
+  /// Number of knob adjustment events to emit per wheel notch (WHEEL_DELTA) when emulating the knob. High-resolution
+  /// wheels report fractions of a notch, which are accumulated until they add up to a full step
+  pub wheel_delta_divisor: u16,
+  /// Forward the fractions of a step reported by high-resolution wheels as they come, instead of accumulating them
+  /// into full steps, for finer control over the brightness
+  pub smooth_scrolling: bool,
+  /// Executable names (e.g. "blender.exe") of the applications that keep the mouse wheel for themselves while they
+  /// are focused, so that zooming or scrolling in them never changes the brightness
+  pub emulation_blacklist: Vec<String>,
+  /// Leave the wheel alone while a game runs in exclusive fullscreen. Borderless-windowed games keep the emulation,
+  /// given that the Game Bar and other overlays can still be brought up on top of them
+  pub pause_emulation_in_fullscreen: bool,
+  /// Executable names mapped to the fullscreen kind to assume for their windows, for the games the detection gets wrong
+  pub fullscreen_overrides: Vec<(String, FullscreenKind)>,
+  /// Only turn the wheel into knob adjustments while these modifiers are held, or while the cursor is over one of the
+  /// regions, so that scrolling keeps working everywhere else. The wheel is always turned into adjustments when there
+  /// are neither modifiers nor regions
+  pub emulation_modifiers: HOT_KEY_MODIFIERS,
+  pub emulation_regions: Vec<ScreenRegion>,
+  /// Triggers of the action bindings, in the same order as the bindings themselves
+  pub action_triggers: Vec<Trigger>,
+  /// Vendor and product IDs of the keyboards with a knob, whose connections and disconnections are reported
+  pub keyboard_ids: Vec<(u16, u16)>,
+  /// Log the function and consumer-control keys that aren't recognized as knob adjustments, to find out which ones
+  /// the knob sends
+  pub learn_knob_keys: bool,
+  /// Turn the volume controls of consumer-control devices, such as a knob left at its default mapping, into brightness
+  /// adjustments. The volume keys are swallowed by the keyboard hook so that the system volume doesn't change too
+  pub capture_volume_controls: bool,
+  /// Let the knob keep controlling the volume on the base layer, while the Fn layer sends the knob keys for the
+  /// brightness. The volume controls adjust the brightness too while the layer key is held. Only what adjusts the
+  /// brightness is swallowed by the keyboard hook, so that no other application reacts to it, while the volume keys go
+  /// through untouched otherwise. Capturing the volume controls makes no sense in this setup and is turned off
+  pub hybrid_layers: bool,
+  /// Key switching the knob to the brightness layer while it's held, with the hybrid layers
+  pub hybrid_layer_key: Option<VIRTUAL_KEY>
+}
+
+impl Default for HandlerSettings {
+  /// Listen for the knob of the GMMK PRO, with every optional behavior turned off
+  fn default() -> Self {
+    Self {
+      emulate_knob: false,
+      knob_input: KnobInput::Hook,
+      knob_device: None,
+      hook_watchdog: false,
+      increment_key: DEFAULT_INCREMENT_KEY,
+      decrement_key: DEFAULT_DECREMENT_KEY,
+      press_key: None,
+      standby_hold: None,
+      suppress_knob_keys: false,
+      wheel_delta_divisor: 1,
+      smooth_scrolling: false,
+      emulation_blacklist: Vec::new(),
+      pause_emulation_in_fullscreen: false,
+      fullscreen_overrides: Vec::new(),
+      emulation_modifiers: HOT_KEY_MODIFIERS(0),
+      emulation_regions: Vec::new(),
+      action_triggers: Vec::new(),
+      keyboard_ids: GMMK_PRO_KEYBOARD_IDS.to_vec(),
+      learn_knob_keys: false,
+      capture_volume_controls: false,
+      hybrid_layers: false,
+      hybrid_layer_key: None
+    }
+  }
+}
+
+/// State of the message loop needed to turn the raw input events into knob adjustments
+struct InputState {
+  wheel_step: i32,
+  wheel_accumulator: i32,
+  smooth_scrolling: bool,
+  emulation_blacklist: Vec<String>,
+  pause_emulation_in_fullscreen: bool,
+  fullscreen_overrides: Vec<(String, FullscreenKind)>,
+  emulation_modifiers: HOT_KEY_MODIFIERS,
+  emulation_regions: Vec<ScreenRegion>,
+  // Resolving the process behind a window is way too slow to be done on every wheel event, so the outcome is cached
+  // until the foreground window changes
+  foreground_hwnd: HWND,
+  foreground_blacklisted: bool
+}
+
+// The values chosen for the messages posted by the hooks are not random, and were chosen according to Microsoft's
+// documentation on application-defined messages
+//
+// Reference: https://learn.microsoft.com/en-us/windows/win32/winmsg/about-messages-and-message-queues#application-defined-messages
+//
+// The hooks only copy the raw input data into the message parameters and leave every decision to the message loop, so
+// that they return well within the LowLevelHooksTimeout, past which Windows silently removes them. WPARAM holds the
+// keyboard message identifier and LPARAM the virtual-key code for RAW_KEY_MSG, while WPARAM holds the wheel delta for
+// RAW_WHEEL_MSG
+const RAW_KEY_MSG: u32 = 0x0500;
+const RAW_WHEEL_MSG: u32 = 0x0502;
+
+/// Represent what the knob did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KnobAction {
+  Increment,
+  Decrement,
+  /// Rotation by a fraction of a step, only reported by high-resolution wheels when smooth scrolling is enabled.
+  /// Positive values increment the brightness and negative values decrement it
+  Partial(f64),
+  Press
+}
+
+impl KnobAction {
+  /// Get the action turning the knob the other way would have produced
+  pub fn reversed(self) -> Self {
+    match self {
+      KnobAction::Increment => KnobAction::Decrement,
+      KnobAction::Decrement => KnobAction::Increment,
+      KnobAction::Partial(fraction) => KnobAction::Partial(-fraction),
+      KnobAction::Press => KnobAction::Press
+    }
+  }
+}
+
+/// Represent where a knob adjustment event came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EventSource {
+  /// The keycodes sent by the knob, captured by the low-level keyboard hook or through Raw Input
+  Keyboard,
+  /// The vertical mouse scroll wheel, when emulating the knob
+  Mouse,
+  /// The keyboard's HID interface
+  Hid,
+  /// The volume controls of the knob, captured as consumer control input through Raw Input
+  ConsumerControl,
+  /// An external program turning the knob through the running instance, with the `up` and `down` commands
+  Ipc
+}
+
+/// Represent a knob adjustment event, along with the metadata needed to tell events apart
+#[derive(Debug, Clone)]
+pub struct KnobAdjustmentEvent {
+  pub action: KnobAction,
+  pub source: EventSource,
+  /// Identifier of the physical device that generated the event, when the source is able to tell
+  pub device_id: Option<String>,
+  /// Monitors the event is meant for, when the source names them
+  pub monitor: Option<MonitorSelection>,
+  /// Modifiers that were held when the event was received
+  pub modifiers: HOT_KEY_MODIFIERS,
+  /// When the event was received, according to a monotonic clock
+  pub timestamp: Instant
+}
+
+impl KnobAdjustmentEvent {
+  pub fn new(action: KnobAction, source: EventSource) -> Self {
+    Self {
+      action,
+      source,
+      device_id: None,
+      monitor: None,
+      modifiers: held_modifiers(),
+      timestamp: Instant::now()
+    }
+  }
 }
 
 /// Register the event handler for adjustments to the knob. These adjustments can come either from the physical keyboard
-/// device, or emulated using the vertical mouse scroll wheel
-pub fn register_knob_adjustment_handler(stop_rx: Receiver<bool>, events_tx: Sender<KnobAdjustmentEvent>, emulate_knob: bool) -> Result<(), HandlerError> {
+/// device, or emulated using the vertical mouse scroll wheel. System events such as transitions to and from the secure
+/// desktop or display changes are forwarded too, given that the other threads can't observe them on their own
+pub fn register_knob_adjustment_handler(stop_rx: StopSignal, events_tx: Sender<KnobAdjustmentEvent>, system_tx: Sender<SystemEvent>, settings: HandlerSettings) -> Result<(), HandlerError> {
+  let mut input_state = InputState::new(&settings);
+
   unsafe {
-    let thread_id = GetCurrentThreadId();
+    let notification_hwnd = create_notification_window()?;
+    // Raw Input takes the place of the keyboard hook when asked for, or when the knob is restricted to a single keyboard
+    // given that the hook can't tell keyboards apart. The hook is still there to fall back on
+    let use_raw_input = settings.knob_input == KnobInput::RawInput || settings.knob_device.is_some();
+    if settings.knob_input == KnobInput::Hook && settings.knob_device.is_some() && !settings.emulate_knob {
+      info!("restricting the knob to a single keyboard, capturing it through Raw Input rather than the keyboard hook");
+    }
+    let mut keyboard_filter = match !settings.emulate_knob && use_raw_input {
+      true => match register_raw_keyboards(notification_hwnd) {
+        Ok(_) => Some(KeyboardFilter::new(settings.keyboard_ids.clone(), settings.knob_device.clone())),
+        Err(e) => {
+          error!("unable to capture the knob through Raw Input, falling back to the keyboard hook - {}", e);
+          None
+        }
+      },
+      false => None
+    };
 
     // Register a hook for capturing low-level input events
-    let hook_id = match emulate_knob {
-      true => SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook), HMODULE(0), 0)?,
-      false => SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), HMODULE(0), 0)?
+    let hook_kind = match (settings.emulate_knob, &keyboard_filter) {
+      (true, _) => Some(HookKind::Mouse),
+      (false, None) => Some(HookKind::Keyboard),
+      (false, Some(_)) => None
     };
-
-    // Spawn a new thread that just waits for the stop signal to forward it to the message loop
-    thread::spawn(move || {
-      for _ in stop_rx {
-        // Send the WM_QUIT message to the main thread so that GetMessageW can return and exit the program gracefully
-        // Note: PostQuitMessage won't work here because we are on a different thread!
-        PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0));
-        println!("INFO: received stop signal");
+    let mut hook_id = match hook_kind {
+      Some(kind) => Some(register_hook(kind)?),
+      None => None
+    };
+    let mut hook_watchdog = match hook_kind.filter(|_| settings.hook_watchdog) {
+      Some(kind) => HookWatchdog::register(kind, notification_hwnd)
+        .map_err(|e| error!("unable to watch the {} hook, it won't be registered again if removed - {}", kind.name(), e))
+        .ok(),
+      None => None
+    };
+    let desktop_hook_id = register_desktop_switch_hook();
+    let hid_notifications = register_hid_notifications(notification_hwnd);
+    let monitor_notifications = register_monitor_notifications(notification_hwnd);
+    if settings.hybrid_layers && settings.capture_volume_controls {
+      error!("capturing the volume controls conflicts with the hybrid layers, the volume is left to the system");
+    }
+    let capture_volume_controls = settings.capture_volume_controls && !settings.hybrid_layers;
+    if keyboard_filter.is_some() && (capture_volume_controls || settings.suppress_knob_keys || settings.hybrid_layers) {
+      error!("the keys can't be swallowed without the keyboard hook, the other applications keep receiving them");
+    }
+    let consumer_control_registered = capture_volume_controls && match register_consumer_control(notification_hwnd) {
+      Ok(_) => true,
+      Err(e) => {
+        error!("unable to capture the volume controls - {}", e);
+        false
       }
+    };
+    SUPPRESS_VOLUME_KEYS.store(consumer_control_registered, Ordering::Relaxed);
+    SUPPRESS_KNOB_KEYS.store(settings.suppress_knob_keys, Ordering::Relaxed);
+    *KNOB_LAYERS.lock().unwrap() = settings.hybrid_layers.then(|| {
+      KnobLayers::new(settings.hybrid_layer_key, settings.increment_key, settings.decrement_key, settings.press_key)
     });
+    INCREMENT_KEY.store(settings.increment_key.0, Ordering::Relaxed);
+    DECREMENT_KEY.store(settings.decrement_key.0, Ordering::Relaxed);
+    PRESS_KEY.store(settings.press_key.map_or(0, |key| key.0), Ordering::Relaxed);
+    if hid_notifications.is_null() {
+      error!("unable to register for HID device notifications, keyboard connections won't be reported");
+    }
+    update_desktop_state();
+
+    // Another application might have grabbed the same combination already, which is not a reason to give up on the knob
+    let mut registered_hotkey_ids = Vec::new();
+    for (index, trigger) in settings.action_triggers.iter().enumerate() {
+      let Trigger::Chord(chord) = trigger;
+      let Some((modifiers, key)) = parse_chord(chord) else {
+        error!("unable to parse the key combination \"{}\"", chord);
+        continue;
+      };
+
+      let hotkey_id = ACTION_HOTKEY_BASE_ID + index as i32;
+      match RegisterHotKey(HWND(0), hotkey_id, modifiers | MOD_NOREPEAT, key).as_bool() {
+        true => registered_hotkey_ids.push(hotkey_id),
+        false => error!("unable to register the hotkey {}, it's probably in use already", chord)
+      };
+    }
+
+    // Make GetMessageW return as soon as the stop signal is received
+    forward_stop_to_message_loop(stop_rx);
+
+    // Only one of the hooks is installed at a time, so that's enough to tell where the events come from
+    let source = if settings.emulate_knob { EventSource::Mouse } else { EventSource::Keyboard };
 
     // Message loop
+    let mut keyboard_connected = None;
+    let mut key_learning = settings.learn_knob_keys.then(KeyLearning::default);
+    // When the press key went down, which it keeps repeating for as long as the knob is held
+    let mut pressed_at: Option<Instant> = None;
+    // Path of the keyboard the keystroke being reposted comes from, when captured through Raw Input. Posted messages are
+    // retrieved ahead of the input ones, so it's read before the next keystroke comes in
+    let mut keystroke_device: Option<String> = None;
     let mut msg: MSG = Default::default();
     while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
       TranslateMessage(&msg);
@@ -53,19 +364,212 @@ pub fn register_knob_adjustment_handler(stop_rx: Receiver<bool>, events_tx: Send
       // Forward the knob adjustment events to the other thread(s)
       let evt = msg.message;
       match evt {
-        evt if evt == KnobAdjustmentEvent::Increment as u32 => events_tx.send(KnobAdjustmentEvent::Increment)?,
-        evt if evt == KnobAdjustmentEvent::Decrement as u32 => events_tx.send(KnobAdjustmentEvent::Decrement)?,
+        RAW_KEY_MSG => {
+          let key_state = msg.wParam.0 as u32;
+          let is_key_up = key_state == WM_KEYUP || key_state == WM_SYSKEYUP;
+          let key_code = VIRTUAL_KEY(msg.lParam.0 as u16);
+          let is_press_key = settings.press_key == Some(key_code);
+          if is_press_key && !is_key_up && pressed_at.is_none() {
+            pressed_at = Some(Instant::now());
+          }
+          let is_long_press = is_press_key && is_key_up && pressed_at
+            .take()
+            .is_some_and(|pressed_at| settings.standby_hold.is_some_and(|standby_hold| pressed_at.elapsed() >= standby_hold));
+          let action = match key_code {
+            _ if !is_key_up || is_long_press => None,
+            _ if key_code == settings.decrement_key => Some(KnobAction::Decrement),
+            _ if key_code == settings.increment_key => Some(KnobAction::Increment),
+            _ if is_press_key => Some(KnobAction::Press),
+            _ => None
+          };
+          if is_long_press {
+            system_tx.send(SystemEvent::StandbyRequested)?
+          }
+          match (action, key_learning.as_mut()) {
+            (Some(_), Some(key_learning)) => key_learning.record_knob_event(),
+            (None, Some(key_learning)) if is_key_up => key_learning.observe(key_code),
+            _ => {}
+          };
+          let device_id = keystroke_device.take();
+          if let Some(action) = action {
+            forward_event(&events_tx, KnobAdjustmentEvent { device_id, ..KnobAdjustmentEvent::new(action, source) })?
+          }
+        },
+        // Leave the wheel alone while a blacklisted application or an exclusive fullscreen game is focused, and unless
+        // the modifiers are held or the cursor is over one of the regions when asked for
+        RAW_WHEEL_MSG if !input_state.is_foreground_blacklisted() && input_state.is_wheel_gate_open() => {
+          for action in input_state.wheel_actions(msg.wParam.0 as u16 as i16) {
+            forward_event(&events_tx, KnobAdjustmentEvent::new(action, source))?
+          }
+        },
+        WM_INPUT => {
+          // The timer only runs while a probe waits to be seen, nothing being checked while the hook is fine
+          if hook_watchdog.as_mut().is_some_and(|watchdog| watchdog.observe_input(msg.lParam, msg.time)) {
+            SetTimer(notification_hwnd, WATCHDOG_TIMER_ID, WATCHDOG_PROBE_TIMEOUT_MS, None);
+          }
+          let keystroke = keyboard_filter.as_mut().and_then(|filter| {
+            let keystroke = read_keystroke(msg.lParam)?;
+            filter.accepts(keystroke.device).map(|path| (keystroke, path.to_string()))
+          });
+          match keystroke {
+            // The keystrokes of the keyboards with a knob go through the same steps as the ones caught by the hook
+            Some((keystroke, path)) => {
+              keystroke_device = Some(path);
+              PostMessageW(HWND(0), RAW_KEY_MSG, WPARAM(keystroke.message as usize), LPARAM(keystroke.key_code.0 as isize));
+            },
+            None if consumer_control_registered => {
+              for control in read_volume_controls(msg.lParam) {
+                let action = match control {
+                  VolumeControl::Up => KnobAction::Increment,
+                  VolumeControl::Down => KnobAction::Decrement,
+                  VolumeControl::Mute => KnobAction::Press
+                };
+                forward_event(&events_tx, KnobAdjustmentEvent::new(action, EventSource::ConsumerControl))?
+              }
+            },
+            None => {}
+          };
+        },
+        DESKTOP_SWITCH_MSG => if let Some(transition) = update_desktop_state() { system_tx.send(transition)? },
+        DISPLAY_CHANGE_MSG => system_tx.send(SystemEvent::DisplayChanged)?,
+        CLOCK_CHANGE_MSG => system_tx.send(SystemEvent::ClockChanged)?,
+        RESUME_MSG => system_tx.send(SystemEvent::Resumed)?,
+        HID_DEVICE_CHANGE_MSG => {
+          // Keyboards expose several HID interfaces, each of them being notified on its own
+          let ids = ((msg.lParam.0 >> 16) as u16, msg.lParam.0 as u16);
+          let connected = msg.wParam.0 != 0;
+          if let Some(filter) = keyboard_filter.as_mut() {
+            filter.clear();
+          }
+          if settings.keyboard_ids.contains(&ids) && keyboard_connected != Some(connected) {
+            keyboard_connected = Some(connected);
+            system_tx.send(if connected { SystemEvent::KeyboardConnected } else { SystemEvent::KeyboardDisconnected })?
+          }
+        },
+        WM_TIMER if msg.wParam.0 == WATCHDOG_TIMER_ID => {
+          KillTimer(notification_hwnd, WATCHDOG_TIMER_ID);
+          let (Some(watchdog), Some(kind)) = (hook_watchdog.as_mut(), hook_kind) else { continue };
+          if !watchdog.probe_seen() {
+            if let Some(removed_hook_id) = hook_id.take() {
+              UnhookWindowsHookEx(removed_hook_id);
+            }
+            // Trying again on the next input it misses when it fails
+            match register_hook(kind) {
+              Ok(registered_hook_id) => {
+                info!("the {} hook was removed by Windows, it has been registered again", kind.name());
+                hook_id = Some(registered_hook_id);
+              },
+              Err(e) => error!("the {} hook was removed by Windows, and couldn't be registered again - {}", kind.name(), e)
+            };
+          }
+        },
+        WM_HOTKEY if registered_hotkey_ids.contains(&(msg.wParam.0 as i32)) => {
+          system_tx.send(SystemEvent::ActionTriggered(msg.wParam.0 - ACTION_HOTKEY_BASE_ID as usize))?
+        },
         _ => {}
       };
 
       DispatchMessageW(&msg);
     }
 
-    UnhookWindowsHookEx(hook_id);
+    if let Some(watchdog) = hook_watchdog {
+      KillTimer(notification_hwnd, WATCHDOG_TIMER_ID);
+      watchdog.unregister();
+    }
+    for hotkey_id in registered_hotkey_ids {
+      UnregisterHotKey(HWND(0), hotkey_id);
+    }
+    if consumer_control_registered {
+      unregister_consumer_control();
+    }
+    if keyboard_filter.is_some() {
+      unregister_raw_keyboards();
+    }
+    unregister_device_notifications(monitor_notifications);
+    unregister_device_notifications(hid_notifications);
+    destroy_notification_window(notification_hwnd);
+    unregister_desktop_switch_hook(desktop_hook_id);
+    if let Some(hook_id) = hook_id {
+      UnhookWindowsHookEx(hook_id);
+    }
     Ok(())
   }
 }
 
+/// Listen for the adjustments of the knob on a thread of its own, until stopped or dropped. This is the entry point for
+/// the applications embedding the knob handling, which read the events from the receivers
+pub struct KnobListener {
+  events_rx: Receiver<KnobAdjustmentEvent>,
+  system_rx: Receiver<SystemEvent>,
+  stop_tx: Option<Sender<()>>,
+  handle: Option<JoinHandle<Result<(), HandlerError>>>
+}
+
+impl KnobListener {
+  /// Start listening with the given settings. At most `queue_capacity` events wait in line to be read, the ones that
+  /// come on top of them are dropped
+  pub fn spawn(settings: HandlerSettings, queue_capacity: usize) -> Self {
+    let (events_tx, events_rx) = bounded(queue_capacity);
+    let (system_tx, system_rx) = unbounded();
+    let (stop_tx, stop_rx) = bounded(0);
+    let handle = thread::spawn(move || register_knob_adjustment_handler(stop_rx, events_tx, system_tx, settings));
+    Self { events_rx, system_rx, stop_tx: Some(stop_tx), handle: Some(handle) }
+  }
+
+  /// Get the receiver of the knob adjustments, which is disconnected once the listener stopped
+  pub fn events(&self) -> &Receiver<KnobAdjustmentEvent> {
+    &self.events_rx
+  }
+
+  /// Get the receiver of the system events noticed along the way, such as display changes or the secure desktop
+  pub fn system_events(&self) -> &Receiver<SystemEvent> {
+    &self.system_rx
+  }
+
+  /// Remove the hooks and wait for the listening thread to exit, returning why it failed if it did
+  pub fn stop(mut self) -> Result<(), HandlerError> {
+    self.stop_and_join()
+  }
+
+  fn stop_and_join(&mut self) -> Result<(), HandlerError> {
+    // The stop signal is sent by disconnecting the channel
+    drop(self.stop_tx.take());
+    match self.handle.take().map(|handle| handle.join()) {
+      Some(Ok(result)) => result,
+      Some(Err(_)) => Err(HandlerError::HookError(windows::core::Error::from(E_FAIL))),
+      None => Ok(())
+    }
+  }
+}
+
+impl Drop for KnobListener {
+  fn drop(&mut self) {
+    let _ = self.stop_and_join();
+  }
+}
+
+/// Forward a knob adjustment event to the other thread(s) without ever blocking the message loop. The events queue is
+/// bounded, so that a hung monitor can't build up a backlog of stale events that would be replayed once it recovers:
+/// events that don't fit are dropped
+pub(crate) fn forward_event(events_tx: &Sender<KnobAdjustmentEvent>, event: KnobAdjustmentEvent) -> Result<(), HandlerError> {
+  match events_tx.try_send(event) {
+    Ok(_) => Ok(()),
+    Err(TrySendError::Full(_)) => {
+      stats::record_dropped();
+      Ok(())
+    },
+    Err(TrySendError::Disconnected(event)) => Err(SendError(event).into())
+  }
+}
+
+/// Register the low-level hook of the given kind on the current thread
+unsafe fn register_hook(kind: HookKind) -> windows::core::Result<HHOOK> {
+  match kind {
+    HookKind::Keyboard => SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), HMODULE(0), 0),
+    HookKind::Mouse => SetWindowsHookExW(WH_MOUSE_LL, Some(mouse_hook), HMODULE(0), 0)
+  }
+}
+
 /// Handle low-level keyboard input events
 /// 
 /// Note: A WH_KEYBOARD_LL hook stores the input event data in a KBDLLHOOKSTRUCT struct pointed by the LPARAM argument
@@ -75,24 +579,32 @@ unsafe extern "system" fn keyboard_hook(code: i32, w_param: WPARAM, l_param: LPA
     return CallNextHookEx(HHOOK(0), code, w_param, l_param);
   }
 
-  // Dereference the pointer to get the keyboard input event data
+  // Dereference the pointer to get the keyboard input event data. The identifier of the keyboard message is simply
+  // stored in the WPARAM argument
   let keyboard_event = *(l_param.0 as *const KBDLLHOOKSTRUCT);
-  let key_code = VIRTUAL_KEY(keyboard_event.vkCode as u16);
+  if record_hook_call(keyboard_event.time, keyboard_event.dwExtraInfo) {
+    return LRESULT(1);
+  }
 
-  // The identifier of the keyboard message is simply stored in the WPARAM argument
-  let key_state = w_param.0 as u32;
-  let is_key_up = key_state == WM_KEYUP || key_state == WM_SYSKEYUP;
-  if !is_key_up {
-    return CallNextHookEx(HHOOK(0), code, w_param, l_param);
+  // With the hybrid layers, the volume controls stand for the knob keys while the layer key is held, and whatever
+  // adjusts the brightness is hidden from the other applications
+  let key_code = VIRTUAL_KEY(keyboard_event.vkCode as u16);
+  let is_key_up = matches!(w_param.0 as u32, WM_KEYUP | WM_SYSKEYUP);
+  let route = KNOB_LAYERS.lock().ok().and_then(|mut layers| layers.as_mut().map(|layers| layers.route(key_code, is_key_up)));
+  let posted_key = route.map_or(key_code, |route| route.key_code);
+  PostMessageW(HWND(0), RAW_KEY_MSG, w_param, LPARAM(posted_key.0 as isize));
+  if route.is_some_and(|route| route.suppressed) {
+    return LRESULT(1);
   }
 
-  // Send the parsed keyboard event back to the message loop
-  if let Some(msg) = match key_code {
-    VK_F19 => Some(KnobAdjustmentEvent::Decrement),
-    VK_F20 => Some(KnobAdjustmentEvent::Increment),
-    _ => None
-  } {
-    PostMessageW(HWND(0), msg as u32, WPARAM(0), LPARAM(0));
+  // The volume controls are already handled through Raw Input, so the system must not see them. The brightness keys
+  // are hidden too when asked for
+  if SUPPRESS_VOLUME_KEYS.load(Ordering::Relaxed) && matches!(key_code, VK_VOLUME_UP | VK_VOLUME_DOWN | VK_VOLUME_MUTE) {
+    return LRESULT(1);
+  }
+  let is_knob_key = [&INCREMENT_KEY, &DECREMENT_KEY, &PRESS_KEY].iter().any(|knob_key| key_code.0 == knob_key.load(Ordering::Relaxed));
+  if SUPPRESS_KNOB_KEYS.load(Ordering::Relaxed) && is_knob_key {
+    return LRESULT(1);
   }
   CallNextHookEx(HHOOK(0), code, w_param, l_param)
 }
@@ -102,7 +614,14 @@ unsafe extern "system" fn keyboard_hook(code: i32, w_param: WPARAM, l_param: LPA
 /// Note: A WH_MOUSE_LL hook stores the input event data in a MSLLHOOKSTRUCT struct pointed by the LPARAM argument
 /// Reference: https://stackoverflow.com/a/68827449
 unsafe extern "system" fn mouse_hook(code: i32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
-  if code != HC_ACTION || w_param != WM_MOUSEWHEEL {
+  if code != HC_ACTION {
+    return CallNextHookEx(HHOOK(0), code, w_param, l_param);
+  }
+  let mouse_event = *(l_param.0 as *const MSLLHOOKSTRUCT);
+  if record_hook_call(mouse_event.time, mouse_event.dwExtraInfo) {
+    return LRESULT(1);
+  }
+  if w_param != WM_MOUSEWHEEL {
     return CallNextHookEx(HHOOK(0), code, w_param, l_param);
   }
 
@@ -112,19 +631,97 @@ unsafe extern "system" fn mouse_hook(code: i32, w_param: WPARAM, l_param: LPARAM
   // backward, towards the user
   // 
   // Reference: https://learn.microsoft.com/en-us/windows/win32/api/winuser/ns-winuser-msllhookstruct#members
-  let mouse_event = *(l_param.0 as *const MSLLHOOKSTRUCT);
-  let mouse_delta = ((mouse_event.mouseData >> 16) & 0xffff) as u16 as i16;
-
-  // Send the parsed mouse event back to the message loop
-  let msg = (if mouse_delta > 0 { KnobAdjustmentEvent::Increment } else { KnobAdjustmentEvent::Decrement }) as u32;
-  PostMessageW(HWND(0), msg, WPARAM(0), LPARAM(0));
+  let mouse_delta = ((mouse_event.mouseData >> 16) & 0xffff) as u16;
+  PostMessageW(HWND(0), RAW_WHEEL_MSG, WPARAM(mouse_delta as usize), LPARAM(0));
   CallNextHookEx(HHOOK(0), code, w_param, l_param)
 }
 
+impl InputState {
+  fn new(settings: &HandlerSettings) -> Self {
+    Self {
+      wheel_step: max(WHEEL_DELTA / max(settings.wheel_delta_divisor as i32, 1), 1),
+      wheel_accumulator: 0,
+      smooth_scrolling: settings.smooth_scrolling,
+      emulation_blacklist: settings.emulation_blacklist.iter().map(|name| name.to_lowercase()).collect(),
+      pause_emulation_in_fullscreen: settings.pause_emulation_in_fullscreen,
+      fullscreen_overrides: settings.fullscreen_overrides.iter().map(|(name, kind)| (name.to_lowercase(), *kind)).collect(),
+      emulation_modifiers: settings.emulation_modifiers,
+      emulation_regions: settings.emulation_regions.clone(),
+      foreground_hwnd: HWND(0),
+      foreground_blacklisted: false
+    }
+  }
+
+  /// Turn a raw wheel delta into knob adjustments, either fractions of a step when smooth scrolling or full steps
+  fn wheel_actions(&mut self, mouse_delta: i16) -> Vec<KnobAction> {
+    if self.smooth_scrolling {
+      return vec![KnobAction::Partial(mouse_delta as f64 / self.wheel_step as f64)];
+    }
+
+    // Accumulate the wheel deltas until they add up to a full step, starting over whenever the wheel changes direction
+    let mouse_delta = mouse_delta as i32;
+    if self.wheel_accumulator.signum() * mouse_delta.signum() < 0 {
+      self.wheel_accumulator = 0;
+    }
+
+    self.wheel_accumulator += mouse_delta;
+    let n_steps = self.wheel_accumulator / self.wheel_step;
+    self.wheel_accumulator -= n_steps * self.wheel_step;
+
+    let action = if n_steps > 0 { KnobAction::Increment } else { KnobAction::Decrement };
+    vec![action; n_steps.unsigned_abs() as usize]
+  }
+
+  /// Check whether the wheel is turned into knob adjustments right now, according to the modifiers and the regions
+  fn is_wheel_gate_open(&self) -> bool {
+    if self.emulation_modifiers.0 == 0 && self.emulation_regions.is_empty() {
+      return true;
+    }
+    let modifiers_held = self.emulation_modifiers.0 != 0 && are_modifiers_held(self.emulation_modifiers);
+    modifiers_held || region_under_cursor().is_some_and(|region| self.emulation_regions.contains(&region))
+  }
+
+  /// Check whether the foreground window belongs to one of the blacklisted applications, or to a game running in
+  /// exclusive fullscreen when the emulation is paused for those
+  fn is_foreground_blacklisted(&mut self) -> bool {
+    if self.emulation_blacklist.is_empty() && !self.pause_emulation_in_fullscreen {
+      return false;
+    }
+
+    let foreground_hwnd = unsafe { GetForegroundWindow() };
+    if foreground_hwnd != self.foreground_hwnd {
+      let process_name = window_process_name(foreground_hwnd).map(|name| name.to_lowercase());
+      let blacklisted = process_name.is_some_and(|name| self.emulation_blacklist.contains(&name));
+      let fullscreen = self.pause_emulation_in_fullscreen
+        && fullscreen_kind(foreground_hwnd, &self.fullscreen_overrides) == FullscreenKind::Exclusive;
+      self.foreground_hwnd = foreground_hwnd;
+      self.foreground_blacklisted = blacklisted || fullscreen;
+    }
+    self.foreground_blacklisted
+  }
+}
+
+/// Check whether every one of the modifiers is being held, on either side of the keyboard
+fn are_modifiers_held(modifiers: HOT_KEY_MODIFIERS) -> bool {
+  held_modifiers().0 & modifiers.0 == modifiers.0
+}
+
+/// Get the modifiers being held, on either side of the keyboard
+fn held_modifiers() -> HOT_KEY_MODIFIERS {
+  // The most significant bit is set while the key is down
+  let is_down = |key: VIRTUAL_KEY| unsafe { GetAsyncKeyState(key.0 as i32) } < 0;
+  [(MOD_CONTROL, VK_CONTROL), (MOD_ALT, VK_MENU), (MOD_SHIFT, VK_SHIFT), (MOD_WIN, VK_LWIN), (MOD_WIN, VK_RWIN)]
+    .into_iter()
+    .filter(|(_, key)| is_down(*key))
+    .fold(HOT_KEY_MODIFIERS(0), |modifiers, (modifier, _)| modifiers | modifier)
+}
+
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum HandlerError {
   HookError(windows::core::Error),
-  EventsTXError(crossbeam_channel::SendError<KnobAdjustmentEvent>)
+  EventsTXError(crossbeam_channel::SendError<KnobAdjustmentEvent>),
+  SystemTXError(crossbeam_channel::SendError<SystemEvent>)
 }
 
 impl From<windows::core::Error> for HandlerError {
@@ -138,3 +735,42 @@ impl From<crossbeam_channel::SendError<KnobAdjustmentEvent>> for HandlerError {
     HandlerError::EventsTXError(value)
   }
 }
+
+impl From<crossbeam_channel::SendError<SystemEvent>> for HandlerError {
+  fn from(value: crossbeam_channel::SendError<SystemEvent>) -> Self {
+    HandlerError::SystemTXError(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn wheel_actions(settings: HandlerSettings, mouse_deltas: &[i16]) -> Vec<Vec<KnobAction>> {
+    let mut input_state = InputState::new(&settings);
+    mouse_deltas.iter().map(|&mouse_delta| input_state.wheel_actions(mouse_delta)).collect()
+  }
+
+  #[test]
+  fn wheel_deltas_are_turned_into_proportional_steps() {
+    use KnobAction::{Decrement, Increment};
+    // High-resolution wheels report fractions of a notch, which add up to a step until the wheel turns the other way
+    assert_eq!(wheel_actions(HandlerSettings::default(), &[240]), vec![vec![Increment, Increment]]);
+    assert_eq!(wheel_actions(HandlerSettings::default(), &[60, 60]), vec![vec![], vec![Increment]]);
+    assert_eq!(wheel_actions(HandlerSettings::default(), &[-120]), vec![vec![Decrement]]);
+    assert_eq!(wheel_actions(HandlerSettings::default(), &[60, -120]), vec![vec![], vec![Decrement]]);
+  }
+
+  #[test]
+  fn wheel_delta_divisor_shortens_the_steps() {
+    use KnobAction::Increment;
+    let settings = HandlerSettings { wheel_delta_divisor: 2, ..HandlerSettings::default() };
+    assert_eq!(wheel_actions(settings, &[120, 60]), vec![vec![Increment, Increment], vec![Increment]]);
+  }
+
+  #[test]
+  fn smooth_scrolling_reports_fractions_of_a_step() {
+    let settings = HandlerSettings { smooth_scrolling: true, ..HandlerSettings::default() };
+    assert_eq!(wheel_actions(settings, &[60, -240]), vec![vec![KnobAction::Partial(0.5)], vec![KnobAction::Partial(-2.0)]]);
+  }
+}
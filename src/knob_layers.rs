@@ -0,0 +1,139 @@
+use windows::Win32::UI::Input::KeyboardAndMouse::{VIRTUAL_KEY, VK_VOLUME_DOWN, VK_VOLUME_MUTE, VK_VOLUME_UP};
+
+/// Layer the knob is on with the hybrid layers, which decides whether its volume controls change the volume or the
+/// brightness
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KnobLayer {
+  /// The volume controls go through to the system, which is the layer the knob is on unless the layer key is held
+  Volume,
+  /// The volume controls adjust the brightness instead, without the system seeing them
+  Brightness
+}
+
+/// What the keyboard hook does with a keystroke, with the hybrid layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyRoute {
+  /// Key the message loop handles the keystroke as, which is the knob key a volume control stands for on the brightness
+  /// layer
+  pub key_code: VIRTUAL_KEY,
+  /// Keep the other applications from receiving the keystroke
+  pub suppressed: bool
+}
+
+/// Hybrid layers of the knob, for a single layout to change both the volume and the brightness. The knob sends its
+/// volume controls on the base layer, which adjust the brightness only while the layer key is held, and the knob keys on
+/// the Fn layer of the keyboard, which always adjust the brightness. Whatever adjusts the brightness is kept from the
+/// other applications, while the rest goes through untouched
+#[derive(Debug, Clone)]
+pub struct KnobLayers {
+  /// Key switching the knob to the brightness layer while it's held, the knob staying on the volume one without it
+  layer_key: Option<VIRTUAL_KEY>,
+  increment_key: VIRTUAL_KEY,
+  decrement_key: VIRTUAL_KEY,
+  press_key: Option<VIRTUAL_KEY>,
+  layer_key_held: bool
+}
+
+impl KnobLayers {
+  pub fn new(layer_key: Option<VIRTUAL_KEY>, increment_key: VIRTUAL_KEY, decrement_key: VIRTUAL_KEY, press_key: Option<VIRTUAL_KEY>) -> Self {
+    Self { layer_key, increment_key, decrement_key, press_key, layer_key_held: false }
+  }
+
+  pub fn layer(&self) -> KnobLayer {
+    if self.layer_key_held { KnobLayer::Brightness } else { KnobLayer::Volume }
+  }
+
+  /// Decide what happens to a keystroke, keeping track of whether the layer key is held. The layer key itself always
+  /// goes through, for a modifier to keep working as one
+  pub fn route(&mut self, key_code: VIRTUAL_KEY, is_key_up: bool) -> KeyRoute {
+    if Some(key_code) == self.layer_key {
+      self.layer_key_held = !is_key_up;
+      return KeyRoute { key_code, suppressed: false };
+    }
+
+    let is_knob_key = key_code == self.increment_key || key_code == self.decrement_key || Some(key_code) == self.press_key;
+    let knob_key = match (self.layer(), key_code) {
+      _ if is_knob_key => Some(key_code),
+      (KnobLayer::Brightness, VK_VOLUME_UP) => Some(self.increment_key),
+      (KnobLayer::Brightness, VK_VOLUME_DOWN) => Some(self.decrement_key),
+      // Presses are only seen through the press key, the mute key going through when there's none
+      (KnobLayer::Brightness, VK_VOLUME_MUTE) => self.press_key,
+      _ => None
+    };
+    match knob_key {
+      Some(knob_key) => KeyRoute { key_code: knob_key, suppressed: true },
+      None => KeyRoute { key_code, suppressed: false }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use windows::Win32::UI::Input::KeyboardAndMouse::{VK_A, VK_F13, VK_F19, VK_F20, VK_PAUSE};
+
+  fn knob_layers(press_key: Option<VIRTUAL_KEY>) -> KnobLayers {
+    KnobLayers::new(Some(VK_F13), VK_F20, VK_F19, press_key)
+  }
+
+  fn forwarded(key_code: VIRTUAL_KEY) -> KeyRoute {
+    KeyRoute { key_code, suppressed: false }
+  }
+
+  fn suppressed(key_code: VIRTUAL_KEY) -> KeyRoute {
+    KeyRoute { key_code, suppressed: true }
+  }
+
+  #[test]
+  fn volume_layer_forwards_the_volume_controls() {
+    let mut layers = knob_layers(Some(VK_PAUSE));
+    assert_eq!(layers.layer(), KnobLayer::Volume);
+    for key_code in [VK_VOLUME_UP, VK_VOLUME_DOWN, VK_VOLUME_MUTE, VK_A] {
+      assert_eq!(layers.route(key_code, false), forwarded(key_code));
+      assert_eq!(layers.route(key_code, true), forwarded(key_code));
+    }
+  }
+
+  #[test]
+  fn brightness_layer_turns_the_volume_controls_into_knob_keys() {
+    let mut layers = knob_layers(Some(VK_PAUSE));
+    assert_eq!(layers.route(VK_F13, false), forwarded(VK_F13));
+    assert_eq!(layers.layer(), KnobLayer::Brightness);
+    assert_eq!(layers.route(VK_VOLUME_UP, true), suppressed(VK_F20));
+    assert_eq!(layers.route(VK_VOLUME_DOWN, true), suppressed(VK_F19));
+    assert_eq!(layers.route(VK_VOLUME_MUTE, true), suppressed(VK_PAUSE));
+    assert_eq!(layers.route(VK_A, true), forwarded(VK_A));
+
+    // Releasing the layer key goes back to the volume layer
+    assert_eq!(layers.route(VK_F13, true), forwarded(VK_F13));
+    assert_eq!(layers.layer(), KnobLayer::Volume);
+    assert_eq!(layers.route(VK_VOLUME_UP, true), forwarded(VK_VOLUME_UP));
+  }
+
+  #[test]
+  fn brightness_layer_forwards_the_mute_key_without_a_press_key() {
+    let mut layers = knob_layers(None);
+    layers.route(VK_F13, false);
+    assert_eq!(layers.route(VK_VOLUME_MUTE, true), forwarded(VK_VOLUME_MUTE));
+  }
+
+  #[test]
+  fn knob_keys_are_suppressed_on_both_layers() {
+    let mut layers = knob_layers(Some(VK_PAUSE));
+    for layer_key_held in [false, true] {
+      layers.route(VK_F13, !layer_key_held);
+      for key_code in [VK_F19, VK_F20, VK_PAUSE] {
+        assert_eq!(layers.route(key_code, true), suppressed(key_code));
+      }
+    }
+  }
+
+  #[test]
+  fn knob_stays_on_the_volume_layer_without_a_layer_key() {
+    let mut layers = KnobLayers::new(None, VK_F20, VK_F19, None);
+    assert_eq!(layers.route(VK_F13, false), forwarded(VK_F13));
+    assert_eq!(layers.layer(), KnobLayer::Volume);
+    assert_eq!(layers.route(VK_VOLUME_UP, true), forwarded(VK_VOLUME_UP));
+    assert_eq!(layers.route(VK_F20, true), suppressed(VK_F20));
+  }
+}
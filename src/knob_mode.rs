@@ -0,0 +1,161 @@
+use crate::keyboard_knob::{KnobAction, KnobAdjustmentEvent};
+use crate::monitor::{BRIGHTNESS_VCP_CODE, CONTRAST_VCP_CODE};
+use crate::range_limit::{LimitBehavior, RangeLimit};
+use crate::strings::Text;
+
+use crossbeam_channel::{Receiver, after, never};
+use ddc::FeatureCode;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Represent the setting of the monitors adjusted by the knob
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KnobMode {
+  Brightness,
+  Contrast,
+  Volume
+}
+
+impl KnobMode {
+  /// Get the VCP code of the setting, as defined by the MCCS standard
+  pub fn vcp_code(&self) -> FeatureCode {
+    match self {
+      KnobMode::Brightness => BRIGHTNESS_VCP_CODE,
+      KnobMode::Contrast => CONTRAST_VCP_CODE,
+      KnobMode::Volume => 0x62
+    }
+  }
+
+  /// Get the user-facing name of the setting
+  pub fn label(&self) -> Text {
+    match self {
+      KnobMode::Brightness => Text::Brightness,
+      KnobMode::Contrast => Text::Contrast,
+      KnobMode::Volume => Text::Volume
+    }
+  }
+}
+
+impl fmt::Display for KnobMode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      KnobMode::Brightness => "brightness",
+      KnobMode::Contrast => "contrast",
+      KnobMode::Volume => "volume"
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Represent what pressing the knob does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PressAction {
+  /// Switch the knob to its next mode, or break through the zero floor when there's no other mode
+  #[default]
+  CycleMode,
+  /// Switch the monitors to their next input source, such as from HDMI 1 to HDMI 2 then DisplayPort
+  CycleInput
+}
+
+/// Cycle through the modes of the knob, starting with the brightness, and go back to it after a period of inactivity so
+/// that a secondary mode left selected by mistake doesn't get in the way the next time the knob is turned
+///
+/// The brightness has transitions and verification of its own, so it's left to the brightness thread. The value of the
+/// secondary modes is tracked here instead, and written straight away
+pub struct ModeCycle {
+  /// Modes along with what turning the knob past the ends of their range does
+  modes: Vec<(KnobMode, RangeLimit)>,
+  index: usize,
+  timeout: Duration,
+  /// Value of the current mode, unless it's the brightness
+  value: Option<i32>,
+  remainder: f64
+}
+
+impl ModeCycle {
+  /// Cycle through the brightness then the given secondary modes, which can't include the brightness a second time.
+  /// The modes missing from the limit behaviors are clamped
+  pub fn new(secondary_modes: &[KnobMode], timeout: Duration, limit_behaviors: &BTreeMap<KnobMode, LimitBehavior>, hold_delay: Duration) -> Self {
+    let range_limit = |mode: KnobMode| RangeLimit::new(limit_behaviors.get(&mode).copied().unwrap_or_default(), hold_delay);
+    let mut modes = vec![(KnobMode::Brightness, range_limit(KnobMode::Brightness))];
+    for mode in secondary_modes {
+      if !modes.iter().any(|(known_mode, _)| known_mode == mode) {
+        modes.push((*mode, range_limit(*mode)));
+      }
+    }
+    Self { modes, index: 0, timeout, value: None, remainder: 0.0 }
+  }
+
+  pub fn current(&self) -> KnobMode {
+    self.modes[self.index].0
+  }
+
+  /// Get the limits of the range of the current mode, which the brightness thread applies to the brightness itself
+  pub fn limit(&mut self) -> &mut RangeLimit {
+    &mut self.modes[self.index].1
+  }
+
+  /// Check whether there's any other mode to switch to
+  pub fn can_cycle(&self) -> bool {
+    self.modes.len() > 1
+  }
+
+  /// Switch to the next mode, wrapping around. The value of a secondary mode is unknown until given by `set_value`
+  pub fn next_mode(&mut self) -> KnobMode {
+    self.index = (self.index + 1) % self.modes.len();
+    self.value = None;
+    self.remainder = 0.0;
+    self.current()
+  }
+
+  /// Switch back to the brightness, returning whether it wasn't the current mode already
+  pub fn reset(&mut self) -> bool {
+    let was_reset = self.index != 0;
+    self.index = 0;
+    self.value = None;
+    self.remainder = 0.0;
+    was_reset
+  }
+
+  pub fn value(&self) -> Option<i32> {
+    self.value
+  }
+
+  pub fn set_value(&mut self, value: i32) {
+    self.value = Some(value);
+  }
+
+  /// Apply a knob event to the value of the current secondary mode, returning the new value to write if it changed.
+  /// Secondary modes go from 0 to 100, like the brightness
+  pub fn adjust(&mut self, event: &KnobAdjustmentEvent, step_size: i32) -> Option<i32> {
+    let value = self.value?;
+    let target = match event.action {
+      KnobAction::Increment => (value + step_size) as f64,
+      KnobAction::Decrement => (value - step_size) as f64,
+      KnobAction::Partial(fraction) => value as f64 + self.remainder + fraction * step_size as f64,
+      KnobAction::Press => return None
+    };
+    let rounded_target = target.round() as i32;
+    let limited_target = self.limit().apply(value, rounded_target, 0, 100, event.timestamp);
+    self.remainder = match event.action {
+      KnobAction::Partial(_) if limited_target == rounded_target => target - target.round(),
+      _ => 0.0
+    };
+
+    self.value = Some(limited_target);
+    (limited_target != value).then_some(limited_target)
+  }
+
+  /// Get a timer going off once the current mode has been left alone for long enough, which never does for the
+  /// brightness. It must be restarted on every adjustment
+  pub fn timer(&self) -> Receiver<Instant> {
+    match self.index {
+      0 => never(),
+      _ => after(self.timeout)
+    }
+  }
+}
@@ -0,0 +1,97 @@
+//! Make a knob, such as the one of the Glorious GMMK PRO keyboard, adjust the brightness of the monitors
+//!
+//! The program built on top of this library only wires these pieces together, which other applications can embed on
+//! their own:
+//!
+//! - [`KnobListener`] captures the adjustments of the knob, on a thread of its own
+//! - [`MonitorController`] opens the monitors and talks to them over DDC/CI, or USB for the ones that need it
+//! - [`BrightnessAnimator`] moves the brightness of the monitors smoothly from one value to another, or the one of
+//!   anything else implementing [`BrightnessTarget`]
+//!
+//! ```no_run
+//! use gmmk_pro_brightness_knob::{BrightnessAnimator, KnobListener, MonitorController};
+//! use gmmk_pro_brightness_knob::keyboard_knob::{HandlerSettings, KnobAction};
+//! use gmmk_pro_brightness_knob::monitor_group::MonitorSelection;
+//! use gmmk_pro_brightness_knob::transition::Transition;
+//!
+//! let listener = KnobListener::spawn(HandlerSettings::default(), 64);
+//! let mut monitors = MonitorController::open(&MonitorSelection::All).unwrap();
+//! let mut brightness = monitors.get_brightness().unwrap() as i32;
+//! let animator = BrightnessAnimator::new(Transition::INSTANT);
+//! for event in listener.events().iter() {
+//!   let target = match event.action {
+//!     KnobAction::Increment => (brightness + 1).min(100),
+//!     KnobAction::Decrement => (brightness - 1).max(0),
+//!     _ => continue
+//!   };
+//!   brightness = animator.animate(&mut monitors, brightness, target, |_, _| {}).unwrap();
+//! }
+//! ```
+
+pub mod animator;
+pub mod edid;
+pub mod keyboard_knob;
+#[cfg(feature = "mock-target")]
+pub mod mock_target;
+pub mod monitor;
+pub mod monitor_group;
+pub mod shutdown;
+pub mod system_events;
+pub mod transition;
+
+// Pieces of the program itself, which are not meant to be embedded and might change at any time
+#[doc(hidden)] pub mod actions;
+#[doc(hidden)] pub mod brightness_loop;
+#[doc(hidden)] pub mod capabilities;
+#[doc(hidden)] pub mod clock;
+#[doc(hidden)] pub mod config;
+#[doc(hidden)] pub mod consumer_control;
+#[doc(hidden)] pub mod content_dimming;
+#[doc(hidden)] pub mod ddc_trace;
+#[doc(hidden)] pub mod desktop;
+#[cfg(feature = "dimming-overlay")]
+#[doc(hidden)] pub mod dimming_overlay;
+#[doc(hidden)] pub mod display_snapshot;
+#[doc(hidden)] pub mod elevation;
+#[doc(hidden)] pub mod foreground;
+#[cfg(feature = "gamma-dimming")]
+#[doc(hidden)] pub mod gamma_ramp;
+#[doc(hidden)] pub mod gesture;
+#[cfg(feature = "raw-hid")]
+#[doc(hidden)] pub mod hid;
+#[doc(hidden)] pub mod hook_watchdog;
+#[doc(hidden)] pub mod installer;
+#[doc(hidden)] pub mod ipc;
+#[doc(hidden)] pub mod key_learning;
+#[doc(hidden)] pub mod knob_layers;
+#[doc(hidden)] pub mod knob_mode;
+#[doc(hidden)] pub mod logging;
+#[doc(hidden)] pub mod osd;
+#[doc(hidden)] pub mod paths;
+#[doc(hidden)] pub mod range_limit;
+#[doc(hidden)] pub mod raw_keyboard;
+#[doc(hidden)] pub mod routing;
+#[doc(hidden)] pub mod saved_brightness;
+#[doc(hidden)] pub mod schedule;
+#[doc(hidden)] pub mod sdr_white_level;
+#[doc(hidden)] pub mod service;
+#[doc(hidden)] pub mod shared_state;
+#[doc(hidden)] pub mod single_instance;
+#[doc(hidden)] pub mod state;
+#[doc(hidden)] pub mod stats;
+#[doc(hidden)] pub mod strings;
+#[doc(hidden)] pub mod sun;
+#[cfg(feature = "tray")]
+#[doc(hidden)] pub mod tray;
+#[doc(hidden)] pub mod usage;
+#[cfg(feature = "usb-monitors")]
+#[doc(hidden)] pub mod usb_monitor;
+#[doc(hidden)] pub mod vendor_software;
+#[doc(hidden)] pub mod watch;
+#[cfg(feature = "wmi-monitors")]
+#[doc(hidden)] pub mod wmi_monitor;
+
+pub use self::animator::{BrightnessAnimator, BrightnessTarget};
+pub use self::keyboard_knob::KnobListener;
+/// Group of monitors adjusted together, which is what the knob controls
+pub use self::monitor_group::MonitorGroup as MonitorController;
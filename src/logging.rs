@@ -0,0 +1,54 @@
+use crate::paths::data_dir;
+
+use std::fs;
+use std::io;
+use tracing::error;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+const LOG_DIR_NAME: &str = "logs";
+const LOG_FILE_PREFIX: &str = "gmmk-pro-brightness-knob";
+/// Number of daily log files kept around, the older ones being deleted as the logs rotate
+const MAX_LOG_FILES: usize = 7;
+
+/// Send the logs at the given level and above to the standard error, leaving the standard output to what the commands
+/// print, and to a file rotated daily when asked to. The returned guard flushes the file once dropped, so it has to be
+/// kept until the program exits
+pub fn init(level: LevelFilter, log_to_file: bool) -> Option<WorkerGuard> {
+  let (file_writer, file_error) = match log_to_file {
+    true => match open_log_file() {
+      Ok(appender) => (Some(tracing_appender::non_blocking(appender)), None),
+      Err(e) => (None, Some(e))
+    },
+    false => (None, None)
+  };
+  let (file_layer, guard) = match file_writer {
+    Some((writer, guard)) => (Some(fmt::layer().with_ansi(false).with_target(false).with_writer(writer)), Some(guard)),
+    None => (None, None)
+  };
+
+  // Escape codes show up as is in the legacy console, which is still what cmd.exe opens in on many systems
+  let console_layer = fmt::layer().without_time().with_ansi(false).with_target(false).with_writer(io::stderr);
+  tracing_subscriber::registry().with(console_layer).with(file_layer).with(level).init();
+
+  if let Some(e) = file_error {
+    error!("unable to open the log file - {}", e);
+  }
+  guard
+}
+
+fn open_log_file() -> io::Result<RollingFileAppender> {
+  let dir = data_dir()?.join(LOG_DIR_NAME);
+  fs::create_dir_all(&dir)?;
+  RollingFileAppender::builder()
+    .rotation(Rotation::DAILY)
+    .filename_prefix(LOG_FILE_PREFIX)
+    .filename_suffix("log")
+    .max_log_files(MAX_LOG_FILES)
+    .build(dir)
+    .map_err(io::Error::other)
+}
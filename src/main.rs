@@ -1,108 +1,540 @@
-mod keyboard_knob;
-mod monitor;
+mod cli;
 
-use self::keyboard_knob::{HandlerError, KnobAdjustmentEvent, register_knob_adjustment_handler};
-use self::monitor::Monitor;
+use self::cli::{Cli, Command, StartupAction, VcpCommand};
 
-use crossbeam_channel::{Receiver, bounded, unbounded};
-use ctrlc;
-use keyframe::{ease, functions::EaseInOutCubic};
-use std::cmp::{max, min};
-use std::hint;
-use std::thread;
-use std::time::{Duration, Instant};
+use gmmk_pro_brightness_knob::{capabilities, config, ddc_trace, display_snapshot, installer, ipc, logging, strings, usage, watch};
+#[cfg(feature = "dimming-overlay")]
+use gmmk_pro_brightness_knob::dimming_overlay;
+#[cfg(feature = "raw-hid")]
+use gmmk_pro_brightness_knob::hid::{self, RawHidSettings};
+#[cfg(feature = "osd")]
+use gmmk_pro_brightness_knob::osd;
+#[cfg(feature = "tray")]
+use gmmk_pro_brightness_knob::tray;
+use gmmk_pro_brightness_knob::BrightnessAnimator;
+use gmmk_pro_brightness_knob::actions::find_conflicts;
+use gmmk_pro_brightness_knob::brightness_loop::{BrightnessChannels, configured_selection, open_monitors, run_brightness_loop};
+use gmmk_pro_brightness_knob::config::{Config, ConfigError};
+use gmmk_pro_brightness_knob::desktop::check_interactive_session;
+use gmmk_pro_brightness_knob::elevation::{HelperTaskError, install_helper_task, is_elevated, uninstall_helper_task};
+use gmmk_pro_brightness_knob::installer::{Autostart, InstallError, Installation};
+use gmmk_pro_brightness_knob::ipc::IpcCommand;
+use gmmk_pro_brightness_knob::keyboard_knob::{HandlerError, HandlerSettings, KnobAdjustmentEvent, register_knob_adjustment_handler};
+use gmmk_pro_brightness_knob::monitor::Monitor;
+use gmmk_pro_brightness_knob::monitor_group::{MonitorGroup, MonitorSelection};
+use gmmk_pro_brightness_knob::shared_state::SharedState;
+use gmmk_pro_brightness_knob::service::{self, ServiceError};
+use gmmk_pro_brightness_knob::shutdown::Shutdown;
+use gmmk_pro_brightness_knob::single_instance::{self, SingleInstance};
+use gmmk_pro_brightness_knob::system_events::SystemEvent;
+use gmmk_pro_brightness_knob::transition::Transition;
+use gmmk_pro_brightness_knob::usage::ReportPeriod;
 
-const ANIM_DURATION: Duration = Duration::from_millis(0);
-const MIN_BRIGHTNESS: i32 = 0;
-const MAX_BRIGHTNESS: i32 = 100;
+use clap::Parser;
+use crossbeam_channel::{bounded, unbounded};
+use std::io;
+use std::time::Duration;
+use tracing::{error, info};
 
 fn main() {
-  let (events_tx, events_rx_1) = unbounded::<KnobAdjustmentEvent>();
-  let events_rx_2 = events_rx_1.clone();
+  let cli = Cli::parse();
+  let loaded_config = config::load();
+  let _log_guard = logging::init(cli.log_level(), loaded_config.as_ref().is_ok_and(|(config, _)| config.log_file));
+  let config = match loaded_config {
+    Ok((config, path)) => {
+      if let Some(path) = path {
+        info!("loaded the config from {}", path.display());
+      }
+      config
+    },
+    Err(ConfigError::IOError(e)) => return error!("unable to read the config file - {}", e),
+    Err(ConfigError::ParseError(e)) => return error!("invalid config file - {}", e),
+    Err(ConfigError::InvalidRange(e)) => return error!("invalid config file - {}", e)
+  };
+  strings::init_language(config.locale.as_deref());
+
+  if cli.trace_ddc {
+    match ddc_trace::enable() {
+      Ok(path) => info!("tracing the DDC/CI traffic to {}", path.display()),
+      Err(e) => error!("unable to open the DDC/CI trace - {}", e)
+    };
+  }
+
+  // Every command but `run` does its thing and exits, without ever touching the knob. The ones about the brightness go
+  // through the running instance when there's one, which would otherwise be left with a stale brightness
+  let is_running_elsewhere = single_instance::is_running();
+  if let Some(delta) = cli.once {
+    return match is_running_elsewhere {
+      true => forward_adjustment(&config, delta),
+      false => adjust_once(&config, delta)
+    };
+  }
+  let command = cli.command.unwrap_or(Command::Run);
+  let is_service = matches!(command, Command::Service);
+  match command {
+    Command::Run | Command::Service => {},
+    Command::List => return list_monitors(),
+    Command::Get if is_running_elsewhere => return forward_get(),
+    Command::Get => return print_brightness(&config),
+    Command::Set { value } if is_running_elsewhere => return forward_set(&config, value),
+    Command::Set { value } => return set_brightness(&config, value),
+    Command::Pause | Command::Resume if !is_running_elsewhere => return error!("the program isn't running"),
+    Command::Pause => return forward_toggle(IpcCommand::Pause),
+    Command::Resume => return forward_toggle(IpcCommand::Resume),
+    Command::Trace if !is_running_elsewhere => return error!("the program isn't running"),
+    Command::Trace => return print_ddc_trace(),
+    Command::Capabilities { monitor, json } => return print_capabilities(monitor.unwrap_or_else(|| configured_selection(&config)), json),
+    Command::Vcp { monitor, command } => return run_vcp_command(monitor.unwrap_or_else(|| configured_selection(&config)), command),
+    Command::Snapshot { name } => return save_display_snapshot(&name),
+    Command::Restore { name, duration_ms } => {
+      return restore_display_snapshot(&name, Transition::new(Duration::from_millis(duration_ms), config.animation_easing))
+    },
+    Command::Startup { action } => return manage_autostart(action),
+    Command::InstallService => return report_service_result(service::install_service(), "installed and started"),
+    Command::UninstallService => return report_service_result(service::uninstall_service(), "stopped and removed"),
+    Command::InstallHelper => return report_helper_task_result(install_helper_task(), "installed"),
+    Command::UninstallHelper => return report_helper_task_result(uninstall_helper_task(), "uninstalled"),
+    Command::Install => return report_install_result(installer::install().map(Some)),
+    Command::Uninstall => return report_install_result(installer::uninstall().map(|_| None)),
+    Command::Report { period, json } => return print_usage_report(period, json),
+    Command::Watch if !is_running_elsewhere => return error!("the program isn't running"),
+    Command::Watch => return watch_changes()
+  };
 
-  // Register a Ctrl-C handler to signal when to stop the other threads
-  let (stop_tx, stop_rx) = bounded::<bool>(1);
-  let ctrlc_handler = move || {
-    println!("INFO: sending stop signal to the other threads...");
-    stop_tx.send(true).unwrap();
+  // A second instance would install a second pair of hooks, adjusting the brightness twice for every turn of the knob
+  let _instance = match SingleInstance::acquire() {
+    Ok(Some(instance)) => Some(instance),
+    Ok(None) => return info!("the program is running already, use `get`, `set`, `pause` or `resume` to control it"),
+    Err(e) => {
+      error!("unable to tell whether the program is running already - {}", e);
+      None
+    }
+  };
+
+  // Without an interactive desktop the hooks can't see the knob, which is better reported plainly than by them failing
+  let has_desktop = match check_interactive_session() {
+    Ok(_) if is_service => false,
+    Ok(_) => true,
+    Err(_) if is_service => {
+      info!("running as a service in session 0, which can't drive the monitors of the logged-in user nor capture the knob");
+      false
+    },
+    Err(reason) if config.run_without_desktop => {
+      info!("{}, only controlling the monitors without the knob, the tray icon nor the on-screen display", reason);
+      false
+    },
+    Err(reason) => {
+      error!("{}, the knob can't be captured", reason);
+      error!("start the program from the session of the logged-on user, or set run_without_desktop to only control the monitors");
+      return;
+    }
   };
 
-  if let Err(err_code) = ctrlc::set_handler(ctrlc_handler) {
-    eprintln!("ERROR: failed to register Ctrl-C handler, error code {}", err_code);
+  if has_desktop && !is_elevated() {
+    info!("not running elevated, knob events won't be captured while an elevated window has focus (see `install-helper`)");
+  }
+
+  // It's better not to start at all than to have some bindings silently never fire, or fire along with another one
+  let action_bindings = config.action_bindings();
+  if config.increment_key == config.decrement_key {
+    error!("the knob can't send the same key both ways, set increment_key and decrement_key to different keys");
+    return;
+  }
+  let binding_conflicts = find_conflicts(&action_bindings, &[config.increment_key, config.decrement_key]);
+  if !binding_conflicts.is_empty() {
+    binding_conflicts.iter().for_each(|conflict| error!("{}", conflict));
+    error!("refusing to start until the conflicting bindings are fixed");
     return;
   }
 
-  let mut threads = Vec::new();
-  threads.push(thread::spawn(move || {
-    register_knob_adjustment_handler(stop_rx, events_tx, false).unwrap_or_else(|err| {
-      match err {
-        HandlerError::HookError(e) => eprintln!("ERROR: failed to register a hook for low-level mouse input events - code: {}", e),
-        HandlerError::EventsTXError(_) => eprintln!("ERROR: unable to forward knob adjustment events to the other threads")
-      };
-    });
-  }));
-  threads.push(thread::spawn(move || {
-    let mut primary_monitor = Monitor::new_primary();
-    let mut curr_brightness = primary_monitor.get_brightness() as i32;
-    let mut next_brightness = curr_brightness;
-
-    for received in events_rx_1 {
-      next_brightness = match received {
-        KnobAdjustmentEvent::Increment => min(next_brightness + 1, MAX_BRIGHTNESS),
-        KnobAdjustmentEvent::Decrement => max(next_brightness - 1, MIN_BRIGHTNESS) 
-      };
+  let (events_tx, events_rx) = bounded::<KnobAdjustmentEvent>(config.event_queue_capacity);
+  #[cfg(feature = "raw-hid")]
+  let raw_hid_events_tx = events_tx.clone();
+  let ipc_events_tx = events_tx.clone();
+  let (system_tx, system_rx) = unbounded::<SystemEvent>();
+
+  // Services don't get Ctrl-C, the service control manager asks them to stop instead. The session has to outlive the
+  // shutdown, for the service to only be reported as stopped once everything was torn down
+  let mut shutdown = Shutdown::new();
+  let _service_session = match is_service {
+    true => match service::start_dispatcher(shutdown.request_sender()) {
+      Ok(service_session) => Some(service_session),
+      Err(e) => return error!("unable to connect to the service control manager - {}", e)
+    },
+    false => None
+  };
+  if !is_service {
+    if let Err(err_code) = shutdown.install_ctrlc_handler() {
+      error!("failed to register Ctrl-C handler, error code {}", err_code);
+      return;
+    }
+  }
 
-      // Avoid unnecessary calls
-      if next_brightness != curr_brightness {
-        curr_brightness = match adjust_brightness(&mut primary_monitor, &events_rx_2, curr_brightness, next_brightness, ANIM_DURATION) {
-          Err(_) => curr_brightness,
-          Ok(value) => value
+  let handler_settings = HandlerSettings {
+    emulate_knob: config.emulate_knob,
+    knob_input: config.knob_input,
+    knob_device: config.knob_device.clone(),
+    hook_watchdog: config.hook_watchdog,
+    increment_key: config.increment_key,
+    decrement_key: config.decrement_key,
+    press_key: config.press_key,
+    standby_hold: config.standby_hold,
+    suppress_knob_keys: config.suppress_knob_keys,
+    wheel_delta_divisor: config.wheel_delta_divisor,
+    smooth_scrolling: config.smooth_scrolling,
+    emulation_blacklist: config.emulation_blacklist.clone(),
+    pause_emulation_in_fullscreen: config.pause_emulation_in_fullscreen,
+    fullscreen_overrides: config.fullscreen_overrides.clone().into_iter().collect(),
+    emulation_modifiers: config.emulation_modifiers,
+    emulation_regions: config.emulation_regions.clone(),
+    action_triggers: action_bindings.iter().map(|binding| binding.trigger.clone()).collect(),
+    keyboard_ids: config.keyboard_ids.clone(),
+    learn_knob_keys: config.learn_knob_keys,
+    capture_volume_controls: config.capture_volume_controls,
+    hybrid_layers: config.hybrid_layers,
+    hybrid_layer_key: config.hybrid_layer_key
+  };
+  let tray_system_tx = system_tx.clone();
+  let ipc_system_tx = system_tx.clone();
+  let (state_tx, state_rx) = watch::channel(None);
+  let (osd_tx, osd_rx) = watch::channel(None);
+  let (overlay_tx, overlay_rx) = watch::channel(0u8);
+  // The brightness thread stops once the input hooks are gone, so their senders are handed to it when there are none
+  let mut idle_senders = None;
+  if has_desktop {
+    shutdown.spawn_stage("input hooks", move |stop_rx| {
+      register_knob_adjustment_handler(stop_rx, events_tx, system_tx, handler_settings).unwrap_or_else(|err| {
+        match err {
+          HandlerError::HookError(e) => error!("failed to register a hook for low-level mouse input events - code: {}", e),
+          HandlerError::EventsTXError(e) => error!("unable to forward knob adjustment events to the other threads - {}", e),
+          HandlerError::SystemTXError(e) => error!("unable to forward system events to the other threads - {}", e)
         };
+      });
+    });
+  } else {
+    idle_senders = Some((events_tx, system_tx));
+  }
+  // The keyboard is talked to directly, which works without an interactive desktop too
+  #[cfg(feature = "raw-hid")]
+  if config.raw_hid {
+    let raw_hid_state_rx = state_tx.subscribe();
+    let raw_hid_settings = RawHidSettings {
+      keyboard_ids: config.keyboard_ids.clone(),
+      backlight_min: config.keyboard_backlight_sync.then_some(config.keyboard_backlight_min)
+    };
+    shutdown.spawn_stage("raw HID", move |stop_rx| hid::run_raw_hid(stop_rx, raw_hid_events_tx, raw_hid_state_rx, raw_hid_settings));
+  }
+  #[cfg(not(feature = "raw-hid"))]
+  if config.raw_hid {
+    info!("built without the \"raw-hid\" feature, the keyboard is not talked to over raw HID");
+  }
+  #[cfg(feature = "osd")]
+  if config.show_osd && has_desktop {
+    shutdown.spawn_stage("on-screen display", move |stop_rx| {
+      if let Err(e) = osd::run_osd(stop_rx, osd_rx) {
+        error!("unable to show the on-screen display - {}", e);
       }
-    }
-  }));
+    });
+  }
+  #[cfg(not(feature = "osd"))]
+  if config.show_osd && has_desktop {
+    drop(osd_rx);
+    info!("built without the \"osd\" feature, the on-screen display is not shown");
+  }
+  #[cfg(feature = "dimming-overlay")]
+  if config.dimming_overlay && has_desktop {
+    shutdown.spawn_stage("dimming overlay", move |stop_rx| {
+      if let Err(e) = dimming_overlay::run_dimming_overlay(stop_rx, overlay_rx) {
+        error!("unable to show the dimming overlay - {}", e);
+      }
+    });
+  }
+  #[cfg(not(feature = "dimming-overlay"))]
+  if config.dimming_overlay && has_desktop {
+    drop(overlay_rx);
+    info!("built without the \"dimming-overlay\" feature, the screens are not darkened past the lowest brightness");
+  }
+  #[cfg(feature = "tray")]
+  if config.show_tray_icon && has_desktop {
+    let preset_names = config.presets.keys().cloned().collect();
+    shutdown.spawn_stage("tray icon", move |stop_rx| {
+      if let Err(e) = tray::run_tray_icon(stop_rx, tray_system_tx, state_rx, preset_names) {
+        error!("unable to show the tray icon - {}", e);
+      }
+    });
+  }
+  #[cfg(not(feature = "tray"))]
+  if config.show_tray_icon && has_desktop {
+    drop((tray_system_tx, state_rx));
+    info!("built without the \"tray\" feature, the tray icon is not shown");
+  }
+  let (changes_tx, changes_rx) = unbounded();
+  let ipc_receivers = config.ipc_server.then(|| (state_tx.subscribe(), changes_rx));
+  let monitor_selection = configured_selection(&config);
+  let overlay_shown = config.dimming_overlay && has_desktop && cfg!(feature = "dimming-overlay");
+  shutdown.spawn_stage("brightness", move |stop_rx| {
+    let _idle_senders = idle_senders;
+    let monitors = open_monitors(&monitor_selection, &config).unwrap_or_else(|e| {
+      error!("unable to open the monitors - {}", e);
+      MonitorGroup::default()
+    });
+    let shared_state = SharedState::create().map_err(|e| error!("unable to create the shared memory block - {}", e)).ok();
+    let channels = BrightnessChannels { events_rx, system_rx, state_tx, osd_tx, overlay_tx, changes_tx, shared_state };
+    run_brightness_loop(stop_rx, &config, monitors, monitor_selection, overlay_shown, channels);
+  });
+
+  // External programs can still set the brightness without an interactive desktop, which is where they matter the most.
+  // The server is stopped last, once the brightness thread flushed its final write and saved the brightness
+  if let Some((ipc_state_rx, ipc_changes_rx)) = ipc_receivers {
+    shutdown.spawn_stage("IPC server", move |stop_rx| {
+      if let Err(e) = ipc::run_ipc_server(stop_rx, ipc_events_tx, ipc_system_tx, ipc_state_rx, ipc_changes_rx, is_service) {
+        error!("unable to accept commands from other programs - {}", e);
+      }
+    });
+  }
 
-  for t in threads { t.join().unwrap(); }
+  shutdown.wait();
 }
 
-/// Adjust the brightness of the monitor by smoothly transitioning from the previous value. If a new knob adjustment
-/// event comes through while busy-waiting for the next frame, the transition is interrupted before finishing and the
-/// new event takes priority
-fn adjust_brightness(monitor: &mut Monitor, events_rx: &Receiver<KnobAdjustmentEvent>, prev_value: i32, target_value: i32, transition_duration: Duration) -> Result<i32, Box<dyn std::error::Error>> {
-  let from_brightness = prev_value as f64;
-  let to_brightness = target_value as f64;
+/// Print every monitor whose brightness can be controlled, one per line, with its number, name and brightness
+/// separated by tabs
+fn list_monitors() {
+  let monitors = match Monitor::enumerate_all() {
+    Ok(monitors) => monitors,
+    Err(e) => return error!("unable to enumerate the monitors - {}", e)
+  };
+  for (index, mut monitor) in monitors.into_iter().enumerate() {
+    match monitor.get_brightness() {
+      Ok(value) => println!("{}\t{}\t{}\t{}", index + 1, monitor.name(), value, monitor.id()),
+      Err(e) => error!("unable to read the brightness of {} - {}", monitor.name(), e)
+    };
+  }
+}
+
+fn print_brightness(config: &Config) {
+  let result = open_monitors(&configured_selection(config), config).and_then(|mut monitors| monitors.get_brightness());
+  match result {
+    Ok(value) => println!("{}", value),
+    Err(e) => error!("unable to read the brightness of the monitors - {}", e)
+  };
+}
 
-  // Compute the number of frames required to smoothly transition to the next brightness value in the given duration
-  let refresh_rate = monitor.refresh_rate_hz as f32;
-  let n_frames = max(((transition_duration.as_millis() as f32 * refresh_rate) / 1000.0).ceil() as i32, 1);
+fn set_brightness(config: &Config, value: i32) {
+  let value = value.clamp(config.min_brightness, config.max_brightness);
+  let result = open_monitors(&configured_selection(config), config).and_then(|mut monitors| monitors.set_brightness(value as u16));
+  if let Err(e) = result {
+    error!("unable to set the brightness of the monitors - {}", e);
+  }
+}
 
-  let frame_time_ms = Duration::from_millis(((1.0 / refresh_rate) * 1000.0).floor() as u64);
-  let mut prev_brightness = -1;
+/// Send a command to the running instance instead of talking to the monitors behind its back, returning its reply
+fn forward_command(command: IpcCommand) -> Option<String> {
+  ipc::send_command(&command).map_err(|e| report_ipc_error(&command, e)).ok()
+}
 
-  for frame in 1..=n_frames {
-    // Ease to the target brightness
-    let t = frame as f64 / n_frames as f64;
-    let next_brightness = ease(EaseInOutCubic, from_brightness, to_brightness, t);
-    let next_brightness = (if from_brightness < to_brightness { next_brightness.ceil() } else { next_brightness.floor() }) as i32;
+fn report_ipc_error(command: &IpcCommand, e: io::Error) {
+  match e.kind() {
+    io::ErrorKind::NotFound => error!("the running instance doesn't accept commands, set ipc_server to true in its config"),
+    _ => error!("the running instance refused the command \"{}\" - {}", command, e)
+  };
+}
 
-    // Avoid unnecessary updates
-    if next_brightness != prev_brightness {
-      println!("frame #{}\tvalue {}\tt {}", frame, next_brightness, t);
-      monitor.set_brightness(next_brightness as u16);
-    }
+/// Print the DDC/CI transactions traced by the running instance, oldest first
+fn print_ddc_trace() {
+  match ipc::fetch_trace() {
+    Ok(transactions) => transactions.iter().for_each(|transaction| println!("{}", transaction)),
+    Err(e) => report_ipc_error(&IpcCommand::Trace, e)
+  };
+}
+
+/// Ask the running instance for the brightness
+fn request_brightness() -> Option<i32> {
+  let reply = forward_command(IpcCommand::Get)?;
+  let brightness = reply.strip_prefix("brightness ").and_then(|value| value.parse().ok());
+  if brightness.is_none() {
+    error!("unexpected reply from the running instance \"{}\"", reply);
+  }
+  brightness
+}
+
+fn forward_get() {
+  if let Some(brightness) = request_brightness() {
+    println!("{}", brightness);
+  }
+}
+
+fn forward_toggle(command: IpcCommand) {
+  forward_command(command);
+}
+
+fn forward_set(config: &Config, value: i32) {
+  forward_command(IpcCommand::Set(value.clamp(config.min_brightness, config.max_brightness)));
+}
+
+/// Adjust the brightness through the running instance, which applies its own transition, printing the new target
+fn forward_adjustment(config: &Config, delta: i32) {
+  let Some(prev_value) = request_brightness() else { return };
+  let target_value = (prev_value + delta).clamp(config.min_brightness, config.max_brightness);
+  if forward_command(IpcCommand::Set(target_value)).is_some() {
+    println!("{}", target_value);
+  }
+}
+
+fn save_display_snapshot(name: &str) {
+  let result = display_snapshot::capture().and_then(|snapshot| display_snapshot::save(name, &snapshot));
+  match result {
+    Ok(path) => info!("saved the state of the monitors to {}", path.display()),
+    Err(e) => error!("unable to save the state of the monitors - {}", e)
+  };
+}
+
+fn restore_display_snapshot(name: &str, transition: Transition) {
+  let snapshot = match display_snapshot::load(name) {
+    Ok(snapshot) => snapshot,
+    Err(e) => return error!("unable to read the snapshot \"{}\" - {}", name, e)
+  };
+  if let Err(e) = display_snapshot::restore(&snapshot, transition) {
+    error!("unable to restore the state of the monitors - {}", e);
+  }
+}
+
+/// Adjust the brightness by the given amount, as a single turn of the knob would with the same transition and limits
+fn adjust_once(config: &Config, delta: i32) {
+  let mut monitors = match open_monitors(&configured_selection(config), config) {
+    Ok(monitors) => monitors,
+    Err(e) => return error!("unable to open the monitors - {}", e)
+  };
+  let prev_value = match monitors.get_brightness() {
+    Ok(value) => value as i32,
+    Err(e) => return error!("unable to read the brightness of the monitors - {}", e)
+  };
+  let target_value = (prev_value + delta).clamp(config.min_brightness, config.max_brightness);
+
+  // Nothing interrupts the transition, short of the process being killed
+  let transition = config.knob_transition().limited_to_rate((target_value - prev_value) as f64, config.max_brightness_rate);
+  match BrightnessAnimator::new(transition).animate(&mut monitors, prev_value, target_value, |_, _| {}) {
+    Ok(value) => println!("{}", value),
+    Err(e) => error!("unable to set the brightness of the monitors - {}", e)
+  };
+}
 
-    // Delays next iteration by a precise time interval
-    // Reference: https://stackoverflow.com/a/72837005
-    let time = Instant::now();
-    while time.elapsed() < frame_time_ms {
-      // Interrupt the transition if a new knob adjustment event was registered
-      if !events_rx.is_empty() { return Ok(prev_value); }
+fn print_capabilities(selection: MonitorSelection, as_json: bool) {
+  let result = MonitorGroup::open(&selection).and_then(|mut monitors| {
+    let capabilities = monitors.get_all_capabilities()?;
+    Ok(monitors.names().into_iter().zip(capabilities).collect::<Vec<_>>())
+  });
+  let monitors = match result {
+    Ok(monitors) => monitors,
+    Err(e) => return error!("unable to get the capabilities of the monitors - {}", e)
+  };
+  match as_json {
+    true => println!("{}", capabilities::format_json(&monitors)),
+    false => monitors.iter().for_each(|(monitor_name, capabilities)| println!("{}", capabilities::format_text(monitor_name, capabilities)))
+  };
+}
 
-      hint::spin_loop();
+fn run_vcp_command(selection: MonitorSelection, command: VcpCommand) {
+  let mut monitors = match MonitorGroup::open(&selection) {
+    Ok(monitors) => monitors,
+    Err(e) => return error!("unable to open the monitors - {}", e)
+  };
+  match command {
+    VcpCommand::Get { code, hex, raw } => {
+      let values = match monitors.get_all_vcp_values(code) {
+        Ok(values) => values,
+        Err(e) => return error!("unable to read the VCP feature 0x{:02x} of the monitors - {}", code, e)
+      };
+      // One line per monitor, with its name, then the current and maximum values separated by tabs
+      for (monitor_name, value) in monitors.names().iter().zip(values) {
+        match (hex, raw) {
+          (_, true) => println!("{}\t{:02x} {:02x} {:02x} {:02x} {:02x}", monitor_name, value.ty, value.mh, value.ml, value.sh, value.sl),
+          (true, _) => println!("{}\t0x{:04x}\t0x{:04x}", monitor_name, value.value(), value.maximum()),
+          _ => println!("{}\t{}\t{}", monitor_name, value.value(), value.maximum())
+        };
+      }
+    },
+    VcpCommand::Set { code, value } => {
+      if let Err(e) = monitors.set_vcp(code, value) {
+        error!("unable to write the VCP feature 0x{:02x} of the monitors - {}", code, e);
+      }
     }
+  };
+}
 
-    prev_brightness = next_brightness;
+/// Print every brightness change applied by the running instance as it happens, from the notifications it sends to its
+/// subscribers, until it exits
+fn watch_changes() {
+  let notifications = match ipc::subscribe() {
+    Ok(notifications) => notifications,
+    Err(e) => return report_ipc_error(&IpcCommand::Subscribe, e)
+  };
+  for line in notifications.map_while(Result::ok) {
+    let Some(change) = line.strip_prefix("change ") else { continue };
+    // The name of the monitor comes last, for the spaces it might have
+    if let [from, to, source, duration_ms, monitor_name] = change.splitn(5, ' ').collect::<Vec<_>>().as_slice() {
+      println!("{}\t{}\t{}\t{}\t{}", monitor_name, from, to, source, duration_ms);
+    }
   }
+}
+
+/// Print the usage report, accepting `day` (the default) or `week` as the period and `--json` for machine-readable
+/// output
+fn print_usage_report(period: ReportPeriod, as_json: bool) {
+  match usage::report(period, as_json) {
+    Ok(report) => println!("{}", report),
+    Err(e) => error!("unable to read the usage records - {}", e)
+  };
+}
+
+fn report_service_result(result: Result<(), ServiceError>, action: &str) {
+  match result {
+    Ok(_) => info!("service {}", action),
+    Err(ServiceError::NotElevated) => error!("the service can only be managed from an elevated prompt"),
+    Err(ServiceError::IOError(e)) => error!("failed to run sc.exe - {}", e),
+    Err(ServiceError::ScError(e)) => error!("sc.exe failed - {}", e)
+  };
+}
+
+fn report_helper_task_result(result: Result<(), HelperTaskError>, action: &str) {
+  match result {
+    Ok(_) => info!("elevated helper task {}", action),
+    Err(HelperTaskError::NotElevated) => error!("the elevated helper task can only be managed from an elevated prompt"),
+    Err(HelperTaskError::IOError(e)) => error!("failed to prepare the elevated helper task - {}", e),
+    Err(HelperTaskError::SchtasksError(e)) => error!("schtasks.exe failed - {}", e)
+  };
+}
 
-  Ok(target_value)
+/// Report the outcome of the `install` subcommand, which comes with where it installed the executable to, or of the
+/// `uninstall` one
+fn report_install_result(result: Result<Option<Installation>, InstallError>) {
+  match result {
+    Ok(Some(installation)) => {
+      let started_by = match installation.autostart {
+        Autostart::RunKey => "when logging on",
+        Autostart::HelperTask => "elevated when logging on"
+      };
+      info!("installed to {}, it will be started {}", installation.exe_path.display(), started_by);
+      info!("the settings can be changed in {}", installation.config_path.display());
+    },
+    Ok(None) => info!("uninstalled, along with the settings and the data stored at runtime"),
+    Err(InstallError::IOError(e)) => error!("unable to copy or remove the files - {}", e),
+    Err(InstallError::RegError(e)) => error!("reg.exe failed - {}", e),
+    Err(InstallError::HelperTask(err)) => report_helper_task_result(Err(err), "")
+  };
+}
+
+fn manage_autostart(action: StartupAction) {
+  let result = match action {
+    StartupAction::Enable => installer::enable_autostart().map(Some),
+    StartupAction::Disable => installer::disable_autostart().map(|_| None),
+    StartupAction::Status => Ok(installer::autostart_status())
+  };
+  match result {
+    Ok(Some(Autostart::RunKey)) => info!("started when logging on, through the Run key"),
+    Ok(Some(Autostart::HelperTask)) => info!("started elevated when logging on, through the helper task"),
+    Ok(None) => info!("not started when logging on"),
+    Err(InstallError::IOError(e)) => error!("unable to locate the executable - {}", e),
+    Err(InstallError::RegError(e)) => error!("reg.exe failed - {}", e),
+    Err(InstallError::HelperTask(err)) => report_helper_task_result(Err(err), "")
+  };
 }
@@ -0,0 +1,150 @@
+use crate::animator::BrightnessTarget;
+use crate::monitor::{BRIGHTNESS_VCP_CODE, BrightnessBackend};
+
+use ddc::{FeatureCode, VcpValue};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Stand-in for the monitors that records every brightness written to it rather than talking to real hardware, for
+/// the transitions to be checked without any monitor connected
+#[derive(Debug, Clone)]
+pub struct MockTarget {
+  refresh_rate_hz: u16,
+  /// Every brightness written so far, in order, along with when it was
+  writes: Vec<(u16, Instant)>,
+  /// Error every write past the given number of them fails with, when set
+  failure: Option<(usize, io::ErrorKind)>
+}
+
+impl MockTarget {
+  /// Create a target refreshing at the given rate, which every write succeeds on
+  pub fn new(refresh_rate_hz: u16) -> Self {
+    Self { refresh_rate_hz, writes: Vec::new(), failure: None }
+  }
+
+  /// Make every write past the given number of them fail with an error of the given kind, as disconnected or
+  /// unresponsive monitors would
+  pub fn failing_after(self, successful_writes: usize, kind: io::ErrorKind) -> Self {
+    Self { failure: Some((successful_writes, kind)), ..self }
+  }
+
+  /// Get every brightness written so far, in order
+  pub fn writes(&self) -> Vec<u16> {
+    self.writes.iter().map(|(value, _)| *value).collect()
+  }
+
+  /// Get when each of the brightness values was written, in order
+  pub fn write_times(&self) -> Vec<Instant> {
+    self.writes.iter().map(|(_, written_at)| *written_at).collect()
+  }
+
+  /// Get the brightness written last, if any
+  pub fn last_write(&self) -> Option<u16> {
+    self.writes.last().map(|(value, _)| *value)
+  }
+
+  /// Forget the writes recorded so far
+  pub fn clear(&mut self) {
+    self.writes.clear();
+  }
+}
+
+impl BrightnessTarget for MockTarget {
+  fn refresh_rate_hz(&self) -> u16 {
+    self.refresh_rate_hz
+  }
+
+  fn set_brightness(&mut self, value: u16) -> io::Result<()> {
+    if let Some((successful_writes, kind)) = self.failure {
+      if self.writes.len() >= successful_writes {
+        return Err(io::Error::new(kind, "the mock target was set to fail"));
+      }
+    }
+    self.writes.push((value, Instant::now()));
+    Ok(())
+  }
+}
+
+/// Stand-in for the backend of a single monitor, answering with the brightness it was set to last and recording every
+/// VCP feature written to it rather than talking to real hardware, for the whole brightness thread to be run without any
+/// monitor connected
+#[derive(Debug)]
+pub struct MockMonitor {
+  brightness: u16,
+  writes: MockWrites,
+  /// Error every write past the given number of them fails with, when set
+  failure: Option<(usize, io::ErrorKind)>
+}
+
+impl MockMonitor {
+  /// Create a monitor starting at the given brightness, which every write succeeds on
+  pub fn new(brightness: u16) -> Self {
+    Self { brightness, writes: MockWrites::default(), failure: None }
+  }
+
+  /// Make every write past the given number of them fail with an error of the given kind, as disconnected or
+  /// unresponsive monitors would
+  pub fn failing_after(self, successful_writes: usize, kind: io::ErrorKind) -> Self {
+    Self { failure: Some((successful_writes, kind)), ..self }
+  }
+
+  /// Get the VCP features written so far, and the ones written from now on
+  pub fn writes(&self) -> MockWrites {
+    self.writes.clone()
+  }
+}
+
+impl BrightnessBackend for MockMonitor {
+  fn name(&self) -> String {
+    "Mock monitor".to_string()
+  }
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue> {
+    match code {
+      BRIGHTNESS_VCP_CODE => Ok(VcpValue { ml: 100, ..VcpValue::from_value(self.brightness) }),
+      _ => Err(io::Error::new(io::ErrorKind::Unsupported, format!("the mock monitor has no VCP feature {:#04x}", code)))
+    }
+  }
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    let mut writes = self.writes.0.lock().unwrap();
+    if let Some((successful_writes, kind)) = self.failure {
+      if writes.len() >= successful_writes {
+        return Err(io::Error::new(kind, "the mock monitor was set to fail"));
+      }
+    }
+    if code == BRIGHTNESS_VCP_CODE {
+      self.brightness = value;
+    }
+    writes.push((code, value, Instant::now()));
+    Ok(())
+  }
+}
+
+/// VCP features written to a mock monitor, in order along with when they were, which stay in reach once the monitor is
+/// opened and handed over to the brightness thread
+#[derive(Debug, Clone, Default)]
+pub struct MockWrites(Arc<Mutex<Vec<(FeatureCode, u16, Instant)>>>);
+
+impl MockWrites {
+  /// Get every brightness written so far, in order
+  pub fn brightness(&self) -> Vec<u16> {
+    self.brightness_writes().map(|(value, _)| value).collect()
+  }
+
+  /// Get when each of the brightness values was written, in order
+  pub fn brightness_times(&self) -> Vec<Instant> {
+    self.brightness_writes().map(|(_, written_at)| written_at).collect()
+  }
+
+  /// Check whether nothing at all was written so far
+  pub fn is_empty(&self) -> bool {
+    self.0.lock().unwrap().is_empty()
+  }
+
+  fn brightness_writes(&self) -> impl Iterator<Item=(u16, Instant)> {
+    let writes = self.0.lock().unwrap().clone();
+    writes.into_iter().filter(|(code, _, _)| *code == BRIGHTNESS_VCP_CODE).map(|(_, value, written_at)| (value, written_at))
+  }
+}
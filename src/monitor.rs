@@ -1,44 +1,653 @@
-use ddc::{Ddc, FeatureCode};
-use ddc_winapi::get_physical_monitors_from_hmonitor;
-use windows::Win32::Foundation::POINT;
-use windows::Win32::Graphics::Gdi::{MonitorFromPoint, MONITOR_DEFAULTTOPRIMARY};
+use crate::ddc_trace::{self, Operation};
+use crate::edid::{self, Edid};
+#[cfg(feature = "gamma-dimming")]
+use crate::gamma_ramp::{GammaRamp, SoftwareDimming};
+use crate::sdr_white_level::SdrWhiteLevel;
+#[cfg(feature = "usb-monitors")]
+use crate::usb_monitor::UsbMonitor;
+#[cfg(feature = "wmi-monitors")]
+use crate::wmi_monitor::WmiMonitor;
 
-const BRIGHTNESS_VCP_CODE: FeatureCode = 0x10;
+use ddc::{Ddc, FeatureCode, VcpValue};
+use ddc_winapi::{enumerate_monitors, get_physical_monitors_from_hmonitor};
+use mccs::Capabilities;
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::mem::size_of;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use windows::core::PCWSTR;
+use windows::Win32::Devices::Display::DestroyPhysicalMonitor;
+use windows::Win32::Foundation::{ERROR_DEVICE_NOT_CONNECTED, ERROR_GRAPHICS_INVALID_PHYSICAL_MONITOR_HANDLE, ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS, HANDLE, POINT};
+use windows::Win32::Graphics::Gdi::{
+  EnumDisplayDevicesW, GetMonitorInfoW, MonitorFromPoint, MonitorFromWindow, DISPLAY_DEVICEW, HMONITOR, MONITORINFO, MONITORINFOEXW,
+  MONITOR_DEFAULTTONEAREST, MONITOR_DEFAULTTOPRIMARY
+};
+use windows::Win32::UI::WindowsAndMessaging::{GetCursorPos, GetForegroundWindow};
+
+pub const BRIGHTNESS_VCP_CODE: FeatureCode = 0x10;
+pub const CONTRAST_VCP_CODE: FeatureCode = 0x12;
+/// Input source the display shows, with the values defined by the MCCS standard such as 0x0f for the first DisplayPort
+pub const INPUT_SOURCE_VCP_CODE: FeatureCode = 0x60;
+/// Power mode of the display, which is 1 while it's on and 2 to 5 for the standby, suspend and off modes
+pub const POWER_MODE_VCP_CODE: FeatureCode = 0xd6;
+const POWER_MODE_ON: u16 = 1;
+/// Off as with the display power management, which the monitors can be woken up from over DDC/CI unlike the hard off.
+/// It's the most widely supported of the standby modes
+const POWER_MODE_SOFT_OFF: u16 = 4;
 const POINT_ZERO: POINT = POINT { x: 0, y: 0 };
+#[cfg(feature = "usb-monitors")]
+const USB_ADAPTER_ID: &str = "USB";
+/// Delays before trying a failed operation again, which double every time. Monitors commonly NAK requests for a short
+/// while when waking up, and need some time between two requests anyway
+const RETRY_DELAYS: &[Duration] = &[Duration::from_millis(40), Duration::from_millis(80)];
+
+// Physical monitor handles currently owned by a `Monitor`, so that the ones that slip through the cracks after repeated
+// hotplug cycles can be found and destroyed
+static LIVE_HANDLES: Mutex<Vec<isize>> = Mutex::new(Vec::new());
 
 /// Represent a monitor connected to the PC
 pub struct Monitor {
-  ddc_handle: ddc_winapi::Monitor,
-  pub refresh_rate_hz: u16
+  backend: Box<dyn BrightnessBackend>,
+  adapter_id: String,
+  /// Display the monitor makes up, which USB monitors are not tied to
+  display: Option<HMONITOR>,
+  pub refresh_rate_hz: u16,
+  /// Number of operations in a row that failed, reset by the first one that succeeds
+  pub consecutive_failures: u32,
+  /// Range the brightness written by `set_brightness` is kept within, from 0 to 100
+  pub brightness_range: Option<(u16, u16)>,
+  /// Difference between the brightness of the monitor and the one of the group it's part of, when linked
+  pub brightness_offset: i32,
+  /// Input sources listed by the capability string, once requested
+  supported_inputs: Option<Vec<u8>>,
+  /// Maximum brightness reported by the monitor, once read
+  brightness_maximum: Option<u16>,
+  /// Identity of the monitor, when its EDID could be read
+  edid: Option<Edid>,
+  /// Dimming of the picture taking over the lowest part of the range of the brightness, when enabled
+  #[cfg(feature = "gamma-dimming")]
+  software_dimming: Option<SoftwareDimming>,
+  /// Brightness of the SDR content of the display, adjusted in place of the brightness while HDR is on, when enabled
+  sdr_white_level: Option<SdrWhiteLevel>
+}
+
+// Physical monitor handles, USB devices and the COM objects of WMI are not tied to the thread that opened them, which
+// lets the monitors driven by different display adapters be written to in parallel
+unsafe impl Send for Monitor {}
+
+/// Represent why an operation on a monitor failed
+#[derive(Debug)]
+pub enum MonitorError {
+  /// The monitor is gone, its handle won't ever work again
+  Disconnected(io::Error),
+  /// The monitor or its backend doesn't support the operation
+  Unsupported(io::Error),
+  /// The monitor didn't answer in time or NAKed the request, which usually goes away after a while. It's only reported
+  /// once the retries didn't help
+  Transient(io::Error)
+}
+
+impl MonitorError {
+  fn is_transient(&self) -> bool {
+    matches!(self, MonitorError::Transient(_))
+  }
+}
+
+impl From<io::Error> for MonitorError {
+  fn from(value: io::Error) -> Self {
+    if is_disconnection_error(&value) {
+      return MonitorError::Disconnected(value);
+    }
+    match value.kind() {
+      io::ErrorKind::Unsupported => MonitorError::Unsupported(value),
+      _ => MonitorError::Transient(value)
+    }
+  }
+}
+
+impl From<MonitorError> for io::Error {
+  fn from(value: MonitorError) -> Self {
+    match value {
+      MonitorError::Disconnected(e) | MonitorError::Unsupported(e) | MonitorError::Transient(e) => e
+    }
+  }
+}
+
+impl fmt::Display for MonitorError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      MonitorError::Disconnected(e) => write!(f, "the monitor is disconnected - {}", e),
+      MonitorError::Unsupported(e) => write!(f, "unsupported by the monitor - {}", e),
+      MonitorError::Transient(e) => write!(f, "the monitor didn't answer - {}", e)
+    }
+  }
+}
+
+/// Channel through which the brightness of a monitor is controlled, which is DDC/CI for most of them. Every backend
+/// takes the brightness as the VCP feature it is over DDC/CI, and might not have any other one
+pub trait BrightnessBackend {
+  /// Get the human-readable description of the monitor
+  fn name(&self) -> String;
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue>;
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()>;
+
+  /// Get the MCCS capability string of the monitor, which only the ones driven over DDC/CI have
+  fn capabilities_string(&mut self) -> io::Result<Vec<u8>> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} has no capability string", self.name())))
+  }
+
+  /// Get the physical monitor handle, for the monitors driven over DDC/CI
+  fn handle_id(&self) -> Option<isize> {
+    None
+  }
+}
+
+impl BrightnessBackend for ddc_winapi::Monitor {
+  fn name(&self) -> String {
+    self.description()
+  }
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue> {
+    Ddc::get_vcp_feature(self, code)
+  }
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    Ddc::set_vcp_feature(self, code, value)
+  }
+
+  fn capabilities_string(&mut self) -> io::Result<Vec<u8>> {
+    Ddc::capabilities_string(self)
+  }
+
+  fn handle_id(&self) -> Option<isize> {
+    Some(self.handle() as isize)
+  }
 }
 
 impl Monitor {
-  /// Create a new struct using the primary monitor info
-  pub fn new_primary() -> Self {
+  /// Create a new struct using the primary monitor info. Monitors that only take the brightness over USB, such as the
+  /// Apple Studio Display, the LG UltraFine and some portable USB-C ones, are preferred whenever one of them is
+  /// connected, in the builds with the "usb-monitors" feature: they still show up as physical monitors, but ignore
+  /// every DDC/CI request
+  pub fn new_primary() -> io::Result<Self> {
+    #[cfg(feature = "usb-monitors")]
+    match UsbMonitor::open_first() {
+      Ok(usb_monitor) => return Ok(Self::from_usb(usb_monitor)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+      Err(e) => error!("unable to open the USB monitor, falling back to DDC/CI - {}", e)
+    };
+    Self::new_primary_ddc()
+  }
+
+  /// Create a new struct for every connected monitor whose brightness can be controlled, starting with the USB one if
+  /// any, then the primary one. The physical monitors that don't answer to DDC/CI brightness requests are left out,
+  /// unless they are built-in displays taking it over WMI in the builds with the "wmi-monitors" feature, or get their
+  /// picture dimmed instead in the builds with the "gamma-dimming" feature
+  pub fn enumerate_all() -> io::Result<Vec<Self>> {
+    let mut monitors = Vec::new();
+    #[cfg(feature = "usb-monitors")]
+    match UsbMonitor::open_first() {
+      Ok(usb_monitor) => monitors.push(Self::from_usb(usb_monitor)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {},
+      Err(e) => error!("unable to open the USB monitor - {}", e)
+    };
+
+    let primary_hmonitor_handle = unsafe { MonitorFromPoint(POINT_ZERO, MONITOR_DEFAULTTOPRIMARY) };
+    let mut hmonitor_handles: Vec<HMONITOR> = enumerate_monitors()?.into_iter().map(|handle| HMONITOR(handle as isize)).collect();
+    hmonitor_handles.sort_by_key(|handle| *handle != primary_hmonitor_handle);
+
+    #[cfg(feature = "wmi-monitors")]
+    let mut wmi_monitors = open_wmi_monitors();
+    for hmonitor_handle in hmonitor_handles {
+      match Self::from_hmonitor(hmonitor_handle) {
+        Ok(display_monitors) => monitors.extend(display_monitors.into_iter().enumerate().filter_map(|(index, mut monitor)| {
+          if monitor.get_brightness().is_ok() {
+            return Some(monitor);
+          }
+          #[cfg(feature = "wmi-monitors")]
+          if let Some(wmi_monitor) = monitor.wmi_counterpart(&mut wmi_monitors) {
+            return Some(wmi_monitor);
+          }
+          // The gamma ramp is the one of the whole display, which only the first of its physical monitors gets
+          #[cfg(feature = "gamma-dimming")]
+          if index == 0 {
+            return monitor.gamma_counterpart();
+          }
+          let _ = index;
+          None
+        })),
+        Err(e) => error!("unable to open the physical monitors of a display - {}", e)
+      };
+    }
+    Ok(monitors)
+  }
+
+  fn new_primary_ddc() -> io::Result<Self> {
     // Get the handle to the primary monitor. By definition, the primary monitor has its upper-left corner at (0, 0)
     let hmonitor_handle = unsafe { MonitorFromPoint(POINT_ZERO, MONITOR_DEFAULTTOPRIMARY) };
-    let physical_handle = get_physical_monitors_from_hmonitor(hmonitor_handle.0 as *mut _).unwrap()[0];
-  
-    let mut ddc_handle = unsafe { ddc_winapi::Monitor::new(physical_handle) };
-    let refresh_rate_hz = match ddc_handle.get_timing_report() {
-      Ok(report) => report.vertical_frequency / 100,
-      _ => 60u16
-    };
-  
+    Self::from_display(hmonitor_handle)
+  }
+
+  /// Create a new struct for the given display, which is driven over DDC/CI. The built-in displays that don't answer it
+  /// are driven over WMI in the builds with the "wmi-monitors" feature, and the other ones get their picture dimmed in
+  /// the builds with the "gamma-dimming" feature. Only the first physical monitor of the display is controlled, the
+  /// handles of the other ones are destroyed as they get dropped
+  pub fn from_display(hmonitor_handle: HMONITOR) -> io::Result<Self> {
+    #[allow(unused_mut)]
+    let mut monitor = Self::from_hmonitor(hmonitor_handle)?
+      .into_iter()
+      .next()
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the display has no physical monitors"))?;
+    #[cfg(feature = "wmi-monitors")]
+    if monitor.get_brightness().is_err() {
+      if let Some(wmi_monitor) = monitor.wmi_counterpart(&mut open_wmi_monitors()) {
+        monitor = wmi_monitor;
+      }
+    }
+    #[cfg(feature = "gamma-dimming")]
+    if monitor.get_brightness().is_err() {
+      if let Some(gamma_monitor) = monitor.gamma_counterpart() {
+        monitor = gamma_monitor;
+      }
+    }
+    Ok(monitor)
+  }
+
+  /// Create a new struct for every physical monitor making up a display, which are driven over DDC/CI
+  fn from_hmonitor(hmonitor_handle: HMONITOR) -> io::Result<Vec<Self>> {
+    let device_name = display_device_name(hmonitor_handle);
+    let adapter_id = device_name.and_then(|device_name| adapter_id(&device_name)).unwrap_or_default();
+    let mut edids = device_name.map(|device_name| edid::read_display_edids(&device_name)).unwrap_or_default().into_iter();
+    let monitors = get_physical_monitors_from_hmonitor(hmonitor_handle.0 as *mut _)?
+      .into_iter()
+      .map(|physical_handle| {
+        let mut ddc_handle = unsafe { ddc_winapi::Monitor::new(physical_handle) };
+        LIVE_HANDLES.lock().unwrap().push(ddc_handle.handle() as isize);
+
+        let refresh_rate_hz = match ddc_handle.get_timing_report() {
+          Ok(report) => report.vertical_frequency / 100,
+          _ => 60u16
+        };
+        Self::with_backend(Box::new(ddc_handle), adapter_id.clone(), Some(hmonitor_handle), edids.next().flatten(), refresh_rate_hz)
+      })
+      .collect();
+    Ok(monitors)
+  }
+
+  #[cfg(feature = "usb-monitors")]
+  fn from_usb(usb_monitor: UsbMonitor) -> Self {
+    Self::with_backend(Box::new(usb_monitor), USB_ADAPTER_ID.to_string(), None, None, 60)
+  }
+
+  /// Create a monitor controlled through the given backend alone, which isn't attached to any display
+  #[cfg(feature = "mock-target")]
+  pub fn from_backend(backend: Box<dyn BrightnessBackend>) -> Self {
+    Self::with_backend(backend, String::new(), None, None, 60)
+  }
+
+  fn with_backend(backend: Box<dyn BrightnessBackend>, adapter_id: String, display: Option<HMONITOR>, edid: Option<Edid>, refresh_rate_hz: u16) -> Self {
     Self {
-      ddc_handle,
-      refresh_rate_hz
+      backend,
+      adapter_id,
+      display,
+      refresh_rate_hz,
+      consecutive_failures: 0,
+      brightness_range: None,
+      brightness_offset: 0,
+      supported_inputs: None,
+      brightness_maximum: None,
+      edid,
+      #[cfg(feature = "gamma-dimming")]
+      software_dimming: None,
+      sdr_white_level: None
+    }
+  }
+
+  /// Create a new struct driving the same monitor over WMI, when it's one of the given built-in displays, which are told
+  /// apart by the model in their EDID. The handle of the monitor is destroyed once it's dropped
+  #[cfg(feature = "wmi-monitors")]
+  fn wmi_counterpart(&self, wmi_monitors: &mut Vec<WmiMonitor>) -> Option<Self> {
+    let model_id = self.edid.as_ref()?.model_id();
+    let index = wmi_monitors.iter().position(|wmi_monitor| wmi_monitor.model_id() == Some(model_id.as_str()))?;
+    info!("{} doesn't answer DDC/CI, controlling it over WMI", model_id);
+    let wmi_monitor = wmi_monitors.swap_remove(index);
+    Some(Self::with_backend(Box::new(wmi_monitor), self.adapter_id.clone(), self.display, self.edid.clone(), self.refresh_rate_hz))
+  }
+
+  /// Create a new struct dimming the picture of the display of the monitor, for the ones that can't be controlled any
+  /// other way. The handle of the monitor is destroyed once it's dropped
+  #[cfg(feature = "gamma-dimming")]
+  fn gamma_counterpart(&self) -> Option<Self> {
+    let gamma_ramp = GammaRamp::open(self.display?).map_err(|e| error!("unable to open the gamma ramp of {} - {}", self.name(), e)).ok()?;
+    info!("{} doesn't answer DDC/CI, dimming its picture instead", self.name());
+    Some(Self::with_backend(Box::new(gamma_ramp), self.adapter_id.clone(), self.display, self.edid.clone(), self.refresh_rate_hz))
+  }
+
+  /// Dim the picture of the monitor once its backlight is at its lowest, over the given share of the range of the
+  /// brightness from 0, for the monitors that are still too bright at their lowest
+  #[cfg(feature = "gamma-dimming")]
+  pub fn enable_software_dimming(&mut self, share: u16) -> io::Result<()> {
+    let display = self.display.ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "the monitor isn't tied to a display"))?;
+    self.software_dimming = Some(SoftwareDimming::new(GammaRamp::open(display)?, share));
+    Ok(())
+  }
+
+  /// Adjust the brightness of the SDR content of the display of the monitor in place of its brightness while HDR is on
+  /// for it, given that most monitors ignore the brightness set over DDC/CI in that mode
+  pub fn enable_sdr_white_level(&mut self) -> io::Result<()> {
+    let display = self.display.ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "the monitor isn't tied to a display"))?;
+    self.sdr_white_level = Some(SdrWhiteLevel::open(display)?);
+    Ok(())
+  }
+
+  /// Get the SDR white level of the display while HDR is on for it, which is when it's what the brightness stands for.
+  /// The state of HDR is read every time, given that it can be toggled without the display configuration changing
+  fn hdr_white_level(&self) -> Option<&SdrWhiteLevel> {
+    self.sdr_white_level.as_ref().filter(|sdr_white_level| sdr_white_level.is_hdr_enabled().unwrap_or(false))
+  }
+
+  /// Get the human-readable description of the monitor, as reported by the driver
+  pub fn name(&self) -> String {
+    self.backend.name()
+  }
+
+  /// Get the identity of the monitor as described by its EDID, which USB monitors don't have
+  pub fn edid(&self) -> Option<&Edid> {
+    self.edid.as_ref()
+  }
+
+  /// Get a stable identifier of the monitor, which is the one of its EDID, falling back to its name when it has none
+  pub fn id(&self) -> String {
+    self.edid.as_ref().map_or_else(|| self.name(), Edid::id)
+  }
+
+  /// Get the identifier of the display adapter driving the monitor, which is shared by all of its outputs. USB monitors
+  /// are not driven by any adapter, so they all share a made-up one
+  pub fn adapter_id(&self) -> &str {
+    &self.adapter_id
+  }
+
+  /// Get the brightness of the monitor from 0 to 100, scaled from the maximum it reports, which isn't 100 for all of
+  /// them. It's the SDR white level while HDR is on, when enabled
+  pub fn get_brightness(&mut self) -> Result<u16, MonitorError> {
+    if let Some(sdr_white_level) = self.hdr_white_level() {
+      return Ok(sdr_white_level.get()?);
+    }
+    let value = self.get_vcp_value(BRIGHTNESS_VCP_CODE)?;
+    let maximum = self.remember_brightness_maximum(value.maximum());
+    let value = ((value.value() as u32 * 100 + maximum as u32 / 2) / maximum as u32).min(100) as u16;
+    #[cfg(feature = "gamma-dimming")]
+    if let Some(software_dimming) = &self.software_dimming {
+      return Ok(software_dimming.brightness(value));
+    }
+    Ok(value)
+  }
+
+  /// Set the brightness of the monitor from 0 to 100, kept within its range and scaled to the maximum it reports. It's
+  /// the SDR white level that's set while HDR is on, when enabled
+  pub fn set_brightness(&mut self, value: u16) -> Result<(), MonitorError> {
+    let value = self.within_range(value);
+    if let Some(sdr_white_level) = self.hdr_white_level() {
+      return Ok(sdr_white_level.set(value)?);
     }
+    #[cfg(feature = "gamma-dimming")]
+    let value = match &mut self.software_dimming {
+      Some(software_dimming) => software_dimming.set_brightness(value)?,
+      None => value
+    };
+    let maximum = match self.brightness_maximum {
+      Some(maximum) => maximum,
+      None => {
+        let maximum = self.get_vcp_value(BRIGHTNESS_VCP_CODE)?.maximum();
+        self.remember_brightness_maximum(maximum)
+      }
+    };
+    let device_value = (value.min(100) as u32 * maximum as u32 + 50) / 100;
+    self.set_vcp(BRIGHTNESS_VCP_CODE, device_value as u16)
+  }
+
+  /// Get the brightness the monitor is set to by its group when the group is at the given one, which is offset when
+  /// the monitors are linked and kept within the range of the monitor
+  pub fn brightness_in_group(&self, value: u16) -> u16 {
+    self.within_range((value as i32 + self.brightness_offset).clamp(0, 100) as u16)
   }
 
-  /// Get the brightness of the current monitor, or fetches the primary monitor first to get the most up-to-date one
-  pub fn get_brightness(&mut self) -> u16 {
-    // The current monitor brightness is held in the low byte of the VCP value
-    let value = self.ddc_handle.get_vcp_feature(BRIGHTNESS_VCP_CODE).unwrap();
-    value.sl as u16
+  fn within_range(&self, value: u16) -> u16 {
+    self.brightness_range.map_or(value, |(min_value, max_value)| value.clamp(min_value, max_value))
   }
 
-  pub fn set_brightness(&mut self, value: u16) {
-    self.ddc_handle.set_vcp_feature(BRIGHTNESS_VCP_CODE, value).unwrap();
+  /// Keep the maximum brightness reported by the monitor, falling back to 100 for the ones that report none
+  fn remember_brightness_maximum(&mut self, maximum: u16) -> u16 {
+    let maximum = if maximum == 0 { 100 } else { maximum };
+    self.brightness_maximum = Some(maximum);
+    maximum
   }
+
+  pub fn get_contrast(&mut self) -> Result<u16, MonitorError> {
+    self.get_vcp(CONTRAST_VCP_CODE)
+  }
+
+  pub fn set_contrast(&mut self, value: u16) -> Result<(), MonitorError> {
+    self.set_vcp(CONTRAST_VCP_CODE, value)
+  }
+
+  /// Get the current value of a VCP feature. The monitors driven by something else than DDC/CI only have the brightness
+  pub fn get_vcp(&mut self, code: FeatureCode) -> Result<u16, MonitorError> {
+    // The current value is held in the low byte of the VCP value
+    self.get_vcp_value(code).map(|value| value.sl as u16)
+  }
+
+  /// Get the whole reply of the monitor to a VCP feature request, including the maximum value. The one of the monitors
+  /// driven by something else than DDC/CI is made up, given that they have no such thing
+  pub fn get_vcp_value(&mut self, code: FeatureCode) -> Result<VcpValue, MonitorError> {
+    self.with_retries(|monitor| {
+      let started_at = Instant::now();
+      let result = monitor.backend.get_vcp_feature(code);
+      monitor.trace(Operation::Read, code, result.as_ref().ok().map(|value| value.value()), started_at, result.as_ref().map(|_| ()));
+      result
+    })
+  }
+
+  pub fn set_vcp(&mut self, code: FeatureCode, value: u16) -> Result<(), MonitorError> {
+    self.with_retries(|monitor| {
+      let started_at = Instant::now();
+      let result = monitor.backend.set_vcp_feature(code, value);
+      monitor.trace(Operation::Write, code, Some(value), started_at, result.as_ref().map(|_| ()));
+      result
+    })
+  }
+
+  /// Get the input sources the display can be switched to, in the order of their values as listed by its capability
+  /// string. The capabilities are only requested the first time, given how long monitors take to send them
+  pub fn supported_inputs(&mut self) -> Result<Vec<u8>, MonitorError> {
+    if self.supported_inputs.is_none() {
+      let capabilities = self.capabilities()?;
+      let inputs = capabilities.vcp_features
+        .get(&INPUT_SOURCE_VCP_CODE)
+        .map(|descriptor| descriptor.values.keys().copied().collect())
+        .unwrap_or_default();
+      self.supported_inputs = Some(inputs);
+    }
+    Ok(self.supported_inputs.clone().unwrap_or_default())
+  }
+
+  /// Switch the display to the next input source it supports, wrapping around, and return it. Fails with
+  /// `MonitorError::Unsupported` when there's no other input to switch to
+  pub fn cycle_input(&mut self) -> Result<u8, MonitorError> {
+    let inputs = self.supported_inputs()?;
+    if inputs.len() < 2 {
+      return Err(MonitorError::Unsupported(io::Error::new(io::ErrorKind::Unsupported, "no other input source is listed by the monitor")));
+    }
+    // Some monitors report the input in both bytes, only the low one is defined by the standard
+    let current_input = self.get_vcp(INPUT_SOURCE_VCP_CODE)? as u8;
+    let next_input = match inputs.iter().position(|input| *input == current_input) {
+      Some(index) => inputs[(index + 1) % inputs.len()],
+      None => inputs[0]
+    };
+    self.set_vcp(INPUT_SOURCE_VCP_CODE, next_input as u16)?;
+    Ok(next_input)
+  }
+
+  /// Check whether the display is in standby or turned off, in which case most monitors acknowledge brightness writes
+  /// and drop them. Fails with `MonitorError::Unsupported` for the monitors that don't report their power mode
+  pub fn is_asleep(&mut self) -> Result<bool, MonitorError> {
+    self.get_vcp(POWER_MODE_VCP_CODE).map(|mode| mode != POWER_MODE_ON)
+  }
+
+  /// Turn the display back on, which takes a few seconds for most of them
+  pub fn wake(&mut self) -> Result<(), MonitorError> {
+    self.set_vcp(POWER_MODE_VCP_CODE, POWER_MODE_ON)
+  }
+
+  /// Put the display in standby, until it's woken up by `wake` or its power button
+  pub fn standby(&mut self) -> Result<(), MonitorError> {
+    self.set_vcp(POWER_MODE_VCP_CODE, POWER_MODE_SOFT_OFF)
+  }
+
+  /// Run an operation until it succeeds, trying again after a while when it failed for a reason that might go away
+  fn with_retries<T>(&mut self, mut operation: impl FnMut(&mut Self) -> io::Result<T>) -> Result<T, MonitorError> {
+    let mut delays = RETRY_DELAYS.iter();
+    loop {
+      let error = match operation(self) {
+        Ok(value) => return Ok(value),
+        Err(e) => MonitorError::from(e)
+      };
+      match delays.next() {
+        Some(delay) if error.is_transient() => {
+          info!("{} didn't answer, trying again in {} ms - {}", self.name(), delay.as_millis(), error);
+          thread::sleep(*delay);
+        },
+        _ => return Err(error)
+      };
+    }
+  }
+
+  /// Request and parse the MCCS capability string of the monitor, which lists the VCP features it supports along with
+  /// their allowed values. Only the monitors driven over DDC/CI have one
+  pub fn capabilities(&mut self) -> io::Result<Capabilities> {
+    mccs_caps::parse_capabilities(self.backend.capabilities_string()?)
+  }
+
+  fn trace(&self, operation: Operation, code: FeatureCode, value: Option<u16>, started_at: Instant, result: Result<(), &io::Error>) {
+    if ddc_trace::is_enabled() {
+      ddc_trace::record(&self.name(), operation, code, value, started_at.elapsed(), result);
+    }
+  }
+
+  /// Get the display the monitor makes up, which USB monitors are not tied to
+  pub fn display(&self) -> Option<HMONITOR> {
+    self.display
+  }
+}
+
+impl Drop for Monitor {
+  fn drop(&mut self) {
+    // The handle itself is destroyed by ddc_winapi right after this
+    let Some(handle_id) = self.backend.handle_id() else { return };
+    let mut live_handles = LIVE_HANDLES.lock().unwrap();
+    if let Some(index) = live_handles.iter().position(|live_handle| *live_handle == handle_id) {
+      live_handles.swap_remove(index);
+    }
+  }
+}
+
+/// Get the display the mouse cursor is on, which is the primary one if the cursor position can't be read
+pub fn display_under_cursor() -> HMONITOR {
+  unsafe {
+    let mut cursor = POINT_ZERO;
+    GetCursorPos(&mut cursor);
+    MonitorFromPoint(cursor, MONITOR_DEFAULTTONEAREST)
+  }
+}
+
+/// Get the display hosting most of the foreground window, which is the primary one while no window has the focus
+pub fn display_of_foreground_window() -> HMONITOR {
+  unsafe { MonitorFromWindow(GetForegroundWindow(), MONITOR_DEFAULTTOPRIMARY) }
+}
+
+/// Get the device name of a display, such as \\.\DISPLAY1, as a NUL-terminated wide string
+pub(crate) fn display_device_name(hmonitor_handle: HMONITOR) -> Option<[u16; 32]> {
+  let mut monitor_info = MONITORINFOEXW::default();
+  monitor_info.monitorInfo.cbSize = size_of::<MONITORINFOEXW>() as u32;
+  match unsafe { GetMonitorInfoW(hmonitor_handle, &mut monitor_info as *mut MONITORINFOEXW as *mut MONITORINFO) }.as_bool() {
+    true => Some(monitor_info.szDevice),
+    false => None
+  }
+}
+
+/// Get the identifier of the display adapter driving a display, given its device name, as its PnP device ID
+fn adapter_id(device_name: &[u16; 32]) -> Option<String> {
+  // Every output of an adapter is listed as a device of its own, all of them carrying the ID of the adapter
+  let mut display_device = DISPLAY_DEVICEW { cb: size_of::<DISPLAY_DEVICEW>() as u32, ..Default::default() };
+  let mut device_index = 0;
+  while unsafe { EnumDisplayDevicesW(PCWSTR::null(), device_index, &mut display_device, 0) }.as_bool() {
+    if display_device.DeviceName == *device_name {
+      return Some(from_wide(&display_device.DeviceID));
+    }
+    device_index += 1;
+  }
+  None
+}
+
+/// Open the built-in displays taking the brightness over WMI, which is none of them when WMI is unavailable
+#[cfg(feature = "wmi-monitors")]
+fn open_wmi_monitors() -> Vec<WmiMonitor> {
+  WmiMonitor::open_all().unwrap_or_else(|e| {
+    error!("unable to look for built-in displays - {}", e);
+    Vec::new()
+  })
+}
+
+pub(crate) fn from_wide(chars: &[u16]) -> String {
+  let length = chars.iter().position(|c| *c == 0).unwrap_or(chars.len());
+  String::from_utf16_lossy(&chars[..length])
+}
+
+/// Outcome of a consistency check of the physical monitor handles
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HandleCheck {
+  /// Handles that were still open without any monitor owning them, which have been destroyed
+  pub leaked: usize,
+  /// Handles that were tracked more than once, which have been deduplicated
+  pub duplicated: usize
+}
+
+/// Check that the open physical monitor handles are exactly the ones owned by `monitors`, which must be every
+/// `Monitor` currently alive, and destroy the others
+pub fn check_physical_handles(monitors: &[&Monitor]) -> HandleCheck {
+  let owned_handles: HashSet<isize> = monitors.iter().filter_map(|monitor| monitor.backend.handle_id()).collect();
+  let mut live_handles = LIVE_HANDLES.lock().unwrap();
+  let mut check = HandleCheck::default();
+
+  let mut seen_handles = HashSet::new();
+  live_handles.retain(|live_handle| {
+    if !seen_handles.insert(*live_handle) {
+      check.duplicated += 1;
+      return false;
+    }
+    if !owned_handles.contains(live_handle) {
+      unsafe { DestroyPhysicalMonitor(HANDLE(*live_handle)); }
+      check.leaked += 1;
+      return false;
+    }
+    true
+  });
+  check
+}
+
+/// Check whether a DDC/CI or USB HID error was caused by the monitor handle becoming invalid, which happens when the
+/// display gets disconnected. Every other error is assumed to be transient
+pub fn is_disconnection_error(err: &io::Error) -> bool {
+  matches!(
+    err.raw_os_error(),
+    Some(code) if code == ERROR_GRAPHICS_INVALID_PHYSICAL_MONITOR_HANDLE.0
+      || code == ERROR_GRAPHICS_MONITOR_NO_LONGER_EXISTS.0
+      || code == ERROR_DEVICE_NOT_CONNECTED.0 as i32
+  )
 }
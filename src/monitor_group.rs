@@ -0,0 +1,558 @@
+use crate::monitor::{CONTRAST_VCP_CODE, Monitor, MonitorError, display_of_foreground_window, display_under_cursor};
+
+use crossbeam_channel::{Receiver, Sender, unbounded};
+use ddc::{FeatureCode, VcpValue};
+use mccs::Capabilities;
+use serde::Deserialize;
+use std::io;
+use std::mem;
+use std::str::FromStr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use windows::Win32::Graphics::Gdi::HMONITOR;
+
+/// Represent which of the connected monitors are adjusted by the knob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MonitorSelection {
+  Primary,
+  All,
+  /// Every monitor whose description contains any of the given names
+  Named(Vec<String>),
+  /// The monitor at the given position among every connected one, starting from 1, as printed by the `list` command
+  Numbered(usize),
+  /// The monitor of the display that's followed, which is looked up again every time the knob starts being turned
+  Followed(FollowedMonitor)
+}
+
+impl FromStr for MonitorSelection {
+  type Err = String;
+
+  /// Parse a monitor given by its number, as printed by the `list` command, or by part of its name
+  fn from_str(arg: &str) -> Result<Self, Self::Err> {
+    match arg.parse::<usize>() {
+      Ok(0) => Err("monitors are numbered from 1".to_string()),
+      Ok(number) => Ok(MonitorSelection::Numbered(number)),
+      Err(_) => Ok(MonitorSelection::Named(vec![arg.to_string()]))
+    }
+  }
+}
+
+/// Represent which display gets adjusted when following the user around the screens
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FollowedMonitor {
+  /// The display the mouse cursor is on
+  Cursor,
+  /// The display hosting the window that has the focus
+  FocusedWindow
+}
+
+impl FollowedMonitor {
+  /// Get the display to adjust right now
+  pub fn display(&self) -> HMONITOR {
+    match self {
+      FollowedMonitor::Cursor => display_under_cursor(),
+      FollowedMonitor::FocusedWindow => display_of_foreground_window()
+    }
+  }
+}
+
+/// Represent what's done with the monitors found in standby or turned off before a transition
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AsleepMonitors {
+  /// Leave them out until they are turned on again, then bring them to the brightness of the others
+  #[default]
+  Skip,
+  /// Turn them on, then adjust them along with the others
+  Wake
+}
+
+/// Shortest delay between two checks of the power mode of the monitors, which would otherwise cost a read to every
+/// monitor on every notch of the knob
+const POWER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Set of monitors adjusted together, grouped by the display adapter driving them. The monitors of an adapter share its
+/// I2C bus, on which interleaved DDC/CI transactions collide and slow each other down, so they are written to one after
+/// the other, while the ones driven by different adapters are written to in parallel
+///
+/// Monitors that get disconnected are removed from the group, and their names are kept around until they are taken.
+/// Monitors that keep failing are set aside for a while instead, so that every adjustment doesn't stall on them, until
+/// they answer again. Monitors found asleep can be left out too, until they are turned on again
+#[derive(Default)]
+pub struct MonitorGroup {
+  adapters: Vec<Vec<Monitor>>,
+  disconnected: Vec<String>,
+  quarantine: Option<Quarantine>,
+  quarantined: Vec<QuarantinedMonitor>,
+  asleep_monitors: Option<AsleepMonitors>,
+  asleep: Vec<Monitor>,
+  power_checked_at: Option<Instant>,
+  /// Threads writing to the monitors of every adapter but the first one, which the calling thread writes to itself
+  writers: Vec<AdapterWriter>
+}
+
+/// Represent when monitors are set aside, and for how long
+#[derive(Debug, Clone, Copy)]
+struct Quarantine {
+  failures: u32,
+  cooldown: Duration
+}
+
+/// Write run on every monitor of an adapter, shared with the writer threads
+type AdapterWrite = Arc<dyn Fn(&mut Monitor) -> Result<(), MonitorError> + Send + Sync>;
+type AdapterResults = Vec<Result<(), MonitorError>>;
+
+/// Thread writing to the monitors of an adapter, kept from one write to the next so that the transitions don't spawn
+/// and join threads on every frame. The monitors are handed over to it for the time of a write, then handed back
+struct AdapterWriter {
+  jobs_tx: Sender<(Vec<Monitor>, AdapterWrite)>,
+  done_rx: Receiver<(Vec<Monitor>, AdapterResults)>
+}
+
+struct QuarantinedMonitor {
+  monitor: Monitor,
+  /// When the monitor is probed next
+  until: Instant
+}
+
+impl MonitorGroup {
+  /// Open the monitors matching the selection, failing when there's none
+  pub fn open(selection: &MonitorSelection) -> io::Result<Self> {
+    let monitors = match selection {
+      MonitorSelection::Primary => vec![Monitor::new_primary()?],
+      MonitorSelection::All => Monitor::enumerate_all()?,
+      MonitorSelection::Named(names) => Monitor::enumerate_all()?
+        .into_iter()
+        .filter(|monitor| {
+          let monitor_name = monitor.name();
+          names.iter().any(|name| monitor_name.contains(name.as_str()))
+        })
+        .collect(),
+      MonitorSelection::Numbered(number) => match number.checked_sub(1) {
+        Some(index) => Monitor::enumerate_all()?.into_iter().nth(index).into_iter().collect(),
+        None => Vec::new()
+      },
+      MonitorSelection::Followed(followed) => vec![Monitor::from_display(followed.display())?]
+    };
+    if monitors.is_empty() {
+      return Err(io::Error::new(io::ErrorKind::NotFound, "no monitor matches the selection"));
+    }
+
+    let mut group = Self::default();
+    monitors.into_iter().for_each(|monitor| group.add(monitor));
+    Ok(group)
+  }
+
+  /// Set aside the monitors failing the given number of operations in a row, until they answer again after the
+  /// cooldown. Monitors are never set aside when `failures` is 0
+  pub fn with_quarantine(mut self, failures: u32, cooldown: Duration) -> Self {
+    self.quarantine = (failures > 0).then_some(Quarantine { failures, cooldown });
+    self
+  }
+
+  /// Check the power mode of the monitors before every transition, and either wake or leave out the ones that are asleep.
+  /// Brightness writes are never checked for when this isn't called, since they are silently dropped by most monitors
+  pub fn with_power_check(mut self, asleep_monitors: AsleepMonitors) -> Self {
+    self.asleep_monitors = Some(asleep_monitors);
+    self
+  }
+
+  /// Apply settings to every monitor, leaving out the ones the closure returns false for
+  pub fn with_monitors_configured(mut self, mut configure: impl FnMut(&mut Monitor) -> bool) -> Self {
+    for adapter in &mut self.adapters {
+      adapter.retain_mut(&mut configure);
+    }
+    self.adapters.retain(|adapter| !adapter.is_empty());
+    self
+  }
+
+  /// Check whether every monitor is gone, the ones set aside still being part of the group
+  pub fn is_empty(&self) -> bool {
+    self.adapters.is_empty() && self.quarantined.is_empty() && self.asleep.is_empty()
+  }
+
+  /// Get the monitors that are being adjusted, leaving out the ones set aside
+  pub fn monitors(&self) -> impl Iterator<Item = &Monitor> {
+    self.adapters.iter().flatten()
+  }
+
+  pub fn monitors_mut(&mut self) -> impl Iterator<Item = &mut Monitor> {
+    self.adapters.iter_mut().flatten()
+  }
+
+  /// Get every monitor owned by the group, including the ones set aside
+  pub fn all_monitors(&self) -> impl Iterator<Item = &Monitor> {
+    self.monitors().chain(self.quarantined.iter().map(|quarantined| &quarantined.monitor)).chain(self.asleep.iter())
+  }
+
+  /// Look for the monitors that went to sleep or woke up since the last check, when the power mode is checked. The ones
+  /// asleep are woken up or left out as configured, while the ones left out that woke up are set to the given brightness
+  /// and adjusted along with the others again. Monitors that don't report their power mode are assumed to be on
+  pub fn check_power(&mut self, brightness: u16) {
+    let Some(asleep_monitors) = self.asleep_monitors else { return };
+    if self.power_checked_at.is_some_and(|checked_at| checked_at.elapsed() < POWER_CHECK_INTERVAL) {
+      return;
+    }
+    self.power_checked_at = Some(Instant::now());
+
+    for mut monitor in mem::take(&mut self.asleep) {
+      match monitor.is_asleep() {
+        Ok(true) => self.asleep.push(monitor),
+        Err(MonitorError::Disconnected(_)) => self.disconnected.push(monitor.name()),
+        // Monitors that stopped telling their power mode are brought back too, rather than being left out for good
+        _ => {
+          info!("{} is on again, setting the brightness to {}", monitor.name(), brightness);
+          if let Err(e) = monitor.set_brightness(monitor.brightness_in_group(brightness)) {
+            error!("unable to restore the brightness of {} - {}", monitor.name(), e);
+          }
+          self.add(monitor);
+        }
+      };
+    }
+
+    for adapter in &mut self.adapters {
+      for mut monitor in mem::take(adapter) {
+        let is_asleep = matches!(monitor.is_asleep(), Ok(true));
+        match asleep_monitors {
+          AsleepMonitors::Wake if is_asleep => {
+            info!("{} is asleep, waking it up", monitor.name());
+            if let Err(e) = monitor.wake() {
+              error!("unable to wake {} up - {}", monitor.name(), e);
+            }
+            adapter.push(monitor);
+          },
+          AsleepMonitors::Skip if is_asleep => {
+            info!("{} is asleep, leaving it out until it's on again", monitor.name());
+            self.asleep.push(monitor);
+          },
+          _ => adapter.push(monitor)
+        };
+      }
+    }
+    self.adapters.retain(|adapter| !adapter.is_empty());
+  }
+
+  /// Put every monitor in standby, including the ones left out while asleep. Returns the names of the monitors that
+  /// failed to, along with why
+  pub fn standby(&mut self) -> Vec<(String, MonitorError)> {
+    self.adapters
+      .iter_mut()
+      .flatten()
+      .chain(self.asleep.iter_mut())
+      .filter_map(|monitor| monitor.standby().err().map(|e| (monitor.name(), e)))
+      .collect()
+  }
+
+  /// Wake every monitor up, including the ones left out while asleep which are brought back by the next power check.
+  /// Returns the names of the monitors that failed to, along with why
+  pub fn wake(&mut self) -> Vec<(String, MonitorError)> {
+    self.adapters
+      .iter_mut()
+      .flatten()
+      .chain(self.asleep.iter_mut())
+      .filter_map(|monitor| monitor.wake().err().map(|e| (monitor.name(), e)))
+      .collect()
+  }
+
+  /// Try the monitors set aside whose cooldown is over once more, by writing the given brightness to them. The ones that
+  /// answer are adjusted along with the others again, and their names are returned
+  pub fn probe_quarantined(&mut self, brightness: u16) -> Vec<String> {
+    let Some(quarantine) = self.quarantine else { return Vec::new() };
+    let now = Instant::now();
+    let mut recovered = Vec::new();
+
+    for mut quarantined in mem::take(&mut self.quarantined) {
+      if quarantined.until > now {
+        self.quarantined.push(quarantined);
+        continue;
+      }
+      match quarantined.monitor.set_brightness(quarantined.monitor.brightness_in_group(brightness)) {
+        Ok(_) => {
+          quarantined.monitor.consecutive_failures = 0;
+          recovered.push(quarantined.monitor.name());
+          self.add(quarantined.monitor);
+        },
+        Err(MonitorError::Disconnected(_)) => self.disconnected.push(quarantined.monitor.name()),
+        Err(_) => {
+          quarantined.until = now + quarantine.cooldown;
+          self.quarantined.push(quarantined);
+        }
+      };
+    }
+    recovered
+  }
+
+  /// Get when the first of the monitors set aside is due to be probed again, if any is
+  pub fn next_probe(&self) -> Option<Instant> {
+    self.quarantined.iter().map(|quarantined| quarantined.until).min()
+  }
+
+  pub fn names(&self) -> Vec<String> {
+    self.monitors().map(|monitor| monitor.name()).collect()
+  }
+
+  /// Get the stable identifiers of the monitors, in the same order as their names
+  pub fn ids(&self) -> Vec<String> {
+    self.monitors().map(|monitor| monitor.id()).collect()
+  }
+
+  /// Get the stable identifiers of the monitors, along with the brightness each of them is at when the group is at the
+  /// given one
+  pub fn brightness_by_id(&self, value: u16) -> Vec<(String, u16)> {
+    self.monitors().map(|monitor| (monitor.id(), monitor.brightness_in_group(value))).collect()
+  }
+
+  /// Get the names of the monitors, along with the brightness each of them is at when the group is at the given one
+  pub fn brightness_by_name(&self, value: u16) -> Vec<(String, u16)> {
+    self.monitors().map(|monitor| (monitor.name(), monitor.brightness_in_group(value))).collect()
+  }
+
+  /// Get the names of the monitors that got disconnected since the last call
+  pub fn take_disconnected(&mut self) -> Vec<String> {
+    mem::take(&mut self.disconnected)
+  }
+
+  /// Get the refresh rate the transitions are timed by, which is the one of the fastest monitor
+  pub fn refresh_rate_hz(&self) -> u16 {
+    self.monitors().map(|monitor| monitor.refresh_rate_hz).max().unwrap_or(60)
+  }
+
+  /// Get the brightness of the first monitor still connected, which the other ones follow
+  pub fn get_brightness(&mut self) -> io::Result<u16> {
+    self.read_first(|monitor| monitor.get_brightness().map(|value| (value as i32 - monitor.brightness_offset).clamp(0, 100) as u16))
+  }
+
+  /// Get the contrast of the first monitor still connected, which the other ones follow
+  pub fn get_contrast(&mut self) -> io::Result<u16> {
+    self.get_vcp(CONTRAST_VCP_CODE)
+  }
+
+  /// Get the value of a VCP feature of the first monitor still connected, which the other ones follow
+  pub fn get_vcp(&mut self, code: FeatureCode) -> io::Result<u16> {
+    self.read_first(|monitor| monitor.get_vcp(code))
+  }
+
+  /// Read something from the first monitor still connected, moving on to the next one as long as they're disconnected
+  fn read_first(&mut self, mut read: impl FnMut(&mut Monitor) -> Result<u16, MonitorError>) -> io::Result<u16> {
+    loop {
+      let Some(monitor) = self.adapters.first_mut().and_then(|adapter| adapter.first_mut()) else {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no monitor is connected"));
+      };
+      match read(monitor) {
+        Err(MonitorError::Disconnected(e)) => {
+          self.disconnected.push(self.adapters[0].remove(0).name());
+          self.adapters.retain(|adapter| !adapter.is_empty());
+          if self.is_empty() {
+            return Err(e);
+          }
+        },
+        result => return result.map_err(io::Error::from)
+      };
+    }
+  }
+
+  /// Get the brightness of every monitor still connected
+  pub fn get_all_brightness(&mut self) -> io::Result<Vec<u16>> {
+    let results = self.adapters
+      .iter_mut()
+      .map(|adapter| adapter.iter_mut().map(|monitor| monitor.get_brightness()).collect())
+      .collect();
+    self.collect_results(results)
+  }
+
+  /// Get the brightness of the group every monitor still connected reports, which is the given one for those that took
+  /// the last write. The ones that didn't are told apart by comparing them with what they were set to, since the ones
+  /// stopped at an end of their range can't be brought back to the brightness of the group
+  pub fn get_all_group_brightness(&mut self, value: u16) -> io::Result<Vec<u16>> {
+    let get_group_brightness = |monitor: &mut Monitor| {
+      let monitor_value = monitor.get_brightness()?;
+      match monitor_value == monitor.brightness_in_group(value) {
+        true => Ok(value),
+        false => Ok((monitor_value as i32 - monitor.brightness_offset).clamp(0, 100) as u16)
+      }
+    };
+    let results = self.adapters
+      .iter_mut()
+      .map(|adapter| adapter.iter_mut().map(get_group_brightness).collect())
+      .collect();
+    self.collect_results(results)
+  }
+
+  /// Get the whole reply of every monitor still connected to a VCP feature request
+  pub fn get_all_vcp_values(&mut self, code: FeatureCode) -> io::Result<Vec<VcpValue>> {
+    let results = self.adapters
+      .iter_mut()
+      .map(|adapter| adapter.iter_mut().map(|monitor| monitor.get_vcp_value(code)).collect())
+      .collect();
+    self.collect_results(results)
+  }
+
+  /// Get the capabilities of every monitor still connected
+  pub fn get_all_capabilities(&mut self) -> io::Result<Vec<Capabilities>> {
+    let results = self.adapters
+      .iter_mut()
+      .map(|adapter| adapter.iter_mut().map(|monitor| monitor.capabilities().map_err(MonitorError::from)).collect())
+      .collect();
+    self.collect_results(results)
+  }
+
+  /// Write the brightness to every monitor still connected, offset for each of them when they are linked
+  pub fn set_brightness(&mut self, value: u16) -> io::Result<()> {
+    self.write_all(move |monitor| monitor.set_brightness(monitor.brightness_in_group(value)))
+  }
+
+  pub fn set_contrast(&mut self, value: u16) -> io::Result<()> {
+    self.set_vcp(CONTRAST_VCP_CODE, value)
+  }
+
+  /// Switch every monitor still connected to its next input source, each of them on its own since they seldom have the
+  /// same inputs. Returns the names of the monitors along with the input they switched to
+  pub fn cycle_inputs(&mut self) -> Vec<(String, Result<u8, MonitorError>)> {
+    self.adapters.iter_mut().flatten().map(|monitor| (monitor.name(), monitor.cycle_input())).collect()
+  }
+
+  /// Write the value of a VCP feature to every monitor still connected
+  pub fn set_vcp(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    self.write_all(move |monitor| monitor.set_vcp(code, value))
+  }
+
+  /// Run a write on every monitor still connected, the ones driven by different adapters in parallel
+  fn write_all(&mut self, write: impl Fn(&mut Monitor) -> Result<(), MonitorError> + Send + Sync + 'static) -> io::Result<()> {
+    if self.adapters.len() <= 1 {
+      let results = self.adapters.iter_mut().map(|adapter| write_adapter(adapter, &write)).collect();
+      return self.collect_results(results).map(|_| ());
+    }
+
+    let write: AdapterWrite = Arc::new(write);
+    while self.writers.len() < self.adapters.len() - 1 {
+      self.writers.push(AdapterWriter::spawn());
+    }
+    let mut adapters = mem::take(&mut self.adapters);
+    let handed_over: Vec<_> = adapters
+      .drain(1..)
+      .zip(&self.writers)
+      .map(|(monitors, writer)| writer.jobs_tx.send((monitors, write.clone())).map_err(|e| e.into_inner().0))
+      .collect();
+    let mut results = vec![write_adapter(&mut adapters[0], &*write)];
+    for (handed_over, writer) in handed_over.into_iter().zip(&self.writers) {
+      let done = match handed_over {
+        Ok(_) => writer.done_rx.recv().ok(),
+        // Monitors that couldn't be handed over are written to from here instead
+        Err(mut monitors) => {
+          let adapter_results = write_adapter(&mut monitors, &*write);
+          Some((monitors, adapter_results))
+        }
+      };
+      // The writer thread can only be gone with the monitors handed over to it if it panicked outside of the write
+      let Some((monitors, adapter_results)) = done else { continue };
+      adapters.push(monitors);
+      results.push(adapter_results);
+    }
+    self.adapters = adapters;
+    self.collect_results(results).map(|_| ())
+  }
+
+  /// Create a group of monitors that were opened already
+  #[cfg(feature = "mock-target")]
+  pub fn from_monitors(monitors: Vec<Monitor>) -> Self {
+    let mut group = Self::default();
+    for monitor in monitors {
+      group.add(monitor);
+    }
+    group
+  }
+
+  /// Add a monitor along with the others driven by the same adapter
+  fn add(&mut self, monitor: Monitor) {
+    match self.adapters.iter_mut().find(|adapter| adapter[0].adapter_id() == monitor.adapter_id()) {
+      Some(adapter) => adapter.push(monitor),
+      None => self.adapters.push(vec![monitor])
+    };
+  }
+
+  /// Collect the outcome of an operation run on every monitor, given in the same order as the monitors, removing the
+  /// ones that got disconnected and setting aside the ones that failed too many times in a row. Fails with the first
+  /// other error, or with the disconnection error once every monitor is gone
+  fn collect_results<T>(&mut self, results: Vec<Vec<Result<T, MonitorError>>>) -> io::Result<Vec<T>> {
+    let mut values = Vec::new();
+    let mut first_error = None;
+    let mut disconnection_error = None;
+
+    for (adapter, results) in self.adapters.iter_mut().zip(results) {
+      let mut results = results.into_iter();
+      adapter.retain_mut(|monitor| match results.next() {
+        Some(Ok(value)) => {
+          monitor.consecutive_failures = 0;
+          values.push(value);
+          true
+        },
+        Some(Err(MonitorError::Disconnected(e))) => {
+          self.disconnected.push(monitor.name());
+          disconnection_error = Some(e);
+          false
+        },
+        Some(Err(e)) => {
+          monitor.consecutive_failures += 1;
+          first_error.get_or_insert(io::Error::from(e));
+          true
+        },
+        None => true
+      });
+    }
+    self.quarantine_failing();
+    self.adapters.retain(|adapter| !adapter.is_empty());
+
+    match (first_error, disconnection_error) {
+      (Some(e), _) => Err(e),
+      (None, Some(e)) if self.is_empty() => Err(e),
+      _ => Ok(values)
+    }
+  }
+
+  /// Set aside the monitors that failed too many times in a row, for the cooldown
+  fn quarantine_failing(&mut self) {
+    let Some(quarantine) = self.quarantine else { return };
+    for adapter in &mut self.adapters {
+      let (failing, working) = mem::take(adapter).into_iter().partition(|monitor| monitor.consecutive_failures >= quarantine.failures);
+      *adapter = working;
+      for monitor in failing {
+        error!(
+          "{} failed {} times in a row, leaving it out until it answers again in {}s",
+          monitor.name(),
+          monitor.consecutive_failures,
+          quarantine.cooldown.as_secs()
+        );
+        self.quarantined.push(QuarantinedMonitor { monitor, until: Instant::now() + quarantine.cooldown });
+      }
+    }
+  }
+}
+
+impl AdapterWriter {
+  fn spawn() -> Self {
+    let (jobs_tx, jobs_rx) = unbounded::<(Vec<Monitor>, AdapterWrite)>();
+    let (done_tx, done_rx) = unbounded();
+    // The thread ends along with the group, once the jobs channel is disconnected
+    thread::spawn(move || {
+      for (mut monitors, write) in jobs_rx {
+        // A write that panicked counts as a failure of every monitor of the adapter, rather than taking the thread down
+        let results = panic::catch_unwind(AssertUnwindSafe(|| write_adapter(&mut monitors, &*write))).unwrap_or_else(|_| {
+          (0..monitors.len()).map(|_| Err(MonitorError::Transient(io::Error::other("the write panicked")))).collect()
+        });
+        if done_tx.send((monitors, results)).is_err() {
+          break;
+        }
+      }
+    });
+    Self { jobs_tx, done_rx }
+  }
+}
+
+/// Run a write on every monitor of an adapter, one after the other
+fn write_adapter(adapter: &mut [Monitor], write: &(impl Fn(&mut Monitor) -> Result<(), MonitorError> + ?Sized)) -> AdapterResults {
+  adapter.iter_mut().map(write).collect()
+}
@@ -0,0 +1,17 @@
+use crate::knob_mode::KnobMode;
+
+// The overlay itself is left out of the builds without the feature, the brightness thread still keeping its state
+#[cfg(feature = "osd")]
+mod window;
+
+#[cfg(feature = "osd")]
+pub use self::window::run_osd;
+
+/// Value shown by the OSD, along with the setting it belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OsdState {
+  pub mode: KnobMode,
+  pub value: u16,
+  /// Whether the knob moves by coarse steps, which is shown next to the name of the setting
+  pub coarse: bool
+}
@@ -0,0 +1,195 @@
+use crate::osd::OsdState;
+use crate::shutdown::{StopSignal, forward_stop_to_message_loop};
+use crate::strings::{Text, text};
+use crate::watch::WatchReceiver;
+
+use std::time::{Duration, Instant};
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{COLORREF, HWND, LPARAM, LRESULT, RECT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+  BeginPaint, CreateFontW, CreateSolidBrush, DeleteObject, DrawTextW, EndPaint, FillRect, InvalidateRect, SelectObject, SetBkMode, SetTextColor,
+  CLEARTYPE_QUALITY, CLIP_DEFAULT_PRECIS, DEFAULT_CHARSET, DEFAULT_PITCH, DT_LEFT, DT_RIGHT, DT_SINGLELINE, DT_VCENTER, FW_SEMIBOLD, HDC, HFONT,
+  OUT_DEFAULT_PRECIS, PAINTSTRUCT, TRANSPARENT
+};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+  CreateWindowExW, DefWindowProcW, DestroyWindow, DispatchMessageW, GetMessageW, GetSystemMetrics, KillTimer, PostMessageW, RegisterClassW,
+  SetLayeredWindowAttributes, SetTimer, SetWindowPos, ShowWindow, TranslateMessage, HMENU, HWND_TOPMOST, LWA_ALPHA, MSG, SM_CXSCREEN, SM_CYSCREEN,
+  SWP_NOACTIVATE, SWP_SHOWWINDOW, SW_HIDE, WM_PAINT, WM_TIMER, WNDCLASSW, WS_EX_LAYERED, WS_EX_NOACTIVATE, WS_EX_TOOLWINDOW, WS_EX_TOPMOST,
+  WS_EX_TRANSPARENT, WS_POPUP
+};
+
+/// Application-defined message posted to the OSD window when the value it shows changed
+const OSD_UPDATE_MSG: u32 = 0x050e;
+
+const OSD_WINDOW_CLASS: PCWSTR = w!("GmmkProBrightnessKnobOsd");
+const FRAME_TIMER_ID: usize = 1;
+const FRAME_INTERVAL_MS: u32 = 16;
+
+const WIDTH: i32 = 260;
+const HEIGHT: i32 = 64;
+const PADDING: i32 = 12;
+const BAR_HEIGHT: i32 = 6;
+/// Distance between the bottom of the screen and the OSD, which is about where the volume flyout shows up
+const BOTTOM_MARGIN: i32 = 120;
+
+const VISIBLE_DURATION: Duration = Duration::from_secs(1);
+const FADE_DURATION: Duration = Duration::from_millis(300);
+const OPACITY: u8 = 230;
+
+// Colors are written as 0x00BBGGRR
+const BACKGROUND_COLOR: COLORREF = COLORREF(0x00202020);
+const TRACK_COLOR: COLORREF = COLORREF(0x00505050);
+const BAR_COLOR: COLORREF = COLORREF(0x00ffffff);
+const TEXT_COLOR: COLORREF = COLORREF(0x00ffffff);
+
+/// Show a small overlay at the bottom of the screen, similar to the volume flyout, with a bar and the percentage of
+/// the value sent by the brightness thread. The overlay shows up every time the value changes and fades out once it
+/// has been left alone for a second. It never takes the focus nor the mouse clicks. Runs until the stop signal is
+/// received
+pub fn run_osd(stop_rx: StopSignal, mut state_rx: WatchReceiver<Option<OsdState>>) -> windows::core::Result<()> {
+  unsafe {
+    let hwnd = create_osd_window()?;
+    let font = CreateFontW(
+      -18, 0, 0, 0,
+      FW_SEMIBOLD.0 as i32,
+      0, 0, 0,
+      DEFAULT_CHARSET.0 as u32,
+      OUT_DEFAULT_PRECIS.0 as u32,
+      CLIP_DEFAULT_PRECIS.0 as u32,
+      CLEARTYPE_QUALITY.0 as u32,
+      DEFAULT_PITCH.0 as u32,
+      w!("Segoe UI")
+    );
+
+    state_rx.on_change(move || {
+      PostMessageW(hwnd, OSD_UPDATE_MSG, WPARAM(0), LPARAM(0));
+    });
+
+    // Make GetMessageW return as soon as the stop signal is received
+    forward_stop_to_message_loop(stop_rx);
+
+    let mut state = None;
+    let mut shown_at: Option<Instant> = None;
+    let mut msg: MSG = Default::default();
+    while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+      match msg.message {
+        OSD_UPDATE_MSG => {
+          let Some(Some(latest_state)) = state_rx.changed() else { continue };
+          state = Some(latest_state);
+          if shown_at.is_none() {
+            show_osd_window(hwnd);
+            SetTimer(hwnd, FRAME_TIMER_ID, FRAME_INTERVAL_MS, None);
+          }
+          shown_at = Some(Instant::now());
+          SetLayeredWindowAttributes(hwnd, COLORREF(0), OPACITY, LWA_ALPHA);
+          InvalidateRect(hwnd, None, false);
+        },
+        WM_TIMER if msg.wParam.0 == FRAME_TIMER_ID => {
+          let Some(since) = shown_at else { continue };
+          match opacity(since.elapsed()) {
+            Some(opacity) => {
+              SetLayeredWindowAttributes(hwnd, COLORREF(0), opacity, LWA_ALPHA);
+            },
+            None => {
+              KillTimer(hwnd, FRAME_TIMER_ID);
+              ShowWindow(hwnd, SW_HIDE);
+              shown_at = None;
+            }
+          };
+        },
+        WM_PAINT => paint(hwnd, font, state.as_ref()),
+        _ => {
+          TranslateMessage(&msg);
+          DispatchMessageW(&msg);
+        }
+      };
+    }
+
+    DestroyWindow(hwnd);
+    DeleteObject(font);
+  }
+  Ok(())
+}
+
+unsafe fn create_osd_window() -> windows::core::Result<HWND> {
+  let instance = GetModuleHandleW(None)?;
+  let window_class = WNDCLASSW {
+    lpfnWndProc: Some(osd_window_proc),
+    hInstance: instance,
+    lpszClassName: OSD_WINDOW_CLASS,
+    ..Default::default()
+  };
+  RegisterClassW(&window_class);
+
+  // Layered and transparent windows let the mouse clicks through, which keeps the OSD from getting in the way
+  let hwnd = CreateWindowExW(
+    WS_EX_LAYERED | WS_EX_TRANSPARENT | WS_EX_TOPMOST | WS_EX_TOOLWINDOW | WS_EX_NOACTIVATE,
+    OSD_WINDOW_CLASS,
+    OSD_WINDOW_CLASS,
+    WS_POPUP,
+    0, 0, WIDTH, HEIGHT,
+    HWND(0),
+    HMENU(0),
+    instance,
+    None
+  );
+  match hwnd.0 {
+    0 => Err(windows::core::Error::from_win32()),
+    _ => Ok(hwnd)
+  }
+}
+
+unsafe extern "system" fn osd_window_proc(hwnd: HWND, msg: u32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+  DefWindowProcW(hwnd, msg, w_param, l_param)
+}
+
+/// Show the window centered at the bottom of the primary screen, without activating it
+unsafe fn show_osd_window(hwnd: HWND) {
+  let x = (GetSystemMetrics(SM_CXSCREEN) - WIDTH) / 2;
+  let y = GetSystemMetrics(SM_CYSCREEN) - HEIGHT - BOTTOM_MARGIN;
+  SetWindowPos(hwnd, HWND_TOPMOST, x, y, WIDTH, HEIGHT, SWP_NOACTIVATE | SWP_SHOWWINDOW);
+}
+
+/// Get the opacity of the window after it has been shown for the given time, or nothing once it has faded out
+fn opacity(elapsed: Duration) -> Option<u8> {
+  if elapsed < VISIBLE_DURATION {
+    return Some(OPACITY);
+  }
+  let fade_progress = (elapsed - VISIBLE_DURATION).as_secs_f64() / FADE_DURATION.as_secs_f64();
+  (fade_progress < 1.0).then_some((OPACITY as f64 * (1.0 - fade_progress)) as u8)
+}
+
+/// Draw the name of the setting and its percentage above a bar filled up to the value
+unsafe fn paint(hwnd: HWND, font: HFONT, state: Option<&OsdState>) {
+  let mut paint = PAINTSTRUCT::default();
+  let hdc = BeginPaint(hwnd, &mut paint);
+  fill_rect(hdc, RECT { left: 0, top: 0, right: WIDTH, bottom: HEIGHT }, BACKGROUND_COLOR);
+
+  if let Some(state) = state {
+    SelectObject(hdc, font);
+    SetBkMode(hdc, TRANSPARENT);
+    SetTextColor(hdc, TEXT_COLOR);
+    let mut text_rect = RECT { left: PADDING, top: PADDING, right: WIDTH - PADDING, bottom: HEIGHT - PADDING - BAR_HEIGHT };
+    let label = match state.coarse {
+      true => format!("{} ({})", text(state.mode.label()), text(Text::CoarseSteps)),
+      false => text(state.mode.label()).to_string()
+    };
+    let mut label: Vec<u16> = label.encode_utf16().collect();
+    DrawTextW(hdc, &mut label, &mut text_rect, DT_LEFT | DT_SINGLELINE | DT_VCENTER);
+    let mut percentage: Vec<u16> = format!("{}%", state.value).encode_utf16().collect();
+    DrawTextW(hdc, &mut percentage, &mut text_rect, DT_RIGHT | DT_SINGLELINE | DT_VCENTER);
+
+    let track_rect = RECT { left: PADDING, top: HEIGHT - PADDING - BAR_HEIGHT, right: WIDTH - PADDING, bottom: HEIGHT - PADDING };
+    fill_rect(hdc, track_rect, TRACK_COLOR);
+    let filled_width = (track_rect.right - track_rect.left) * state.value.min(100) as i32 / 100;
+    fill_rect(hdc, RECT { right: track_rect.left + filled_width, ..track_rect }, BAR_COLOR);
+  }
+  EndPaint(hwnd, &paint);
+}
+
+unsafe fn fill_rect(hdc: HDC, rect: RECT, color: COLORREF) {
+  let brush = CreateSolidBrush(color);
+  FillRect(hdc, &rect, brush);
+  DeleteObject(brush);
+}
@@ -0,0 +1,33 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const APP_DIR_NAME: &str = "gmmk-pro-brightness-knob";
+
+/// Get the directory where the data produced at runtime is stored, creating it if it doesn't exist yet. It lives in
+/// %LOCALAPPDATA% given that none of it is worth roaming across machines
+pub fn data_dir() -> io::Result<PathBuf> {
+  let base_dir = env::var_os("LOCALAPPDATA").map(PathBuf::from).unwrap_or_else(env::temp_dir);
+  let dir = base_dir.join(APP_DIR_NAME);
+  fs::create_dir_all(&dir)?;
+  Ok(dir)
+}
+
+/// Get the directory the executable is installed to by the `install` subcommand, which is the per-user location for
+/// programs that don't require elevation to be installed
+pub fn install_dir() -> io::Result<PathBuf> {
+  let base_dir = env::var_os("LOCALAPPDATA")
+    .map(PathBuf::from)
+    .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "%LOCALAPPDATA% is not set"))?;
+  Ok(base_dir.join("Programs").join(APP_DIR_NAME))
+}
+
+/// Get the directory where the config file is looked for when there's none next to the executable, creating it if it
+/// doesn't exist yet. It lives in %APPDATA% so that the settings follow the user across machines
+pub fn config_dir() -> io::Result<PathBuf> {
+  let base_dir = env::var_os("APPDATA").map(PathBuf::from).unwrap_or_else(env::temp_dir);
+  let dir = base_dir.join(APP_DIR_NAME);
+  fs::create_dir_all(&dir)?;
+  Ok(dir)
+}
@@ -0,0 +1,59 @@
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// Represent what turning the knob past either end of the range of a setting does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitBehavior {
+  /// Stop at the limit
+  #[default]
+  Clamp,
+  /// Go around to the other end of the range, once the limit has been reached
+  Wrap,
+  /// Stop at the limit for the rest of the turn, then go around to the other end on the next one
+  Hold
+}
+
+/// Keep the targets computed for a setting within its range, according to its limit behavior
+#[derive(Debug, Clone)]
+pub struct RangeLimit {
+  behavior: LimitBehavior,
+  hold_delay: Duration,
+  /// When the limit was last pushed against, while holding it
+  held_since: Option<Instant>
+}
+
+impl RangeLimit {
+  pub fn new(behavior: LimitBehavior, hold_delay: Duration) -> Self {
+    Self { behavior, hold_delay, held_since: None }
+  }
+
+  /// Bring the target computed for an event back within `min..=max`, given the value targeted before it. Turning past
+  /// a limit that isn't reached yet always stops at it, so that it's never skipped over by a large step
+  pub fn apply(&mut self, prev_value: i32, target_value: i32, min: i32, max: i32, timestamp: Instant) -> i32 {
+    if (min..=max).contains(&target_value) {
+      self.held_since = None;
+      return target_value;
+    }
+
+    let clamped_value = target_value.clamp(min, max);
+    let wrapped_value = if target_value > max { min } else { max };
+    let is_at_limit = prev_value == clamped_value;
+    match self.behavior {
+      LimitBehavior::Clamp => clamped_value,
+      LimitBehavior::Wrap if is_at_limit => wrapped_value,
+      LimitBehavior::Wrap => clamped_value,
+      // Events from the same turn that reached the limit keep it held, while a new turn goes around
+      LimitBehavior::Hold => match self.held_since {
+        Some(since) if is_at_limit && timestamp.saturating_duration_since(since) >= self.hold_delay => {
+          self.held_since = None;
+          wrapped_value
+        },
+        _ => {
+          self.held_since = Some(timestamp);
+          clamped_value
+        }
+      }
+    }
+  }
+}
@@ -0,0 +1,133 @@
+use crate::monitor::from_wide;
+use crate::system_events::parse_vid_pid;
+
+use std::collections::HashMap;
+use std::mem::size_of;
+use tracing::info;
+use windows::Win32::Foundation::{HANDLE, HWND, LPARAM};
+use windows::Win32::UI::Input::{
+  GetRawInputData, GetRawInputDeviceInfoW, RegisterRawInputDevices, HRAWINPUT, RAWINPUT, RAWINPUTDEVICE, RAWINPUTHEADER, RIDEV_INPUTSINK,
+  RIDEV_REMOVE, RIDI_DEVICENAME, RID_HEADER, RID_INPUT, RIM_TYPEKEYBOARD
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::VIRTUAL_KEY;
+
+// Usages of the Generic Desktop Page, as defined by the HID Usage Tables specification
+const GENERIC_DESKTOP_USAGE_PAGE: u16 = 0x01;
+const KEYBOARD_USAGE: u16 = 0x06;
+/// Virtual-key code of the keystrokes that are part of an escaped sequence rather than keys of their own
+const FAKE_KEY: u16 = 0xff;
+
+/// Represent a keystroke read from the Raw Input of a keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawKeystroke {
+  /// Handle of the keyboard the keystroke came from
+  pub device: HANDLE,
+  /// Keyboard message identifier, such as WM_KEYDOWN or WM_KEYUP
+  pub message: u32,
+  pub key_code: VIRTUAL_KEY
+}
+
+/// Receive the keystrokes of every keyboard as WM_INPUT messages posted to the given window, even while it's not in the
+/// foreground. Unlike the low-level keyboard hook, this doesn't delay the keystrokes of the other applications, and
+/// can't be removed by Windows when the message loop is slow, but the keystrokes can't be swallowed either
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/inputdev/about-raw-input
+pub fn register_raw_keyboards(hwnd: HWND) -> windows::core::Result<()> {
+  let device = RAWINPUTDEVICE { usUsagePage: GENERIC_DESKTOP_USAGE_PAGE, usUsage: KEYBOARD_USAGE, dwFlags: RIDEV_INPUTSINK, hwndTarget: hwnd };
+  match unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32) }.as_bool() {
+    true => Ok(()),
+    false => Err(windows::core::Error::from_win32())
+  }
+}
+
+pub fn unregister_raw_keyboards() {
+  let device = RAWINPUTDEVICE { usUsagePage: GENERIC_DESKTOP_USAGE_PAGE, usUsage: KEYBOARD_USAGE, dwFlags: RIDEV_REMOVE, hwndTarget: HWND(0) };
+  unsafe { RegisterRawInputDevices(&[device], size_of::<RAWINPUTDEVICE>() as u32); }
+}
+
+/// Get the keystroke carried by a WM_INPUT message, unless it comes from another kind of device
+pub fn read_keystroke(l_param: LPARAM) -> Option<RawKeystroke> {
+  unsafe {
+    let raw_input = HRAWINPUT(l_param.0);
+    let header_size = size_of::<RAWINPUTHEADER>() as u32;
+    let mut size = 0u32;
+    if GetRawInputData(raw_input, RID_INPUT, None, &mut size, header_size) != 0 || size as usize > size_of::<RAWINPUT>() {
+      return None;
+    }
+
+    let mut buffer = RAWINPUT::default();
+    if GetRawInputData(raw_input, RID_INPUT, Some(&mut buffer as *mut RAWINPUT as *mut _), &mut size, header_size) == u32::MAX {
+      return None;
+    }
+    if buffer.header.dwType != RIM_TYPEKEYBOARD.0 || buffer.data.keyboard.VKey == FAKE_KEY {
+      return None;
+    }
+    Some(RawKeystroke { device: buffer.header.hDevice, message: buffer.data.keyboard.Message, key_code: VIRTUAL_KEY(buffer.data.keyboard.VKey) })
+  }
+}
+
+/// Get the type of the device the input carried by a WM_INPUT message comes from, such as RIM_TYPEKEYBOARD
+pub fn raw_input_type(l_param: LPARAM) -> Option<u32> {
+  let mut header = RAWINPUTHEADER::default();
+  let header_size = size_of::<RAWINPUTHEADER>() as u32;
+  let mut size = header_size;
+  let read = unsafe { GetRawInputData(HRAWINPUT(l_param.0), RID_HEADER, Some(&mut header as *mut RAWINPUTHEADER as *mut _), &mut size, header_size) };
+  (read != u32::MAX).then_some(header.dwType)
+}
+
+/// Get the path of the device behind a Raw Input handle, such as "\\?\HID#VID_320F&PID_5044&MI_00#..."
+pub fn device_path(device: HANDLE) -> Option<String> {
+  unsafe {
+    // The size is in characters rather than bytes for the device name
+    let mut size = 0u32;
+    GetRawInputDeviceInfoW(device, RIDI_DEVICENAME, None, &mut size);
+    if size == 0 {
+      return None;
+    }
+
+    let mut buffer = vec![0u16; size as usize];
+    match GetRawInputDeviceInfoW(device, RIDI_DEVICENAME, Some(buffer.as_mut_ptr() as *mut _), &mut size) {
+      u32::MAX => None,
+      _ => Some(from_wide(&buffer))
+    }
+  }
+}
+
+/// Tell the keyboards with a knob apart from the other ones, by the vendor and product IDs in their device path, or
+/// tell the one chosen keyboard apart by a part of its device path. The outcome is kept for every handle, which stays
+/// the same for as long as the keyboard is connected
+pub struct KeyboardFilter {
+  keyboard_ids: Vec<(u16, u16)>,
+  /// Part of the device path of the only keyboard to listen to, in lowercase
+  chosen_device: Option<String>,
+  /// Path of every device seen so far, for the ones that are accepted
+  known_devices: HashMap<isize, Option<String>>
+}
+
+impl KeyboardFilter {
+  pub fn new(keyboard_ids: Vec<(u16, u16)>, chosen_device: Option<String>) -> Self {
+    Self { keyboard_ids, chosen_device: chosen_device.map(|device| device.to_lowercase()), known_devices: HashMap::new() }
+  }
+
+  /// Get the path of the given device when it's one of the keyboards to listen to. Every device is logged the first
+  /// time it's seen, for the path of the one to choose to be found
+  pub fn accepts(&mut self, device: HANDLE) -> Option<&str> {
+    let keyboard_ids = &self.keyboard_ids;
+    let chosen_device = &self.chosen_device;
+    self.known_devices.entry(device.0).or_insert_with(|| {
+      let path = device_path(device)?;
+      let accepted = match chosen_device {
+        Some(chosen_device) => path.to_lowercase().contains(chosen_device.as_str()),
+        None => parse_vid_pid(&path).is_some_and(|ids| keyboard_ids.contains(&ids))
+      };
+      info!("{} the keystrokes of {}", if accepted { "listening to" } else { "ignoring" }, path);
+      accepted.then_some(path)
+    }).as_deref()
+  }
+
+  /// Forget the outcome kept for every handle, whose values get reused once keyboards are disconnected
+  pub fn clear(&mut self) {
+    self.known_devices.clear();
+  }
+}
+
@@ -0,0 +1,59 @@
+use crate::keyboard_knob::{EventSource, KnobAdjustmentEvent};
+use crate::monitor_group::{FollowedMonitor, MonitorSelection};
+
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Represent which monitors the knob adjustment events of a source adjust, rather than the selected ones
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RouteValue")]
+pub enum Route {
+  Primary,
+  All,
+  /// The monitor of the display that's followed, looked up again every time the knob starts being turned
+  Followed(FollowedMonitor),
+  /// Every monitor whose description contains any of the given names
+  Named(Vec<String>),
+  /// The monitors named by the event itself, such as the ones given to the `up` and `down` commands, or the selected
+  /// ones when it names none
+  Explicit
+}
+
+/// Route as written in the config file, either a keyword or the names of some monitors
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RouteValue {
+  Keyword(String),
+  Names(Vec<String>)
+}
+
+impl TryFrom<RouteValue> for Route {
+  type Error = String;
+
+  fn try_from(value: RouteValue) -> Result<Self, Self::Error> {
+    match value {
+      RouteValue::Keyword(keyword) => match keyword.as_str() {
+        "primary" => Ok(Route::Primary),
+        "all" => Ok(Route::All),
+        "cursor" => Ok(Route::Followed(FollowedMonitor::Cursor)),
+        "focused-window" => Ok(Route::Followed(FollowedMonitor::FocusedWindow)),
+        "explicit" => Ok(Route::Explicit),
+        _ => Err(format!("unknown route \"{}\", expected \"primary\", \"all\", \"cursor\", \"focused-window\", \"explicit\" or a list of monitor names", keyword))
+      },
+      RouteValue::Names(names) => Ok(Route::Named(names))
+    }
+  }
+}
+
+/// Get the monitors adjusted by an event, according to the route of its source. The events of the sources without one
+/// adjust the selected monitors
+pub fn route_selection(routes: &BTreeMap<EventSource, Route>, event: &KnobAdjustmentEvent, selected: &MonitorSelection) -> MonitorSelection {
+  match routes.get(&event.source) {
+    None => selected.clone(),
+    Some(Route::Primary) => MonitorSelection::Primary,
+    Some(Route::All) => MonitorSelection::All,
+    Some(Route::Followed(followed)) => MonitorSelection::Followed(*followed),
+    Some(Route::Named(names)) => MonitorSelection::Named(names.clone()),
+    Some(Route::Explicit) => event.monitor.clone().unwrap_or_else(|| selected.clone())
+  }
+}
@@ -0,0 +1,50 @@
+use crate::paths::data_dir;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const SAVED_BRIGHTNESS_FILE_NAME: &str = "brightness.toml";
+
+/// Brightness each monitor was last left at, keyed by the stable identifier of the monitor, for it to be restored the
+/// next time the program starts
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SavedBrightness {
+  pub monitors: BTreeMap<String, u16>
+}
+
+impl SavedBrightness {
+  /// Read the saved brightness, which is empty until it has been saved once
+  pub fn load() -> io::Result<Self> {
+    match fs::read_to_string(saved_brightness_path()?) {
+      Ok(contents) => toml::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+      Err(e) => Err(e)
+    }
+  }
+
+  /// Remember the brightness of the given monitors, keeping the one of the others around for when they come back
+  pub fn update(&mut self, brightness_by_id: Vec<(String, u16)>) {
+    self.monitors.extend(brightness_by_id);
+  }
+
+  pub fn get(&self, monitor_id: &str) -> Option<u16> {
+    self.monitors.get(monitor_id).copied()
+  }
+
+  /// Get the brightness saved for the first of the given monitors that has one
+  pub fn find(&self, monitor_ids: &[String]) -> Option<u16> {
+    monitor_ids.iter().find_map(|monitor_id| self.monitors.get(monitor_id).copied())
+  }
+
+  pub fn save(&self) -> io::Result<()> {
+    let contents = toml::to_string_pretty(self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    fs::write(saved_brightness_path()?, contents)
+  }
+}
+
+fn saved_brightness_path() -> io::Result<PathBuf> {
+  Ok(data_dir()?.join(SAVED_BRIGHTNESS_FILE_NAME))
+}
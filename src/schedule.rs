@@ -0,0 +1,192 @@
+use crate::clock::{TimeOfDay, next_occurrence};
+use crate::sun::{Location, SunEvent, next_sun_event};
+
+use crossbeam_channel::{Receiver, after, never};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info};
+
+/// Longest the schedule waits before looking at the wall clock again. The timers run on the monotonic clock, which
+/// drifts away from the wall clock while the computer sleeps and isn't told about the clock being changed when there is
+/// no message loop to receive WM_TIMECHANGE
+const MAX_WAIT: Duration = Duration::from_secs(10 * 60);
+/// Shortest time between two steps of a ramp, for the ramps going through many values quickly not to keep the
+/// brightness thread busy
+const MIN_RAMP_STEP: Duration = Duration::from_secs(1);
+
+/// When an entry of the schedule comes due, written as "HH:MM" in the config file, or as "sunrise" or "sunset" followed
+/// by an optional offset in minutes such as "sunset-30"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScheduleTime {
+  Clock(TimeOfDay),
+  Sun { event: SunEvent, offset_minutes: i32 }
+}
+
+impl ScheduleTime {
+  /// Get the first time after the given one the entry comes due at, the ones following the sun needing the location
+  fn next_occurrence(&self, location: Option<Location>, after: SystemTime) -> Option<SystemTime> {
+    match *self {
+      ScheduleTime::Clock(time_of_day) => next_occurrence(time_of_day, after),
+      ScheduleTime::Sun { event, offset_minutes } => {
+        let offset = Duration::from_secs(offset_minutes.unsigned_abs() as u64 * 60);
+        // The sun event is looked for after the given time minus the offset, for the offset time to come after it
+        match offset_minutes < 0 {
+          true => next_sun_event(event, location?, after.checked_add(offset)?)?.checked_sub(offset),
+          false => next_sun_event(event, location?, after.checked_sub(offset)?)?.checked_add(offset)
+        }
+      }
+    }
+  }
+}
+
+impl FromStr for ScheduleTime {
+  type Err = String;
+
+  fn from_str(value: &str) -> Result<Self, Self::Err> {
+    let (event, offset) = match value {
+      _ if value.starts_with("sunrise") => (SunEvent::Sunrise, &value["sunrise".len()..]),
+      _ if value.starts_with("sunset") => (SunEvent::Sunset, &value["sunset".len()..]),
+      _ => return value.parse().map(ScheduleTime::Clock)
+    };
+    let offset_minutes = match offset {
+      "" => 0,
+      _ if offset.starts_with(['+', '-']) => offset.parse().map_err(|_| format!("invalid offset \"{}\" from the {}, expected minutes", offset, event))?,
+      _ => return Err(format!("invalid time \"{}\", expected HH:MM, sunrise or sunset", value))
+    };
+    Ok(ScheduleTime::Sun { event, offset_minutes })
+  }
+}
+
+impl<'de> Deserialize<'de> for ScheduleTime {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+    String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+  }
+}
+
+impl fmt::Display for ScheduleTime {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      ScheduleTime::Clock(time_of_day) => write!(f, "{}", time_of_day),
+      ScheduleTime::Sun { event, offset_minutes: 0 } => write!(f, "{}", event),
+      ScheduleTime::Sun { event, offset_minutes } => write!(f, "{}{:+}", event, offset_minutes)
+    }
+  }
+}
+
+/// Brightness to set at a given time of the day, as written in the config file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+  pub time: ScheduleTime,
+  pub brightness: i32,
+  /// How long the brightness takes to get to the one of the entry, one step at a time, from the time of the entry.
+  /// It's set with the transition of the knob when 0
+  #[serde(default, rename = "ramp_ms", deserialize_with = "crate::config::milliseconds")]
+  pub ramp: Duration
+}
+
+/// Brightness moving to the one of an entry over its ramp
+#[derive(Debug, Clone, Copy)]
+struct Ramp {
+  from: i32,
+  to: i32,
+  started_at: Instant,
+  duration: Duration,
+  /// Brightness set by the last step, which is compared with the target to tell whether something else changed it
+  last_value: i32
+}
+
+impl Ramp {
+  fn value_at(&self, now: Instant) -> i32 {
+    let progress = (now.saturating_duration_since(self.started_at).as_secs_f64() / self.duration.as_secs_f64()).min(1.0);
+    (self.from as f64 + (self.to - self.from) as f64 * progress).round() as i32
+  }
+
+  /// Get how long to wait until the ramp moves by one step
+  fn step_interval(&self) -> Duration {
+    (self.duration / (self.to - self.from).unsigned_abs().max(1)).max(MIN_RAMP_STEP)
+  }
+}
+
+/// Represent what the ramp of the schedule did once its timer fired
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RampStep {
+  /// No ramp is ongoing
+  Idle,
+  /// The ramp moved on to the given brightness
+  Moved(i32),
+  /// The brightness was changed by something else during the ramp, which was given up on
+  Overridden
+}
+
+/// Keep track of the entry of the schedule that comes next, on the local wall clock
+#[derive(Debug)]
+pub struct Schedule {
+  entries: Vec<ScheduleEntry>,
+  /// Where the sun is followed from, for the entries happening at sunrise or sunset
+  location: Option<Location>,
+  /// Next entry along with when it's due, which is unset when the schedule is empty
+  due: Option<(SystemTime, ScheduleEntry)>,
+  ramp: Option<Ramp>
+}
+
+impl Schedule {
+  pub fn new(entries: &[ScheduleEntry], location: Option<Location>) -> Self {
+    if location.is_none() && entries.iter().any(|entry| matches!(entry.time, ScheduleTime::Sun { .. })) {
+      error!("the entries of the schedule at sunrise or sunset are ignored, since no location is set");
+    }
+    Self { entries: entries.to_vec(), location, due: None, ramp: None }
+  }
+
+  /// Work out the entry that comes next from the wall clock as it is now, returning the timer to wait on for it. Has to
+  /// be called again whenever the clock or the time zone changed
+  pub fn timer(&mut self) -> Receiver<Instant> {
+    let now = SystemTime::now();
+    self.due = self.entries
+      .iter()
+      .filter_map(|entry| entry.time.next_occurrence(self.location, now).map(|time| (time, *entry)))
+      .min_by_key(|(time, _)| *time);
+    let until_due = self.due.map(|(time, _)| time.duration_since(now).unwrap_or_default().min(MAX_WAIT));
+    let until_step = self.ramp.map(|ramp| ramp.step_interval());
+    match until_due.into_iter().chain(until_step).min() {
+      Some(wait) => after(wait),
+      None => never()
+    }
+  }
+
+  /// Start moving the brightness from the given one to the one of an entry that came due, over its ramp
+  pub fn start_ramp(&mut self, entry: &ScheduleEntry, from: i32, to: i32) {
+    self.ramp = Some(Ramp { from, to, started_at: Instant::now(), duration: entry.ramp, last_value: from });
+  }
+
+  /// Move the ongoing ramp on, if any, once the timer fired. The ramp is given up on as soon as the brightness being
+  /// transitioned to isn't the one it set last, which is how the knob and everything else override it until the next
+  /// entry
+  pub fn ramp_step(&mut self, target: i32) -> RampStep {
+    let Some(ramp) = self.ramp.as_mut() else { return RampStep::Idle };
+    if target != ramp.last_value {
+      info!("the brightness was changed during the ramp of the schedule, leaving it there until the next entry");
+      self.ramp = None;
+      return RampStep::Overridden;
+    }
+    let value = ramp.value_at(Instant::now());
+    ramp.last_value = value;
+    if value == ramp.to {
+      self.ramp = None;
+    }
+    RampStep::Moved(value)
+  }
+
+  /// Take the entry that is due, if its time has come, once the timer fired
+  pub fn take_due(&mut self) -> Option<ScheduleEntry> {
+    match self.due {
+      Some((time, entry)) if SystemTime::now() >= time => {
+        self.due = None;
+        Some(entry)
+      },
+      _ => None
+    }
+  }
+}
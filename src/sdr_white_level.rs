@@ -0,0 +1,120 @@
+use crate::monitor::display_device_name;
+
+use std::io;
+use std::mem::size_of;
+use windows::Win32::Devices::Display::{
+  DisplayConfigGetDeviceInfo, DisplayConfigSetDeviceInfo, GetDisplayConfigBufferSizes, QueryDisplayConfig, DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO,
+  DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL, DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME, DISPLAYCONFIG_DEVICE_INFO_HEADER, DISPLAYCONFIG_DEVICE_INFO_TYPE,
+  DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO, DISPLAYCONFIG_MODE_INFO, DISPLAYCONFIG_PATH_INFO, DISPLAYCONFIG_SDR_WHITE_LEVEL, DISPLAYCONFIG_SOURCE_DEVICE_NAME,
+  QDC_ONLY_ACTIVE_PATHS
+};
+use windows::Win32::Foundation::{ERROR_SUCCESS, LUID};
+use windows::Win32::Graphics::Gdi::HMONITOR;
+
+/// Undocumented request setting the SDR white level, which is what the "SDR content brightness" slider of the Settings
+/// app sends
+const DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL: DISPLAYCONFIG_DEVICE_INFO_TYPE = DISPLAYCONFIG_DEVICE_INFO_TYPE(-18);
+/// Bit of the advanced color state telling that HDR is turned on
+const ADVANCED_COLOR_ENABLED: u32 = 0b10;
+/// Range of the SDR white level, in thousandths of 80 nits, that the slider of the Settings app goes over
+const MIN_WHITE_LEVEL: u32 = 1000;
+const MAX_WHITE_LEVEL: u32 = 6000;
+
+#[repr(C)]
+struct DisplayConfigSetSdrWhiteLevel {
+  header: DISPLAYCONFIG_DEVICE_INFO_HEADER,
+  sdr_white_level: u32,
+  /// Whether this is the value the slider was let go at, rather than one of the values it went through
+  final_value: u8
+}
+
+/// Brightness of the SDR content of a display in HDR mode, which most monitors take rather than the brightness set over
+/// DDC/CI while in that mode. It's set from 0 to 100 like the brightness, which is the range of the slider the Settings
+/// app has for it
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/api/wingdi/ns-wingdi-displayconfig_sdr_white_level
+pub struct SdrWhiteLevel {
+  adapter_id: LUID,
+  target_id: u32
+}
+
+impl SdrWhiteLevel {
+  /// Find the output of the graphics adapter the given display is shown on
+  pub fn open(hmonitor_handle: HMONITOR) -> io::Result<Self> {
+    let device_name = display_device_name(hmonitor_handle).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the display has no device name"))?;
+    active_paths()?
+      .into_iter()
+      .find(|path| source_device_name(path).is_ok_and(|source_name| source_name == device_name))
+      .map(|path| Self { adapter_id: path.targetInfo.adapterId, target_id: path.targetInfo.id })
+      .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the display isn't shown on any output"))
+  }
+
+  /// Tell whether HDR is turned on for the display, which is when the SDR white level is the brightness to adjust
+  pub fn is_hdr_enabled(&self) -> io::Result<bool> {
+    let mut color_info = DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO {
+      header: self.header(DISPLAYCONFIG_DEVICE_INFO_GET_ADVANCED_COLOR_INFO, size_of::<DISPLAYCONFIG_GET_ADVANCED_COLOR_INFO>()),
+      ..Default::default()
+    };
+    check(unsafe { DisplayConfigGetDeviceInfo(&mut color_info.header) })?;
+    Ok(unsafe { color_info.Anonymous.value } & ADVANCED_COLOR_ENABLED != 0)
+  }
+
+  /// Get the SDR white level from 0 to 100
+  pub fn get(&self) -> io::Result<u16> {
+    let mut white_level = DISPLAYCONFIG_SDR_WHITE_LEVEL {
+      header: self.header(DISPLAYCONFIG_DEVICE_INFO_GET_SDR_WHITE_LEVEL, size_of::<DISPLAYCONFIG_SDR_WHITE_LEVEL>()),
+      ..Default::default()
+    };
+    check(unsafe { DisplayConfigGetDeviceInfo(&mut white_level.header) })?;
+    let level = white_level.SDRWhiteLevel.clamp(MIN_WHITE_LEVEL, MAX_WHITE_LEVEL) - MIN_WHITE_LEVEL;
+    Ok(((level * 100 + (MAX_WHITE_LEVEL - MIN_WHITE_LEVEL) / 2) / (MAX_WHITE_LEVEL - MIN_WHITE_LEVEL)) as u16)
+  }
+
+  /// Set the SDR white level from 0 to 100
+  pub fn set(&self, value: u16) -> io::Result<()> {
+    let white_level = DisplayConfigSetSdrWhiteLevel {
+      header: self.header(DISPLAYCONFIG_DEVICE_INFO_SET_SDR_WHITE_LEVEL, size_of::<DisplayConfigSetSdrWhiteLevel>()),
+      sdr_white_level: MIN_WHITE_LEVEL + (value.min(100) as u32 * (MAX_WHITE_LEVEL - MIN_WHITE_LEVEL) + 50) / 100,
+      final_value: 1
+    };
+    check(unsafe { DisplayConfigSetDeviceInfo(&white_level.header) })
+  }
+
+  fn header(&self, request: DISPLAYCONFIG_DEVICE_INFO_TYPE, size: usize) -> DISPLAYCONFIG_DEVICE_INFO_HEADER {
+    DISPLAYCONFIG_DEVICE_INFO_HEADER { r#type: request, size: size as u32, adapterId: self.adapter_id, id: self.target_id }
+  }
+}
+
+/// Get the paths from the sources of the graphics adapters to the displays that are currently shown
+fn active_paths() -> io::Result<Vec<DISPLAYCONFIG_PATH_INFO>> {
+  let mut path_count = 0;
+  let mut mode_count = 0;
+  unsafe { GetDisplayConfigBufferSizes(QDC_ONLY_ACTIVE_PATHS, &mut path_count, &mut mode_count) }.ok()?;
+  let mut paths = vec![DISPLAYCONFIG_PATH_INFO::default(); path_count as usize];
+  let mut modes = vec![DISPLAYCONFIG_MODE_INFO::default(); mode_count as usize];
+  unsafe { QueryDisplayConfig(QDC_ONLY_ACTIVE_PATHS, &mut path_count, paths.as_mut_ptr(), &mut mode_count, modes.as_mut_ptr(), None) }.ok()?;
+  paths.truncate(path_count as usize);
+  Ok(paths)
+}
+
+/// Get the device name of the display a path starts from, such as \\.\DISPLAY1, as a NUL-terminated wide string
+fn source_device_name(path: &DISPLAYCONFIG_PATH_INFO) -> io::Result<[u16; 32]> {
+  let mut source_name = DISPLAYCONFIG_SOURCE_DEVICE_NAME {
+    header: DISPLAYCONFIG_DEVICE_INFO_HEADER {
+      r#type: DISPLAYCONFIG_DEVICE_INFO_GET_SOURCE_NAME,
+      size: size_of::<DISPLAYCONFIG_SOURCE_DEVICE_NAME>() as u32,
+      adapterId: path.sourceInfo.adapterId,
+      id: path.sourceInfo.id
+    },
+    ..Default::default()
+  };
+  check(unsafe { DisplayConfigGetDeviceInfo(&mut source_name.header) })?;
+  Ok(source_name.viewGdiDeviceName)
+}
+
+fn check(result: i32) -> io::Result<()> {
+  match result == ERROR_SUCCESS.0 as i32 {
+    true => Ok(()),
+    false => Err(io::Error::from_raw_os_error(result))
+  }
+}
@@ -0,0 +1,174 @@
+use crate::elevation::is_elevated;
+
+use crossbeam_channel::{Receiver, Sender, bounded};
+use std::env;
+use std::ffi::c_void;
+use std::io;
+use std::iter;
+use std::process::Command;
+use std::sync::OnceLock;
+use std::thread::{self, JoinHandle};
+use tracing::info;
+use windows::core::{w, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{ERROR_CALL_NOT_IMPLEMENTED, NO_ERROR};
+use windows::Win32::System::Services::{
+  RegisterServiceCtrlHandlerExW, SetServiceStatus, StartServiceCtrlDispatcherW, SERVICE_ACCEPT_SHUTDOWN, SERVICE_ACCEPT_STOP,
+  SERVICE_CONTROL_INTERROGATE, SERVICE_CONTROL_SHUTDOWN, SERVICE_CONTROL_STOP, SERVICE_RUNNING, SERVICE_STATUS, SERVICE_STATUS_CURRENT_STATE,
+  SERVICE_STATUS_HANDLE, SERVICE_STOPPED, SERVICE_STOP_PENDING, SERVICE_TABLE_ENTRYW, SERVICE_WIN32_OWN_PROCESS
+};
+
+const SERVICE_NAME: &str = "GmmkProBrightnessKnob";
+const SERVICE_NAME_WIDE: PCWSTR = w!("GmmkProBrightnessKnob");
+const SERVICE_DISPLAY_NAME: &str = "GMMK Pro Brightness Knob";
+const SERVICE_DESCRIPTION: &str = "Controls the brightness of the monitors from boot, without a logged-in console window";
+/// Subcommand the service control manager starts the executable with
+const SERVICE_SUBCOMMAND: &str = "service";
+/// How long the service control manager is told to wait for the program to stop, which is when it forcibly exits
+const STOP_WAIT_HINT_MS: u32 = 5000;
+
+/// Channels shared with the callbacks of the service control manager, which run on threads of its own
+struct ServiceChannels {
+  stop_tx: Sender<()>,
+  started_tx: Sender<windows::core::Result<()>>,
+  stopped_rx: Receiver<()>
+}
+
+static SERVICE_CHANNELS: OnceLock<ServiceChannels> = OnceLock::new();
+static STATUS_HANDLE: OnceLock<SERVICE_STATUS_HANDLE> = OnceLock::new();
+
+/// Connection to the service control manager, which reports the service as stopped once dropped. It has to outlive the
+/// shutdown of every thread, for the service control manager not to consider the program as crashed
+pub struct ServiceSession {
+  stopped_tx: Option<Sender<()>>,
+  dispatcher: Option<JoinHandle<()>>
+}
+
+/// Connect to the service control manager, which only works when the program was started by it, and report the
+/// service as running. Stop and shutdown requests are forwarded to `stop_tx`, in place of Ctrl-C. Only the first call
+/// does anything
+pub fn start_dispatcher(stop_tx: Sender<()>) -> windows::core::Result<ServiceSession> {
+  let (started_tx, started_rx) = bounded::<windows::core::Result<()>>(1);
+  let (stopped_tx, stopped_rx) = bounded::<()>(0);
+  let _ = SERVICE_CHANNELS.set(ServiceChannels { stop_tx, started_tx: started_tx.clone(), stopped_rx });
+
+  // The dispatcher only returns once the service stopped, and runs the service entry point on a thread of its own
+  let dispatcher = thread::spawn(move || {
+    let mut service_name: Vec<u16> = SERVICE_NAME.encode_utf16().chain(iter::once(0)).collect();
+    let service_table = [
+      SERVICE_TABLE_ENTRYW { lpServiceName: PWSTR(service_name.as_mut_ptr()), lpServiceProc: Some(service_main) },
+      SERVICE_TABLE_ENTRYW::default()
+    ];
+    if !unsafe { StartServiceCtrlDispatcherW(service_table.as_ptr()) }.as_bool() {
+      let _ = started_tx.send(Err(windows::core::Error::from_win32()));
+    }
+  });
+
+  match started_rx.recv() {
+    Ok(Ok(_)) => Ok(ServiceSession { stopped_tx: Some(stopped_tx), dispatcher: Some(dispatcher) }),
+    Ok(Err(e)) => Err(e),
+    Err(_) => Err(windows::core::Error::from_win32())
+  }
+}
+
+impl Drop for ServiceSession {
+  fn drop(&mut self) {
+    drop(self.stopped_tx.take());
+    if let Some(dispatcher) = self.dispatcher.take() {
+      let _ = dispatcher.join();
+    }
+  }
+}
+
+unsafe extern "system" fn service_main(_argc: u32, _argv: *mut PWSTR) {
+  let Some(channels) = SERVICE_CHANNELS.get() else { return };
+  let status_handle = match RegisterServiceCtrlHandlerExW(SERVICE_NAME_WIDE, Some(control_handler), None) {
+    Ok(status_handle) => status_handle,
+    Err(e) => {
+      let _ = channels.started_tx.send(Err(e));
+      return;
+    }
+  };
+  let _ = STATUS_HANDLE.set(status_handle);
+  report_status(SERVICE_RUNNING);
+  let _ = channels.started_tx.send(Ok(()));
+
+  // Returning from here is what tells the service control manager the service stopped
+  let _ = channels.stopped_rx.recv();
+  report_status(SERVICE_STOPPED);
+}
+
+unsafe extern "system" fn control_handler(control: u32, _event_type: u32, _event_data: *mut c_void, _context: *mut c_void) -> u32 {
+  match control {
+    SERVICE_CONTROL_STOP | SERVICE_CONTROL_SHUTDOWN => {
+      report_status(SERVICE_STOP_PENDING);
+      if let Some(channels) = SERVICE_CHANNELS.get() {
+        info!("asked to stop by the service control manager");
+        let _ = channels.stop_tx.try_send(());
+      }
+      NO_ERROR.0
+    },
+    SERVICE_CONTROL_INTERROGATE => NO_ERROR.0,
+    _ => ERROR_CALL_NOT_IMPLEMENTED.0
+  }
+}
+
+fn report_status(state: SERVICE_STATUS_CURRENT_STATE) {
+  let Some(status_handle) = STATUS_HANDLE.get() else { return };
+  let status = SERVICE_STATUS {
+    dwServiceType: SERVICE_WIN32_OWN_PROCESS,
+    dwCurrentState: state,
+    dwControlsAccepted: if state == SERVICE_RUNNING { SERVICE_ACCEPT_STOP | SERVICE_ACCEPT_SHUTDOWN } else { 0 },
+    dwWaitHint: if state == SERVICE_STOP_PENDING { STOP_WAIT_HINT_MS } else { 0 },
+    ..Default::default()
+  };
+  unsafe { SetServiceStatus(*status_handle, &status); }
+}
+
+/// Register this executable as a service started at boot, then start it. The service runs as LocalSystem in session 0,
+/// apart from the session of the logged-in user, which means that it can't drive the monitors the user sees, nor
+/// capture the knob or the action bindings. Only its IPC server is reachable from the session of the user, whose
+/// interactive users are granted access to the pipe, while its shared memory block stays in session 0 where nothing of
+/// the user can open it. Its config file is the one next to the executable
+pub fn install_service() -> Result<(), ServiceError> {
+  if !is_elevated() {
+    return Err(ServiceError::NotElevated);
+  }
+
+  let bin_path = format!("\"{}\" {}", env::current_exe()?.display(), SERVICE_SUBCOMMAND);
+  run_sc(&["create", SERVICE_NAME, "binPath=", &bin_path, "start=", "auto", "DisplayName=", SERVICE_DISPLAY_NAME])?;
+  run_sc(&["description", SERVICE_NAME, SERVICE_DESCRIPTION])?;
+  run_sc(&["start", SERVICE_NAME])
+}
+
+/// Stop the service if it's running, then remove it
+pub fn uninstall_service() -> Result<(), ServiceError> {
+  if !is_elevated() {
+    return Err(ServiceError::NotElevated);
+  }
+
+  // Fails when the service isn't running, which is fine
+  let _ = run_sc(&["stop", SERVICE_NAME]);
+  run_sc(&["delete", SERVICE_NAME])
+}
+
+fn run_sc(args: &[&str]) -> Result<(), ServiceError> {
+  let output = Command::new("sc.exe").args(args).output()?;
+  match output.status.success() {
+    true => Ok(()),
+    // sc.exe reports its errors on the standard output
+    false => Err(ServiceError::ScError(String::from_utf8_lossy(&output.stdout).trim().to_string()))
+  }
+}
+
+#[derive(Debug)]
+pub enum ServiceError {
+  NotElevated,
+  IOError(io::Error),
+  ScError(String)
+}
+
+impl From<io::Error> for ServiceError {
+  fn from(value: io::Error) -> Self {
+    ServiceError::IOError(value)
+  }
+}
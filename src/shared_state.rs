@@ -0,0 +1,158 @@
+use std::mem::size_of;
+use std::ptr;
+use std::sync::atomic::{fence, Ordering};
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+use windows::Win32::System::Memory::{
+  CreateFileMappingW, MapViewOfFile, UnmapViewOfFile, FILE_MAP_WRITE, MEMORYMAPPEDVIEW_HANDLE, PAGE_READWRITE
+};
+
+/// Name of the file mapping, which lives in the session namespace so that every program of the logged in user can open
+/// it. The block of the instance running as the service is in session 0, out of reach of the programs of the user
+const SHARED_STATE_NAME: PCWSTR = w!("Local\\GmmkProBrightnessKnobState");
+const SHARED_STATE_MAGIC: u32 = u32::from_le_bytes(*b"GPBK");
+const SHARED_STATE_VERSION: u32 = 1;
+const MAX_SHARED_MONITORS: usize = 8;
+const MONITOR_NAME_LENGTH: usize = 64;
+
+/// Set in `flags` while the knob adjustments are paused
+pub const FLAG_PAUSED: u32 = 1;
+
+/// Stable binary layout of the shared memory block. Every field is a little-endian 32 bit integer, except for the
+/// monitor names:
+///
+/// | Offset | Field         | Description                                                             |
+/// |--------|---------------|-------------------------------------------------------------------------|
+/// | 0      | magic         | "GPBK" in ASCII                                                         |
+/// | 4      | version       | Layout version, only bumped on breaking changes                        |
+/// | 8      | sequence      | Odd while the block is being updated, readers retry until it's even    |
+/// |        |               | and unchanged across their read                                         |
+/// | 12     | flags         | Bit 0 set while the knob adjustments are paused                         |
+/// | 16     | mode          | Index of the active knob mode                                           |
+/// | 20     | monitor_count | Number of valid entries in `monitors`                                   |
+/// | 24     | monitors      | 8 entries of 132 bytes: the brightness (-1 while disconnected) followed |
+/// |        |               | by the NUL-padded UTF-16 name, 64 code units long                       |
+/// | 1080   | change_count  | Number of brightness changes applied so far, 0 before the first one     |
+/// | 1084   | change_source | What made the last change: 0 knob, 1 mouse wheel, 2 external program,  |
+/// |        |               | 3 action binding, 4 schedule, 5 outside of this program, 6 dimming      |
+/// |        |               | following the content of the screens                                    |
+/// | 1088   | change_from   | Brightness before the last change                                       |
+/// | 1092   | change_to     | Brightness after the last change                                        |
+/// | 1096   | change_ms     | How long the transition of the last change took, in milliseconds       |
+#[repr(C)]
+struct SharedStateLayout {
+  magic: u32,
+  version: u32,
+  sequence: u32,
+  flags: u32,
+  mode: u32,
+  monitor_count: u32,
+  monitors: [SharedMonitorLayout; MAX_SHARED_MONITORS],
+  change_count: u32,
+  change_source: u32,
+  change_from: i32,
+  change_to: i32,
+  change_ms: u32
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SharedMonitorLayout {
+  brightness: i32,
+  name: [u16; MONITOR_NAME_LENGTH]
+}
+
+/// State of a monitor, as published in the shared memory block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedMonitorState {
+  pub name: String,
+  /// Current brightness, unset while the monitor is disconnected
+  pub brightness: Option<u16>
+}
+
+/// Last brightness change applied to the monitors, as published in the shared memory block
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SharedChange {
+  /// Number of changes applied so far, which tells a new change apart from the previous one
+  pub count: u32,
+  pub source: u32,
+  pub from: i32,
+  pub to: i32,
+  pub duration_ms: u32
+}
+
+/// Publish the current state in a named shared memory block, which external tools (e.g. Rainmeter skins or AutoHotkey
+/// scripts) can poll at almost no cost
+pub struct SharedState {
+  mapping: HANDLE,
+  view: MEMORYMAPPEDVIEW_HANDLE,
+  sequence: u32
+}
+
+impl SharedState {
+  pub fn create() -> windows::core::Result<Self> {
+    unsafe {
+      let size = size_of::<SharedStateLayout>() as u32;
+      let mapping = CreateFileMappingW(INVALID_HANDLE_VALUE, None, PAGE_READWRITE, 0, size, SHARED_STATE_NAME)?;
+      let view = match MapViewOfFile(mapping, FILE_MAP_WRITE, 0, 0, size as usize) {
+        Ok(view) => view,
+        Err(e) => {
+          CloseHandle(mapping);
+          return Err(e);
+        }
+      };
+
+      let mut shared_state = Self { mapping, view, sequence: 0 };
+      shared_state.publish(&[], 0, 0, SharedChange::default());
+      Ok(shared_state)
+    }
+  }
+
+  /// Replace the published state. Monitors past the first 8 are left out
+  pub fn publish(&mut self, monitors: &[SharedMonitorState], flags: u32, mode: u32, change: SharedChange) {
+    let mut layout = SharedStateLayout {
+      magic: SHARED_STATE_MAGIC,
+      version: SHARED_STATE_VERSION,
+      sequence: 0,
+      flags,
+      mode,
+      monitor_count: monitors.len().min(MAX_SHARED_MONITORS) as u32,
+      monitors: [SharedMonitorLayout { brightness: 0, name: [0; MONITOR_NAME_LENGTH] }; MAX_SHARED_MONITORS],
+      change_count: change.count,
+      change_source: change.source,
+      change_from: change.from,
+      change_to: change.to,
+      change_ms: change.duration_ms
+    };
+    for (entry, monitor) in layout.monitors.iter_mut().zip(monitors) {
+      entry.brightness = monitor.brightness.map_or(-1, |brightness| brightness as i32);
+      for (unit, c) in entry.name.iter_mut().take(MONITOR_NAME_LENGTH - 1).zip(monitor.name.encode_utf16()) {
+        *unit = c;
+      }
+    }
+
+    // Readers can tell the block is being updated while the sequence number is odd
+    let block = self.view.0 as *mut SharedStateLayout;
+    unsafe {
+      self.sequence = self.sequence.wrapping_add(1);
+      ptr::write_volatile(ptr::addr_of_mut!((*block).sequence), self.sequence);
+      fence(Ordering::Release);
+
+      layout.sequence = self.sequence;
+      ptr::write_volatile(block, layout);
+      fence(Ordering::Release);
+
+      self.sequence = self.sequence.wrapping_add(1);
+      ptr::write_volatile(ptr::addr_of_mut!((*block).sequence), self.sequence);
+    }
+  }
+}
+
+impl Drop for SharedState {
+  fn drop(&mut self) {
+    unsafe {
+      UnmapViewOfFile(self.view);
+      CloseHandle(self.mapping);
+    }
+  }
+}
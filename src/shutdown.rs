@@ -0,0 +1,117 @@
+use crossbeam_channel::{Receiver, Sender, bounded};
+use std::process;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tracing::{error, info};
+use windows::Win32::Foundation::{LPARAM, WPARAM};
+use windows::Win32::System::Threading::GetCurrentThreadId;
+use windows::Win32::UI::WindowsAndMessaging::{PostThreadMessageW, WM_QUIT};
+
+const FORCE_EXIT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Signal telling a thread that it's its turn to stop. The signal is sent by disconnecting the channel, so that every
+/// clone of the receiver observes it, unlike a message that only one of them would get
+pub type StopSignal = Receiver<()>;
+
+/// Own the lifecycle of the long-running threads of the program. Shutdown is requested either by Ctrl-C, the service
+/// control manager or by any of the threads returning on their own, after which the threads are stopped one at a time
+/// in the order they were spawned: first the input hooks are removed so that no more events come through, then the
+/// brightness thread flushes its final write and saves the brightness, and the IPC server is closed last. If the
+/// teardown takes too long the process is forcibly terminated
+pub struct Shutdown {
+  requested_tx: Sender<()>,
+  requested_rx: Receiver<()>,
+  stages: Vec<Stage>
+}
+
+struct Stage {
+  name: &'static str,
+  stop_tx: Sender<()>,
+  handle: JoinHandle<()>
+}
+
+impl Shutdown {
+  pub fn new() -> Self {
+    let (requested_tx, requested_rx) = bounded::<()>(1);
+    Self {
+      requested_tx,
+      requested_rx,
+      stages: Vec::new()
+    }
+  }
+
+  /// Register a Ctrl-C handler that requests the shutdown
+  pub fn install_ctrlc_handler(&self) -> Result<(), ctrlc::Error> {
+    let requested_tx = self.requested_tx.clone();
+    ctrlc::set_handler(move || {
+      info!("received Ctrl-C");
+      let _ = requested_tx.try_send(());
+    })
+  }
+
+  /// Get a sender requesting the shutdown, for whatever else than Ctrl-C asks the program to stop, such as the service
+  /// control manager
+  pub fn request_sender(&self) -> Sender<()> {
+    self.requested_tx.clone()
+  }
+
+  /// Spawn a new thread that runs until its stop signal is sent. Threads are stopped in the same order they were
+  /// spawned
+  pub fn spawn_stage<F>(&mut self, name: &'static str, f: F) where F: FnOnce(StopSignal) + Send + 'static {
+    let (stop_tx, stop_rx) = bounded::<()>(0);
+    let guard = RequestOnDrop(self.requested_tx.clone());
+
+    let handle = thread::spawn(move || {
+      // The guard is moved in so that the shutdown is requested even if the thread panics
+      let _guard = guard;
+      f(stop_rx);
+    });
+    self.stages.push(Stage { name, stop_tx, handle });
+  }
+
+  /// Block until the shutdown is requested, then stop every thread in order
+  pub fn wait(self) {
+    let _ = self.requested_rx.recv();
+    info!("shutting down...");
+
+    // Nothing here can be trusted to return in a timely manner, DDC/CI writes in particular can hang for a long time
+    thread::spawn(|| {
+      thread::sleep(FORCE_EXIT_TIMEOUT);
+      error!("shutdown timed out after {:?}, forcing exit", FORCE_EXIT_TIMEOUT);
+      process::exit(1);
+    });
+
+    for stage in self.stages {
+      drop(stage.stop_tx);
+      if stage.handle.join().is_err() {
+        error!("the {} thread panicked", stage.name);
+      }
+    }
+  }
+}
+
+impl Default for Shutdown {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+struct RequestOnDrop(Sender<()>);
+
+impl Drop for RequestOnDrop {
+  fn drop(&mut self) {
+    let _ = self.0.try_send(());
+  }
+}
+
+/// Forward the stop signal to the message loop running on the current thread, so that GetMessageW can return
+pub fn forward_stop_to_message_loop(stop_rx: StopSignal) {
+  let thread_id = unsafe { GetCurrentThreadId() };
+
+  thread::spawn(move || {
+    let _ = stop_rx.recv();
+
+    // Note: PostQuitMessage won't work here because we are on a different thread!
+    unsafe { PostThreadMessageW(thread_id, WM_QUIT, WPARAM(0), LPARAM(0)); }
+  });
+}
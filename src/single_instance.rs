@@ -0,0 +1,44 @@
+use windows::core::{w, PCWSTR};
+use windows::Win32::Foundation::{CloseHandle, GetLastError, ERROR_ALREADY_EXISTS, HANDLE};
+use windows::Win32::System::Threading::{CreateMutexW, OpenMutexW, SYNCHRONIZATION_SYNCHRONIZE};
+
+/// Name of the mutex owned by the running instance, in the session namespace so that every user can run their own
+const INSTANCE_MUTEX_NAME: PCWSTR = w!("Local\\GmmkProBrightnessKnobInstance");
+
+/// Mark this process as the running instance for as long as it's kept around, so that starting the program again
+/// doesn't install a second pair of hooks adjusting the brightness twice for every turn of the knob
+pub struct SingleInstance {
+  mutex: HANDLE
+}
+
+impl SingleInstance {
+  /// Returns nothing when another instance is running already
+  pub fn acquire() -> windows::core::Result<Option<Self>> {
+    unsafe {
+      let mutex = CreateMutexW(None, false, INSTANCE_MUTEX_NAME)?;
+      // The handle opens the existing mutex in that case, which must not be kept open for it to go away with its owner
+      if GetLastError() == ERROR_ALREADY_EXISTS {
+        CloseHandle(mutex);
+        return Ok(None);
+      }
+      Ok(Some(Self { mutex }))
+    }
+  }
+}
+
+impl Drop for SingleInstance {
+  fn drop(&mut self) {
+    unsafe { CloseHandle(self.mutex); }
+  }
+}
+
+/// Tell whether another process holds the instance mutex, without claiming it
+pub fn is_running() -> bool {
+  match unsafe { OpenMutexW(SYNCHRONIZATION_SYNCHRONIZE, false, INSTANCE_MUTEX_NAME) } {
+    Ok(mutex) => {
+      unsafe { CloseHandle(mutex); }
+      true
+    },
+    Err(_) => false
+  }
+}
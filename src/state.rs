@@ -0,0 +1,229 @@
+use crate::keyboard_knob::EventSource;
+use crate::knob_mode::{KnobMode, ModeCycle};
+use crate::monitor_group::{MonitorGroup, MonitorSelection};
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::mem;
+use std::time::Duration;
+
+/// State of the brightness, owned by the brightness thread. The other threads never see it directly, they are sent
+/// snapshots of it instead, which are all taken at the same point of the thread's loop so that they are consistent
+#[derive(Debug)]
+pub struct BrightnessState {
+  /// Brightness the monitors are at. It's kept while they are all disconnected, so that it can be restored as soon as
+  /// they are connected again
+  pub current: i32,
+  /// Brightness being transitioned to, which is the current one once the transition settled
+  pub target: i32,
+  /// Whether the current brightness was read from the monitors, or set on them, rather than only taken from the one
+  /// saved the last time because they couldn't be read. Nothing is written to them until it's known, for them not to
+  /// jump to a value they were never at
+  pub known: bool,
+  /// Fractions of a step that didn't add up to a whole brightness value yet, so that slow rotations of a
+  /// high-resolution wheel are not lost
+  pub remainder: f64,
+  pub paused: bool,
+  pub selection: MonitorSelection,
+  /// Names of the monitors adjusted by the knob, which is empty while they are all disconnected
+  pub monitor_names: Vec<String>,
+  /// What set the brightness being transitioned to
+  pub source: ChangeSource,
+  /// Last change of the brightness that was applied to the monitors
+  pub last_change: Option<AppliedChange>,
+  /// Changes applied since they were last taken, oldest first, for none of them to be missed by the notifications
+  pub unsent_changes: Vec<AppliedChange>,
+  /// Offsets of the monitors from the brightness, by identifier, when they are linked rather than all set to the same
+  /// brightness. The ones of the monitors that got disconnected are kept for when they come back
+  pub offsets: Option<BTreeMap<String, i32>>
+}
+
+impl BrightnessState {
+  pub fn new(brightness: i32, known: bool, selection: MonitorSelection, monitor_names: Vec<String>) -> Self {
+    Self {
+      current: brightness,
+      target: brightness,
+      known,
+      remainder: 0.0,
+      paused: false,
+      selection,
+      monitor_names,
+      source: ChangeSource::Knob,
+      last_change: None,
+      unsent_changes: Vec::new(),
+      offsets: None
+    }
+  }
+
+  /// Give the monitors their offset from the brightness when they are linked, measuring it from the brightness they are
+  /// at for the ones never seen before
+  pub fn link(&mut self, monitors: &mut MonitorGroup) {
+    let Some(offsets) = self.offsets.as_mut() else { return };
+    for monitor in monitors.monitors_mut() {
+      let monitor_id = monitor.id();
+      let offset = match offsets.get(&monitor_id) {
+        Some(offset) => *offset,
+        // The ones whose brightness can't be read simply follow the others
+        None => monitor.get_brightness().map_or(0, |value| value as i32 - self.current)
+      };
+      offsets.insert(monitor_id, offset);
+      monitor.brightness_offset = offset;
+    }
+  }
+
+  /// Remember that the monitors were moved from one brightness to another, by whatever set the target
+  pub fn record_change(&mut self, from: i32, to: i32, duration: Duration, monitors: &MonitorGroup) {
+    let sequence = self.last_change.as_ref().map_or(1, |change| change.sequence.wrapping_add(1));
+    let monitor_changes = monitors
+      .brightness_by_name(from as u16)
+      .into_iter()
+      .zip(monitors.brightness_by_name(to as u16))
+      .map(|((name, monitor_from), (_, monitor_to))| MonitorChange { name, from: monitor_from, to: monitor_to })
+      .collect();
+    let change = AppliedChange { sequence, from: from as u16, to: to as u16, source: self.source, duration, monitors: monitor_changes };
+    self.unsent_changes.push(change.clone());
+    self.last_change = Some(change);
+  }
+
+  /// Take the changes applied since the last call
+  pub fn take_changes(&mut self) -> Vec<AppliedChange> {
+    mem::take(&mut self.unsent_changes)
+  }
+
+  /// Take a snapshot of the state, along with the mode of the knob and the brightness every monitor is at, which is
+  /// offset from the one of the group when they are linked and kept within the range of each of them
+  pub fn snapshot(&self, mode_cycle: &ModeCycle, monitors: &MonitorGroup) -> StateSnapshot {
+    let brightness = (!self.monitor_names.is_empty()).then_some(self.current as u16);
+    StateSnapshot {
+      brightness,
+      target: brightness.map(|_| self.target as u16),
+      mode: mode_cycle.current(),
+      mode_value: mode_cycle.value().map(|value| value as u16),
+      paused: self.paused,
+      selection: self.selection.clone(),
+      monitors: monitor_snapshots(monitors, self.current as u16),
+      last_change: self.last_change.clone()
+    }
+  }
+}
+
+/// State of the program as seen by the tray icon, the shared memory block and anything else reporting it
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateSnapshot {
+  /// Brightness of the monitors, which is unset while they are all disconnected. It follows every frame of the
+  /// transitions
+  pub brightness: Option<u16>,
+  /// Brightness the ongoing transition ends at, if any
+  pub target: Option<u16>,
+  /// Setting adjusted by the knob, along with its value when it's not the brightness
+  pub mode: KnobMode,
+  pub mode_value: Option<u16>,
+  pub paused: bool,
+  pub selection: MonitorSelection,
+  pub monitors: Vec<MonitorSnapshot>,
+  pub last_change: Option<AppliedChange>
+}
+
+impl StateSnapshot {
+  /// Update the brightness in between two snapshots, for a frame of a transition of the given monitors, returning
+  /// whether it changed
+  pub fn show_brightness(&mut self, value: u16, monitors: &MonitorGroup) -> bool {
+    if self.brightness.is_none() || self.brightness == Some(value) {
+      return false;
+    }
+    self.brightness = Some(value);
+    self.monitors = monitor_snapshots(monitors, value);
+    true
+  }
+}
+
+fn monitor_snapshots(monitors: &MonitorGroup, value: u16) -> Vec<MonitorSnapshot> {
+  monitors
+    .brightness_by_name(value)
+    .into_iter()
+    .map(|(name, brightness)| MonitorSnapshot { name, brightness: Some(brightness) })
+    .collect()
+}
+
+/// State of a single monitor adjusted by the knob
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorSnapshot {
+  pub name: String,
+  pub brightness: Option<u16>
+}
+
+/// Represent what changed the brightness
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum ChangeSource {
+  /// The knob of the keyboard
+  Knob,
+  /// The mouse wheel, when emulating the knob
+  Wheel,
+  /// An external program talking to the running instance
+  Ipc,
+  /// An action binding
+  Action,
+  /// An entry of the schedule
+  Schedule,
+  /// Something other than this program, such as the buttons of the monitor
+  External,
+  /// The dimming following the content of the screens
+  Content
+}
+
+impl ChangeSource {
+  const ALL: [ChangeSource; 7] = [Self::Knob, Self::Wheel, Self::Ipc, Self::Action, Self::Schedule, Self::External, Self::Content];
+
+  /// Get the source written as the given number in the shared memory block
+  pub fn from_index(index: u32) -> Option<Self> {
+    Self::ALL.get(index as usize).copied()
+  }
+}
+
+impl From<EventSource> for ChangeSource {
+  fn from(value: EventSource) -> Self {
+    match value {
+      EventSource::Keyboard | EventSource::Hid | EventSource::ConsumerControl => Self::Knob,
+      EventSource::Mouse => Self::Wheel,
+      EventSource::Ipc => Self::Ipc
+    }
+  }
+}
+
+impl fmt::Display for ChangeSource {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      Self::Knob => "knob",
+      Self::Wheel => "wheel",
+      Self::Ipc => "ipc",
+      Self::Action => "action",
+      Self::Schedule => "schedule",
+      Self::External => "external",
+      Self::Content => "content"
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Change of the brightness applied to the monitors, which is numbered so that two identical ones can be told apart
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedChange {
+  pub sequence: u32,
+  pub from: u16,
+  pub to: u16,
+  pub source: ChangeSource,
+  /// How long the transition took, which is 0 when written straight away
+  pub duration: Duration,
+  /// Brightness each of the monitors was moved from and to, which is offset from the one of the group when they are
+  /// linked and kept within the range of each of them
+  pub monitors: Vec<MonitorChange>
+}
+
+/// Change of the brightness of a single monitor, as part of the change applied to all of them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MonitorChange {
+  pub name: String,
+  pub from: u16,
+  pub to: u16
+}
@@ -0,0 +1,63 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static STATS: Stats = Stats {
+  events_processed: AtomicU64::new(0),
+  events_dropped: AtomicU64::new(0),
+  events_coalesced: AtomicU64::new(0),
+  writes_ignored: AtomicU64::new(0),
+  wakeups: AtomicU64::new(0)
+};
+
+/// Counters describing how the knob adjustment events were handled since the program started
+struct Stats {
+  events_processed: AtomicU64,
+  events_dropped: AtomicU64,
+  events_coalesced: AtomicU64,
+  writes_ignored: AtomicU64,
+  wakeups: AtomicU64
+}
+
+/// Copy of the counters taken at a given point in time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatsSnapshot {
+  /// Events applied by the brightness thread
+  pub events_processed: u64,
+  /// Events discarded because too many of them were waiting to be processed already
+  pub events_dropped: u64,
+  /// Events whose transition was cut short and merged into the one of the event that came after them
+  pub events_coalesced: u64,
+  /// Brightness writes that the monitor acknowledged without applying them, even after retrying
+  pub writes_ignored: u64,
+  /// Times the brightness thread woke up, for whatever reason, which nothing should make it do while the knob is idle
+  pub wakeups: u64
+}
+
+pub fn record_processed() {
+  STATS.events_processed.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_dropped() {
+  STATS.events_dropped.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_coalesced() {
+  STATS.events_coalesced.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_ignored_write() {
+  STATS.writes_ignored.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_wakeup() {
+  STATS.wakeups.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn snapshot() -> StatsSnapshot {
+  StatsSnapshot {
+    events_processed: STATS.events_processed.load(Ordering::Relaxed),
+    events_dropped: STATS.events_dropped.load(Ordering::Relaxed),
+    events_coalesced: STATS.events_coalesced.load(Ordering::Relaxed),
+    writes_ignored: STATS.writes_ignored.load(Ordering::Relaxed),
+    wakeups: STATS.wakeups.load(Ordering::Relaxed)
+  }
+}
@@ -0,0 +1,151 @@
+use std::sync::OnceLock;
+use windows::Win32::Globalization::GetUserDefaultLocaleName;
+
+// Maximum length of a locale name, including the terminating NUL
+//
+// Reference: https://learn.microsoft.com/en-us/windows/win32/intl/locale-name-constants
+const LOCALE_NAME_MAX_LENGTH: usize = 85;
+
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Represent a language the user-facing strings are translated to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+  English,
+  German,
+  French,
+  Spanish
+}
+
+impl Language {
+  /// Get the language matching a locale name such as "de-DE" or "fr", if there's a translation for it
+  pub fn from_locale_name(locale_name: &str) -> Option<Self> {
+    let language_code = locale_name.split(['-', '_']).next()?.to_lowercase();
+    match language_code.as_str() {
+      "en" => Some(Language::English),
+      "de" => Some(Language::German),
+      "fr" => Some(Language::French),
+      "es" => Some(Language::Spanish),
+      _ => None
+    }
+  }
+}
+
+/// Identify a user-facing string
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Text {
+  UsageOverLastDay,
+  UsageOverLastWeek,
+  NoUsageRecorded,
+  Adjustments,
+  ScheduleOverrides,
+  Brightness,
+  Contrast,
+  Volume,
+  CoarseSteps,
+  Paused,
+  PauseKnob,
+  ResumeKnob,
+  PrimaryMonitor,
+  AllMonitors,
+  CursorMonitor,
+  FocusedWindowMonitor,
+  Exit
+}
+
+/// Select the language of the user-facing strings, either the given locale or the one of the user when unset. Only the
+/// first call has any effect, and English is used for the locales that have no translation
+pub fn init_language(locale_name: Option<&str>) {
+  let locale_name = locale_name.map(|name| name.to_string()).or_else(user_locale_name).unwrap_or_default();
+  let _ = LANGUAGE.set(Language::from_locale_name(&locale_name).unwrap_or(Language::English));
+}
+
+/// Get a user-facing string in the selected language
+pub fn text(key: Text) -> &'static str {
+  let language = *LANGUAGE.get_or_init(|| {
+    user_locale_name().and_then(|name| Language::from_locale_name(&name)).unwrap_or(Language::English)
+  });
+
+  match (language, key) {
+    (Language::English, Text::UsageOverLastDay) => "Usage over the last day",
+    (Language::English, Text::UsageOverLastWeek) => "Usage over the last week",
+    (Language::English, Text::NoUsageRecorded) => "No usage recorded",
+    (Language::English, Text::Adjustments) => "Adjustments",
+    (Language::English, Text::ScheduleOverrides) => "Schedule overrides",
+    (Language::English, Text::Brightness) => "Brightness",
+    (Language::English, Text::Contrast) => "Contrast",
+    (Language::English, Text::Volume) => "Volume",
+    (Language::English, Text::CoarseSteps) => "coarse",
+    (Language::English, Text::Paused) => "paused",
+    (Language::English, Text::PauseKnob) => "Pause the knob",
+    (Language::English, Text::ResumeKnob) => "Resume the knob",
+    (Language::English, Text::PrimaryMonitor) => "Primary monitor",
+    (Language::English, Text::AllMonitors) => "All monitors",
+    (Language::English, Text::CursorMonitor) => "Monitor under the cursor",
+    (Language::English, Text::FocusedWindowMonitor) => "Monitor of the focused window",
+    (Language::English, Text::Exit) => "Exit",
+
+    (Language::German, Text::UsageOverLastDay) => "Nutzung am letzten Tag",
+    (Language::German, Text::UsageOverLastWeek) => "Nutzung in der letzten Woche",
+    (Language::German, Text::NoUsageRecorded) => "Keine Nutzung aufgezeichnet",
+    (Language::German, Text::Adjustments) => "Anpassungen",
+    (Language::German, Text::ScheduleOverrides) => "Übersteuerungen des Zeitplans",
+    (Language::German, Text::Brightness) => "Helligkeit",
+    (Language::German, Text::Contrast) => "Kontrast",
+    (Language::German, Text::Volume) => "Lautstärke",
+    (Language::German, Text::CoarseSteps) => "grob",
+    (Language::German, Text::Paused) => "pausiert",
+    (Language::German, Text::PauseKnob) => "Drehregler pausieren",
+    (Language::German, Text::ResumeKnob) => "Drehregler fortsetzen",
+    (Language::German, Text::PrimaryMonitor) => "Hauptbildschirm",
+    (Language::German, Text::AllMonitors) => "Alle Bildschirme",
+    (Language::German, Text::CursorMonitor) => "Bildschirm unter dem Mauszeiger",
+    (Language::German, Text::FocusedWindowMonitor) => "Bildschirm des aktiven Fensters",
+    (Language::German, Text::Exit) => "Beenden",
+
+    (Language::French, Text::UsageOverLastDay) => "Utilisation au cours du dernier jour",
+    (Language::French, Text::UsageOverLastWeek) => "Utilisation au cours de la dernière semaine",
+    (Language::French, Text::NoUsageRecorded) => "Aucune utilisation enregistrée",
+    (Language::French, Text::Adjustments) => "Réglages",
+    (Language::French, Text::ScheduleOverrides) => "Dérogations au programme",
+    (Language::French, Text::Brightness) => "Luminosité",
+    (Language::French, Text::Contrast) => "Contraste",
+    (Language::French, Text::Volume) => "Volume",
+    (Language::French, Text::CoarseSteps) => "grossier",
+    (Language::French, Text::Paused) => "en pause",
+    (Language::French, Text::PauseKnob) => "Mettre la molette en pause",
+    (Language::French, Text::ResumeKnob) => "Reprendre la molette",
+    (Language::French, Text::PrimaryMonitor) => "Écran principal",
+    (Language::French, Text::AllMonitors) => "Tous les écrans",
+    (Language::French, Text::CursorMonitor) => "Écran sous le curseur",
+    (Language::French, Text::FocusedWindowMonitor) => "Écran de la fenêtre active",
+    (Language::French, Text::Exit) => "Quitter",
+
+    (Language::Spanish, Text::UsageOverLastDay) => "Uso durante el último día",
+    (Language::Spanish, Text::UsageOverLastWeek) => "Uso durante la última semana",
+    (Language::Spanish, Text::NoUsageRecorded) => "No se ha registrado ningún uso",
+    (Language::Spanish, Text::Adjustments) => "Ajustes",
+    (Language::Spanish, Text::ScheduleOverrides) => "Anulaciones de la programación",
+    (Language::Spanish, Text::Brightness) => "Brillo",
+    (Language::Spanish, Text::Contrast) => "Contraste",
+    (Language::Spanish, Text::Volume) => "Volumen",
+    (Language::Spanish, Text::CoarseSteps) => "grueso",
+    (Language::Spanish, Text::Paused) => "en pausa",
+    (Language::Spanish, Text::PauseKnob) => "Pausar la rueda",
+    (Language::Spanish, Text::ResumeKnob) => "Reanudar la rueda",
+    (Language::Spanish, Text::PrimaryMonitor) => "Pantalla principal",
+    (Language::Spanish, Text::AllMonitors) => "Todas las pantallas",
+    (Language::Spanish, Text::CursorMonitor) => "Pantalla bajo el cursor",
+    (Language::Spanish, Text::FocusedWindowMonitor) => "Pantalla de la ventana activa",
+    (Language::Spanish, Text::Exit) => "Salir"
+  }
+}
+
+fn user_locale_name() -> Option<String> {
+  let mut locale_name = [0u16; LOCALE_NAME_MAX_LENGTH];
+  let length = unsafe { GetUserDefaultLocaleName(&mut locale_name) };
+  if length <= 1 {
+    return None;
+  }
+  Some(String::from_utf16_lossy(&locale_name[..length as usize - 1]))
+}
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use std::f64::consts::PI;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Julian date of the UNIX epoch
+const UNIX_EPOCH_JULIAN_DATE: f64 = 2_440_587.5;
+/// Julian date of 2000-01-01 at noon, which the sunrise equation counts the days from
+const J2000_JULIAN_DATE: f64 = 2_451_545.0;
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// Altitude of the center of the sun when its upper edge touches the horizon, accounting for the atmospheric refraction
+const HORIZON_ALTITUDE: f64 = -0.833;
+const EARTH_AXIAL_TILT: f64 = 23.4397;
+
+/// Place on Earth the sun is followed at, in degrees, with the north and the east being positive
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+  pub latitude: f64,
+  pub longitude: f64
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SunEvent {
+  Sunrise,
+  Sunset
+}
+
+impl fmt::Display for SunEvent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let name = match self {
+      SunEvent::Sunrise => "sunrise",
+      SunEvent::Sunset => "sunset"
+    };
+    write!(f, "{}", name)
+  }
+}
+
+/// Get the first time after the given one the sun rises or sets at the location, which is none for as long as the sun
+/// doesn't rise or set there at all, as during the polar days and nights
+pub fn next_sun_event(event: SunEvent, location: Location, after: SystemTime) -> Option<SystemTime> {
+  let day = after.duration_since(UNIX_EPOCH).ok()?.as_secs() / SECONDS_PER_DAY as u64;
+  // The event of a day on the UTC calendar can fall on the day before or after it, far enough from Greenwich
+  (day.saturating_sub(1)..=day + 2).filter_map(|day| sun_event(event, location, day)).find(|time| *time > after)
+}
+
+/// Compute when the sun rises or sets at the location on the given day, counted from the UNIX epoch
+///
+/// Reference: https://en.wikipedia.org/wiki/Sunrise_equation#Complete_calculation_on_Earth
+fn sun_event(event: SunEvent, location: Location, day: u64) -> Option<SystemTime> {
+  let julian_day = (day as f64 + UNIX_EPOCH_JULIAN_DATE - J2000_JULIAN_DATE + 0.0008).ceil();
+  let mean_solar_time = julian_day - location.longitude / 360.0;
+  let mean_anomaly = (357.5291 + 0.98560028 * mean_solar_time).rem_euclid(360.0);
+  let center = 1.9148 * sin(mean_anomaly) + 0.02 * sin(2.0 * mean_anomaly) + 0.0003 * sin(3.0 * mean_anomaly);
+  let ecliptic_longitude = (mean_anomaly + center + 180.0 + 102.9372).rem_euclid(360.0);
+  let transit = J2000_JULIAN_DATE + mean_solar_time + 0.0053 * sin(mean_anomaly) - 0.0069 * sin(2.0 * ecliptic_longitude);
+
+  let declination_sin = sin(ecliptic_longitude) * sin(EARTH_AXIAL_TILT);
+  let declination_cos = (1.0 - declination_sin * declination_sin).sqrt();
+  let hour_angle_cos = (sin(HORIZON_ALTITUDE) - sin(location.latitude) * declination_sin) / (cos(location.latitude) * declination_cos);
+  if !(-1.0..=1.0).contains(&hour_angle_cos) {
+    return None;
+  }
+  let hour_angle = hour_angle_cos.acos() * 180.0 / PI;
+
+  let julian_date = match event {
+    SunEvent::Sunrise => transit - hour_angle / 360.0,
+    SunEvent::Sunset => transit + hour_angle / 360.0
+  };
+  let seconds = (julian_date - UNIX_EPOCH_JULIAN_DATE) * SECONDS_PER_DAY;
+  (seconds >= 0.0).then(|| UNIX_EPOCH + Duration::from_secs_f64(seconds))
+}
+
+fn sin(degrees: f64) -> f64 {
+  (degrees * PI / 180.0).sin()
+}
+
+fn cos(degrees: f64) -> f64 {
+  (degrees * PI / 180.0).cos()
+}
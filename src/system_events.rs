@@ -0,0 +1,183 @@
+use crate::monitor_group::MonitorSelection;
+
+use std::ffi::c_void;
+use std::mem::size_of;
+use std::ptr;
+use std::slice;
+use windows::core::{w, GUID, PCWSTR};
+use windows::Win32::Foundation::{HANDLE, HWND, LPARAM, LRESULT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::WindowsAndMessaging::{
+  CreateWindowExW, DefWindowProcW, DestroyWindow, PostMessageW, RegisterClassW, RegisterDeviceNotificationW, UnregisterDeviceNotification,
+  DBT_DEVICEARRIVAL, DBT_DEVICEREMOVECOMPLETE, DBT_DEVTYP_DEVICEINTERFACE, DEVICE_NOTIFY_WINDOW_HANDLE, DEV_BROADCAST_DEVICEINTERFACE_W,
+  DEV_BROADCAST_HDR, HMENU, PBT_APMRESUMEAUTOMATIC, WINDOW_EX_STYLE, WM_DEVICECHANGE, WM_DISPLAYCHANGE, WM_POWERBROADCAST, WM_TIMECHANGE,
+  WNDCLASSW, WS_OVERLAPPED
+};
+
+/// Application-defined message posted to the message loop when the display configuration changes, or when a monitor is
+/// plugged or unplugged
+pub const DISPLAY_CHANGE_MSG: u32 = 0x0506;
+/// Application-defined message posted to the message loop when a HID device is connected or disconnected. WPARAM is 1
+/// on arrival and 0 on removal, while LPARAM holds the vendor ID in its high word and the product ID in its low word
+pub const HID_DEVICE_CHANGE_MSG: u32 = 0x050a;
+/// Application-defined message posted to the message loop when the system time or the time zone changes
+pub const CLOCK_CHANGE_MSG: u32 = 0x0510;
+/// Application-defined message posted to the message loop when the system resumed from sleep or hibernation
+pub const RESUME_MSG: u32 = 0x0512;
+
+// Reference: https://learn.microsoft.com/en-us/windows-hardware/drivers/install/guid-devinterface-hid
+const GUID_DEVINTERFACE_HID: GUID = GUID::from_u128(0x4d1e55b2_f16f_11cf_88cb_001111000030);
+// Reference: https://learn.microsoft.com/en-us/windows-hardware/drivers/install/guid-devinterface-monitor
+const GUID_DEVINTERFACE_MONITOR: GUID = GUID::from_u128(0xe6f07b5f_ee97_4a90_b076_33f57bf4eaa7);
+
+const NOTIFICATION_WINDOW_CLASS: PCWSTR = w!("GmmkProBrightnessKnobNotifications");
+
+/// Represent something other than a knob adjustment that the brightness thread has to react to, mostly changes in the
+/// system state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SystemEvent {
+  /// The secure desktop (UAC prompts, lock screen, Ctrl+Alt+Del screen) became the input desktop. DDC/CI writes issued
+  /// while it is active frequently time out
+  EnteredSecureDesktop,
+  LeftSecureDesktop,
+  /// A display was connected, disconnected or changed its mode
+  DisplayChanged,
+  /// The system time was set or the time zone changed, which makes the times computed from the wall clock stale
+  ClockChanged,
+  /// The system resumed from sleep or hibernation, after which the handles of the monitors commonly stop working
+  Resumed,
+  /// The keyboard with the knob was connected
+  KeyboardConnected,
+  /// The keyboard with the knob was disconnected
+  KeyboardDisconnected,
+  /// The trigger of the action binding at the given index fired
+  ActionTriggered(usize),
+  /// Pausing the knob adjustments, or resuming them, was asked for from the tray icon
+  PauseToggled,
+  /// Other monitors were selected from the tray icon
+  MonitorsSelected(MonitorSelection),
+  /// An external program asked for the brightness to be set to the given value
+  BrightnessRequested(i32),
+  /// An external program asked for the knob adjustments to be paused, or resumed
+  PauseRequested(bool),
+  /// The knob was held pressed for long enough to put the monitors in standby
+  StandbyRequested,
+  /// The preset of the given name was picked from the tray icon
+  PresetSelected(String)
+}
+
+/// Create a hidden window that receives the system-wide broadcasts the message loop is interested in. Message-only
+/// windows can't be used here because they don't receive broadcast messages such as WM_DISPLAYCHANGE
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/winmsg/window-features#message-only-windows
+pub(crate) fn create_notification_window() -> windows::core::Result<HWND> {
+  unsafe {
+    let instance = GetModuleHandleW(None)?;
+    let window_class = WNDCLASSW {
+      lpfnWndProc: Some(notification_window_proc),
+      hInstance: instance,
+      lpszClassName: NOTIFICATION_WINDOW_CLASS,
+      ..Default::default()
+    };
+    RegisterClassW(&window_class);
+
+    // The window is never shown, so neither its style nor its size matter
+    let hwnd = CreateWindowExW(
+      WINDOW_EX_STYLE(0),
+      NOTIFICATION_WINDOW_CLASS,
+      NOTIFICATION_WINDOW_CLASS,
+      WS_OVERLAPPED,
+      0, 0, 0, 0,
+      HWND(0),
+      HMENU(0),
+      instance,
+      None
+    );
+    match hwnd.0 {
+      0 => Err(windows::core::Error::from_win32()),
+      _ => Ok(hwnd)
+    }
+  }
+}
+
+pub(crate) fn destroy_notification_window(hwnd: HWND) {
+  unsafe { DestroyWindow(hwnd); }
+}
+
+/// Ask for the arrival and removal of HID devices to be notified to the given window, returning the notification handle
+/// or a null pointer on failure
+pub(crate) fn register_hid_notifications(hwnd: HWND) -> *mut c_void {
+  register_device_notifications(hwnd, GUID_DEVINTERFACE_HID)
+}
+
+/// Ask for monitors being plugged or unplugged to be notified to the given window. WM_DISPLAYCHANGE alone misses the
+/// monitors that are swapped for another one without the display mode changing, such as when re-docking a laptop
+pub(crate) fn register_monitor_notifications(hwnd: HWND) -> *mut c_void {
+  register_device_notifications(hwnd, GUID_DEVINTERFACE_MONITOR)
+}
+
+fn register_device_notifications(hwnd: HWND, class_guid: GUID) -> *mut c_void {
+  let filter = DEV_BROADCAST_DEVICEINTERFACE_W {
+    dbcc_size: size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>() as u32,
+    dbcc_devicetype: DBT_DEVTYP_DEVICEINTERFACE.0,
+    dbcc_classguid: class_guid,
+    ..Default::default()
+  };
+  unsafe { RegisterDeviceNotificationW(HANDLE(hwnd.0), &filter as *const _ as *const c_void, DEVICE_NOTIFY_WINDOW_HANDLE) }
+}
+
+pub(crate) fn unregister_device_notifications(handle: *mut c_void) {
+  if !handle.is_null() {
+    unsafe { UnregisterDeviceNotification(handle); }
+  }
+}
+
+/// Handle the messages sent to the notification window by forwarding the relevant ones to the thread's message queue,
+/// given that sent messages bypass GetMessageW entirely
+unsafe extern "system" fn notification_window_proc(hwnd: HWND, msg: u32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+  if msg == WM_DISPLAYCHANGE {
+    PostMessageW(HWND(0), DISPLAY_CHANGE_MSG, WPARAM(0), LPARAM(0));
+  }
+  if msg == WM_TIMECHANGE {
+    PostMessageW(HWND(0), CLOCK_CHANGE_MSG, WPARAM(0), LPARAM(0));
+  }
+  // Sent on every resume, whether the user is there or not, unlike PBT_APMRESUMESUSPEND
+  if msg == WM_POWERBROADCAST && w_param.0 as u32 == PBT_APMRESUMEAUTOMATIC {
+    PostMessageW(HWND(0), RESUME_MSG, WPARAM(0), LPARAM(0));
+  }
+
+  // The device path is only valid during the call, so the IDs are extracted right away
+  let arrived = w_param.0 as u32 == DBT_DEVICEARRIVAL;
+  if msg == WM_DEVICECHANGE && (arrived || w_param.0 as u32 == DBT_DEVICEREMOVECOMPLETE) && l_param.0 != 0 {
+    let header = &*(l_param.0 as *const DEV_BROADCAST_HDR);
+    let broadcast = l_param.0 as *const DEV_BROADCAST_DEVICEINTERFACE_W;
+    if header.dbch_devicetype == DBT_DEVTYP_DEVICEINTERFACE && (*broadcast).dbcc_classguid == GUID_DEVINTERFACE_MONITOR {
+      PostMessageW(HWND(0), DISPLAY_CHANGE_MSG, WPARAM(0), LPARAM(0));
+    } else if header.dbch_devicetype == DBT_DEVTYP_DEVICEINTERFACE {
+      let device_path = device_interface_path(broadcast);
+      if let Some((vendor_id, product_id)) = parse_vid_pid(&device_path) {
+        let ids = ((vendor_id as u32) << 16) | product_id as u32;
+        PostMessageW(HWND(0), HID_DEVICE_CHANGE_MSG, WPARAM(arrived as usize), LPARAM(ids as isize));
+      }
+    }
+  }
+  DefWindowProcW(hwnd, msg, w_param, l_param)
+}
+
+/// Read the NUL-terminated device path that trails the broadcast structure
+unsafe fn device_interface_path(broadcast: *const DEV_BROADCAST_DEVICEINTERFACE_W) -> String {
+  let name = ptr::addr_of!((*broadcast).dbcc_name) as *const u16;
+  let max_length = ((*broadcast).dbcc_size as usize).saturating_sub(size_of::<DEV_BROADCAST_DEVICEINTERFACE_W>()) / 2 + 1;
+  let length = (0..max_length).position(|i| *name.add(i) == 0).unwrap_or(max_length);
+  String::from_utf16_lossy(slice::from_raw_parts(name, length))
+}
+
+/// Extract the vendor and product IDs from a device path such as "\\?\HID#VID_320F&PID_5044&MI_02#..."
+pub fn parse_vid_pid(device_path: &str) -> Option<(u16, u16)> {
+  let device_path = device_path.to_uppercase();
+  let parse_id = |prefix: &str| {
+    let start = device_path.find(prefix)? + prefix.len();
+    u16::from_str_radix(device_path.get(start..start + 4)?, 16).ok()
+  };
+  Some((parse_id("VID_")?, parse_id("PID_")?))
+}
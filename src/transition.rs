@@ -0,0 +1,64 @@
+use keyframe::ease;
+use keyframe::functions::{EaseInCubic, EaseInOutCubic, EaseOutCubic, Linear};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Represent the curve followed by the brightness during a transition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Easing {
+  Linear,
+  EaseIn,
+  EaseOut,
+  EaseInOut
+}
+
+impl Easing {
+  /// Interpolate between two brightness values, `t` being the progress of the transition in the 0-1 range
+  pub fn ease(&self, from: f64, to: f64, t: f64) -> f64 {
+    match self {
+      Easing::Linear => ease(Linear, from, to, t),
+      Easing::EaseIn => ease(EaseInCubic, from, to, t),
+      Easing::EaseOut => ease(EaseOutCubic, from, to, t),
+      Easing::EaseInOut => ease(EaseInOutCubic, from, to, t)
+    }
+  }
+
+  /// How many times faster than a linear transition of the same duration the brightness changes at the steepest point
+  /// of the curve
+  pub fn peak_speed(&self) -> f64 {
+    match self {
+      Easing::Linear => 1.0,
+      Easing::EaseIn | Easing::EaseOut | Easing::EaseInOut => 3.0
+    }
+  }
+}
+
+/// Represent how the brightness gets from one value to another
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+  pub duration: Duration,
+  pub easing: Easing
+}
+
+impl Transition {
+  /// Jump to the target value in a single write
+  pub const INSTANT: Transition = Transition::new(Duration::ZERO, Easing::Linear);
+
+  pub const fn new(duration: Duration, easing: Easing) -> Self {
+    Self { duration, easing }
+  }
+
+  /// Stretch the transition as needed for the brightness not to change faster than `max_rate` percent per second when
+  /// going over the given distance, for the sake of photosensitive users. The rate is capped at the steepest point of
+  /// the easing curve, which is where the brightness changes the fastest
+  pub fn limited_to_rate(self, distance: f64, max_rate: Option<f64>) -> Self {
+    match max_rate {
+      Some(rate) if rate > 0.0 => {
+        let min_duration = Duration::from_secs_f64(self.easing.peak_speed() * distance.abs() / rate);
+        Self { duration: self.duration.max(min_duration), ..self }
+      },
+      _ => self
+    }
+  }
+}
@@ -0,0 +1,224 @@
+use crate::monitor_group::{FollowedMonitor, MonitorSelection};
+use crate::shutdown::{StopSignal, forward_stop_to_message_loop};
+use crate::state::StateSnapshot;
+use crate::strings::{Text, text};
+use crate::system_events::SystemEvent;
+use crate::watch::WatchReceiver;
+
+use crossbeam_channel::Sender;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU32, Ordering};
+use windows::core::{w, HSTRING, PCWSTR};
+use windows::Win32::Foundation::{HWND, LPARAM, LRESULT, POINT, WPARAM};
+use windows::Win32::System::LibraryLoader::GetModuleHandleW;
+use windows::Win32::UI::Shell::{Shell_NotifyIconW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE, NIM_MODIFY, NOTIFYICONDATAW};
+use windows::Win32::UI::WindowsAndMessaging::{
+  AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DestroyWindow, DispatchMessageW, GetCursorPos, GetMessageW, LoadIconW,
+  PostMessageW, RegisterClassW, RegisterWindowMessageW, SetForegroundWindow, TrackPopupMenu, TranslateMessage, HMENU, IDI_APPLICATION, MENU_ITEM_FLAGS,
+  MF_CHECKED, MF_GRAYED, MF_SEPARATOR, MF_STRING, MSG, TPM_BOTTOMALIGN, TPM_RETURNCMD, TPM_RIGHTBUTTON, WINDOW_EX_STYLE, WM_CONTEXTMENU, WM_NULL,
+  WM_RBUTTONUP, WNDCLASSW, WS_OVERLAPPED
+};
+
+/// Application-defined message posted to the tray window when the state shown by the icon changed
+const TRAY_UPDATE_MSG: u32 = 0x0508;
+/// Application-defined message the notification area reports the mouse events of the icon with, in LPARAM
+const TRAY_CALLBACK_MSG: u32 = 0x050c;
+
+const TRAY_WINDOW_CLASS: PCWSTR = w!("GmmkProBrightnessKnobTray");
+const TRAY_ICON_ID: u32 = 1;
+const APP_NAME: &str = "GMMK Pro brightness knob";
+
+const PAUSE_ITEM_ID: usize = 1;
+const PRIMARY_MONITOR_ITEM_ID: usize = 2;
+const ALL_MONITORS_ITEM_ID: usize = 3;
+const EXIT_ITEM_ID: usize = 4;
+const CURSOR_MONITOR_ITEM_ID: usize = 5;
+const FOCUSED_WINDOW_MONITOR_ITEM_ID: usize = 6;
+/// ID of the item of the first preset, the ones of the others following it
+const FIRST_PRESET_ITEM_ID: usize = 100;
+
+// Broadcast by Explorer when the taskbar is created again after a crash, at which point the icon must be added back
+static TASKBAR_CREATED_MSG: AtomicU32 = AtomicU32::new(0);
+
+/// Put an icon in the notification area showing the state sent by the brightness thread, which is unset until it
+/// starts, and whose menu controls it through system events. Runs until the stop signal is received, or until Exit is
+/// picked from the menu, in which case returning requests the shutdown. The menu lists the given presets as well
+pub fn run_tray_icon(stop_rx: StopSignal, system_tx: Sender<SystemEvent>, mut state_rx: WatchReceiver<Option<StateSnapshot>>, preset_names: Vec<String>) -> windows::core::Result<()> {
+  unsafe {
+    let hwnd = create_tray_window()?;
+    TASKBAR_CREATED_MSG.store(RegisterWindowMessageW(w!("TaskbarCreated")), Ordering::Relaxed);
+
+    let mut icon = NOTIFYICONDATAW {
+      cbSize: size_of::<NOTIFYICONDATAW>() as u32,
+      hWnd: hwnd,
+      uID: TRAY_ICON_ID,
+      uFlags: NIF_ICON | NIF_TIP | NIF_MESSAGE,
+      uCallbackMessage: TRAY_CALLBACK_MSG,
+      hIcon: LoadIconW(None, IDI_APPLICATION)?,
+      ..Default::default()
+    };
+    let mut state = state_rx.latest();
+    set_tooltip(&mut icon, state.as_ref());
+    Shell_NotifyIconW(NIM_ADD, &icon);
+
+    // Updates are sent as often as every frame of a transition, so that the tooltip follows the monitor closely
+    state_rx.on_change(move || {
+      PostMessageW(hwnd, TRAY_UPDATE_MSG, WPARAM(0), LPARAM(0));
+    });
+
+    // Make GetMessageW return as soon as the stop signal is received
+    forward_stop_to_message_loop(stop_rx);
+
+    let mut msg: MSG = Default::default();
+    while GetMessageW(&mut msg, HWND(0), 0, 0).as_bool() {
+      match msg.message {
+        TRAY_UPDATE_MSG => {
+          // Several updates might have been coalesced into this one already
+          let Some(latest_state) = state_rx.changed() else { continue };
+          state = latest_state;
+          set_tooltip(&mut icon, state.as_ref());
+          Shell_NotifyIconW(NIM_MODIFY, &icon);
+        },
+        TRAY_CALLBACK_MSG if matches!(msg.lParam.0 as u32, WM_RBUTTONUP | WM_CONTEXTMENU) => {
+          let system_event = match show_menu(hwnd, state.as_ref(), &preset_names) {
+            PAUSE_ITEM_ID => Some(SystemEvent::PauseToggled),
+            PRIMARY_MONITOR_ITEM_ID => Some(SystemEvent::MonitorsSelected(MonitorSelection::Primary)),
+            ALL_MONITORS_ITEM_ID => Some(SystemEvent::MonitorsSelected(MonitorSelection::All)),
+            CURSOR_MONITOR_ITEM_ID => Some(SystemEvent::MonitorsSelected(MonitorSelection::Followed(FollowedMonitor::Cursor))),
+            FOCUSED_WINDOW_MONITOR_ITEM_ID => Some(SystemEvent::MonitorsSelected(MonitorSelection::Followed(FollowedMonitor::FocusedWindow))),
+            EXIT_ITEM_ID => break,
+            item_id => item_id
+              .checked_sub(FIRST_PRESET_ITEM_ID)
+              .and_then(|index| preset_names.get(index))
+              .map(|preset_name| SystemEvent::PresetSelected(preset_name.clone()))
+          };
+          if let Some(system_event) = system_event {
+            if system_tx.send(system_event).is_err() {
+              break;
+            }
+          }
+        },
+        msg_id if msg_id == TASKBAR_CREATED_MSG.load(Ordering::Relaxed) => {
+          Shell_NotifyIconW(NIM_ADD, &icon);
+        },
+        _ => {
+          TranslateMessage(&msg);
+          DispatchMessageW(&msg);
+        }
+      };
+    }
+
+    Shell_NotifyIconW(NIM_DELETE, &icon);
+    DestroyWindow(hwnd);
+  }
+  Ok(())
+}
+
+unsafe fn create_tray_window() -> windows::core::Result<HWND> {
+  let instance = GetModuleHandleW(None)?;
+  let window_class = WNDCLASSW {
+    lpfnWndProc: Some(tray_window_proc),
+    hInstance: instance,
+    lpszClassName: TRAY_WINDOW_CLASS,
+    ..Default::default()
+  };
+  RegisterClassW(&window_class);
+
+  // The window is never shown, it only exists to receive the messages of the icon and the TaskbarCreated broadcast
+  let hwnd = CreateWindowExW(
+    WINDOW_EX_STYLE(0),
+    TRAY_WINDOW_CLASS,
+    TRAY_WINDOW_CLASS,
+    WS_OVERLAPPED,
+    0, 0, 0, 0,
+    HWND(0),
+    HMENU(0),
+    instance,
+    None
+  );
+  match hwnd.0 {
+    0 => Err(windows::core::Error::from_win32()),
+    _ => Ok(hwnd)
+  }
+}
+
+/// Forward the messages of the icon to the thread's message queue, given that sent messages bypass GetMessageW entirely
+unsafe extern "system" fn tray_window_proc(hwnd: HWND, msg: u32, w_param: WPARAM, l_param: LPARAM) -> LRESULT {
+  if msg == TRAY_CALLBACK_MSG || msg == TASKBAR_CREATED_MSG.load(Ordering::Relaxed) {
+    PostMessageW(hwnd, msg, w_param, l_param);
+    return LRESULT(0);
+  }
+  DefWindowProcW(hwnd, msg, w_param, l_param)
+}
+
+fn set_tooltip(icon: &mut NOTIFYICONDATAW, state: Option<&StateSnapshot>) {
+  let tooltip = match state {
+    Some(StateSnapshot { brightness: Some(brightness), mode, mode_value, paused, .. }) => {
+      let paused = if *paused { format!(" ({})", text(Text::Paused)) } else { String::new() };
+      let mode = match mode_value {
+        Some(value) => format!("\n{} {}%", text(mode.label()), value),
+        None => String::new()
+      };
+      format!("{}\n{} {}%{}{}", APP_NAME, text(Text::Brightness), brightness, paused, mode)
+    },
+    _ => APP_NAME.to_string()
+  };
+
+  // The tooltip is truncated to fit the fixed-size buffer, which must stay NUL-terminated
+  let chars: Vec<u16> = tooltip.encode_utf16().take(icon.szTip.len() - 1).collect();
+  icon.szTip = [0; 128];
+  icon.szTip[..chars.len()].copy_from_slice(&chars);
+}
+
+/// Show the menu of the icon at the cursor position, returning the ID of the item that was picked or 0 if none was
+unsafe fn show_menu(hwnd: HWND, state: Option<&StateSnapshot>, preset_names: &[String]) -> usize {
+  let Ok(menu) = CreatePopupMenu() else { return 0 };
+  let paused = state.is_some_and(|state| state.paused);
+  let selection = state.map(|state| &state.selection);
+  let checked = |is_checked: bool| if is_checked { MF_CHECKED } else { MENU_ITEM_FLAGS(0) };
+
+  AppendMenuW(menu, MF_STRING, PAUSE_ITEM_ID, &HSTRING::from(text(if paused { Text::ResumeKnob } else { Text::PauseKnob })));
+  AppendMenuW(menu, MF_SEPARATOR, 0, None);
+  AppendMenuW(
+    menu,
+    MF_STRING | checked(selection == Some(&MonitorSelection::Primary)),
+    PRIMARY_MONITOR_ITEM_ID,
+    &HSTRING::from(text(Text::PrimaryMonitor))
+  );
+  AppendMenuW(menu, MF_STRING | checked(selection == Some(&MonitorSelection::All)), ALL_MONITORS_ITEM_ID, &HSTRING::from(text(Text::AllMonitors)));
+  AppendMenuW(
+    menu,
+    MF_STRING | checked(selection == Some(&MonitorSelection::Followed(FollowedMonitor::Cursor))),
+    CURSOR_MONITOR_ITEM_ID,
+    &HSTRING::from(text(Text::CursorMonitor))
+  );
+  AppendMenuW(
+    menu,
+    MF_STRING | checked(selection == Some(&MonitorSelection::Followed(FollowedMonitor::FocusedWindow))),
+    FOCUSED_WINDOW_MONITOR_ITEM_ID,
+    &HSTRING::from(text(Text::FocusedWindowMonitor))
+  );
+  for monitor in state.map(|state| state.monitors.as_slice()).unwrap_or_default() {
+    AppendMenuW(menu, MF_STRING | MF_GRAYED, 0, &HSTRING::from(format!("    {}", monitor.name)));
+  }
+  if !preset_names.is_empty() {
+    AppendMenuW(menu, MF_SEPARATOR, 0, None);
+  }
+  for (index, preset_name) in preset_names.iter().enumerate() {
+    AppendMenuW(menu, MF_STRING, FIRST_PRESET_ITEM_ID + index, &HSTRING::from(preset_name.as_str()));
+  }
+  AppendMenuW(menu, MF_SEPARATOR, 0, None);
+  AppendMenuW(menu, MF_STRING, EXIT_ITEM_ID, &HSTRING::from(text(Text::Exit)));
+
+  // The window must be in the foreground for the menu to go away when clicking elsewhere
+  //
+  // Reference: https://learn.microsoft.com/en-us/windows/win32/api/winuser/nf-winuser-trackpopupmenu#remarks
+  let mut cursor = POINT::default();
+  GetCursorPos(&mut cursor);
+  SetForegroundWindow(hwnd);
+  let item_id = TrackPopupMenu(menu, TPM_RETURNCMD | TPM_RIGHTBUTTON | TPM_BOTTOMALIGN, cursor.x, cursor.y, 0, hwnd, None).0 as usize;
+  PostMessageW(hwnd, WM_NULL, WPARAM(0), LPARAM(0));
+
+  DestroyMenu(menu);
+  item_id
+}
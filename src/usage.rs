@@ -0,0 +1,258 @@
+use crate::paths::data_dir;
+use crate::strings::{Text, text};
+
+use clap::ValueEnum;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::error;
+
+const USAGE_LOG_FILE_NAME: &str = "usage.log";
+const RETENTION: Duration = Duration::from_secs(8 * 24 * 60 * 60);
+const BRIGHTNESS_BANDS: [(u16, u16); 5] = [(0, 19), (20, 39), (40, 59), (60, 79), (80, 100)];
+
+/// Represent the time span covered by a usage report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportPeriod {
+  Day,
+  Week
+}
+
+impl ReportPeriod {
+  fn duration(&self) -> Duration {
+    match self {
+      ReportPeriod::Day => Duration::from_secs(24 * 60 * 60),
+      ReportPeriod::Week => Duration::from_secs(7 * 24 * 60 * 60)
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      ReportPeriod::Day => "day",
+      ReportPeriod::Week => "week"
+    }
+  }
+
+  fn title(&self) -> &'static str {
+    match self {
+      ReportPeriod::Day => text(Text::UsageOverLastDay),
+      ReportPeriod::Week => text(Text::UsageOverLastWeek)
+    }
+  }
+}
+
+/// Represent what happened to a monitor at a given point in time. Usage records are appended to a plain text file, one
+/// per line, with their fields separated by tabs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RecordKind {
+  /// The monitor was opened with the given brightness
+  Start,
+  /// The brightness was adjusted to the given value
+  Set,
+  /// The brightness was changed to the given value during a ramp of the schedule, which was given up on
+  Override,
+  /// The monitor is not controlled anymore
+  Stop
+}
+
+struct Record {
+  timestamp: u64,
+  kind: RecordKind,
+  brightness: u16,
+  monitor: String
+}
+
+/// Record that a monitor started being controlled, with its current brightness
+pub fn record_start(monitor: &str, brightness: u16) {
+  append(RecordKind::Start, monitor, brightness);
+}
+
+/// Record that the brightness of a monitor was adjusted
+pub fn record_set(monitor: &str, brightness: u16) {
+  append(RecordKind::Set, monitor, brightness);
+}
+
+/// Record that the brightness of a monitor was changed during a ramp of the schedule, overriding it
+pub fn record_override(monitor: &str, brightness: u16) {
+  append(RecordKind::Override, monitor, brightness);
+}
+
+/// Record that a monitor stopped being controlled, either because it was disconnected or because the program is exiting
+pub fn record_stop(monitor: &str) {
+  append(RecordKind::Stop, monitor, 0);
+}
+
+/// Drop the records that are too old to show up in any report
+pub fn prune() -> io::Result<()> {
+  let cutoff = unix_now().saturating_sub(RETENTION.as_secs());
+  let records = read_records()?;
+  let contents = records.iter()
+    .filter(|record| record.timestamp >= cutoff)
+    .map(format_record)
+    .collect::<String>();
+  fs::write(usage_log_path()?, contents)
+}
+
+/// Usage of a single monitor over the period of a report
+#[derive(Debug, Default)]
+struct Summary {
+  /// Time spent in each brightness band, in seconds
+  band_seconds: [u64; BRIGHTNESS_BANDS.len()],
+  adjustments: u64,
+  schedule_overrides: u64
+}
+
+/// Summarize the usage records of the given period, as either a human-readable table or JSON
+pub fn report(period: ReportPeriod, as_json: bool) -> io::Result<String> {
+  let now = unix_now();
+  let cutoff = now.saturating_sub(period.duration().as_secs());
+
+  let mut summaries: BTreeMap<String, Summary> = BTreeMap::new();
+  let mut applied_since: BTreeMap<String, (u64, u16)> = BTreeMap::new();
+
+  for record in read_records()? {
+    let summary = summaries.entry(record.monitor.clone()).or_default();
+    let in_period = record.timestamp >= cutoff;
+    match record.kind {
+      // A monitor still open when the program starts again wasn't stopped, such as after a crash or a power loss, and
+      // the time the program wasn't running for is left out rather than counted in its last band
+      RecordKind::Start => { applied_since.insert(record.monitor.clone(), (record.timestamp, record.brightness)); },
+      RecordKind::Set => {
+        if let Some((since, brightness)) = applied_since.insert(record.monitor.clone(), (record.timestamp, record.brightness)) {
+          summary.band_seconds[band_index(brightness)] += overlap(since, record.timestamp, cutoff, now);
+        }
+        summary.adjustments += in_period as u64;
+      },
+      RecordKind::Override => summary.schedule_overrides += in_period as u64,
+      RecordKind::Stop => {
+        if let Some((since, brightness)) = applied_since.remove(&record.monitor) {
+          summary.band_seconds[band_index(brightness)] += overlap(since, record.timestamp, cutoff, now);
+        }
+      }
+    };
+  }
+
+  // Monitors that are still being controlled count up to now
+  for (monitor, (since, brightness)) in applied_since {
+    summaries.entry(monitor).or_default().band_seconds[band_index(brightness)] += overlap(since, now, cutoff, now);
+  }
+  summaries.retain(|_, summary| summary.adjustments > 0 || summary.schedule_overrides > 0 || summary.band_seconds.iter().any(|seconds| *seconds > 0));
+
+  Ok(match as_json {
+    true => format_json_report(period, &summaries),
+    false => format_table_report(period, &summaries)
+  })
+}
+
+fn format_table_report(period: ReportPeriod, summaries: &BTreeMap<String, Summary>) -> String {
+  let mut output = format!("{}\n", period.title());
+  if summaries.is_empty() {
+    let _ = writeln!(output, "\n{}", text(Text::NoUsageRecorded));
+  }
+
+  for (monitor, summary) in summaries {
+    let _ = writeln!(output, "\n{}\n  {}: {}", monitor, text(Text::Adjustments), summary.adjustments);
+    let _ = writeln!(output, "  {}: {}", text(Text::ScheduleOverrides), summary.schedule_overrides);
+    for ((from, to), seconds) in BRIGHTNESS_BANDS.iter().zip(&summary.band_seconds) {
+      let _ = writeln!(output, "  {:>3}-{:<3}%  {:>3}h {:02}m", from, to, seconds / 3600, (seconds % 3600) / 60);
+    }
+  }
+  output
+}
+
+fn format_json_report(period: ReportPeriod, summaries: &BTreeMap<String, Summary>) -> String {
+  let monitors = summaries.iter()
+    .map(|(monitor, summary)| {
+      let bands = BRIGHTNESS_BANDS.iter().zip(&summary.band_seconds)
+        .map(|((from, to), seconds)| format!("{{\"from\":{},\"to\":{},\"seconds\":{}}}", from, to, seconds))
+        .collect::<Vec<_>>()
+        .join(",");
+      format!(
+        "{{\"monitor\":\"{}\",\"adjustments\":{},\"schedule_overrides\":{},\"bands\":[{}]}}",
+        json_escape(monitor), summary.adjustments, summary.schedule_overrides, bands
+      )
+    })
+    .collect::<Vec<_>>()
+    .join(",");
+  format!("{{\"period\":\"{}\",\"monitors\":[{}]}}", period.name(), monitors)
+}
+
+pub fn json_escape(value: &str) -> String {
+  value.chars().fold(String::new(), |mut escaped, c| {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      c if c.is_control() => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+      c => escaped.push(c)
+    };
+    escaped
+  })
+}
+
+fn band_index(brightness: u16) -> usize {
+  BRIGHTNESS_BANDS.iter().position(|(_, to)| brightness <= *to).unwrap_or(BRIGHTNESS_BANDS.len() - 1)
+}
+
+/// Number of seconds the [from, to) interval shares with the [cutoff, now) one
+fn overlap(from: u64, to: u64, cutoff: u64, now: u64) -> u64 {
+  to.min(now).saturating_sub(from.max(cutoff))
+}
+
+fn append(kind: RecordKind, monitor: &str, brightness: u16) {
+  let record = Record { timestamp: unix_now(), kind, brightness, monitor: monitor.replace(['\t', '\n'], " ") };
+  let result = usage_log_path().and_then(|path| {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format_record(&record).as_bytes())
+  });
+
+  if let Err(e) = result {
+    error!("unable to record usage - {}", e);
+  }
+}
+
+fn read_records() -> io::Result<Vec<Record>> {
+  let contents = match fs::read_to_string(usage_log_path()?) {
+    Ok(contents) => contents,
+    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+    Err(e) => return Err(e)
+  };
+
+  // Malformed lines, e.g. from a write interrupted halfway, are skipped
+  Ok(contents.lines().filter_map(parse_record).collect())
+}
+
+fn parse_record(line: &str) -> Option<Record> {
+  let mut fields = line.splitn(4, '\t');
+  let timestamp = fields.next()?.parse().ok()?;
+  let kind = match fields.next()? {
+    "start" => RecordKind::Start,
+    "set" => RecordKind::Set,
+    "override" => RecordKind::Override,
+    "stop" => RecordKind::Stop,
+    _ => return None
+  };
+  let brightness = fields.next()?.parse().ok()?;
+  let monitor = fields.next()?.to_string();
+  Some(Record { timestamp, kind, brightness, monitor })
+}
+
+fn format_record(record: &Record) -> String {
+  let kind = match record.kind {
+    RecordKind::Start => "start",
+    RecordKind::Set => "set",
+    RecordKind::Override => "override",
+    RecordKind::Stop => "stop"
+  };
+  format!("{}\t{}\t{}\t{}\n", record.timestamp, kind, record.brightness, record.monitor)
+}
+
+fn usage_log_path() -> io::Result<PathBuf> {
+  Ok(data_dir()?.join(USAGE_LOG_FILE_NAME))
+}
+
+fn unix_now() -> u64 {
+  SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0)
+}
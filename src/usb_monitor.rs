@@ -0,0 +1,142 @@
+use crate::monitor::{BRIGHTNESS_VCP_CODE, BrightnessBackend};
+
+use ddc::{FeatureCode, VcpValue};
+use hidapi::{HidApi, HidDevice, HidError};
+use std::io;
+
+/// Describe how a vendor's USB HID interface exposes the brightness of a monitor. The brightness is read and written
+/// through a feature report, holding the raw value as a little-endian integer right after the report ID
+#[derive(Debug)]
+pub struct HidBrightnessProtocol {
+  pub name: &'static str,
+  pub vendor_id: u16,
+  pub product_ids: &'static [u16],
+  /// Interface carrying the brightness feature report, for devices that expose more than one
+  pub interface_number: Option<i32>,
+  pub report_id: u8,
+  /// Length of the feature report, including the report ID
+  pub report_length: usize,
+  /// Width in bytes of the raw brightness value
+  pub value_width: usize,
+  pub min_value: u32,
+  pub max_value: u32
+}
+
+/// Monitors known to expose their brightness over USB HID, matched by VID/PID
+pub const HID_BRIGHTNESS_PROTOCOLS: &[HidBrightnessProtocol] = &[
+  // Reference: https://github.com/juliuszint/asdbctl
+  HidBrightnessProtocol {
+    name: "Apple Studio Display",
+    vendor_id: 0x05ac,
+    product_ids: &[0x1114],
+    interface_number: Some(7),
+    report_id: 1,
+    report_length: 7,
+    value_width: 4,
+    min_value: 400,
+    max_value: 60000
+  },
+  // Reference: https://github.com/csujedihy/LG-Ultrafine-Brightness
+  // The panel takes the brightness through a SET_REPORT control transfer on its second interface, with no report ID
+  HidBrightnessProtocol {
+    name: "LG UltraFine",
+    vendor_id: 0x043e,
+    product_ids: &[0x9a40, 0x9a63, 0x9a70],
+    interface_number: Some(1),
+    report_id: 0,
+    report_length: 7,
+    value_width: 2,
+    min_value: 400,
+    max_value: 54000
+  }
+];
+
+/// Represent a monitor whose brightness is controlled over USB HID rather than DDC/CI
+pub struct UsbMonitor {
+  device: HidDevice,
+  protocol: &'static HidBrightnessProtocol
+}
+
+impl UsbMonitor {
+  /// Open the first connected monitor that matches one of the known protocols
+  pub fn open_first() -> io::Result<Self> {
+    let hid_api = HidApi::new().map_err(hid_to_io_error)?;
+    for device_info in hid_api.device_list() {
+      let Some(protocol) = find_protocol(device_info.vendor_id(), device_info.product_id(), device_info.interface_number()) else { continue };
+      let device = device_info.open_device(&hid_api).map_err(hid_to_io_error)?;
+      return Ok(Self { device, protocol });
+    }
+
+    Err(io::Error::new(io::ErrorKind::NotFound, "no known USB HID monitor is connected"))
+  }
+
+  pub fn name(&self) -> String {
+    self.protocol.name.to_string()
+  }
+
+  /// Get the brightness of the monitor, scaled to the 0-100 range
+  pub fn get_brightness(&mut self) -> io::Result<u16> {
+    let protocol = self.protocol;
+    let mut report = vec![0u8; protocol.report_length];
+    report[0] = protocol.report_id;
+    self.device.get_feature_report(&mut report).map_err(hid_to_io_error)?;
+
+    let raw_value = report[1..1 + protocol.value_width].iter().rev().fold(0u32, |value, byte| (value << 8) | *byte as u32);
+    let range = (protocol.max_value - protocol.min_value) as f64;
+    let value = (raw_value.clamp(protocol.min_value, protocol.max_value) - protocol.min_value) as f64 * 100.0 / range;
+    Ok(value.round() as u16)
+  }
+
+  /// Set the brightness of the monitor, given a value in the 0-100 range
+  pub fn set_brightness(&mut self, value: u16) -> io::Result<()> {
+    let protocol = self.protocol;
+    let range = (protocol.max_value - protocol.min_value) as f64;
+    let raw_value = protocol.min_value + (value.min(100) as f64 * range / 100.0).round() as u32;
+
+    let mut report = vec![0u8; protocol.report_length];
+    report[0] = protocol.report_id;
+    report[1..1 + protocol.value_width].copy_from_slice(&raw_value.to_le_bytes()[..protocol.value_width]);
+    self.device.send_feature_report(&report).map_err(hid_to_io_error)
+  }
+}
+
+impl BrightnessBackend for UsbMonitor {
+  fn name(&self) -> String {
+    UsbMonitor::name(self)
+  }
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue> {
+    match code {
+      BRIGHTNESS_VCP_CODE => self.get_brightness().map(|value| VcpValue { ml: 100, ..VcpValue::from_value(value) }),
+      _ => Err(unsupported_usb_feature(code))
+    }
+  }
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    match code {
+      BRIGHTNESS_VCP_CODE => self.set_brightness(value),
+      _ => Err(unsupported_usb_feature(code))
+    }
+  }
+}
+
+fn find_protocol(vendor_id: u16, product_id: u16, interface_number: i32) -> Option<&'static HidBrightnessProtocol> {
+  HID_BRIGHTNESS_PROTOCOLS.iter().find(|protocol| {
+    protocol.vendor_id == vendor_id
+      && protocol.product_ids.contains(&product_id)
+      && protocol.interface_number.is_none_or(|number| number == interface_number)
+  })
+}
+
+/// Convert HID errors to the same error type used by DDC/CI, keeping the OS error codes so that disconnections can still
+/// be told apart
+fn hid_to_io_error(err: HidError) -> io::Error {
+  match err {
+    HidError::IoError { error } => error,
+    err => io::Error::other(err.to_string())
+  }
+}
+
+fn unsupported_usb_feature(code: FeatureCode) -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, format!("USB monitors have no VCP feature 0x{:02x}", code))
+}
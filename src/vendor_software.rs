@@ -0,0 +1,76 @@
+use std::io;
+use std::mem::size_of;
+use std::path::Path;
+use std::process::Command;
+use windows::Win32::Foundation::CloseHandle;
+use windows::Win32::System::Diagnostics::ToolHelp::{CreateToolhelp32Snapshot, Process32FirstW, Process32NextW, PROCESSENTRY32W, TH32CS_SNAPPROCESS};
+
+/// Describe a monitor control application that talks DDC/CI on its own, and would fight over the brightness (VCP 0x10)
+/// with this program otherwise
+#[derive(Debug)]
+pub struct VendorSoftware {
+  pub name: &'static str,
+  /// Lowercase executable names of its processes
+  pub process_names: &'static [&'static str],
+  /// Command line tool able to set the brightness, along with the argument preceding the value
+  pub cli: Option<(&'static str, &'static str)>
+}
+
+/// Known monitor control applications
+pub const VENDOR_SOFTWARE: &[VendorSoftware] = &[
+  // The original version ships a command line interface, which its successor dropped
+  VendorSoftware {
+    name: "Dell Display Manager",
+    process_names: &["ddm.exe"],
+    cli: Some((r"C:\Program Files (x86)\Dell\Dell Display Manager\ddm.exe", "/SetBrightnessLevel"))
+  },
+  VendorSoftware {
+    name: "Dell Display Manager 2",
+    process_names: &["delldisplaymanager.exe"],
+    cli: None
+  }
+];
+
+impl VendorSoftware {
+  /// Check whether the command line tool is installed, in which case the brightness should be set through it
+  pub fn has_cli(&self) -> bool {
+    self.cli.is_some_and(|(path, _)| Path::new(path).exists())
+  }
+
+  /// Set the brightness of the monitor through the command line tool, waiting for it to finish
+  pub fn set_brightness(&self, value: u16) -> io::Result<()> {
+    let Some((path, argument)) = self.cli else {
+      return Err(io::Error::new(io::ErrorKind::Unsupported, format!("{} has no command line interface", self.name)));
+    };
+
+    let status = Command::new(path).args([argument, &value.to_string()]).status()?;
+    match status.success() {
+      true => Ok(()),
+      false => Err(io::Error::other(format!("{} exited with {}", path, status)))
+    }
+  }
+}
+
+/// Find the first known monitor control application currently running
+pub fn detect_running() -> Option<&'static VendorSoftware> {
+  let process_names = running_process_names();
+  VENDOR_SOFTWARE.iter().find(|software| software.process_names.iter().any(|name| process_names.iter().any(|running| running == name)))
+}
+
+/// Get the lowercase executable names of every running process
+fn running_process_names() -> Vec<String> {
+  let mut process_names = Vec::new();
+  unsafe {
+    let Ok(snapshot) = CreateToolhelp32Snapshot(TH32CS_SNAPPROCESS, 0) else { return process_names };
+    let mut entry = PROCESSENTRY32W { dwSize: size_of::<PROCESSENTRY32W>() as u32, ..Default::default() };
+
+    let mut has_entry = Process32FirstW(snapshot, &mut entry).as_bool();
+    while has_entry {
+      let name_length = entry.szExeFile.iter().position(|c| *c == 0).unwrap_or(entry.szExeFile.len());
+      process_names.push(String::from_utf16_lossy(&entry.szExeFile[..name_length]).to_lowercase());
+      has_entry = Process32NextW(snapshot, &mut entry).as_bool();
+    }
+    CloseHandle(snapshot);
+  }
+  process_names
+}
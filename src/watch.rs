@@ -0,0 +1,78 @@
+use std::sync::{Arc, Mutex};
+
+/// Create a channel holding a single value, which every send replaces. The receiver only ever sees the latest value,
+/// so that a slow one neither holds the sender back nor falls behind, which suits state shown to the user and updated
+/// as often as every frame of a transition
+pub fn channel<T>(initial: T) -> (WatchSender<T>, WatchReceiver<T>) {
+  let shared = Arc::new(Mutex::new(Shared { value: initial, version: 0, wakers: Vec::new() }));
+  (WatchSender { shared: shared.clone() }, WatchReceiver { shared, seen_version: 0 })
+}
+
+struct Shared<T> {
+  value: T,
+  version: u64,
+  wakers: Vec<Box<dyn Fn() + Send>>
+}
+
+pub struct WatchSender<T> {
+  shared: Arc<Mutex<Shared<T>>>
+}
+
+impl<T: PartialEq> WatchSender<T> {
+  /// Replace the value, unless it's the same already
+  pub fn send(&self, value: T) {
+    self.send_if_modified(|current| {
+      let modified = *current != value;
+      *current = value;
+      modified
+    });
+  }
+}
+
+impl<T> WatchSender<T> {
+  /// Create another receiver of the same value, which starts off having seen none of it
+  pub fn subscribe(&self) -> WatchReceiver<T> {
+    WatchReceiver { shared: self.shared.clone(), seen_version: 0 }
+  }
+
+  /// Modify the value in place, notifying the receiver if `modify` returns true
+  pub fn send_if_modified(&self, modify: impl FnOnce(&mut T) -> bool) {
+    let mut shared = self.shared.lock().unwrap();
+    if modify(&mut shared.value) {
+      shared.version += 1;
+      shared.wakers.iter().for_each(|waker| waker());
+    }
+  }
+}
+
+pub struct WatchReceiver<T> {
+  shared: Arc<Mutex<Shared<T>>>,
+  seen_version: u64
+}
+
+impl<T: Clone> WatchReceiver<T> {
+  /// Get the value if it changed since the last call
+  pub fn changed(&mut self) -> Option<T> {
+    let shared = self.shared.lock().unwrap();
+    if shared.version == self.seen_version {
+      return None;
+    }
+    self.seen_version = shared.version;
+    Some(shared.value.clone())
+  }
+
+  /// Get the value, marking it as seen
+  pub fn latest(&mut self) -> T {
+    let shared = self.shared.lock().unwrap();
+    self.seen_version = shared.version;
+    shared.value.clone()
+  }
+}
+
+impl<T> WatchReceiver<T> {
+  /// Call the given function on the sending thread every time the value changes, which is how threads blocked in a
+  /// message loop get woken up. The function must return quickly, and must not use the channel
+  pub fn on_change(&self, waker: impl Fn() + Send + 'static) {
+    self.shared.lock().unwrap().wakers.push(Box::new(waker));
+  }
+}
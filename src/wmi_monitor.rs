@@ -0,0 +1,192 @@
+use crate::monitor::{BRIGHTNESS_VCP_CODE, BrightnessBackend};
+
+use ddc::{FeatureCode, VcpValue};
+use std::io;
+use std::ptr;
+use windows::core::{w, BSTR, PCWSTR};
+use windows::Win32::System::Com::{
+  CoCreateInstance, CoInitializeEx, CoSetProxyBlanket, CoUninitialize, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED, EOAC_NONE,
+  RPC_C_AUTHN_LEVEL_CALL, RPC_C_IMP_LEVEL_IMPERSONATE, VARIANT, VT_BSTR, VT_I4, VT_UI1
+};
+use windows::Win32::System::Ole::VariantClear;
+use windows::Win32::System::Rpc::{RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE};
+use windows::Win32::System::Wmi::{
+  IEnumWbemClassObject, IWbemClassObject, IWbemLocator, IWbemServices, WbemLocator, WBEM_FLAG_FORWARD_ONLY, WBEM_FLAG_RETURN_IMMEDIATELY,
+  WBEM_INFINITE
+};
+
+const WMI_NAMESPACE: &str = "ROOT\\WMI";
+const NAME: &str = "Built-in display";
+
+thread_local! {
+  static COM: ComGuard = ComGuard::initialize();
+}
+
+/// Keep COM initialized on the current thread until it exits, for the WMI objects to be usable from it. Threads that
+/// had it initialized in another mode already keep it that way, which works just as well for WMI
+struct ComGuard {
+  initialized: bool
+}
+
+impl ComGuard {
+  fn initialize() -> Self {
+    Self { initialized: unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.is_ok() }
+  }
+}
+
+impl Drop for ComGuard {
+  fn drop(&mut self) {
+    if self.initialized {
+      unsafe { CoUninitialize(); }
+    }
+  }
+}
+
+/// Represent a display panel whose brightness is controlled over WMI rather than DDC/CI, which is how the built-in
+/// panels of laptops take it
+///
+/// Reference: https://learn.microsoft.com/en-us/windows/win32/wmicoreprov/wmimonitorbrightnessmethods
+pub struct WmiMonitor {
+  services: IWbemServices,
+  /// Name of the instance of WmiMonitorBrightness standing for the panel, such as "DISPLAY\BOE0812\4&1b2c3d4&0&UID265988_0"
+  instance_name: String
+}
+
+impl WmiMonitor {
+  /// Open every panel that takes the brightness over WMI, which is none on most desktop computers
+  pub fn open_all() -> io::Result<Vec<Self>> {
+    COM.with(|_| {});
+    let services = unsafe { connect()? };
+    let objects = unsafe { query(&services, "SELECT InstanceName FROM WmiMonitorBrightnessMethods WHERE Active = TRUE")? };
+    let monitors = objects
+      .iter()
+      .filter_map(|object| unsafe { get_string(object, w!("InstanceName")) })
+      .map(|instance_name| Self { services: services.clone(), instance_name })
+      .collect();
+    Ok(monitors)
+  }
+
+  pub fn name(&self) -> String {
+    NAME.to_string()
+  }
+
+  /// Get the manufacturer and product code of the panel, such as "BOE0812", as they are in its EDID
+  pub fn model_id(&self) -> Option<&str> {
+    self.instance_name.split('\\').nth(1)
+  }
+
+  /// Get the brightness of the panel, from 0 to 100
+  pub fn get_brightness(&mut self) -> io::Result<u16> {
+    COM.with(|_| {});
+    let query_text = format!("SELECT CurrentBrightness FROM WmiMonitorBrightness WHERE InstanceName = '{}'", escape(&self.instance_name));
+    let objects = unsafe { query(&self.services, &query_text)? };
+    let object = objects.first().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the built-in display is gone"))?;
+    unsafe { get_byte(object, w!("CurrentBrightness")) }
+      .map(|value| value as u16)
+      .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "the built-in display reported no brightness"))
+  }
+
+  /// Set the brightness of the panel, given a value from 0 to 100. Panels only support some levels within that range,
+  /// and pick the closest one
+  pub fn set_brightness(&mut self, value: u16) -> io::Result<()> {
+    COM.with(|_| {});
+    unsafe {
+      let mut class = None;
+      self.services.GetObject(&BSTR::from("WmiMonitorBrightnessMethods"), 0, None, Some(&mut class), None)?;
+      let class = class.ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "WMI has no brightness methods"))?;
+      let mut in_signature = None;
+      class.GetMethod(w!("WmiSetBrightness"), 0, &mut in_signature, ptr::null_mut())?;
+      let in_parameters = in_signature
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "WMI has no method to set the brightness"))?
+        .SpawnInstance(0)?;
+
+      // Setting the brightness right away, rather than for a while before the previous one comes back
+      let mut timeout = VARIANT::default();
+      (*timeout.Anonymous.Anonymous).vt = VT_I4;
+      (*timeout.Anonymous.Anonymous).Anonymous.lVal = 0;
+      in_parameters.Put(w!("Timeout"), 0, &timeout, 0)?;
+      let mut brightness = VARIANT::default();
+      (*brightness.Anonymous.Anonymous).vt = VT_UI1;
+      (*brightness.Anonymous.Anonymous).Anonymous.bVal = value.min(100) as u8;
+      in_parameters.Put(w!("Brightness"), 0, &brightness, 0)?;
+
+      let object_path = format!("WmiMonitorBrightnessMethods.InstanceName='{}'", escape(&self.instance_name));
+      self.services.ExecMethod(&BSTR::from(object_path), &BSTR::from("WmiSetBrightness"), 0, None, &in_parameters, None, None)?;
+    }
+    Ok(())
+  }
+}
+
+impl BrightnessBackend for WmiMonitor {
+  fn name(&self) -> String {
+    WmiMonitor::name(self)
+  }
+
+  fn get_vcp_feature(&mut self, code: FeatureCode) -> io::Result<VcpValue> {
+    match code {
+      BRIGHTNESS_VCP_CODE => self.get_brightness().map(|value| VcpValue { ml: 100, ..VcpValue::from_value(value) }),
+      _ => Err(unsupported_wmi_feature(code))
+    }
+  }
+
+  fn set_vcp_feature(&mut self, code: FeatureCode, value: u16) -> io::Result<()> {
+    match code {
+      BRIGHTNESS_VCP_CODE => self.set_brightness(value),
+      _ => Err(unsupported_wmi_feature(code))
+    }
+  }
+}
+
+unsafe fn connect() -> io::Result<IWbemServices> {
+  let locator: IWbemLocator = CoCreateInstance(&WbemLocator, None, CLSCTX_INPROC_SERVER)?;
+  let services = locator.ConnectServer(&BSTR::from(WMI_NAMESPACE), &BSTR::new(), &BSTR::new(), &BSTR::new(), 0, &BSTR::new(), None)?;
+  // Reference: https://learn.microsoft.com/en-us/windows/win32/wmisdk/setting-the-security-levels-on-a-wmi-connection
+  CoSetProxyBlanket(&services, RPC_C_AUTHN_WINNT, RPC_C_AUTHZ_NONE, PCWSTR::null(), RPC_C_AUTHN_LEVEL_CALL, RPC_C_IMP_LEVEL_IMPERSONATE, None, EOAC_NONE)?;
+  Ok(services)
+}
+
+unsafe fn query(services: &IWbemServices, query_text: &str) -> io::Result<Vec<IWbemClassObject>> {
+  let enumerator: IEnumWbemClassObject =
+    services.ExecQuery(&BSTR::from("WQL"), &BSTR::from(query_text), WBEM_FLAG_FORWARD_ONLY | WBEM_FLAG_RETURN_IMMEDIATELY, None)?;
+  let mut objects = Vec::new();
+  loop {
+    let mut object = [None];
+    let mut returned = 0;
+    enumerator.Next(WBEM_INFINITE, &mut object, &mut returned).ok()?;
+    match object {
+      [Some(object)] if returned > 0 => objects.push(object),
+      _ => return Ok(objects)
+    };
+  }
+}
+
+unsafe fn get_string(object: &IWbemClassObject, name: PCWSTR) -> Option<String> {
+  let mut value = VARIANT::default();
+  object.Get(name, 0, &mut value, None, None).ok()?;
+  let text = match (*value.Anonymous.Anonymous).vt == VT_BSTR {
+    true => Some((*value.Anonymous.Anonymous).Anonymous.bstrVal.to_string()),
+    false => None
+  };
+  let _ = VariantClear(&mut value);
+  text
+}
+
+unsafe fn get_byte(object: &IWbemClassObject, name: PCWSTR) -> Option<u8> {
+  let mut value = VARIANT::default();
+  object.Get(name, 0, &mut value, None, None).ok()?;
+  let byte = match (*value.Anonymous.Anonymous).vt == VT_UI1 {
+    true => Some((*value.Anonymous.Anonymous).Anonymous.bVal),
+    false => None
+  };
+  let _ = VariantClear(&mut value);
+  byte
+}
+
+fn unsupported_wmi_feature(code: FeatureCode) -> io::Error {
+  io::Error::new(io::ErrorKind::Unsupported, format!("built-in displays have no VCP feature 0x{:02x}", code))
+}
+
+/// Escape the backslashes and quotes of a string put between quotes in a WQL query or an object path
+fn escape(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('\'', "\\'")
+}
@@ -0,0 +1,286 @@
+//! Run the brightness thread of the program over a mock monitor, feeding it synthetic knob adjustment events, and check
+//! the brightness written to the monitor along the way: the queued events folded into a single target kept within the
+//! range, the transitions to it and what interrupts them, and the thread staying asleep while the knob is idle. Only
+//! built with the "simulated-input" feature
+
+use crossbeam_channel::{Receiver, Sender, bounded, unbounded};
+use gmmk_pro_brightness_knob::{stats, watch};
+use gmmk_pro_brightness_knob::brightness_loop::{BrightnessChannels, run_brightness_loop};
+use gmmk_pro_brightness_knob::config::{Config, MonitorConfig};
+use gmmk_pro_brightness_knob::keyboard_knob::{EventSource, KnobAction, KnobAdjustmentEvent};
+use gmmk_pro_brightness_knob::knob_mode::KnobMode;
+use gmmk_pro_brightness_knob::mock_target::{MockMonitor, MockWrites};
+use gmmk_pro_brightness_knob::monitor::Monitor;
+use gmmk_pro_brightness_knob::monitor_group::{MonitorGroup, MonitorSelection};
+use gmmk_pro_brightness_knob::range_limit::LimitBehavior;
+use gmmk_pro_brightness_knob::state::{AppliedChange, StateSnapshot};
+use gmmk_pro_brightness_knob::system_events::SystemEvent;
+use gmmk_pro_brightness_knob::transition::Easing;
+use gmmk_pro_brightness_knob::watch::WatchReceiver;
+use std::env;
+use std::io;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const SLOW_TRANSITION: Duration = Duration::from_millis(500);
+/// How long the brightness thread is left alone for, which is longer than any of the timers it could have armed
+const IDLE_PERIOD: Duration = Duration::from_secs(3);
+/// How long the brightness thread is given to get through the events sent to it before the test fails
+const PROCESSING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters of the brightness thread are shared by the whole process, so the tests running it take turns
+static BRIGHTNESS_THREAD: Mutex<()> = Mutex::new(());
+
+/// Brightness thread of the program driving a mock monitor, with the knob turned by the tests
+struct Harness {
+  events_tx: Sender<KnobAdjustmentEvent>,
+  _system_tx: Sender<SystemEvent>,
+  stop_tx: Sender<()>,
+  state_rx: WatchReceiver<Option<StateSnapshot>>,
+  changes_rx: Receiver<AppliedChange>,
+  writes: MockWrites,
+  /// Everything the brightness thread is started with, until it is
+  pending: Option<(Config, MonitorGroup, Receiver<()>, BrightnessChannels)>,
+  brightness_thread: Option<JoinHandle<()>>,
+  /// Events processed by the brightness thread before the test started, and events sent to it since
+  processed_before: u64,
+  events_sent: u64,
+  _turn: MutexGuard<'static, ()>
+}
+
+impl Harness {
+  /// Get ready to run the brightness thread over a single mock monitor, leaving it to the test to start it once some
+  /// events are queued if it needs them to be handled all at once
+  fn new(monitor: MockMonitor, config: Config) -> Self {
+    let turn = BRIGHTNESS_THREAD.lock().unwrap_or_else(PoisonError::into_inner);
+    // The saved brightness and the usage records of the user are left alone
+    env::set_var("LOCALAPPDATA", env::temp_dir().join("gmmk-pro-brightness-knob-tests"));
+    let writes = monitor.writes();
+    let monitors = MonitorGroup::from_monitors(vec![Monitor::from_backend(Box::new(monitor))]);
+    let (events_tx, events_rx) = unbounded();
+    let (system_tx, system_rx) = unbounded();
+    let (state_tx, state_rx) = watch::channel(None);
+    let (osd_tx, _) = watch::channel(None);
+    let (overlay_tx, _) = watch::channel(0);
+    let (changes_tx, changes_rx) = unbounded();
+    let (stop_tx, stop_rx) = bounded(1);
+    let channels = BrightnessChannels { events_rx, system_rx, state_tx, osd_tx, overlay_tx, changes_tx, shared_state: None };
+    Self {
+      events_tx,
+      _system_tx: system_tx,
+      stop_tx,
+      state_rx,
+      changes_rx,
+      writes,
+      pending: Some((config, monitors, stop_rx, channels)),
+      brightness_thread: None,
+      processed_before: stats::snapshot().events_processed,
+      events_sent: 0,
+      _turn: turn
+    }
+  }
+
+  fn start(&mut self) {
+    let (config, monitors, stop_rx, channels) = self.pending.take().expect("the brightness thread was already started");
+    self.brightness_thread = Some(thread::spawn(move || {
+      run_brightness_loop(stop_rx, &config, monitors, MonitorSelection::All, false, channels);
+    }));
+  }
+
+  /// Turn the knob by the given number of notches, positive ones turning it up
+  fn turn(&mut self, notches: i32) {
+    turn(&self.events_tx, notches);
+    self.events_sent += u64::from(notches.unsigned_abs());
+  }
+
+  /// Turn the knob by the given number of notches once the delay has passed, from another thread
+  fn turn_later(&mut self, notches: i32, delay: Duration) -> JoinHandle<()> {
+    let events_tx = self.events_tx.clone();
+    self.events_sent += u64::from(notches.unsigned_abs());
+    thread::spawn(move || {
+      thread::sleep(delay);
+      turn(&events_tx, notches);
+    })
+  }
+
+  /// Wait for the next change the brightness thread applies to the monitor
+  fn next_change(&self) -> AppliedChange {
+    self.changes_rx.recv_timeout(PROCESSING_TIMEOUT).expect("no change was applied to the monitor")
+  }
+
+  /// Wait for the brightness thread to move the monitor to the given brightness, through as many changes as it takes
+  fn settle_at(&self, brightness: u16) {
+    while self.next_change().to != brightness {}
+  }
+
+  /// Wait for the brightness thread to get through every event sent to it so far, whether or not they changed anything
+  fn wait_until_processed(&self) {
+    let deadline = Instant::now() + PROCESSING_TIMEOUT;
+    while stats::snapshot().events_processed - self.processed_before < self.events_sent {
+      assert!(Instant::now() < deadline, "the brightness thread didn't get through the events sent to it");
+      thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  /// Send the stop signal to the brightness thread and wait for it to exit
+  fn stop(&mut self) {
+    self.stop_tx.send(()).unwrap();
+    self.brightness_thread.take().expect("the brightness thread wasn't started").join().unwrap();
+  }
+
+  /// Get the brightness of the monitor last reported by the brightness thread
+  fn reported_brightness(&mut self) -> Option<u16> {
+    self.state_rx.latest().and_then(|snapshot| snapshot.brightness)
+  }
+}
+
+fn turn(events_tx: &Sender<KnobAdjustmentEvent>, notches: i32) {
+  let action = if notches > 0 { KnobAction::Increment } else { KnobAction::Decrement };
+  for _ in 0..notches.abs() {
+    events_tx.send(KnobAdjustmentEvent::new(action, EventSource::Keyboard)).unwrap();
+  }
+}
+
+fn slow_transition() -> Config {
+  Config { animation_duration: SLOW_TRANSITION, animation_easing: Easing::Linear, ..Config::default() }
+}
+
+#[test]
+fn queued_events_are_coalesced_into_a_single_write() {
+  let mut harness = Harness::new(MockMonitor::new(50), Config::default());
+  harness.turn(5);
+  harness.start();
+  assert_eq!(harness.next_change().to, 55);
+  harness.stop();
+  assert_eq!(harness.writes.brightness(), vec![55]);
+}
+
+#[test]
+fn queued_events_are_coalesced_into_a_single_transition() {
+  let mut harness = Harness::new(MockMonitor::new(50), slow_transition());
+  harness.turn(10);
+  harness.start();
+  assert_eq!(harness.next_change().to, 60);
+  harness.stop();
+
+  let writes = harness.writes.brightness();
+  assert_eq!(writes.last(), Some(&60));
+  assert!(writes.windows(2).all(|pair| pair[0] < pair[1]), "the transition went back and forth: {:?}", writes);
+}
+
+#[test]
+fn targets_are_clamped_to_the_range() {
+  let mut harness = Harness::new(MockMonitor::new(97), Config::default());
+  harness.turn(10);
+  harness.start();
+  harness.settle_at(100);
+
+  // Pushing against the limit doesn't write anything more
+  harness.turn(3);
+  harness.wait_until_processed();
+  harness.turn(-100);
+  harness.settle_at(0);
+  harness.stop();
+  let writes = harness.writes.brightness();
+  assert_eq!(writes[0], 100);
+  assert_eq!(writes.last(), Some(&0));
+  assert!(writes[1..].iter().all(|&value| value < 100), "the brightness was pushed past the limit: {:?}", writes);
+}
+
+#[test]
+fn wrapping_goes_around_once_at_the_limit() {
+  let mut config = Config::default();
+  config.limit_behavior.insert(KnobMode::Brightness, LimitBehavior::Wrap);
+  let mut harness = Harness::new(MockMonitor::new(98), config);
+  harness.turn(2);
+  harness.start();
+  harness.settle_at(100);
+  harness.turn(1);
+  harness.settle_at(0);
+  harness.stop();
+  assert_eq!(harness.writes.brightness(), vec![100, 0]);
+}
+
+#[test]
+fn inverted_monitors_turn_the_other_way() {
+  let mut config = Config::default();
+  config.monitors.insert("Mock monitor".to_string(), MonitorConfig { inverted: true, ..MonitorConfig::default() });
+  let mut harness = Harness::new(MockMonitor::new(50), config);
+  harness.turn(3);
+  harness.start();
+  assert_eq!(harness.next_change().to, 47);
+  harness.stop();
+  assert_eq!(harness.writes.brightness(), vec![47]);
+}
+
+#[test]
+fn new_events_interrupt_the_transition() {
+  let mut harness = Harness::new(MockMonitor::new(0), slow_transition());
+  harness.turn(40);
+  let turned = harness.turn_later(-1, Duration::from_millis(150));
+  harness.start();
+  let interrupted_at = harness.next_change().to;
+  assert!(0 < interrupted_at && interrupted_at < 40, "the transition wasn't interrupted: {}", interrupted_at);
+
+  // Turning the other way starts from the brightness displayed, without going back to the previous target first
+  assert_eq!(harness.next_change().to, interrupted_at - 1);
+  turned.join().unwrap();
+  harness.stop();
+  let writes = harness.writes.brightness();
+  assert_eq!(writes[writes.len() - 2..], [interrupted_at, interrupted_at - 1]);
+}
+
+#[test]
+fn the_stop_signal_interrupts_the_transition() {
+  let mut harness = Harness::new(MockMonitor::new(0), slow_transition());
+  harness.turn(40);
+  harness.start();
+  thread::sleep(Duration::from_millis(150));
+  harness.stop();
+
+  // The rest of the transition is skipped, with the target written straight away before the thread exits
+  let writes = harness.writes.brightness();
+  assert_eq!(writes.last(), Some(&40));
+  assert!(writes.len() < 30, "the transition wasn't interrupted: {:?}", writes);
+  assert!(writes[writes.len() - 2] < 40, "the transition wasn't interrupted: {:?}", writes);
+}
+
+#[test]
+fn frames_follow_the_refresh_rate() {
+  let mut harness = Harness::new(MockMonitor::new(0), slow_transition());
+  harness.turn(100);
+  harness.start();
+  assert_eq!(harness.next_change().to, 100);
+  harness.stop();
+
+  // 500 ms at the 60 Hz of the mock monitor, with every frame writing a new value given how far the brightness goes
+  let write_times = harness.writes.brightness_times();
+  assert_eq!(write_times.len(), 30);
+  let elapsed = write_times.last().unwrap().duration_since(write_times[0]);
+  assert!(elapsed >= Duration::from_millis(400), "the transition was too quick: {:?}", elapsed);
+}
+
+#[test]
+fn failed_writes_keep_the_brightness() {
+  let monitor = MockMonitor::new(50).failing_after(0, io::ErrorKind::TimedOut);
+  let mut harness = Harness::new(monitor, Config::default());
+  harness.turn(5);
+  harness.start();
+  harness.wait_until_processed();
+  harness.stop();
+  assert_eq!(harness.reported_brightness(), Some(50));
+  assert!(harness.writes.is_empty());
+}
+
+#[test]
+fn idle_brightness_thread_stays_asleep() {
+  let mut harness = Harness::new(MockMonitor::new(50), Config::default());
+  let wakeups_before = stats::snapshot().wakeups;
+  harness.start();
+  thread::sleep(IDLE_PERIOD);
+  let wakeups = stats::snapshot().wakeups - wakeups_before;
+  harness.stop();
+  assert_eq!(wakeups, 0);
+  assert!(harness.writes.is_empty(), "the monitor was written to: {:?}", harness.writes.brightness());
+}